@@ -1,78 +1,343 @@
 //! Structs and enums that will store the output of the Go parser, facilitating LLVM code
 //! generation.
 
-use inkwell::{context::Context, types::BasicTypeEnum, AddressSpace};
+use crate::constant::Value;
+use inkwell::{
+    context::Context,
+    types::{BasicType, BasicTypeEnum},
+    AddressSpace,
+};
 
 /// A block of code (which is a vector of statements)
 pub type CodeBlock = Vec<Statement>;
-/// Of the form `[(name, type), ...]`
-pub type Params = Vec<(String, Type)>;
+/// Of the form `[(name, type, span), ...]`
+pub type Params = Vec<(String, Type, Span)>;
+
+/// A region of source code: the bytes `start..end` of the file with id `file_id` (see
+/// `source_map::SourceMap`). Every node of the AST carries one, so that errors can point back to
+/// the code that caused them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub file_id: usize,
+    pub start: usize,
+    pub end: usize,
+}
 
 /// The top-level go package.
 #[derive(Debug)]
 pub struct Program {
     /// `package package_name`
     pub package_name: String,
-    /// `import (mod_1, ..)`
-    pub imports: Vec<String>,
+    /// The span of the package name
+    pub package_span: Span,
+    /// `import (mod_1, ..)`, along with the span of each import path
+    pub imports: Vec<(String, Span)>,
+    /// Package-level type declarations
+    pub types: Vec<TypeDecl>,
+    /// Package-level constant declarations (`Statement::Const`)
+    pub consts: Vec<Statement>,
     pub functions: Vec<FuncDef>,
 }
 
-/// A function in the go package. If `return_value` is `None`, then
-/// the function is of type `void`.
+/// `type <name> struct { <fields> }`. Only struct types can be declared for now
+#[derive(Debug)]
+pub struct TypeDecl {
+    pub name: String,
+    pub fields: Vec<Field>,
+    /// The span of the name
+    pub span: Span,
+}
+
+/// A field of a struct type: `<name> <field_type>`, or just `<field_type>` for an embedded field,
+/// which is named after its type. The fields of an embedded struct are promoted: they can be
+/// selected as if they were fields of the struct embedding it
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    pub field_type: Type,
+    pub embedded: bool,
+    pub span: Span,
+}
+
+/// A function in the go package. A function without results is of type `void`.
 #[derive(Debug)]
 pub struct FuncDef {
     pub name: String,
     /// `(name1 type1, name2 type2, ..)`
     pub params: Params,
-    pub return_type: Option<Type>,
+    /// `T`, `(T1, T2, ..)` or `(name1 T1, name2 T2, ..)`. Like unnamed parameters, unnamed results
+    /// are named `_`
+    pub results: Params,
+    /// Whether the results are named, in which case they're variables of the function (starting
+    /// out as zero), which a bare `return` returns
+    pub named_results: bool,
     pub code: CodeBlock,
+    /// From `func` up to the end of the signature
+    pub span: Span,
 }
 
 /// Currently, only some go types are supported:
 /// * `go_type` (`llvm_type`)
-/// * `int` (`i64`)
+/// * `int`, `int64`, `uint`, `uint64` and `uintptr` (`i64`)
+/// * `int8` and `uint8` (`i8`), `int16` and `uint16` (`i16`), `int32` and `uint32` (`i32`)
 /// * `bool` (`i1`)
 /// * `float32` (`f32`)
 /// * `float64` (`f64`)
-/// * `string` (`i8*`)
-#[derive(Debug, PartialEq, Eq)]
+/// * `string` (`{ i8*, i64 }`: a pointer to the bytes and their number, which may include NULs)
+/// * `[N]T` (`[N x T]`)
+/// * `[]T` (`{ T*, i64, i64 }`: a pointer to the first element, the length and the capacity)
+/// * `T`, declared with `type T struct { .. }` (`%T`, a named LLVM struct type with its fields)
+/// * `*T` (`T*`, which is null for `nil`)
+/// * `map[K]V` (`i8*`, a pointer to the runtime's hash table, which is null for `nil`)
+///
+/// * `(T1, T2, ..)`, the results of a function with several (`{ T1, T2, .. }`)
+///
+/// `byte` and `rune` are aliases for `uint8` and `int32`. `int`, `uint` and `uintptr` are 64 bits
+/// wide, as on every 64-bit target.
+///
+/// `Unknown` is a placeholder the parser uses for types it can't determine syntactically (e.g. the
+/// type of a name); it must be resolved before code generation.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Type {
     Int,
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    Uint,
+    Uint8,
+    Uint16,
+    Uint32,
+    Uint64,
+    Uintptr,
     Bool,
     Float32,
     Float64,
     GoString,
+    /// `[N]T`, `N` values of type `T`
+    Array(u64, Box<Type>),
+    /// `[]T`, a window onto an array of `T`s that the slices of it share
+    Slice(Box<Type>),
+    /// A struct type declared with `type <name> struct { .. }`. The parser makes every type name
+    /// that isn't predeclared one: the semantic checker makes sure it's declared
+    Named(String),
+    /// `*T`, the address of a variable of type `T` (or `nil`)
+    Pointer(Box<Type>),
+    /// `map[K]V`, a hash table from keys of type `K` (which must be comparable) to values of type
+    /// `V`, which the copies of a map share
+    Map(Box<Type>, Box<Type>),
+    /// `error`, the predeclared interface. Interfaces aren't supported, so its only values are
+    /// `nil` and the errors `fmt.Errorf` makes, pointers to an object with their message
+    Error,
+    /// The types of the results of a call of a function with several, which can only be assigned
+    /// to as many variables, returned, or passed as the arguments of another call. It isn't a type
+    /// of the language
+    Tuple(Vec<Type>),
+    Unknown,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Expression {
     Name {
         expr_type: Type,
         name: String,
+        span: Span,
     },
-    /// Something like an integer of float. Note that if the literal is a bool,
-    /// then the value is either 0 for false, or 1 for true
+    /// Something like an integer or float. The parser tags literals with their default type;
+    /// the semantic checker also turns constant expressions into literals of their final type
     Literal {
         expr_type: Type,
-        value: String,
+        value: Value,
+        span: Span,
     },
     BinaryOp {
         expr_type: Type,
         op: BinaryOp,
         left: Box<Expression>,
         right: Box<Expression>,
+        span: Span,
     },
     Call {
         expr_type: Option<Type>,
+        /// The package of a qualified call like `fmt.Println(...)`, and the span of its name
+        package: Option<(String, Span)>,
         /// Currently you only call a function, not an expression that
         /// evaluates to a function (e.g. closure, methods, etc.)
         func: String,
+        /// The span of the function's name
+        func_span: Span,
+        args: Vec<Expression>,
+        /// Whether the last argument is followed by `...`
+        has_dots: bool,
+        span: Span,
+    },
+    UnaryOp {
+        expr_type: Type,
+        op: UnaryOp,
+        expr: Box<Expression>,
+        span: Span,
+    },
+    /// A call of a predeclared function (which the semantic checker turns calls into)
+    BuiltinCall {
+        expr_type: Option<Type>,
+        builtin: Builtin,
+        args: Vec<Expression>,
+        /// Whether the last argument is followed by `...` (only valid for `append`)
+        has_dots: bool,
+        span: Span,
+    },
+    /// A call of a function of the `fmt` package (which the semantic checker turns qualified calls
+    /// into)
+    FmtCall {
+        expr_type: Option<Type>,
+        func: FmtFunc,
         args: Vec<Expression>,
+        span: Span,
+    },
+    /// A call of a function of the `runtime` package (which the semantic checker turns qualified
+    /// calls into)
+    RuntimeCall {
+        expr_type: Option<Type>,
+        func: RuntimeFunc,
+        args: Vec<Expression>,
+        span: Span,
+    },
+    /// `<expr>[<index>]`
+    Index {
+        expr_type: Type,
+        expr: Box<Expression>,
+        index: Box<Expression>,
+        span: Span,
+    },
+    /// `<expr>[<low>:<high>]`, where both indices are optional, or `<expr>[<low>:<high>:<max>]`,
+    /// where only `low` is
+    Slice {
+        expr_type: Type,
+        expr: Box<Expression>,
+        low: Option<Box<Expression>>,
+        high: Option<Box<Expression>>,
+        max: Option<Box<Expression>>,
+        span: Span,
+    },
+    /// `<expr>.<field>`, a field of a struct
+    Selector {
+        expr_type: Type,
+        expr: Box<Expression>,
+        field: String,
+        /// The indices of the fields to go through, filled in by the semantic checker: more than
+        /// one for a field promoted from an embedded struct
+        path: Vec<usize>,
+        span: Span,
+    },
+    /// `<expr_type>{<elements>}`, an array, slice, map or struct literal
+    CompositeLit {
+        expr_type: Type,
+        /// How the type is written. For `[...]T`, the parser leaves the length at 0, and for an
+        /// elided type the type is `Unknown`: the semantic checker fills them in
+        syntax: LitType,
+        elements: Vec<KeyedElement>,
+        span: Span,
+    },
+    /// The zero value of `expr_type`, which variables of array, slice, struct, pointer, map and
+    /// error types declared without an initializer start out with
+    ZeroValue { expr_type: Type, span: Span },
+    /// The predeclared `nil` (which the semantic checker turns names into), whose type is the
+    /// pointer, slice or map type it's used as
+    Nil { expr_type: Type, span: Span },
+    /// `new(T)`, the address of a new variable of type `T`. The parser recognizes it by name, so
+    /// `new` can't be shadowed. The type is `*T`
+    New { expr_type: Type, span: Span },
+    /// `make(T, <args>)`, a new map of type `T` with room for the number of entries given by the
+    /// optional argument. Like `new`, the parser recognizes it by name
+    Make {
+        expr_type: Type,
+        args: Vec<Expression>,
+        span: Span,
+    },
+    /// `<expr_type>(<expr>)`, which converts `expr` to `expr_type`
+    Conversion {
+        expr_type: Type,
+        expr: Box<Expression>,
+        span: Span,
     },
 }
 
-#[derive(Debug)]
+/// How the type of a composite literal is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LitType {
+    /// `T{...}`
+    Explicit,
+    /// `[...]T{...}`, an array as long as its elements need
+    InferredLen,
+    /// `{...}`, an element of another composite literal, which has the element type
+    Elided,
+}
+
+/// An element of a composite literal: `<key>: <value>`, or just `<value>`. The key of an element
+/// of an array or slice literal is its index, that of an element of a map literal is its key, and
+/// that of an element of a struct literal is the name of its field (an `Expression::Name`)
+#[derive(Debug, Clone)]
+pub struct KeyedElement {
+    pub key: Option<Expression>,
+    pub value: Expression,
+}
+
+/// The predeclared functions, which are part of the language rather than of the runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    /// `len(s)`, the number of bytes in a string, of elements in an array or slice, or of entries
+    /// in a map
+    Len,
+    /// `cap(s)`, the number of elements the array of a slice has room for from its start
+    Cap,
+    /// `append(s, elems...)`, which appends to a slice, moving it to a larger array if needed
+    Append,
+    /// `copy(dst, src)`, which copies as many elements as both slices have from `src` to `dst`
+    Copy,
+    /// `print(args...)`, which writes its arguments to stderr
+    Print,
+    /// `println(args...)`, which writes its arguments to stderr separated by spaces, and then a
+    /// newline
+    Println,
+    /// `new(T)`, which the parser makes an `Expression::New`
+    New,
+    /// `make(T, args...)`, which the parser makes an `Expression::Make`
+    Make,
+    /// `delete(m, key)`, which removes the entry with the key `key` from the map `m` (if there is
+    /// one)
+    Delete,
+}
+
+/// The functions of the `fmt` package, which the runtime implements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FmtFunc {
+    /// `fmt.Print(args...)`, which writes its arguments to stdout, with spaces between those that
+    /// aren't strings
+    Print,
+    /// `fmt.Println(args...)`, which writes its arguments to stdout separated by spaces, and then
+    /// a newline
+    Println,
+    /// `fmt.Printf(format, args...)`, which writes its arguments to stdout as `format` describes
+    Printf,
+    /// `fmt.Sprint(args...)`, which formats like `fmt.Print` into a string
+    Sprint,
+    /// `fmt.Sprintln(args...)`, which formats like `fmt.Println` into a string
+    Sprintln,
+    /// `fmt.Sprintf(format, args...)`, which formats like `fmt.Printf` into a string
+    Sprintf,
+    /// `fmt.Errorf(format, args...)`, which returns an `error` whose message is formatted like
+    /// `fmt.Sprintf`, except that `%w` formats `error`s too
+    Errorf,
+}
+
+/// The functions of the `runtime` package, which the runtime implements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeFunc {
+    /// `runtime.GC()`, which collects garbage right away
+    GC,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BinaryOp {
     /// +
     Add,
@@ -82,6 +347,8 @@ pub enum BinaryOp {
     Mul,
     /// /
     Div,
+    /// %
+    Rem,
     /// ==
     Eq,
     /// !=
@@ -94,35 +361,326 @@ pub enum BinaryOp {
     Geq,
     /// \<=
     Leq,
+    /// <<
+    Shl,
+    /// \>>
+    Shr,
+    /// &
+    And,
+    /// |
+    Or,
+    /// ^
+    Xor,
+    /// &^
+    AndNot,
+    /// &&, which only evaluates its right operand if the left one is true
+    LogicalAnd,
+    /// ||, which only evaluates its right operand if the left one is false
+    LogicalOr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnaryOp {
+    /// +
+    Plus,
+    /// \-
+    Neg,
+    /// !
+    Not,
+    /// ^ (bitwise complement)
+    Complement,
+    /// & (the address of a variable, or of a new variable holding a composite literal)
+    Addr,
+    /// \* (the variable a pointer points to)
+    Deref,
 }
 
 #[derive(Debug)]
 pub enum Statement {
-    /// `var <name> <var_type> = <expr>`
+    /// `var <name> <var_type> = <expr>`. The span starts at `name`, so that errors about the
+    /// variable point to it
     Assignment {
         name: String,
         var_type: Type,
         expr: Expression,
+        span: Span,
+    },
+    /// `<vars> := <exprs>`, which declares the variables not declared in the block yet, and assigns
+    /// to the others. In `v, ok := m[k]`, the map index gives both the value and whether the key
+    /// was in the map (a `bool`)
+    ShortVarDecl {
+        vars: Vec<ShortVar>,
+        exprs: Vec<Expression>,
+        span: Span,
+    },
+    /// `<lhs> = <rhs>`, or `<lhs> <op>= <rhs>` (with a single variable on each side). All the
+    /// right-hand sides are evaluated before any variable is assigned to. `v, ok = m[k]` has a
+    /// single right-hand side, which gives two values (see `ShortVarDecl`)
+    Assign {
+        lhs: Vec<Expression>,
+        op: Option<BinaryOp>,
+        rhs: Vec<Expression>,
+        span: Span,
+    },
+    /// `<expr>++` (`op` is `Add`) or `<expr>--` (`op` is `Sub`)
+    IncDec {
+        expr: Expression,
+        op: BinaryOp,
+        span: Span,
     },
     /// `if <cond> { <then_block> } else { <else_block2> }`
-    If { cond: Expression, then_block: CodeBlock, else_block: CodeBlock },
-    /// `return <expr>`
-    Return { expr: Expression },
+    If {
+        cond: Expression,
+        then_block: CodeBlock,
+        else_block: CodeBlock,
+        span: Span,
+    },
+    /// `for <init>; <cond>; <post> { <body> }`. `for <cond> { <body> }` has neither an init nor a
+    /// post statement, and `for { <body> }` doesn't have a condition either. The init and post
+    /// statements are simple statements (e.g. an expression)
+    For {
+        init: Option<Box<Statement>>,
+        cond: Option<Expression>,
+        post: Option<Box<Statement>>,
+        body: CodeBlock,
+        span: Span,
+    },
+    /// `for <key>, <value> := range <expr> { <body> }`, or `=` instead of `:=` to assign to
+    /// existing variables. The key and the value are optional. Ranging over a string gives the
+    /// byte index and the value of each rune, and ranging over a map gives its entries in a random
+    /// order
+    ForRange {
+        key: Option<Expression>,
+        value: Option<Expression>,
+        /// Whether the key and value are declared (`:=`) rather than assigned to (`=`)
+        define: bool,
+        expr: Box<Expression>,
+        body: CodeBlock,
+        span: Span,
+    },
+    /// `switch <init>; <tag> { <cases> }`. Without a tag, the switch is on `true` (the cases are
+    /// conditions)
+    Switch {
+        init: Option<Box<Statement>>,
+        tag: Option<Expression>,
+        cases: Vec<CaseClause>,
+        span: Span,
+    },
+    /// `fallthrough`, which may only end the body of a case, and continues with the body of the
+    /// next one
+    Fallthrough { span: Span },
+    /// `break`, which exits the innermost loop or switch, or `break <label>`, which exits the loop
+    /// or switch labeled `label`
+    Break { label: Option<String>, span: Span },
+    /// `continue`, which starts the next iteration of the innermost loop (or of the loop labeled
+    /// `label`)
+    Continue { label: Option<String>, span: Span },
+    /// `<name>:`, which labels the statement after it in the block (if there is one: a label can
+    /// also be the last statement of a block)
+    Label { name: String, span: Span },
+    /// `goto <label>`
+    Goto { label: String, span: Span },
+    /// `return <exprs>`. A bare `return` (without expressions) returns the named results of the
+    /// function, if it has any
+    Return { exprs: Vec<Expression>, span: Span },
     /// `<expr>`
-    Expression { expr: Expression },
+    Expression { expr: Expression, span: Span },
+    /// `const ( <spec1>; <spec2>; .. )`. Constants don't exist at runtime: the semantic checker
+    /// evaluates them, and replaces the uses of constants with their values
+    Const { specs: Vec<ConstSpec>, span: Span },
+}
+
+/// `case <exprs>: <body>` or `default: <body>` in a switch
+#[derive(Debug)]
+pub struct CaseClause {
+    /// `None` for the default case
+    pub exprs: Option<Vec<Expression>>,
+    pub body: CodeBlock,
+    pub span: Span,
+}
+
+/// A variable on the left-hand side of `:=`
+#[derive(Debug)]
+pub struct ShortVar {
+    pub name: String,
+    /// Filled in by the semantic checker
+    pub var_type: Type,
+    /// Whether `:=` declares the variable, rather than assigning to a variable declared earlier in
+    /// the same block. Filled in by the semantic checker
+    pub is_new: bool,
+    pub span: Span,
+}
+
+/// `<names> <const_type> = <exprs>` in a constant declaration
+#[derive(Debug)]
+pub struct ConstSpec {
+    pub names: Vec<(String, Span)>,
+    /// `Type::Unknown` if the type is omitted
+    pub const_type: Type,
+    /// If omitted, the type and expressions of the previous spec are repeated
+    pub exprs: Vec<Expression>,
+    /// The value of `iota` in the spec (its index in the declaration)
+    pub iota: u64,
+}
+
+impl FuncDef {
+    /// The type of a call of the function: the type of its result, the tuple of the types of its
+    /// results if it has several, or `None` if it has none
+    pub fn result_type(&self) -> Option<Type> {
+        match self.results.as_slice() {
+            [] => None,
+            [(_, ty, _)] => Some(ty.clone()),
+            results => Some(Type::Tuple(
+                results.iter().map(|(_, ty, _)| ty.clone()).collect(),
+            )),
+        }
+    }
 }
 
 impl Type {
     /// Convert to an LLVM type. Very useful during code generation
     pub fn to_llvm<'ctx>(&self, context: &'ctx Context) -> BasicTypeEnum<'ctx> {
         match self {
-            Type::Int => BasicTypeEnum::IntType(context.i64_type()),
+            Type::Int8 | Type::Uint8 => BasicTypeEnum::IntType(context.i8_type()),
+            Type::Int16 | Type::Uint16 => BasicTypeEnum::IntType(context.i16_type()),
+            Type::Int32 | Type::Uint32 => BasicTypeEnum::IntType(context.i32_type()),
+            Type::Int | Type::Int64 | Type::Uint | Type::Uint64 | Type::Uintptr => {
+                BasicTypeEnum::IntType(context.i64_type())
+            }
             Type::Float32 => BasicTypeEnum::FloatType(context.f32_type()),
             Type::Float64 => BasicTypeEnum::FloatType(context.f64_type()),
             Type::Bool => BasicTypeEnum::IntType(context.bool_type()),
-            Type::GoString => {
+            Type::GoString => BasicTypeEnum::StructType(context.struct_type(
+                &[
+                    context.i8_type().ptr_type(AddressSpace::Generic).into(),
+                    context.i64_type().into(),
+                ],
+                false,
+            )),
+            // The parser rejects longer arrays
+            Type::Array(len, elem) => {
+                BasicTypeEnum::ArrayType(elem.to_llvm(context).array_type(*len as u32))
+            }
+            Type::Slice(elem) => BasicTypeEnum::StructType(context.struct_type(
+                &[
+                    elem.to_llvm(context).ptr_type(AddressSpace::Generic).into(),
+                    context.i64_type().into(),
+                    context.i64_type().into(),
+                ],
+                false,
+            )),
+            Type::Named(name) => BasicTypeEnum::StructType(
+                context
+                    .get_struct_type(name)
+                    .expect("struct types are declared before code generation"),
+            ),
+            Type::Pointer(elem) => {
+                BasicTypeEnum::PointerType(elem.to_llvm(context).ptr_type(AddressSpace::Generic))
+            }
+            Type::Map(..) | Type::Error => {
                 BasicTypeEnum::PointerType(context.i8_type().ptr_type(AddressSpace::Generic))
             }
+            Type::Tuple(types) => {
+                let types: Vec<_> = types.iter().map(|ty| ty.to_llvm(context)).collect();
+                BasicTypeEnum::StructType(context.struct_type(&types, false))
+            }
+            Type::Unknown => panic!("unresolved type passed to codegen"),
+        }
+    }
+
+    /// The number of bits of an integer type, or `None` for other types
+    pub fn int_bits(&self) -> Option<u32> {
+        match self {
+            Type::Int8 | Type::Uint8 => Some(8),
+            Type::Int16 | Type::Uint16 => Some(16),
+            Type::Int32 | Type::Uint32 => Some(32),
+            Type::Int | Type::Int64 | Type::Uint | Type::Uint64 | Type::Uintptr => Some(64),
+            _ => None,
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.int_bits().is_some()
+    }
+
+    pub fn is_unsigned(&self) -> bool {
+        matches!(
+            self,
+            Type::Uint | Type::Uint8 | Type::Uint16 | Type::Uint32 | Type::Uint64 | Type::Uintptr
+        )
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Type::Float32 | Type::Float64)
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        self.is_integer() || self.is_float()
+    }
+
+    /// The type of the elements of an array or slice
+    pub fn elem(&self) -> Option<&Type> {
+        match self {
+            Type::Array(_, elem) | Type::Slice(elem) => Some(elem),
+            _ => None,
+        }
+    }
+}
+
+impl Builtin {
+    /// The name the function is predeclared with
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Len => "len",
+            Builtin::Cap => "cap",
+            Builtin::Append => "append",
+            Builtin::Copy => "copy",
+            Builtin::Print => "print",
+            Builtin::Println => "println",
+            Builtin::New => "new",
+            Builtin::Make => "make",
+            Builtin::Delete => "delete",
+        }
+    }
+}
+
+impl FmtFunc {
+    pub const ALL: &'static [FmtFunc] = &[
+        FmtFunc::Print,
+        FmtFunc::Println,
+        FmtFunc::Printf,
+        FmtFunc::Sprint,
+        FmtFunc::Sprintln,
+        FmtFunc::Sprintf,
+        FmtFunc::Errorf,
+    ];
+
+    /// The name the function is exported with
+    pub fn name(&self) -> &'static str {
+        match self {
+            FmtFunc::Print => "Print",
+            FmtFunc::Println => "Println",
+            FmtFunc::Printf => "Printf",
+            FmtFunc::Sprint => "Sprint",
+            FmtFunc::Sprintln => "Sprintln",
+            FmtFunc::Sprintf => "Sprintf",
+            FmtFunc::Errorf => "Errorf",
+        }
+    }
+
+    /// Whether the first argument is a format string
+    pub fn has_format(&self) -> bool {
+        matches!(self, FmtFunc::Printf | FmtFunc::Sprintf | FmtFunc::Errorf)
+    }
+}
+
+impl RuntimeFunc {
+    pub const ALL: &'static [RuntimeFunc] = &[RuntimeFunc::GC];
+
+    /// The name the function is exported with
+    pub fn name(&self) -> &'static str {
+        match self {
+            RuntimeFunc::GC => "GC",
         }
     }
 }
@@ -134,9 +692,233 @@ impl Expression {
             Expression::Name { expr_type, .. } => expr_type,
             Expression::Literal { expr_type, .. } => expr_type,
             Expression::BinaryOp { expr_type, .. } => expr_type,
-            Expression::Call { expr_type, .. } => expr_type
+            Expression::UnaryOp { expr_type, .. } => expr_type,
+            Expression::Conversion { expr_type, .. } => expr_type,
+            Expression::Index { expr_type, .. } => expr_type,
+            Expression::Slice { expr_type, .. } => expr_type,
+            Expression::Selector { expr_type, .. } => expr_type,
+            Expression::CompositeLit { expr_type, .. } => expr_type,
+            Expression::ZeroValue { expr_type, .. } => expr_type,
+            Expression::Nil { expr_type, .. } => expr_type,
+            Expression::New { expr_type, .. } => expr_type,
+            Expression::Make { expr_type, .. } => expr_type,
+            Expression::Call { expr_type, .. }
+            | Expression::BuiltinCall { expr_type, .. }
+            | Expression::FmtCall { expr_type, .. }
+            | Expression::RuntimeCall { expr_type, .. } => expr_type
                 .as_ref()
                 .expect("Expression::get_type() should not be called on a void function"),
         }
     }
+
+    /// Returns the part of the source the expression was parsed from
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Name { span, .. }
+            | Expression::Literal { span, .. }
+            | Expression::BinaryOp { span, .. }
+            | Expression::UnaryOp { span, .. }
+            | Expression::Conversion { span, .. }
+            | Expression::Index { span, .. }
+            | Expression::Slice { span, .. }
+            | Expression::Selector { span, .. }
+            | Expression::CompositeLit { span, .. }
+            | Expression::ZeroValue { span, .. }
+            | Expression::Nil { span, .. }
+            | Expression::New { span, .. }
+            | Expression::Make { span, .. }
+            | Expression::Call { span, .. }
+            | Expression::BuiltinCall { span, .. }
+            | Expression::FmtCall { span, .. }
+            | Expression::RuntimeCall { span, .. } => *span,
+        }
+    }
+
+    /// Whether evaluating the expression calls a function or a builtin. The semantic checker
+    /// replaces the calls it evaluates at compile time (e.g. `len("abc")`) with their values
+    pub fn contains_call(&self) -> bool {
+        let mut found = false;
+        self.walk(&mut |expr| {
+            found |= matches!(
+                expr,
+                Expression::Call { .. }
+                    | Expression::BuiltinCall { .. }
+                    | Expression::FmtCall { .. }
+                    | Expression::RuntimeCall { .. }
+            );
+        });
+        found
+    }
+
+    /// Calls `f` on the expression and on every expression nested in it, outer ones first
+    pub fn walk<'a>(&'a self, f: &mut impl FnMut(&'a Expression)) {
+        f(self);
+        match self {
+            Expression::Name { .. }
+            | Expression::Literal { .. }
+            | Expression::ZeroValue { .. }
+            | Expression::Nil { .. }
+            | Expression::New { .. } => {}
+            Expression::BinaryOp { left, right, .. } => {
+                left.walk(f);
+                right.walk(f);
+            }
+            Expression::UnaryOp { expr, .. }
+            | Expression::Conversion { expr, .. }
+            | Expression::Selector { expr, .. } => expr.walk(f),
+            Expression::Call { args, .. }
+            | Expression::BuiltinCall { args, .. }
+            | Expression::FmtCall { args, .. }
+            | Expression::RuntimeCall { args, .. }
+            | Expression::Make { args, .. } => {
+                for arg in args {
+                    arg.walk(f);
+                }
+            }
+            Expression::Index { expr, index, .. } => {
+                expr.walk(f);
+                index.walk(f);
+            }
+            Expression::Slice {
+                expr,
+                low,
+                high,
+                max,
+                ..
+            } => {
+                expr.walk(f);
+                for index in [low, high, max].into_iter().flatten() {
+                    index.walk(f);
+                }
+            }
+            Expression::CompositeLit { elements, .. } => {
+                for element in elements {
+                    if let Some(key) = &element.key {
+                        key.walk(f);
+                    }
+                    element.value.walk(f);
+                }
+            }
+        }
+    }
+}
+
+impl Statement {
+    /// Calls `f` on every expression of the statement and of the statements nested in it (see
+    /// `Expression::walk`)
+    pub fn walk_exprs<'a>(&'a self, f: &mut impl FnMut(&'a Expression)) {
+        let walk_block = |block: &'a [Statement], f: &mut _| {
+            for stmt in block {
+                stmt.walk_exprs(f);
+            }
+        };
+        match self {
+            Statement::Assignment { expr, .. } | Statement::IncDec { expr, .. } => expr.walk(f),
+            Statement::Expression { expr, .. } => expr.walk(f),
+            Statement::ShortVarDecl { exprs, .. } => {
+                for expr in exprs {
+                    expr.walk(f);
+                }
+            }
+            Statement::Assign { lhs, rhs, .. } => {
+                for expr in lhs.iter().chain(rhs) {
+                    expr.walk(f);
+                }
+            }
+            Statement::If {
+                cond,
+                then_block,
+                else_block,
+                ..
+            } => {
+                cond.walk(f);
+                walk_block(then_block, f);
+                walk_block(else_block, f);
+            }
+            Statement::For {
+                init,
+                cond,
+                post,
+                body,
+                ..
+            } => {
+                if let Some(init) = init {
+                    init.walk_exprs(f);
+                }
+                if let Some(cond) = cond {
+                    cond.walk(f);
+                }
+                if let Some(post) = post {
+                    post.walk_exprs(f);
+                }
+                walk_block(body, f);
+            }
+            Statement::ForRange {
+                key,
+                value,
+                expr,
+                body,
+                ..
+            } => {
+                for var in [key, value].into_iter().flatten() {
+                    var.walk(f);
+                }
+                expr.walk(f);
+                walk_block(body, f);
+            }
+            Statement::Switch {
+                init, tag, cases, ..
+            } => {
+                if let Some(init) = init {
+                    init.walk_exprs(f);
+                }
+                if let Some(tag) = tag {
+                    tag.walk(f);
+                }
+                for case in cases {
+                    for expr in case.exprs.iter().flatten() {
+                        expr.walk(f);
+                    }
+                    walk_block(&case.body, f);
+                }
+            }
+            Statement::Return { exprs, .. } => {
+                for expr in exprs {
+                    expr.walk(f);
+                }
+            }
+            Statement::Const { specs, .. } => {
+                for expr in specs.iter().flat_map(|spec| &spec.exprs) {
+                    expr.walk(f);
+                }
+            }
+            Statement::Fallthrough { .. }
+            | Statement::Break { .. }
+            | Statement::Continue { .. }
+            | Statement::Label { .. }
+            | Statement::Goto { .. } => {}
+        }
+    }
+
+    /// Returns the part of the source the statement was parsed from
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Assignment { span, .. }
+            | Statement::ShortVarDecl { span, .. }
+            | Statement::Assign { span, .. }
+            | Statement::IncDec { span, .. }
+            | Statement::If { span, .. }
+            | Statement::For { span, .. }
+            | Statement::ForRange { span, .. }
+            | Statement::Switch { span, .. }
+            | Statement::Fallthrough { span }
+            | Statement::Break { span, .. }
+            | Statement::Continue { span, .. }
+            | Statement::Label { span, .. }
+            | Statement::Goto { span, .. }
+            | Statement::Return { span, .. }
+            | Statement::Expression { span, .. }
+            | Statement::Const { span, .. } => *span,
+        }
+    }
 }
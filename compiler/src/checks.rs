@@ -0,0 +1,926 @@
+//! Finds the runtime checks the generated code makes (that a divisor isn't 0, that a shift count
+//! isn't negative, that indices are in range, that dereferenced pointers aren't nil, and that maps
+//! assigned to aren't nil), and which of them can never fail, so that code generation can leave
+//! those out.
+//!
+//! A check can't fail if the facts known where it's made prove it. Facts are relations between
+//! integer constants, integer, pointer and map variables (`nil` being 0), and the lengths and
+//! capacities of variables, which come from the conditions of the `if`s, `for`s and `switch`es the
+//! check is in (or of an earlier `if` that doesn't fall through), from the `&&` and `||` it's on
+//! the right of, and from ranging over a string, array or slice (but not a map, whose keys aren't
+//! indices). A fact about a variable holds until the variable is assigned to (or a variable of the
+//! same name is declared). A variable whose address is taken may change through a pointer at any
+//! time, so there are no facts about it. Everything else is ignored: the checks that can't be
+//! proven stay in the program.
+
+use crate::ast::{BinaryOp, Builtin, Expression, Span, Statement, Type, UnaryOp};
+use crate::ast::{FuncDef, Program};
+use crate::sema::is_terminating_block;
+use std::collections::HashSet;
+
+/// A runtime check, which panics if it fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Check {
+    /// The divisor of an integer `/` or `%` isn't 0
+    DivideByZero,
+    /// The count of a shift isn't negative (which only signed counts can be)
+    NegativeShift,
+    /// `0 <= i < len(x)` for `x[i]`
+    Index,
+    /// `0 <= low <= high <= max <= cap(x)` for `x[low:high:max]`
+    Slice,
+    /// `p != nil` for `*p`, and for `p.f` if `p` is a pointer
+    NilDeref,
+    /// `m != nil` for an assignment to `m[k]`
+    NilMap,
+}
+
+impl Check {
+    /// What the check catches, in the words of the panic it makes
+    pub fn describe(self) -> &'static str {
+        match self {
+            Check::DivideByZero => "integer divide by zero",
+            Check::NegativeShift => "negative shift amount",
+            Check::Index => "index out of range",
+            Check::Slice => "slice bounds out of range",
+            Check::NilDeref => "nil pointer dereference",
+            Check::NilMap => "assignment to entry in nil map",
+        }
+    }
+}
+
+/// The runtime checks of a program. A check is identified by its kind and a span: that of the
+/// index for an index check, that of the `*p` or `p.f` for a nil check, that of the `m[k]`
+/// assigned to for a nil map check, and that of the operation (or of the `op=` assignment) for the
+/// others
+#[derive(Default)]
+pub struct Checks {
+    /// Every check, in the order they were found
+    checks: Vec<(Span, Check)>,
+    /// The checks that can never fail
+    redundant: HashSet<(Span, Check)>,
+    /// The variables of the function being checked whose address is taken
+    addressed: HashSet<String>,
+}
+
+impl Checks {
+    /// Whether the check at `span` may fail, and must be generated. Checks that weren't found
+    /// (which is a bug) are generated too
+    pub fn is_needed(&self, span: Span, check: Check) -> bool {
+        !self.redundant.contains(&(span, check))
+    }
+
+    /// The checks that may fail, in source order
+    pub fn remaining(&self) -> Vec<(Span, Check)> {
+        let mut remaining: Vec<_> = self
+            .checks
+            .iter()
+            .copied()
+            .filter(|&(span, check)| self.is_needed(span, check))
+            .collect();
+        remaining.sort_by_key(|(span, _)| (span.file_id, span.start));
+        remaining
+    }
+
+    fn add(&mut self, span: Span, check: Check, redundant: bool) {
+        self.checks.push((span, check));
+        if redundant {
+            self.redundant.insert((span, check));
+        }
+    }
+
+    fn function(&mut self, func: &FuncDef) {
+        self.addressed.clear();
+        for stmt in &func.code {
+            stmt.walk_exprs(&mut |expr| {
+                if let Expression::UnaryOp {
+                    op: UnaryOp::Addr,
+                    expr,
+                    ..
+                } = expr
+                {
+                    if let Some(name) = addressed_var(expr) {
+                        self.addressed.insert(name.to_string());
+                    }
+                }
+            });
+        }
+        self.block(&func.code, &Facts::default());
+    }
+
+    /// Drops the facts about the variables whose address is taken
+    fn forget_addressed(&self, facts: &mut Facts) {
+        facts.forget(&self.addressed.iter().map(String::as_str).collect());
+    }
+
+    /// Finds the checks of `block`, which is entered with `facts`, and returns the facts that hold
+    /// when control falls off its end
+    fn block(&mut self, block: &[Statement], facts: &Facts) -> Facts {
+        // The facts about the variables whose address is taken are dropped as they're found (the
+        // facts of every condition end up at the start of a block)
+        let mut entry = facts.clone();
+        self.forget_addressed(&mut entry);
+        let entry = &entry;
+        let mut facts = entry.clone();
+        for stmt in block {
+            if let Statement::Label { .. } = stmt {
+                // The label may be jumped to from anywhere in the block (but not from outside of
+                // it), after any of the variables assigned in it have changed
+                let mut assigned = HashSet::new();
+                for stmt in block {
+                    add_assigned(stmt, &mut assigned);
+                }
+                facts = entry.clone();
+                facts.forget(&assigned);
+            }
+            self.statement(stmt, &mut facts);
+        }
+        // The variables declared in the block go out of scope, and the names refer to the outer
+        // variables (if any) again
+        let mut declared = HashSet::new();
+        for stmt in block {
+            add_declared(stmt, &mut declared);
+        }
+        facts.forget(&declared);
+        facts
+    }
+
+    /// Finds the checks of `stmt`, and updates `facts` to those that hold after it
+    fn statement(&mut self, stmt: &Statement, facts: &mut Facts) {
+        match stmt {
+            Statement::If {
+                cond,
+                then_block,
+                else_block,
+                ..
+            } => {
+                self.expr(cond, facts);
+                let mut then_facts = facts.clone();
+                then_facts.assume(cond, true);
+                let then_facts = self.block(then_block, &then_facts);
+                let mut else_facts = facts.clone();
+                else_facts.assume(cond, false);
+                let else_facts = self.block(else_block, &else_facts);
+                // Only the branches that fall through get past the `if`
+                *facts = match (
+                    is_terminating_block(then_block),
+                    is_terminating_block(else_block),
+                ) {
+                    (true, _) => else_facts,
+                    (false, true) => then_facts,
+                    (false, false) => then_facts.intersect(&else_facts),
+                };
+            }
+            Statement::For {
+                init,
+                cond,
+                post,
+                body,
+                ..
+            } => {
+                let mut loop_facts = facts.clone();
+                let counter = counter(init.as_deref(), cond.as_ref(), post.as_deref(), body, facts);
+                if let Some(init) = init {
+                    self.statement(init, &mut loop_facts);
+                }
+                // The condition and the post statement are also reached after an iteration, which
+                // may have changed the variables assigned in the loop
+                let mut assigned = HashSet::new();
+                for stmt in body.iter().chain(post.as_deref()) {
+                    add_assigned(stmt, &mut assigned);
+                }
+                loop_facts.forget(&assigned);
+                let mut body_facts = loop_facts.clone();
+                if let Some(cond) = cond {
+                    self.expr(cond, &loop_facts);
+                    body_facts.assume(cond, true);
+                }
+                if let Some(counter) = counter {
+                    body_facts.add(Term::Const(0), Rel::Le, Term::Var(counter.to_string()));
+                }
+                self.block(body, &body_facts);
+                if let Some(post) = post {
+                    self.statement(post, &mut loop_facts.clone());
+                }
+                // The variables declared by the init statement are scoped to the loop
+                if let Some(init) = init {
+                    let mut declared = HashSet::new();
+                    add_declared(init, &mut declared);
+                    loop_facts.forget(&declared);
+                }
+                *facts = loop_facts;
+            }
+            Statement::ForRange {
+                key,
+                value,
+                expr,
+                body,
+                ..
+            } => {
+                self.expr(expr, facts);
+                for target in [key, value].into_iter().flatten() {
+                    self.map_assign(target, facts);
+                }
+                let mut assigned = HashSet::new();
+                for stmt in body {
+                    add_assigned(stmt, &mut assigned);
+                }
+                let mut targets = HashSet::new();
+                for target in [key, value].into_iter().flatten() {
+                    add_assigned_expr(target, &mut targets);
+                }
+                // The key is the index of an element (or of the first byte of a rune), unless the
+                // body changes it or what's ranged over (or the value is named like the latter).
+                // The key of a map can be anything
+                let mut range_facts = Facts::default();
+                let is_map = matches!(expr.get_type(), Type::Map(..));
+                if let (Some(Expression::Name { name, .. }), false) = (key.as_ref(), is_map) {
+                    let (len, _) = len_cap(expr);
+                    let key = Term::Var(name.clone());
+                    if name != "_" && !key.mentions(&assigned) && !len.mentions(&assigned) {
+                        if !len.is_unknown() && !len.mentions(&targets) {
+                            range_facts.add(key.clone(), Rel::Lt, len);
+                        }
+                        range_facts.add(Term::Const(0), Rel::Le, key);
+                    }
+                }
+                assigned.extend(targets);
+                facts.forget(&assigned);
+                let mut body_facts = facts.clone();
+                body_facts.0.extend(range_facts.0);
+                self.block(body, &body_facts);
+            }
+            Statement::Switch {
+                init, tag, cases, ..
+            } => {
+                let mut switch_facts = facts.clone();
+                if let Some(init) = init {
+                    self.statement(init, &mut switch_facts);
+                }
+                if let Some(tag) = tag {
+                    self.expr(tag, &switch_facts);
+                }
+                for case in cases {
+                    for expr in case.exprs.iter().flatten() {
+                        self.expr(expr, &switch_facts);
+                    }
+                }
+                let mut assigned = HashSet::new();
+                let mut fallthrough = false;
+                for case in cases {
+                    // Without a tag, the condition of a case holds in its body, unless the body is
+                    // reached by falling through from the previous one
+                    let mut case_facts = switch_facts.clone();
+                    match case.exprs.as_deref() {
+                        Some([cond]) if tag.is_none() && !fallthrough => {
+                            case_facts.assume(cond, true)
+                        }
+                        _ => {}
+                    }
+                    self.block(&case.body, &case_facts);
+                    fallthrough = matches!(case.body.last(), Some(Statement::Fallthrough { .. }));
+                    for stmt in &case.body {
+                        add_assigned(stmt, &mut assigned);
+                    }
+                }
+                if let Some(init) = init {
+                    add_declared(init, &mut assigned);
+                }
+                switch_facts.forget(&assigned);
+                *facts = switch_facts;
+            }
+            Statement::Assign {
+                lhs,
+                op: Some(op),
+                rhs,
+                span,
+            } => {
+                self.binop(op, &lhs[0], &rhs[0], *span, facts);
+                self.map_assign(&lhs[0], facts);
+                self.expr(&lhs[0], facts);
+                self.expr(&rhs[0], facts);
+                let mut assigned = HashSet::new();
+                add_assigned(stmt, &mut assigned);
+                facts.forget(&assigned);
+            }
+            _ => {
+                match stmt {
+                    Statement::Assign { lhs, .. } => {
+                        for target in lhs {
+                            self.map_assign(target, facts);
+                        }
+                    }
+                    Statement::IncDec { expr, .. } => self.map_assign(expr, facts),
+                    _ => {}
+                }
+                for expr in simple_exprs(stmt) {
+                    self.expr(expr, facts);
+                }
+                let mut assigned = HashSet::new();
+                add_assigned(stmt, &mut assigned);
+                facts.forget(&assigned);
+            }
+        }
+    }
+
+    /// Finds the checks of `expr`, where `facts` hold
+    fn expr(&mut self, expr: &Expression, facts: &Facts) {
+        match expr {
+            Expression::Name { .. }
+            | Expression::Literal { .. }
+            | Expression::ZeroValue { .. }
+            | Expression::Nil { .. }
+            | Expression::New { .. } => {}
+            Expression::Make { args, .. } => {
+                for arg in args {
+                    self.expr(arg, facts);
+                }
+            }
+            // The right operand is only evaluated if the left one is true (`&&`) or false (`||`)
+            Expression::BinaryOp {
+                op: op @ (BinaryOp::LogicalAnd | BinaryOp::LogicalOr),
+                left,
+                right,
+                ..
+            } => {
+                self.expr(left, facts);
+                let mut right_facts = facts.clone();
+                right_facts.assume(left, *op == BinaryOp::LogicalAnd);
+                self.forget_addressed(&mut right_facts);
+                self.expr(right, &right_facts);
+            }
+            Expression::BinaryOp {
+                op,
+                left,
+                right,
+                span,
+                ..
+            } => {
+                self.binop(op, left, right, *span, facts);
+                self.expr(left, facts);
+                self.expr(right, facts);
+            }
+            Expression::UnaryOp {
+                op: UnaryOp::Deref,
+                expr: inner,
+                span,
+                ..
+            } => {
+                self.add(*span, Check::NilDeref, is_nonnil(inner, facts));
+                self.expr(inner, facts);
+            }
+            Expression::Selector {
+                expr: inner, span, ..
+            } if matches!(inner.get_type(), Type::Pointer(_)) => {
+                self.add(*span, Check::NilDeref, is_nonnil(inner, facts));
+                self.expr(inner, facts);
+            }
+            Expression::UnaryOp { expr, .. }
+            | Expression::Conversion { expr, .. }
+            | Expression::Selector { expr, .. } => self.expr(expr, facts),
+            Expression::Call { args, .. }
+            | Expression::BuiltinCall { args, .. }
+            | Expression::FmtCall { args, .. }
+            | Expression::RuntimeCall { args, .. } => {
+                for arg in args {
+                    self.expr(arg, facts);
+                }
+            }
+            // Looking up a key in a map can't fail
+            Expression::Index { expr, index, .. } if matches!(expr.get_type(), Type::Map(..)) => {
+                self.expr(expr, facts);
+                self.expr(index, facts);
+            }
+            Expression::Index { expr, index, .. } => {
+                let (len, _) = len_cap(expr);
+                let in_range = term(index).is_some_and(|index| {
+                    facts.proves(&Term::Const(0), &index, false) && facts.proves(&index, &len, true)
+                });
+                self.add(index.span(), Check::Index, in_range);
+                self.expr(expr, facts);
+                self.expr(index, facts);
+            }
+            Expression::Slice {
+                expr,
+                low,
+                high,
+                max,
+                span,
+                ..
+            } => {
+                // An omitted `low` is 0, `high` the length, and `max` the capacity
+                let (len, cap) = len_cap(expr);
+                let bound = |index: &Option<Box<Expression>>, default: &Term| match index {
+                    Some(index) => term(index),
+                    None => Some(default.clone()),
+                };
+                let chain = [
+                    Some(Term::Const(0)),
+                    bound(low, &Term::Const(0)),
+                    bound(high, &len),
+                    bound(max, &cap),
+                    Some(cap.clone()),
+                ];
+                let in_range = chain.windows(2).all(|pair| match pair {
+                    [Some(lesser), Some(greater)] => facts.proves(lesser, greater, false),
+                    _ => false,
+                });
+                self.add(*span, Check::Slice, in_range);
+                self.expr(expr, facts);
+                for index in [low, high, max].into_iter().flatten() {
+                    self.expr(index, facts);
+                }
+            }
+            Expression::CompositeLit { elements, .. } => {
+                for element in elements {
+                    if let Some(key) = &element.key {
+                        self.expr(key, facts);
+                    }
+                    self.expr(&element.value, facts);
+                }
+            }
+        }
+    }
+
+    /// Finds the check of an assignment to `target`, if it's an element of a map, where `facts`
+    /// hold
+    fn map_assign(&mut self, target: &Expression, facts: &Facts) {
+        if let Expression::Index { expr, .. } = target {
+            if matches!(expr.get_type(), Type::Map(..)) {
+                self.add(target.span(), Check::NilMap, is_nonnil(expr, facts));
+            }
+        }
+    }
+
+    /// Finds the check of the integer operation `left op right` (or `left op= right`) at `span`
+    fn binop(
+        &mut self,
+        op: &BinaryOp,
+        left: &Expression,
+        right: &Expression,
+        span: Span,
+        facts: &Facts,
+    ) {
+        if !left.get_type().is_integer() {
+            return;
+        }
+        let right_term = term(right);
+        match op {
+            BinaryOp::Div | BinaryOp::Rem => {
+                let nonzero = right_term.is_some_and(|divisor| facts.proves_nonzero(&divisor));
+                self.add(span, Check::DivideByZero, nonzero);
+            }
+            BinaryOp::Shl | BinaryOp::Shr if !right.get_type().is_unsigned() => {
+                let nonnegative =
+                    right_term.is_some_and(|count| facts.proves(&Term::Const(0), &count, false));
+                self.add(span, Check::NegativeShift, nonnegative);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Finds the runtime checks of a program that has been type checked
+pub fn find_checks(program: &Program) -> Checks {
+    let mut checks = Checks::default();
+    for func in &program.functions {
+        checks.function(func);
+    }
+    checks
+}
+
+/// An integer that facts are about
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+    Const(i64),
+    Var(String),
+    /// `len(x)` for the string or slice variable `x`
+    Len(String),
+    /// `cap(x)` for the slice variable `x`
+    Cap(String),
+}
+
+impl Term {
+    fn mentions(&self, names: &HashSet<&str>) -> bool {
+        match self {
+            Term::Const(_) => false,
+            Term::Var(name) | Term::Len(name) | Term::Cap(name) => names.contains(name.as_str()),
+        }
+    }
+
+    /// Whether this is the length or capacity of a string or slice that isn't a variable, which no
+    /// fact may be about
+    fn is_unknown(&self) -> bool {
+        matches!(self, Term::Len(name) | Term::Cap(name) if name.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rel {
+    Lt,
+    Le,
+    Ne,
+}
+
+/// The facts known to hold at some point of a function, as `(a, rel, b)` for `a rel b`
+#[derive(Debug, Clone, Default)]
+struct Facts(Vec<(Term, Rel, Term)>);
+
+impl Facts {
+    fn add(&mut self, left: Term, rel: Rel, right: Term) {
+        self.0.push((left, rel, right));
+    }
+
+    /// Adds the facts that follow from `cond` evaluating to `truth`
+    fn assume(&mut self, cond: &Expression, truth: bool) {
+        let (op, left, right) = match cond {
+            Expression::UnaryOp {
+                op: UnaryOp::Not,
+                expr,
+                ..
+            } => return self.assume(expr, !truth),
+            Expression::BinaryOp {
+                op: BinaryOp::LogicalAnd,
+                left,
+                right,
+                ..
+            } if truth => {
+                self.assume(left, true);
+                return self.assume(right, true);
+            }
+            Expression::BinaryOp {
+                op: BinaryOp::LogicalOr,
+                left,
+                right,
+                ..
+            } if !truth => {
+                self.assume(left, false);
+                return self.assume(right, false);
+            }
+            Expression::BinaryOp {
+                op, left, right, ..
+            } if left.get_type().is_integer() || is_reference(left.get_type()) => (op, left, right),
+            _ => return,
+        };
+        let (left, right) = match (term(left), term(right)) {
+            (Some(left), Some(right)) => (left, right),
+            _ => return,
+        };
+        // `a > b` is `b < a`, and `!(a < b)` is `b <= a`
+        match (op, truth) {
+            (BinaryOp::Le, true) | (BinaryOp::Geq, false) => self.add(left, Rel::Lt, right),
+            (BinaryOp::Leq, true) | (BinaryOp::Ge, false) => self.add(left, Rel::Le, right),
+            (BinaryOp::Ge, true) | (BinaryOp::Leq, false) => self.add(right, Rel::Lt, left),
+            (BinaryOp::Geq, true) | (BinaryOp::Le, false) => self.add(right, Rel::Le, left),
+            (BinaryOp::Eq, true) | (BinaryOp::Neq, false) => {
+                self.add(left.clone(), Rel::Le, right.clone());
+                self.add(right, Rel::Le, left);
+            }
+            (BinaryOp::Neq, true) | (BinaryOp::Eq, false) => self.add(left, Rel::Ne, right),
+            _ => {}
+        }
+    }
+
+    /// Drops the facts about the variables `names`
+    fn forget(&mut self, names: &HashSet<&str>) {
+        self.0
+            .retain(|(left, _, right)| !left.mentions(names) && !right.mentions(names));
+    }
+
+    /// The facts that both `self` and `other` have
+    fn intersect(mut self, other: &Facts) -> Facts {
+        self.0.retain(|fact| other.0.contains(fact));
+        self
+    }
+
+    /// Whether `a < b` (if `strict`) or `a <= b` follows from the facts
+    fn proves(&self, a: &Term, b: &Term, strict: bool) -> bool {
+        // Follow the chains of facts starting at `a`, remembering whether they went through a `<`
+        let mut stack = vec![(a.clone(), false)];
+        let mut seen = vec![];
+        while let Some((term, is_strict)) = stack.pop() {
+            if seen.contains(&(term.clone(), is_strict)) {
+                continue;
+            }
+            if let Some(lt) = known_le(&term, b) {
+                if lt || is_strict || !strict {
+                    return true;
+                }
+            }
+            for (left, rel, right) in &self.0 {
+                let lt = match (known_le(&term, left), rel) {
+                    (Some(lt), Rel::Le) => lt,
+                    (Some(_), Rel::Lt) => true,
+                    _ => continue,
+                };
+                stack.push((right.clone(), is_strict || lt));
+            }
+            seen.push((term, is_strict));
+        }
+        false
+    }
+
+    /// Whether `a != 0` follows from the facts
+    fn proves_nonzero(&self, a: &Term) -> bool {
+        let zero = Term::Const(0);
+        self.0.iter().any(|(left, rel, right)| {
+            *rel == Rel::Ne && ((left == a && *right == zero) || (*left == zero && right == a))
+        }) || self.proves(&zero, a, true)
+            || self.proves(a, &zero, true)
+    }
+}
+
+/// Whether `a <= b` holds without any facts: `Some(true)` if `a < b` does, `Some(false)` if only
+/// `a <= b` is known, and `None` if neither is
+fn known_le(a: &Term, b: &Term) -> Option<bool> {
+    match (a, b) {
+        _ if a == b => Some(false),
+        (Term::Const(a), Term::Const(b)) if a <= b => Some(a < b),
+        // Lengths and capacities aren't negative, and a slice's length doesn't exceed its capacity
+        (Term::Const(a), Term::Len(_) | Term::Cap(_)) if *a <= 0 => Some(*a < 0),
+        (Term::Len(a), Term::Cap(b)) if a == b => Some(false),
+        _ => None,
+    }
+}
+
+/// Whether values of type `ty` are compared to `nil` like integers are to 0: pointers and maps
+fn is_reference(ty: &Type) -> bool {
+    matches!(ty, Type::Pointer(_) | Type::Map(..))
+}
+
+/// `expr` as a term, if it's an integer constant, `nil`, an integer, pointer or map variable, or
+/// the length or capacity of a variable (or of an array)
+fn term(expr: &Expression) -> Option<Term> {
+    match expr {
+        Expression::Literal {
+            expr_type, value, ..
+        } if expr_type.is_integer() => value.to_i64().map(Term::Const),
+        Expression::Nil { .. } => Some(Term::Const(0)),
+        Expression::Name {
+            expr_type, name, ..
+        } if (expr_type.is_integer() || is_reference(expr_type)) && name != "_" => {
+            Some(Term::Var(name.clone()))
+        }
+        Expression::BuiltinCall {
+            builtin: builtin @ (Builtin::Len | Builtin::Cap),
+            args,
+            ..
+        } => {
+            let (len, cap) = len_cap(&args[0]);
+            let term = if *builtin == Builtin::Len { len } else { cap };
+            (!term.is_unknown()).then_some(term)
+        }
+        _ => None,
+    }
+}
+
+/// Whether the pointer or map `expr` can't be nil where `facts` hold: it takes an address or
+/// allocates, or the facts prove it
+fn is_nonnil(expr: &Expression, facts: &Facts) -> bool {
+    match expr {
+        Expression::UnaryOp {
+            op: UnaryOp::Addr, ..
+        }
+        | Expression::New { .. }
+        | Expression::Make { .. }
+        | Expression::CompositeLit { .. } => true,
+        _ => term(expr).is_some_and(|pointer| facts.proves_nonzero(&pointer)),
+    }
+}
+
+/// The variable `&expr` takes the address of (part of), if any. The elements of a slice, and what
+/// a pointer points to, aren't part of a variable
+fn addressed_var(expr: &Expression) -> Option<&str> {
+    match expr {
+        Expression::Name { name, .. } => Some(name),
+        Expression::Index { expr, .. } if matches!(expr.get_type(), Type::Array(..)) => {
+            addressed_var(expr)
+        }
+        Expression::Selector { expr, .. } if !matches!(expr.get_type(), Type::Pointer(_)) => {
+            addressed_var(expr)
+        }
+        _ => None,
+    }
+}
+
+/// The length and capacity of the string, array, slice or map `expr` (a string's capacity being
+/// its length). Those of strings and slices that aren't variables are unknown: they're only known
+/// to be related to each other. So are the lengths of maps, which change without assigning them
+fn len_cap(expr: &Expression) -> (Term, Term) {
+    match (expr, expr.get_type()) {
+        (_, Type::Array(len, _)) => (Term::Const(*len as i64), Term::Const(*len as i64)),
+        (_, Type::Map(..)) => (Term::Len(String::new()), Term::Len(String::new())),
+        (Expression::Literal { value, .. }, Type::GoString) => {
+            let len = Term::Const(value.string_bytes().map_or(0, <[u8]>::len) as i64);
+            (len.clone(), len)
+        }
+        (Expression::Name { name, .. }, Type::GoString) => {
+            (Term::Len(name.clone()), Term::Len(name.clone()))
+        }
+        (Expression::Name { name, .. }, _) => (Term::Len(name.clone()), Term::Cap(name.clone())),
+        (_, Type::GoString) => (Term::Len(String::new()), Term::Len(String::new())),
+        _ => (Term::Len(String::new()), Term::Cap(String::new())),
+    }
+}
+
+/// The variable `i` of a loop like `for i := 0; i < n; i++ { .. }`, which starts out non-negative
+/// and isn't assigned to in the body. It's never negative in the body: it's only incremented while
+/// it's less than something, so it can't overflow
+fn counter<'a>(
+    init: Option<&'a Statement>,
+    cond: Option<&Expression>,
+    post: Option<&Statement>,
+    body: &[Statement],
+    facts: &Facts,
+) -> Option<&'a str> {
+    let (name, start) = match init? {
+        Statement::ShortVarDecl { vars, exprs, .. } if vars.len() == 1 => {
+            (vars[0].name.as_str(), &exprs[0])
+        }
+        Statement::Assign {
+            lhs, op: None, rhs, ..
+        } if lhs.len() == 1 => match &lhs[0] {
+            Expression::Name { name, .. } => (name.as_str(), &rhs[0]),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let is_counter =
+        |expr: &Expression| matches!(expr, Expression::Name { name: var, .. } if var == name);
+    let is_bounded = match cond? {
+        Expression::BinaryOp {
+            op: BinaryOp::Le,
+            left,
+            ..
+        } => is_counter(left),
+        Expression::BinaryOp {
+            op: BinaryOp::Ge,
+            right,
+            ..
+        } => is_counter(right),
+        _ => false,
+    };
+    let is_incremented = matches!(
+        post?,
+        Statement::IncDec { expr, op: BinaryOp::Add, .. } if is_counter(expr)
+    );
+    let mut assigned = HashSet::new();
+    for stmt in body {
+        add_assigned(stmt, &mut assigned);
+    }
+    let starts_nonnegative =
+        term(start).is_some_and(|start| facts.proves(&Term::Const(0), &start, false));
+    (is_bounded && is_incremented && starts_nonnegative && !assigned.contains(name)).then_some(name)
+}
+
+/// The expressions evaluated by a statement without nested statements
+fn simple_exprs(stmt: &Statement) -> Vec<&Expression> {
+    match stmt {
+        Statement::Assignment { expr, .. }
+        | Statement::IncDec { expr, .. }
+        | Statement::Expression { expr, .. } => vec![expr],
+        Statement::Return { exprs, .. } | Statement::ShortVarDecl { exprs, .. } => {
+            exprs.iter().collect()
+        }
+        Statement::Assign { lhs, rhs, .. } => lhs.iter().chain(rhs).collect(),
+        _ => vec![],
+    }
+}
+
+/// Adds the names of the variables `stmt` (or a statement nested in it) assigns to or declares to
+/// `names`
+fn add_assigned<'a>(stmt: &'a Statement, names: &mut HashSet<&'a str>) {
+    let add_block = |block: &'a [Statement], names: &mut HashSet<&'a str>| {
+        for stmt in block {
+            add_assigned(stmt, names);
+        }
+    };
+    match stmt {
+        Statement::Assignment { name, .. } => {
+            names.insert(name);
+        }
+        Statement::ShortVarDecl { vars, .. } => names.extend(vars.iter().map(|var| &*var.name)),
+        Statement::Assign { lhs, .. } => {
+            for expr in lhs {
+                add_assigned_expr(expr, names);
+            }
+        }
+        Statement::IncDec { expr, .. } => add_assigned_expr(expr, names),
+        Statement::If {
+            then_block,
+            else_block,
+            ..
+        } => {
+            add_block(then_block, names);
+            add_block(else_block, names);
+        }
+        Statement::For {
+            init, post, body, ..
+        } => {
+            for stmt in init.iter().chain(post) {
+                add_assigned(stmt, names);
+            }
+            add_block(body, names);
+        }
+        Statement::ForRange {
+            key, value, body, ..
+        } => {
+            for expr in [key, value].into_iter().flatten() {
+                add_assigned_expr(expr, names);
+            }
+            add_block(body, names);
+        }
+        Statement::Switch { init, cases, .. } => {
+            if let Some(init) = init {
+                add_assigned(init, names);
+            }
+            for case in cases {
+                add_block(&case.body, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Adds the name of the variable that assigning to `expr` changes (if any: assigning to an element
+/// or a field doesn't change a length) to `names`
+fn add_assigned_expr<'a>(expr: &'a Expression, names: &mut HashSet<&'a str>) {
+    if let Expression::Name { name, .. } = expr {
+        names.insert(name);
+    }
+}
+
+/// Adds the names of the variables `stmt` itself declares to `names`
+fn add_declared<'a>(stmt: &'a Statement, names: &mut HashSet<&'a str>) {
+    match stmt {
+        Statement::Assignment { name, .. } => {
+            names.insert(name);
+        }
+        Statement::ShortVarDecl { vars, .. } => names.extend(vars.iter().map(|var| &*var.name)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser, sema};
+
+    /// The checks left in the program `src`
+    fn remaining(src: &str) -> Vec<Check> {
+        let mut program = parser::parse(src, 0).unwrap();
+        sema::check_program(&mut program).unwrap();
+        let checks = find_checks(&program);
+        checks.remaining().into_iter().map(|(_, check)| check).collect()
+    }
+
+    #[test]
+    fn range_over_slice_proves_index() {
+        let src = "package main
+func main() {
+	a := []int{1, 2, 3}
+	for i := range a {
+		a[i] = 0
+	}
+}
+";
+        assert!(!remaining(src).contains(&Check::Index));
+    }
+
+    #[test]
+    fn range_over_map_keeps_checks() {
+        let src = "package main
+func main() {
+	var a [4]int
+	x := 1
+	m := map[int]int{100: 1}
+	for k := range m {
+		if len(m) <= 4 {
+			_ = a[k]
+			_ = x << k
+		}
+	}
+}
+";
+        let checks = remaining(src);
+        assert!(checks.contains(&Check::Index));
+        assert!(checks.contains(&Check::NegativeShift));
+    }
+
+    #[test]
+    fn map_length_is_unknown() {
+        let src = "package main
+func main() {
+	var a [4]int
+	m := map[int]int{}
+	if len(m) <= len(a) {
+		m[10] = 1
+		for i := 0; i < len(m); i++ {
+			_ = a[i]
+		}
+	}
+}
+";
+        assert!(remaining(src).contains(&Check::Index));
+    }
+}
@@ -8,22 +8,73 @@
 //     - Implement a better API?
 use crate::ast::{
     BinaryOp::{self, *},
-    Expression, FuncDef, Program, Statement, Type,
+    Builtin, CaseClause, Expression, Field, FmtFunc, FuncDef, KeyedElement, Program, RuntimeFunc,
+    Span, Statement, Type, UnaryOp,
 };
+use crate::checks::{Check, Checks};
+use crate::constant::Value;
+use crate::diagnostics::{Code, Diagnostic};
 use crate::errors::*;
+use crate::escape_analysis::Escapes;
+use crate::pretty_printer::format_type;
+use inkwell::basic_block::BasicBlock;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
-use inkwell::module::Module;
+use inkwell::module::{Linkage, Module};
 use inkwell::passes::{PassManager, PassManagerBuilder};
 use inkwell::targets::{
     CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine,
 };
-use inkwell::types::BasicType;
-use inkwell::values::{BasicValue, BasicValueEnum, FunctionValue, PointerValue};
-use inkwell::{FloatPredicate, IntPredicate, OptimizationLevel};
+use inkwell::types::{BasicType, BasicTypeEnum, StructType};
+use inkwell::values::{
+    ArrayValue, BasicMetadataValueEnum, BasicValueEnum, FunctionValue, IntValue, PointerValue,
+    StructValue,
+};
+use inkwell::{AddressSpace, FloatPredicate, IntPredicate, OptimizationLevel};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::path::Path;
 
+/// An error raised during code generation, along with the span of the node that caused it. These
+/// are all things the earlier passes should have rejected.
+#[derive(Debug)]
+pub struct CodeGenError {
+    pub msg: &'static str,
+    pub span: Span,
+}
+
+impl From<CodeGenError> for Diagnostic {
+    fn from(err: CodeGenError) -> Self {
+        Diagnostic::error(Code::InternalError, err.msg, err.span)
+            .with_note("this is a bug in the compiler")
+    }
+}
+
+// The kinds of values in an `FmtArg`, which tell the runtime how to read its bits
+const FMT_BOOL: u64 = 0;
+const FMT_INT: u64 = 1;
+const FMT_UINT: u64 = 2;
+const FMT_FLOAT32: u64 = 3;
+const FMT_FLOAT64: u64 = 4;
+const FMT_STRING: u64 = 5;
+const FMT_POINTER: u64 = 6;
+const FMT_ERROR: u64 = 7;
+
+// The kinds of the fields of map keys, which tell the runtime how to hash and compare them (see
+// `CodeGen::key_fields`)
+const KEY_MEMORY: u64 = 0;
+const KEY_FLOAT: u64 = 1;
+const KEY_STRING: u64 = 2;
+
+/// The type of the arguments passed to the runtime's `fmt` functions, which matches its `FmtArg`:
+/// the kind of the value, the bits of a non-string value (floats are widened to a `float64`, and
+/// pointers converted to their address), the string value, and the name of the type (for `%T`)
+pub fn fmt_arg_type(context: &Context) -> StructType<'_> {
+    let i64_type = context.i64_type().into();
+    let string_type = Type::GoString.to_llvm(context);
+    context.struct_type(&[i64_type, i64_type, string_type, string_type], false)
+}
+
 pub struct CodeGen<'ctx> {
     pub context: &'ctx Context,
     pub module: Module<'ctx>,
@@ -31,40 +82,82 @@ pub struct CodeGen<'ctx> {
 
     symbol_table: HashMap<String, PointerValue<'ctx>>,
     current_function: Option<FunctionValue<'ctx>>,
+    /// The loops and switches around the code being generated, innermost last
+    targets: Vec<BranchTarget<'ctx>>,
+    /// The blocks starting at the labels of the current function. They are created by the first
+    /// `goto` or label that refers to them
+    labels: HashMap<String, BasicBlock<'ctx>>,
+    /// The fields of the struct types of the program, by name
+    structs: HashMap<String, Vec<Field>>,
+    /// The runtime checks of the program, of which only those that may fail are generated
+    checks: Checks,
+    /// The variables and allocations of the program that are on the heap
+    escapes: Escapes,
+    /// The roots of the current function (see `build_root`): their slots, their types, and their
+    /// type descriptors
+    roots: RefCell<Vec<(PointerValue<'ctx>, BasicTypeEnum<'ctx>, PointerValue<'ctx>)>>,
+    /// Where `return` jumps to in the current function, and the variable it stores the result in
+    exit: Option<(BasicBlock<'ctx>, Option<PointerValue<'ctx>>)>,
+    /// The variables of the named results of the current function, which a bare `return` returns
+    named_results: Vec<PointerValue<'ctx>>,
+}
+
+/// A statement that can be left with `break`: a loop or a switch
+struct BranchTarget<'ctx> {
+    label: Option<String>,
+    /// Where `continue` jumps to, or `None` for a switch
+    continue_bb: Option<BasicBlock<'ctx>>,
+    /// Where `break` jumps to
+    break_bb: BasicBlock<'ctx>,
+}
+
+/// What the left-hand side of an assignment refers to
+enum AssignTarget<'ctx> {
+    /// The storage of a variable (or of an element or field of one, of an element of a slice, or
+    /// of what a pointer points to)
+    Ptr(PointerValue<'ctx>),
+    /// The entry of a map for the key at `key` (see `gen_map_key`), which is only added once the
+    /// value is assigned. `span` is that of the index expression
+    MapEntry {
+        map: PointerValue<'ctx>,
+        key: PointerValue<'ctx>,
+        span: Span,
+    },
+    /// The blank identifier
+    Blank,
 }
 
 impl<'ctx> CodeGen<'ctx> {
-    pub fn new(context: &'ctx Context) -> Self {
+    pub fn new(context: &'ctx Context, checks: Checks, escapes: Escapes) -> Self {
+        // The type descriptors of the garbage collector depend on the layout of the types, so the
+        // module must use the target's
+        let module = context.create_module("main");
+        let target_machine = target_machine();
+        module.set_triple(&target_machine.get_triple());
+        module.set_data_layout(&target_machine.get_target_data().get_data_layout());
         Self {
             context,
-            module: context.create_module("main"),
+            module,
             builder: context.create_builder(),
             symbol_table: HashMap::new(),
             current_function: None,
+            targets: vec![],
+            labels: HashMap::new(),
+            structs: HashMap::new(),
+            checks,
+            escapes,
+            roots: RefCell::new(vec![]),
+            exit: None,
+            named_results: vec![],
         }
     }
 
     /// Outputs the generated program to an object file. The function `gen_program` must have been
     /// called first. Optionally, the optimizer could also have been run.
-    pub fn to_object_file(&self, obj_file_name: &str) {
-        Target::initialize_all(&InitializationConfig::default());
-        let triple = TargetMachine::get_default_triple();
-        let target =
-            Target::from_triple(&triple).expect("Couldn't create target from target triple");
-
-        let target_machine = target
-            .create_target_machine(
-                &triple,
-                "generic",
-                "",
-                OptimizationLevel::None,
-                RelocMode::Default,
-                CodeModel::Default,
-            )
-            .expect("Unable to create target machine");
-        target_machine
+    pub fn to_object_file(&self, obj_file_name: &str) -> Result<(), String> {
+        target_machine()
             .write_to_file(&self.module, FileType::Object, Path::new(obj_file_name))
-            .expect("Unable to write module to file");
+            .map_err(|err| err.to_string())
     }
 
     /// Optimizes the program at the specified level (e.g. all optimizations are turned on in
@@ -78,25 +171,46 @@ impl<'ctx> CodeGen<'ctx> {
         pass_manager.run_on(&self.module);
     }
 
-    /// Loops through all functions and generates their code
-    pub fn gen_program(&mut self, program: &Program) -> Result<(), &'static str> {
+    /// Declares the struct types, then loops through all functions and generates their code
+    pub fn gen_program(&mut self, program: &Program) -> Result<(), CodeGenError> {
+        // The types are all declared before their bodies are set, since they may refer to each
+        // other regardless of the order they're declared in
+        for decl in &program.types {
+            self.context.opaque_struct_type(&decl.name);
+        }
+        for decl in &program.types {
+            let field_types: Vec<_> = decl
+                .fields
+                .iter()
+                .map(|field| field.field_type.to_llvm(self.context))
+                .collect();
+            let struct_type = self.context.get_struct_type(&decl.name).unwrap();
+            struct_type.set_body(&field_types, false);
+            self.structs.insert(decl.name.clone(), decl.fields.clone());
+        }
         for func in &program.functions {
             self.gen_function(func)?;
         }
         Ok(())
     }
 
-    fn gen_function(&mut self, func: &FuncDef) -> Result<(), &'static str> {
+    /// Generates the function `func`. A function with several results returns them in an
+    /// anonymous struct (see `Type::Tuple`), which LLVM returns in registers or, if it's too large,
+    /// through a pointer to memory the caller provides (`sret`)
+    fn gen_function(&mut self, func: &FuncDef) -> Result<(), CodeGenError> {
         let FuncDef {
             name,
             params,
-            return_type,
+            results,
+            named_results,
             code: block,
+            ..
         } = func;
+        let return_type = func.result_type();
         // The function parameter types
         let llvm_params = params
             .iter()
-            .map(|(_, x)| x.to_llvm(self.context).into())
+            .map(|(_, x, _)| x.to_llvm(self.context).into())
             .collect::<Vec<_>>();
         // The signature the function in LLVM terms
         let llvm_fn_sig = match return_type {
@@ -104,108 +218,1031 @@ impl<'ctx> CodeGen<'ctx> {
             None => self.context.void_type().fn_type(&llvm_params, false),
         };
         let function = self.module.add_function(name, llvm_fn_sig, None);
+        // The entry block only holds allocas (see `build_entry_alloca`) until the body has been
+        // generated, and then the code that pushes the function's frame (see `build_frame`)
         let entry = self.context.append_basic_block(function, "entry");
-        self.builder.position_at_end(entry);
+        let body = self.context.append_basic_block(function, "body");
+        let exit = self.context.append_basic_block(function, "exit");
         self.current_function = Some(function);
+        self.symbol_table.clear();
+        self.labels.clear();
+        self.roots.borrow_mut().clear();
+        let result = return_type
+            .as_ref()
+            .map(|ty| self.build_entry_alloca(ty.to_llvm(self.context), "result"));
+        self.exit = Some((exit, result));
+        self.named_results.clear();
+        self.builder.position_at_end(body);
         // Set param names, an generate alloca and store instructions for them
-        for (param, (param_name, param_type)) in function.get_param_iter().zip(params) {
+        for (param, (param_name, param_type, span)) in function.get_param_iter().zip(params) {
             param.set_name(param_name);
-            let alloca = self
-                .builder
-                .build_alloca(param_type.to_llvm(self.context), name);
+            let alloca = self.build_var(param_type, param_name, *span);
             self.builder.build_store(alloca, param);
             self.symbol_table.insert(param_name.clone(), alloca);
         }
+        // Named results are variables, which start out zeroed
+        if *named_results {
+            for (result_name, result_type, span) in results {
+                let alloca = self.build_var(result_type, result_name, *span);
+                self.builder
+                    .build_store(alloca, const_zero(result_type.to_llvm(self.context)));
+                if result_name != "_" {
+                    self.symbol_table.insert(result_name.clone(), alloca);
+                }
+                self.named_results.push(alloca);
+            }
+        }
         self.gen_block(block)?;
-        // We've got to return something, even if the function doesn't return
-        if return_type.is_none() {
-            self.builder.build_return(None);
+        if !self.block_terminated() {
+            // We've got to return something, even if the function doesn't return. Falling off
+            // the end of a function with a result can't happen in a valid program
+            if results.is_empty() {
+                self.builder.build_unconditional_branch(exit);
+            } else {
+                self.builder.build_unreachable();
+            }
+        }
+        let last = function.get_last_basic_block().unwrap();
+        if last != exit {
+            exit.move_after(last).unwrap();
         }
+        self.build_frame(name, entry, body, exit, result);
         Ok(())
     }
 
-    fn gen_block(&mut self, block: &[Statement]) -> Result<(), &'static str> {
+    /// Generates the code that pushes the frame of the current function `name` onto the runtime's
+    /// stack of frames (at the end of `entry`, which then jumps to `body`), and pops it before the
+    /// function returns (in `exit`, which returns the value of `result`). The frame points to the
+    /// roots (see `build_root`), which are zeroed first, and to the frame map: a global with their
+    /// type descriptors. A function without roots has no frame
+    fn build_frame(
+        &self,
+        name: &str,
+        entry: BasicBlock<'ctx>,
+        body: BasicBlock<'ctx>,
+        exit: BasicBlock<'ctx>,
+        result: Option<PointerValue<'ctx>>,
+    ) {
+        let roots = self.roots.borrow();
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
+        let byte_ptr_type = self.context.i8_type().ptr_type(AddressSpace::Generic);
+        let frames = self
+            .module
+            .get_global("__go_frames")
+            .unwrap()
+            .as_pointer_value();
+        self.builder.position_at_end(entry);
+        let prev = if roots.is_empty() {
+            None
+        } else {
+            let types: Vec<_> = roots.iter().map(|(_, _, desc)| *desc).collect();
+            let map = self.context.const_struct(
+                &[
+                    i64_type.const_int(roots.len() as u64, false).into(),
+                    byte_ptr_type.const_array(&types).into(),
+                ],
+                false,
+            );
+            let map_global =
+                self.module
+                    .add_global(map.get_type(), None, &format!("{}.frame_map", name));
+            map_global.set_linkage(Linkage::Private);
+            map_global.set_constant(true);
+            map_global.set_initializer(&map);
+            let frame_type = self.context.struct_type(
+                &[
+                    byte_ptr_type.into(),
+                    byte_ptr_type.into(),
+                    byte_ptr_type.array_type(roots.len() as u32).into(),
+                ],
+                false,
+            );
+            let frame = self.builder.build_alloca(frame_type, "frame");
+            let prev = self.builder.build_load(frames, "prev_frame");
+            let field_ptr = |idx: &[u64], name| {
+                let idx: Vec<_> = idx
+                    .iter()
+                    .map(|idx| i32_type.const_int(*idx, false))
+                    .collect();
+                unsafe { self.builder.build_in_bounds_gep(frame, &idx, name) }
+            };
+            self.builder.build_store(field_ptr(&[0, 0], "prev_ptr"), prev);
+            let map_ptr = map_global.as_pointer_value().const_cast(byte_ptr_type);
+            self.builder.build_store(field_ptr(&[0, 1], "map_ptr"), map_ptr);
+            for (idx, (root, ty, _)) in roots.iter().enumerate() {
+                self.builder.build_store(*root, const_zero(*ty));
+                let root_ptr = field_ptr(&[0, 2, idx as u64], "root_ptr");
+                let root = self.builder.build_pointer_cast(*root, byte_ptr_type, "root");
+                self.builder.build_store(root_ptr, root);
+            }
+            let frame = self.builder.build_pointer_cast(frame, byte_ptr_type, "frame");
+            self.builder.build_store(frames, frame);
+            Some(prev)
+        };
+        self.builder.build_unconditional_branch(body);
+
+        self.builder.position_at_end(exit);
+        if let Some(prev) = prev {
+            self.builder.build_store(frames, prev);
+        }
+        match result {
+            Some(result) => {
+                let value = self.builder.build_load(result, "result");
+                self.builder.build_return(Some(&value))
+            }
+            None => self.builder.build_return(None),
+        };
+    }
+
+    /// Stores the result of `return` (if any), and jumps to where the current function returns
+    fn build_return(&self, value: Option<BasicValueEnum<'ctx>>) {
+        let (exit, result) = self.exit.unwrap();
+        if let (Some(value), Some(result)) = (value, result) {
+            self.builder.build_store(result, value);
+        }
+        self.builder.build_unconditional_branch(exit);
+    }
+
+    /// Builds a tuple (an anonymous struct, see `Type::Tuple`) of `values`
+    fn build_tuple(&self, values: &[BasicValueEnum<'ctx>]) -> BasicValueEnum<'ctx> {
+        let types: Vec<_> = values.iter().map(|value| value.get_type()).collect();
+        let mut tuple = self.context.struct_type(&types, false).get_undef();
+        for (idx, value) in values.iter().enumerate() {
+            tuple = self
+                .builder
+                .build_insert_value(tuple, *value, idx as u32, "tuple")
+                .unwrap()
+                .into_struct_value();
+        }
+        tuple.into()
+    }
+
+    /// Allocates a variable at the start of the current function's entry block. Variables are
+    /// never allocated where they're declared: an alloca in a loop would grow the stack on every
+    /// iteration, and mem2reg only promotes the allocas in the entry block to registers (inserting
+    /// the phi nodes needed for variables that are carried from one iteration to the next)
+    fn build_entry_alloca(&self, ty: BasicTypeEnum<'ctx>, name: &str) -> PointerValue<'ctx> {
+        let builder = self.context.create_builder();
+        let entry = self
+            .current_function
+            .unwrap()
+            .get_first_basic_block()
+            .unwrap();
+        match entry.get_first_instruction() {
+            Some(first) => builder.position_before(&first),
+            None => builder.position_at_end(entry),
+        }
+        builder.build_alloca(ty, name)
+    }
+
+    /// Allocates the variable `name` of type `ty` (declared at `span`) where it's declared. A
+    /// variable whose address escapes (see `escape_analysis`) is allocated on the heap, anew each
+    /// time, and other variables in the entry block (see `build_entry_alloca`). Variables with
+    /// pointers are roots, or for those on the heap, the pointers to them are
+    fn build_var(&self, ty: &Type, name: &str, span: Span) -> PointerValue<'ctx> {
+        if self.escapes.is_heap(span) {
+            let one = self.context.i64_type().const_int(1, false);
+            let ptr = self.build_alloc(ty, one, name);
+            self.root_value(ptr.into(), &Type::Pointer(Box::new(ty.clone())));
+            return ptr;
+        }
+        if self.has_pointers(ty) {
+            return self.build_root(ty, name);
+        }
+        self.build_entry_alloca(ty.to_llvm(self.context), name)
+    }
+
+    /// Allocates a root of type `ty`: a variable that's part of the current function's frame (see
+    /// `build_frame`), so that the garbage collector keeps what it points to alive
+    fn build_root(&self, ty: &Type, name: &str) -> PointerValue<'ctx> {
+        let llvm_type = ty.to_llvm(self.context);
+        let root = self.build_entry_alloca(llvm_type, name);
+        let desc = self.type_desc(ty);
+        self.roots.borrow_mut().push((root, llvm_type, desc));
+        root
+    }
+
+    /// Keeps `value` (the result of an expression that allocates, of type `ty`) in a root, so
+    /// that what it points to stays alive while the rest of the expression it's in is evaluated
+    fn root_value(&self, value: BasicValueEnum<'ctx>, ty: &Type) -> BasicValueEnum<'ctx> {
+        if self.has_pointers(ty) {
+            let root = self.build_root(ty, "tmp_root");
+            self.builder.build_store(root, value);
+        }
+        value
+    }
+
+    /// Allocates `count` zeroed values of type `ty` on the heap, and tells the garbage collector
+    /// their type
+    fn build_alloc(&self, ty: &Type, count: IntValue<'ctx>, name: &str) -> PointerValue<'ctx> {
+        let llvm_type = ty.to_llvm(self.context);
+        let size = self
+            .builder
+            .build_int_mul(llvm_type.size_of().unwrap(), count, "size");
+        let ptr = self
+            .build_runtime_call(
+                "__go_alloc",
+                &[size.into(), self.type_desc(ty).into()],
+                name,
+            )
+            .into_pointer_value();
+        self.builder
+            .build_pointer_cast(ptr, llvm_type.ptr_type(AddressSpace::Generic), name)
+    }
+
+    /// Whether values of type `ty` hold pointers (which strings, slices, maps and errors do)
+    fn has_pointers(&self, ty: &Type) -> bool {
+        match ty {
+            Type::Pointer(_) | Type::GoString | Type::Slice(_) | Type::Map(..) | Type::Error => {
+                true
+            }
+            Type::Array(len, elem) => *len > 0 && self.has_pointers(elem),
+            Type::Tuple(types) => types.iter().any(|ty| self.has_pointers(ty)),
+            Type::Named(name) => self.structs[name]
+                .iter()
+                .any(|field| self.has_pointers(&field.field_type)),
+            _ => false,
+        }
+    }
+
+    /// Returns (as an `i8*`) the type descriptor of `ty`, which tells the garbage collector where
+    /// the pointers in its values are: a global with the size of the type, the number of pointers
+    /// and their offsets. Types without pointers have a null descriptor
+    fn type_desc(&self, ty: &Type) -> PointerValue<'ctx> {
+        let byte_ptr_type = self.context.i8_type().ptr_type(AddressSpace::Generic);
+        if !self.has_pointers(ty) {
+            return byte_ptr_type.const_null();
+        }
+        let name = format!("type.{}", format_type(ty));
+        if let Some(global) = self.module.get_global(&name) {
+            return global.as_pointer_value().const_cast(byte_ptr_type);
+        }
+        let llvm_type = ty.to_llvm(self.context);
+        let mut offsets = vec![];
+        let null = llvm_type.ptr_type(AddressSpace::Generic).const_null();
+        self.pointer_offsets(ty, null, &mut offsets);
+        self.build_type_desc(&name, llvm_type, &offsets)
+    }
+
+    /// Adds the type descriptor `name` of the LLVM type `llvm_type`, whose pointers are at
+    /// `offsets` (see `type_desc`), and returns it as an `i8*`
+    fn build_type_desc(
+        &self,
+        name: &str,
+        llvm_type: BasicTypeEnum<'ctx>,
+        offsets: &[IntValue<'ctx>],
+    ) -> PointerValue<'ctx> {
+        let byte_ptr_type = self.context.i8_type().ptr_type(AddressSpace::Generic);
+        let i64_type = self.context.i64_type();
+        let desc = self.context.const_struct(
+            &[
+                llvm_type.size_of().unwrap().into(),
+                i64_type.const_int(offsets.len() as u64, false).into(),
+                i64_type.const_array(offsets).into(),
+            ],
+            false,
+        );
+        let global = self.module.add_global(desc.get_type(), None, name);
+        global.set_linkage(Linkage::Private);
+        global.set_constant(true);
+        global.set_initializer(&desc);
+        global.as_pointer_value().const_cast(byte_ptr_type)
+    }
+
+    /// Adds the offsets of the pointers in a value of type `ty` to `offsets`, as constants. `ptr`
+    /// is the address of the value relative to null (the offset of the value itself)
+    fn pointer_offsets(
+        &self,
+        ty: &Type,
+        ptr: PointerValue<'ctx>,
+        offsets: &mut Vec<IntValue<'ctx>>,
+    ) {
+        let i32_type = self.context.i32_type();
+        let zero = i32_type.const_zero();
+        match ty {
+            // Strings and slices start with their pointer, and maps and errors are one
+            Type::Pointer(_) | Type::GoString | Type::Slice(_) | Type::Map(..) | Type::Error => {
+                offsets.push(ptr.const_to_int(self.context.i64_type()))
+            }
+            Type::Array(len, elem) if self.has_pointers(elem) => {
+                for idx in 0..*len {
+                    let idx = self.context.i64_type().const_int(idx, false);
+                    let elem_ptr = unsafe { ptr.const_gep(&[zero, idx]) };
+                    self.pointer_offsets(elem, elem_ptr, offsets);
+                }
+            }
+            Type::Named(name) => {
+                for (idx, field) in self.structs[name].iter().enumerate() {
+                    let idx = i32_type.const_int(idx as u64, false);
+                    let field_ptr = unsafe { ptr.const_gep(&[zero, idx]) };
+                    self.pointer_offsets(&field.field_type, field_ptr, offsets);
+                }
+            }
+            Type::Tuple(types) => {
+                for (idx, ty) in types.iter().enumerate() {
+                    let idx = i32_type.const_int(idx as u64, false);
+                    let elem_ptr = unsafe { ptr.const_gep(&[zero, idx]) };
+                    self.pointer_offsets(ty, elem_ptr, offsets);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The type of the entries of the map type `ty`: the state of the entry (which only the
+    /// runtime uses), the key and the value
+    fn map_entry_type(&self, ty: &Type) -> StructType<'ctx> {
+        let (key, value) = match ty {
+            Type::Map(key, value) => (key, value),
+            _ => unreachable!("map entry of a non-map type"),
+        };
+        self.context.struct_type(
+            &[
+                self.context.i64_type().into(),
+                key.to_llvm(self.context),
+                value.to_llvm(self.context),
+            ],
+            false,
+        )
+    }
+
+    /// Returns (as an `i8*`) the type of the map type `ty` for the runtime, which matches its
+    /// `MapType`: a global with the size of an entry, the offset and size of the key, the offset
+    /// of the value, the type descriptor of an entry, and the fields of the key (see `key_fields`)
+    fn map_type(&self, ty: &Type) -> PointerValue<'ctx> {
+        let byte_ptr_type = self.context.i8_type().ptr_type(AddressSpace::Generic);
+        let name = format!("map.{}", format_type(ty));
+        if let Some(global) = self.module.get_global(&name) {
+            return global.as_pointer_value().const_cast(byte_ptr_type);
+        }
+        let (key, value) = match ty {
+            Type::Map(key, value) => (key, value),
+            _ => unreachable!("map type of a non-map type"),
+        };
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
+        let entry_type = self.map_entry_type(ty);
+        let null = entry_type.ptr_type(AddressSpace::Generic).const_null();
+        let zero = i32_type.const_zero();
+        let key_ptr = unsafe { null.const_gep(&[zero, i32_type.const_int(1, false)]) };
+        let value_ptr = unsafe { null.const_gep(&[zero, i32_type.const_int(2, false)]) };
+        let mut offsets = vec![];
+        self.pointer_offsets(key, key_ptr, &mut offsets);
+        self.pointer_offsets(value, value_ptr, &mut offsets);
+        let entry_desc = if offsets.is_empty() {
+            byte_ptr_type.const_null()
+        } else {
+            let desc_name = format!("type.{}.entry", format_type(ty));
+            self.build_type_desc(&desc_name, entry_type.into(), &offsets)
+        };
+        let key_type = key.to_llvm(self.context);
+        let mut fields = vec![];
+        let null_key = key_type.ptr_type(AddressSpace::Generic).const_null();
+        self.key_fields(key, null_key, &mut fields);
+        let field_type = self
+            .context
+            .struct_type(&[i64_type.as_basic_type_enum(); 3], false);
+        let typ = self.context.const_struct(
+            &[
+                entry_type.size_of().unwrap().into(),
+                key_ptr.const_to_int(i64_type).into(),
+                key_type.size_of().unwrap().into(),
+                value_ptr.const_to_int(i64_type).into(),
+                entry_desc.into(),
+                i64_type.const_int(fields.len() as u64, false).into(),
+                field_type.const_array(&fields).into(),
+            ],
+            false,
+        );
+        let global = self.module.add_global(typ.get_type(), None, &name);
+        global.set_linkage(Linkage::Private);
+        global.set_constant(true);
+        global.set_initializer(&typ);
+        global.as_pointer_value().const_cast(byte_ptr_type)
+    }
+
+    /// Adds the fields of a map key of type `ty` to `fields`, as constant `{ offset, size, kind }`
+    /// structs, which tell the runtime how to hash and compare them. Arrays and structs are
+    /// flattened (leaving out blank fields, which aren't compared), so that their padding is never
+    /// read. `ptr` is the address of the key relative to null, like in `pointer_offsets`
+    fn key_fields(&self, ty: &Type, ptr: PointerValue<'ctx>, fields: &mut Vec<StructValue<'ctx>>) {
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
+        let zero = i32_type.const_zero();
+        let kind = match ty {
+            Type::Array(len, elem) => {
+                for idx in 0..*len {
+                    let idx = i64_type.const_int(idx, false);
+                    let elem_ptr = unsafe { ptr.const_gep(&[zero, idx]) };
+                    self.key_fields(elem, elem_ptr, fields);
+                }
+                return;
+            }
+            Type::Named(name) => {
+                for (idx, field) in self.structs[name].iter().enumerate() {
+                    if field.name == "_" {
+                        continue;
+                    }
+                    let idx = i32_type.const_int(idx as u64, false);
+                    let field_ptr = unsafe { ptr.const_gep(&[zero, idx]) };
+                    self.key_fields(&field.field_type, field_ptr, fields);
+                }
+                return;
+            }
+            Type::Float32 | Type::Float64 => KEY_FLOAT,
+            Type::GoString => KEY_STRING,
+            _ => KEY_MEMORY,
+        };
+        let size = ty.to_llvm(self.context).size_of().unwrap();
+        fields.push(self.context.const_struct(
+            &[
+                ptr.const_to_int(i64_type).into(),
+                size.into(),
+                i64_type.const_int(kind, false).into(),
+            ],
+            false,
+        ));
+    }
+
+    /// Returns the block starting at `label`, creating it if needed
+    fn label_block(&mut self, label: &str) -> BasicBlock<'ctx> {
+        let function = self.current_function.unwrap();
+        *self
+            .labels
+            .entry(label.to_string())
+            .or_insert_with(|| self.context.append_basic_block(function, label))
+    }
+
+    /// Whether the block the builder is positioned at already ends with a terminator (e.g. a
+    /// `return`), in which case no more instructions may be added to it
+    fn block_terminated(&self) -> bool {
+        self.builder
+            .get_insert_block()
+            .and_then(|bb| bb.get_terminator())
+            .is_some()
+    }
+
+    fn gen_block(&mut self, block: &[Statement]) -> Result<(), CodeGenError> {
+        // Variables declared in the block go out of scope at its end, uncovering the ones they
+        // shadow
+        let outer_scope = self.symbol_table.clone();
+        let mut label = None;
         for stmt in block {
-            self.gen_statement(stmt)?
+            // Labels start blocks of their own
+            if self.block_terminated() && !matches!(stmt, Statement::Label { .. }) {
+                // Code after a return is dead, but it still has to go in a block of its own
+                let dead_bb = self
+                    .context
+                    .append_basic_block(self.current_function.unwrap(), "dead_bb");
+                self.builder.position_at_end(dead_bb);
+            }
+            match stmt {
+                Statement::For {
+                    init,
+                    cond,
+                    post,
+                    body,
+                    ..
+                } => self.gen_for(label, init.as_deref(), cond.as_ref(), post.as_deref(), body)?,
+                Statement::ForRange {
+                    key,
+                    value,
+                    define,
+                    expr,
+                    body,
+                    ..
+                } => self.gen_for_range(label, key.as_ref(), value.as_ref(), *define, expr, body)?,
+                Statement::Switch {
+                    init, tag, cases, ..
+                } => self.gen_switch(label, init.as_deref(), tag.as_ref(), cases)?,
+                _ => self.gen_statement(stmt)?,
+            }
+            // The label of the next statement
+            label = match stmt {
+                Statement::Label { name, .. } => Some(name.as_str()),
+                _ => None,
+            };
         }
+        self.symbol_table = outer_scope;
         Ok(())
     }
 
-    fn gen_statement(&mut self, stmt: &Statement) -> Result<(), &'static str> {
+    fn gen_statement(&mut self, stmt: &Statement) -> Result<(), CodeGenError> {
         match stmt {
             Statement::Assignment {
                 name,
                 var_type,
                 expr,
+                span,
             } => {
+                if *var_type == Type::Unknown {
+                    return Err(CodeGenError {
+                        msg: "variable of unknown type passed to codegen (should have been caught by the type checker)",
+                        span: *span,
+                    });
+                }
                 let rhs = self.gen_expr(expr)?;
-                let alloca = self
-                    .builder
-                    .build_alloca(var_type.to_llvm(self.context), name);
+                let alloca = self.build_var(var_type, name, *span);
                 self.builder.build_store(alloca, rhs);
                 self.symbol_table.insert(name.clone(), alloca);
             }
-            Statement::Return { expr } => {
-                self.builder.build_return(Some(&self.gen_expr(expr)?));
+            Statement::ShortVarDecl { vars, exprs, .. } => {
+                let values = self.gen_assign_values(exprs, vars.len())?;
+                for (var, value) in vars.iter().zip(values) {
+                    if var.name == "_" {
+                        continue;
+                    }
+                    let ptr = if var.is_new {
+                        let alloca = self.build_var(&var.var_type, &var.name, var.span);
+                        self.symbol_table.insert(var.name.clone(), alloca);
+                        alloca
+                    } else {
+                        self.gen_lvalue(&var.name, var.span)?
+                    };
+                    self.builder.build_store(ptr, value);
+                }
+            }
+            Statement::Assign {
+                lhs, op: None, rhs, ..
+            } => {
+                // All the values and targets are computed before any of them is stored, so that
+                // e.g. `a, b = b, a` swaps `a` and `b`, and `i, s[i] = 1, 2` assigns to the
+                // element at the old `i`
+                let values = self.gen_assign_values(rhs, lhs.len())?;
+                let mut targets = vec![];
+                for lhs in lhs {
+                    targets.push(self.gen_assign_target(lhs)?);
+                }
+                for (target, value) in targets.into_iter().zip(values) {
+                    self.build_assign(target, value);
+                }
+            }
+            Statement::Assign {
+                lhs,
+                op: Some(op),
+                rhs,
+                span,
+            } => self.gen_assign_op(&lhs[0], op, &rhs[0], *span)?,
+            Statement::IncDec { expr, op, span } => {
+                // `x++` is `x += 1`
+                let one = Expression::Literal {
+                    expr_type: expr.get_type().clone(),
+                    value: Value::Int(1.into()),
+                    span: *span,
+                };
+                self.gen_assign_op(expr, op, &one, *span)?
             }
-            Statement::Expression { expr } => {
+            Statement::Return { exprs, .. } => {
+                // A bare `return` returns the named results, and the results of a call that
+                // returns several are already a tuple
+                let values: Vec<_> = match &exprs[..] {
+                    [] => self
+                        .named_results
+                        .iter()
+                        .map(|ptr| self.builder.build_load(*ptr, "result"))
+                        .collect(),
+                    [expr] => vec![self.gen_expr(expr)?],
+                    exprs => exprs
+                        .iter()
+                        .map(|expr| self.gen_expr(expr))
+                        .collect::<Result<_, _>>()?,
+                };
+                let value = match &values[..] {
+                    [] => None,
+                    [value] => Some(*value),
+                    values => Some(self.build_tuple(values)),
+                };
+                self.build_return(value);
+            }
+            Statement::Expression { expr, .. } => {
                 self.gen_expr(expr)?;
             }
             Statement::If {
                 cond,
                 then_block,
                 else_block,
+                ..
             } => self.gen_if(cond, then_block, else_block)?,
+            Statement::For {
+                init,
+                cond,
+                post,
+                body,
+                ..
+            } => self.gen_for(None, init.as_deref(), cond.as_ref(), post.as_deref(), body)?,
+            Statement::ForRange {
+                key,
+                value,
+                define,
+                expr,
+                body,
+                ..
+            } => self.gen_for_range(None, key.as_ref(), value.as_ref(), *define, expr, body)?,
+            Statement::Switch {
+                init, tag, cases, ..
+            } => self.gen_switch(None, init.as_deref(), tag.as_ref(), cases)?,
+            Statement::Break { label, span } | Statement::Continue { label, span } => {
+                let is_break = matches!(stmt, Statement::Break { .. });
+                // The statement with the label, or else the innermost loop (or switch, for a
+                // `break`)
+                let target = self.targets.iter().rev().find(|target| match label {
+                    Some(label) => target.label.as_ref() == Some(label),
+                    None => is_break || target.continue_bb.is_some(),
+                });
+                let target = match target {
+                    Some(target) if is_break => Some(target.break_bb),
+                    Some(target) => target.continue_bb,
+                    None => None,
+                };
+                let target = match target {
+                    Some(target) => target,
+                    None => {
+                        return Err(CodeGenError {
+                            msg: "break or continue outside of its loop (should have been caught by the semantic checker)",
+                            span: *span,
+                        })
+                    }
+                };
+                self.builder.build_unconditional_branch(target);
+            }
+            Statement::Label { name, .. } => {
+                let label_bb = self.label_block(name);
+                if !self.block_terminated() {
+                    self.builder.build_unconditional_branch(label_bb);
+                }
+                self.builder.position_at_end(label_bb);
+            }
+            Statement::Goto { label, .. } => {
+                let label_bb = self.label_block(label);
+                self.builder.build_unconditional_branch(label_bb);
+            }
+            // The semantic checker already replaced the uses of constants with their values
+            Statement::Const { .. } => {}
+            // A `fallthrough` ending a case is generated along with the switch
+            Statement::Fallthrough { span } => {
+                return Err(CodeGenError {
+                    msg: "misplaced fallthrough (should have been caught by the semantic checker)",
+                    span: *span,
+                })
+            }
         };
         Ok(())
     }
 
-    fn gen_expr(&self, expr: &Expression) -> Result<BasicValueEnum, &'static str> {
+    /// Generates the `count` values assigned by an assignment or short variable declaration:
+    /// those of `exprs`, the results of a call of a function with several, or for `v, ok = m[k]`,
+    /// the value of the key and whether the map has it
+    fn gen_assign_values(
+        &self,
+        exprs: &[Expression],
+        count: usize,
+    ) -> Result<Vec<BasicValueEnum<'ctx>>, CodeGenError> {
+        match (exprs, count) {
+            ([expr], _) if matches!(expr.get_type(), Type::Tuple(_)) => self.gen_tuple_values(expr),
+            ([Expression::Index { expr, index, .. }], 2) => {
+                let (value, found) = self.gen_map_index(expr, index)?;
+                Ok(vec![value, found.into()])
+            }
+            _ => exprs.iter().map(|expr| self.gen_expr(expr)).collect(),
+        }
+    }
+
+    /// Generates the call `expr` of a function with several results, and returns them
+    fn gen_tuple_values(
+        &self,
+        expr: &Expression,
+    ) -> Result<Vec<BasicValueEnum<'ctx>>, CodeGenError> {
+        let tuple = self.gen_expr(expr)?.into_struct_value();
+        Ok((0..tuple.get_type().count_fields())
+            .map(|idx| {
+                self.builder
+                    .build_extract_value(tuple, idx, "result")
+                    .unwrap()
+            })
+            .collect())
+    }
+
+    fn gen_expr(&self, expr: &Expression) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
         match expr {
-            Expression::Literal { expr_type, value } => Ok(self.gen_literal(expr_type, value)?),
+            Expression::Literal {
+                expr_type,
+                value,
+                span,
+            } => Ok(self.gen_literal(expr_type, value, *span)?),
             Expression::BinaryOp {
-                op, left, right, ..
-            } => Ok(self.gen_binop(op, left, right)?),
-            Expression::Name { name, .. } => Ok(self.gen_var_ref(name)?),
-            Expression::Call { func, args, .. } => Ok(self.gen_call(func, args)?),
+                op,
+                left,
+                right,
+                span,
+                ..
+            } => Ok(self.gen_binop(op, left, right, *span)?),
+            Expression::UnaryOp {
+                op: UnaryOp::Addr,
+                expr: inner,
+                span,
+                ..
+            } => self.gen_addr(inner, *span),
+            Expression::UnaryOp {
+                op: UnaryOp::Deref, ..
+            } => {
+                let ptr = self.gen_value_ptr(expr)?;
+                Ok(self.builder.build_load(ptr, "dereftmp"))
+            }
+            Expression::UnaryOp { op, expr, span, .. } => self.gen_unary(op, expr, *span),
+            Expression::Name { name, span, .. } => Ok(self.gen_var_ref(name, *span)?),
+            Expression::Call {
+                expr_type,
+                func,
+                args,
+                span,
+                ..
+            } => {
+                let value = self.gen_call(func, args, *span)?;
+                Ok(match expr_type {
+                    Some(ty) => self.root_value(value, ty),
+                    None => value,
+                })
+            }
+            Expression::BuiltinCall {
+                builtin: builtin @ (Builtin::Len | Builtin::Cap),
+                args,
+                ..
+            } => {
+                let value = self.gen_expr(&args[0])?;
+                // Strings and slices have their length as their second field, and slices their
+                // capacity as their third. The runtime keeps the length of maps
+                Ok(match args[0].get_type() {
+                    Type::Array(len, _) => self.context.i64_type().const_int(*len, false).into(),
+                    Type::Map(..) => self.build_runtime_call("__go_maplen", &[value.into()], "len"),
+                    _ => {
+                        let field = if *builtin == Builtin::Len { 1 } else { 2 };
+                        self.builder
+                            .build_extract_value(value.into_struct_value(), field, builtin.name())
+                            .unwrap()
+                    }
+                })
+            }
+            Expression::BuiltinCall {
+                builtin: builtin @ (Builtin::Print | Builtin::Println),
+                args,
+                ..
+            } => self.gen_print(args, *builtin == Builtin::Println),
+            Expression::BuiltinCall {
+                builtin: Builtin::Append,
+                args,
+                has_dots,
+                ..
+            } => self.gen_append(args, *has_dots),
+            Expression::BuiltinCall {
+                builtin: Builtin::Copy,
+                args,
+                ..
+            } => {
+                let dst = self.gen_expr(&args[0])?.into_struct_value();
+                let src = self.gen_expr(&args[1])?.into_struct_value();
+                let elem_type = args[0].get_type().elem().unwrap().to_llvm(self.context);
+                Ok(self.build_copy(dst, src, elem_type).into())
+            }
+            Expression::BuiltinCall {
+                builtin: Builtin::Delete,
+                args,
+                ..
+            } => {
+                let map = self.gen_expr(&args[0])?;
+                let key = self.gen_map_key(args[0].get_type(), &args[1])?;
+                let delete = self.module.get_function("__go_mapdelete").unwrap();
+                self.builder
+                    .build_call(delete, &[map.into(), key.into()], "delete");
+                // Like calls of other functions without a result, it returns a value that's never
+                // used
+                Ok(BasicValueEnum::IntValue(
+                    self.context.bool_type().const_int(1, true),
+                ))
+            }
+            Expression::FmtCall { func, args, .. } => self.gen_fmt_call(*func, args),
+            Expression::RuntimeCall {
+                func: RuntimeFunc::GC,
+                ..
+            } => {
+                let gc = self.module.get_function("__go_gc").unwrap();
+                self.builder.build_call(gc, &[], "gc");
+                // Like calls of other functions without a result, it returns a value that's never
+                // used
+                Ok(BasicValueEnum::IntValue(
+                    self.context.bool_type().const_int(1, true),
+                ))
+            }
+            Expression::Index { expr, index, .. } => self.gen_index(expr, index),
+            Expression::Selector { .. } => {
+                let ptr = self.gen_value_ptr(expr)?;
+                Ok(self.builder.build_load(ptr, "fieldtmp"))
+            }
+            Expression::Slice {
+                expr,
+                low,
+                high,
+                max,
+                span,
+                ..
+            } => self.gen_slice(expr, low.as_deref(), high.as_deref(), max.as_deref(), *span),
+            Expression::CompositeLit {
+                expr_type,
+                elements,
+                span,
+                ..
+            } => self.gen_composite_lit(expr_type, elements, *span),
+            Expression::ZeroValue { expr_type, .. } | Expression::Nil { expr_type, .. } => {
+                Ok(const_zero(expr_type.to_llvm(self.context)))
+            }
+            Expression::New {
+                expr_type: Type::Pointer(elem),
+                span,
+            } => Ok(self.build_new(elem, *span).into()),
+            Expression::Make {
+                expr_type, args, ..
+            } => {
+                let hint = match args.first() {
+                    Some(size) => self.gen_index_value(size)?,
+                    None => self.context.i64_type().const_zero(),
+                };
+                let map = self.build_runtime_call(
+                    "__go_makemap",
+                    &[self.map_type(expr_type).into(), hint.into()],
+                    "map",
+                );
+                Ok(self.root_value(map, expr_type))
+            }
+            Expression::New { span, .. } => Err(CodeGenError {
+                msg: "new of a non-pointer type (should have been caught by the type checker)",
+                span: *span,
+            }),
+            Expression::Conversion {
+                expr_type,
+                expr,
+                span,
+            } => {
+                let value = self.gen_expr(expr)?;
+                self.gen_conversion(value, expr.get_type(), expr_type, *span)
+            }
         }
     }
 
-    fn gen_var_ref(&self, name: &String) -> Result<BasicValueEnum, &'static str> {
+    fn gen_var_ref(&self, name: &str, span: Span) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
         match self.symbol_table.get(name) {
             Some(var) => Ok(self.builder.build_load(*var, name)),
-            None => {
-                Err("reference to undefined variable (should have been caught by semantic checker)")
+            None => Err(CodeGenError {
+                msg: "reference to undefined variable (should have been caught by semantic checker)",
+                span,
+            }),
+        }
+    }
+
+    /// The storage of the variable `name`, which is assigned to
+    fn gen_lvalue(&self, name: &str, span: Span) -> Result<PointerValue<'ctx>, CodeGenError> {
+        self.symbol_table.get(name).copied().ok_or(CodeGenError {
+            msg: "assignment to undefined variable (should have been caught by semantic checker)",
+            span,
+        })
+    }
+
+    /// What `expr` refers to on the left-hand side of an assignment. The map and the key of a map
+    /// entry are evaluated here, but the entry is only added when it's assigned (see
+    /// `build_assign`)
+    fn gen_assign_target(&self, expr: &Expression) -> Result<AssignTarget<'ctx>, CodeGenError> {
+        match expr {
+            Expression::Name { name, .. } if name == "_" => Ok(AssignTarget::Blank),
+            Expression::Name { name, span, .. } => {
+                Ok(AssignTarget::Ptr(self.gen_lvalue(name, *span)?))
+            }
+            Expression::Index { expr, index, .. } if expr.get_type().elem().is_some() => {
+                Ok(AssignTarget::Ptr(self.gen_element_ptr(expr, index)?))
+            }
+            Expression::Index {
+                expr: map,
+                index,
+                span,
+                ..
+            } if matches!(map.get_type(), Type::Map(..)) => {
+                let map_value = self.gen_expr(map)?.into_pointer_value();
+                Ok(AssignTarget::MapEntry {
+                    map: map_value,
+                    key: self.gen_map_key(map.get_type(), index)?,
+                    span: *span,
+                })
             }
+            Expression::Selector { .. }
+            | Expression::UnaryOp {
+                op: UnaryOp::Deref, ..
+            } => Ok(AssignTarget::Ptr(self.gen_value_ptr(expr)?)),
+            _ => Err(CodeGenError {
+                msg: "assignment to a non-variable (should have been caught by the type checker)",
+                span: expr.span(),
+            }),
         }
     }
 
-    fn gen_literal(&self, expr_type: &Type, value: &str) -> Result<BasicValueEnum, &'static str> {
-        match expr_type {
-            Type::Int => Ok(BasicValueEnum::IntValue(
-                self.context
-                    .i64_type()
-                    .const_int(value.parse::<i64>().unwrap() as u64, true),
+    /// Generates `lhs op= rhs`. The target is only computed once (e.g. the index in
+    /// `s[f()] += 1`)
+    fn gen_assign_op(
+        &self,
+        lhs: &Expression,
+        op: &BinaryOp,
+        rhs: &Expression,
+        span: Span,
+    ) -> Result<(), CodeGenError> {
+        let target = self.gen_assign_target(lhs)?;
+        let lhs_value = match target {
+            AssignTarget::Ptr(ptr) => self.builder.build_load(ptr, "lhs"),
+            AssignTarget::MapEntry { map, key, .. } => {
+                self.build_map_access(map, key, lhs.get_type()).0
+            }
+            AssignTarget::Blank => {
+                return Err(CodeGenError {
+                    msg: "operation on the blank identifier (should have been caught by the type checker)",
+                    span,
+                })
+            }
+        };
+        let rhs_value = self.gen_expr(rhs)?;
+        let value = self.build_binop(op, lhs_value, rhs_value, lhs, rhs, span)?;
+        self.build_assign(target, value);
+        Ok(())
+    }
+
+    /// Assigns `value` to `target`. A map entry is added to the map (which panics if it's nil)
+    /// if it doesn't have it yet
+    fn build_assign(&self, target: AssignTarget<'ctx>, value: BasicValueEnum<'ctx>) {
+        match target {
+            AssignTarget::Ptr(ptr) => {
+                self.builder.build_store(ptr, value);
+            }
+            AssignTarget::MapEntry { map, key, span } => {
+                if self.checks.is_needed(span, Check::NilMap) {
+                    let is_not_nil = self.builder.build_is_not_null(map, "is_not_nil");
+                    self.build_runtime_check(is_not_nil, ERR_NIL_MAP, "nil_map");
+                }
+                self.build_map_assign(map, key, value);
+            }
+            AssignTarget::Blank => {}
+        }
+    }
+
+    /// Stores `value` in the entry of the non-nil `map` for the key at `key` (see `gen_map_key`),
+    /// which the runtime adds if needed
+    fn build_map_assign(
+        &self,
+        map: PointerValue<'ctx>,
+        key: PointerValue<'ctx>,
+        value: BasicValueEnum<'ctx>,
+    ) {
+        let ptr = self
+            .build_runtime_call("__go_mapassign", &[map.into(), key.into()], "valueptr")
+            .into_pointer_value();
+        let value_type = value.get_type().ptr_type(AddressSpace::Generic);
+        let ptr = self.builder.build_pointer_cast(ptr, value_type, "valueptr");
+        self.builder.build_store(ptr, value);
+    }
+
+    fn gen_literal(
+        &self,
+        expr_type: &Type,
+        value: &Value,
+        span: Span,
+    ) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
+        let not_representable = CodeGenError {
+            msg: "constant not representable in its type (should have been caught by the type checker)",
+            span,
+        };
+        match (expr_type, value) {
+            (_, Value::Int(_)) if expr_type.is_integer() => Ok(BasicValueEnum::IntValue(
+                expr_type
+                    .to_llvm(self.context)
+                    .into_int_type()
+                    .const_int(value.to_bits().ok_or(not_representable)?, false),
             )),
-            Type::Float32 => Ok(BasicValueEnum::FloatValue(
+            // Float constants are already rounded to the precision of their type
+            (Type::Float32, _) => Ok(BasicValueEnum::FloatValue(
                 self.context
                     .f32_type()
-                    .const_float(value.parse::<f32>().unwrap().into()),
+                    .const_float(value.to_f64().ok_or(not_representable)?),
             )),
-            Type::Float64 => Ok(BasicValueEnum::FloatValue(
+            (Type::Float64, _) => Ok(BasicValueEnum::FloatValue(
                 self.context
                     .f64_type()
-                    .const_float(value.parse::<f64>().unwrap()),
+                    .const_float(value.to_f64().ok_or(not_representable)?),
             )),
-            Type::Bool => Ok(BasicValueEnum::IntValue(
-                self.context
-                    .bool_type()
-                    .const_int(value.parse::<u64>().unwrap(), true),
+            (Type::Bool, Value::Bool(boolean)) => Ok(BasicValueEnum::IntValue(
+                self.context.bool_type().const_int(*boolean as u64, false),
             )),
-            Type::GoString => Ok(self
-                .builder
-                .build_global_string_ptr(&value.replace("\\n", "\n"), "str")
-                .as_basic_value_enum()),
+            (Type::GoString, Value::String(_)) => {
+                Ok(self.const_string(value.string_bytes().unwrap(), "str").into())
+            }
+            (Type::Unknown, _) => Err(CodeGenError {
+                msg: "literal of unknown type passed to codegen (should have been caught by the type checker)",
+                span,
+            }),
+            _ => Err(not_representable),
         }
     }
 
@@ -214,76 +1251,88 @@ impl<'ctx> CodeGen<'ctx> {
         op: &BinaryOp,
         left: &Expression,
         right: &Expression,
-    ) -> Result<BasicValueEnum, &'static str> {
+        span: Span,
+    ) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
+        if matches!(op, LogicalAnd | LogicalOr) {
+            return self.gen_logical(op, left, right);
+        }
         let left_gen = self.gen_expr(left)?;
         let right_gen = self.gen_expr(right)?;
+        self.build_binop(op, left_gen, right_gen, left, right, span)
+    }
+
+    /// Applies `op` to the values of `left` and `right` (which are only looked at for their types,
+    /// and for constant divisors and shift counts)
+    fn build_binop(
+        &self,
+        op: &BinaryOp,
+        left_gen: BasicValueEnum<'ctx>,
+        right_gen: BasicValueEnum<'ctx>,
+        left: &Expression,
+        right: &Expression,
+        span: Span,
+    ) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
         match (left_gen, right_gen) {
-            // Binary operation of two ints
+            // Binary operation of two integers (of the same type, except for shifts). Division,
+            // ordered comparisons and right shifts depend on whether the type is unsigned
             (BasicValueEnum::IntValue(lhs), BasicValueEnum::IntValue(rhs)) => {
+                let unsigned = left.get_type().is_unsigned();
+                let compare = |signed_pred, unsigned_pred, name| {
+                    let pred = if unsigned { unsigned_pred } else { signed_pred };
+                    self.builder.build_int_compare(pred, lhs, rhs, name)
+                };
                 Ok(BasicValueEnum::IntValue(match op {
                     Add => self.builder.build_int_add(lhs, rhs, "addtmp"),
                     Sub => self.builder.build_int_sub(lhs, rhs, "subtmp"),
                     Mul => self.builder.build_int_mul(lhs, rhs, "multmp"),
-                    Div => {
-                        // Check if we are dividing by zero (results in undefined behavior)
-                        let is_not_div_by_zero = self.builder.build_int_compare(
-                            IntPredicate::NE,
-                            rhs,
-                            self.context.i64_type().const_int(0, true),
-                            "is_not_div_by_zero"
-                        );
-                        let parent_bb = self.current_function.unwrap();
-                        let panic_bb = self.context.append_basic_block(parent_bb, "panic_bb");
-                        let cont_bb = self.context.append_basic_block(parent_bb, "cont_bb");
-                        self.builder.build_conditional_branch(is_not_div_by_zero, cont_bb, panic_bb);
-
-                        // panic_bb basic block
-                        self.builder.position_at_end(panic_bb);
-                        let error_msg = self.builder
-                            .build_global_string_ptr(ERR_DIV_BY_ZERO, "div_by_zero")
-                            .as_basic_value_enum();
-                        self.builder.build_call(
-                            self.module.get_function("__gopanic").unwrap(),
-                            &[error_msg.into()],
-                            "panic"
-                        );
-                        // Terminator instruction
-                        self.builder.build_unreachable();
-
-                        // If all is fine, continue at cont_bb
-                        self.builder.position_at_end(cont_bb);
-                        self.builder.build_int_signed_div(lhs, rhs, "divtmp")
+                    Div | Rem => {
+                        let divisor = match right {
+                            Expression::Literal { value, .. } => Some(value),
+                            _ => None,
+                        };
+                        self.build_int_division(op, lhs, rhs, divisor, unsigned, span)
                     },
                     Eq => self.builder.build_int_compare(IntPredicate::EQ, lhs, rhs, "eqtmp"),
                     Neq => self.builder.build_int_compare(IntPredicate::NE, lhs, rhs, "neqtmp"),
-                    Ge => {
-                        self.builder
-                            .build_int_compare(IntPredicate::SGT, lhs, rhs, "getmp")
-                    }
-                    Le => {
-                        self.builder
-                            .build_int_compare(IntPredicate::SLT, lhs, rhs, "letmp")
-                    }
-                    Geq => self.builder.build_int_compare(
-                        IntPredicate::SGE,
-                        lhs,
-                        rhs,
-                        "geqtmp",
-                    ),
-                    Leq => self.builder.build_int_compare(
-                        IntPredicate::SLE,
-                        lhs,
-                        rhs,
-                        "leqtmp",
-                    ),
+                    Ge => compare(IntPredicate::SGT, IntPredicate::UGT, "getmp"),
+                    Le => compare(IntPredicate::SLT, IntPredicate::ULT, "letmp"),
+                    Geq => compare(IntPredicate::SGE, IntPredicate::UGE, "geqtmp"),
+                    Leq => compare(IntPredicate::SLE, IntPredicate::ULE, "leqtmp"),
+                    Shl | Shr => {
+                        // Unsigned counts can't be negative
+                        let signed_count = !right.get_type().is_unsigned();
+                        if signed_count && self.checks.is_needed(span, Check::NegativeShift) {
+                            let is_not_negative = self.builder.build_int_compare(
+                                IntPredicate::SGE,
+                                rhs,
+                                rhs.get_type().const_zero(),
+                                "is_not_negative"
+                            );
+                            self.build_runtime_check(
+                                is_not_negative,
+                                ERR_NEGATIVE_SHIFT,
+                                "negative_shift"
+                            );
+                        }
+                        self.build_shift(op, lhs, rhs, unsigned)
+                    },
+                    And => self.builder.build_and(lhs, rhs, "andtmp"),
+                    Or => self.builder.build_or(lhs, rhs, "ortmp"),
+                    Xor => self.builder.build_xor(lhs, rhs, "xortmp"),
+                    AndNot => {
+                        let not_rhs = self.builder.build_not(rhs, "nottmp");
+                        self.builder.build_and(lhs, not_rhs, "andnottmp")
+                    },
+                    LogicalAnd | LogicalOr => unreachable!("generated by gen_logical"),
                 }))
             }
             // Binary operation of two floats (of same size)
             (BasicValueEnum::FloatValue(lhs), BasicValueEnum::FloatValue(rhs)) => {
                 if left.get_type() != right.get_type() {
-                    return Err(
-                        "cannot perform binary operation on float32 and float64 (should have been caught by the type checker)"
-                    );
+                    return Err(CodeGenError {
+                        msg: "cannot perform binary operation on float32 and float64 (should have been caught by the type checker)",
+                        span,
+                    });
                 }
                 Ok(match op {
                     Add => BasicValueEnum::FloatValue(
@@ -346,66 +1395,2003 @@ impl<'ctx> CodeGen<'ctx> {
                             "leqtmp",
                         ))
                     }
+                    Rem | Shl | Shr | And | Or | Xor | AndNot | LogicalAnd | LogicalOr => {
+                        return Err(CodeGenError {
+                            msg: "integer operation on floats (should have been caught by the type checker)",
+                            span,
+                        })
+                    }
                 })
             }
-            _ => Err("binary operations on unsupported types (should have been caught by the type checker)"),
-        }
-    }
-
-    fn gen_call(&self, func: &String, args: &[Expression]) -> Result<BasicValueEnum, &'static str> {
-        match self.module.get_function(func) {
-            Some(func_value) => {
-                let mut compiled_args = vec![];
-                for arg in args {
-                    compiled_args.push(self.gen_expr(arg)?.into());
-                }
-                match self
-                    .builder
-                    .build_call(func_value, compiled_args.as_slice(), "calltmp")
-                    .try_as_basic_value()
-                    .left()
-                {
-                    Some(value) => Ok(value),
-                    // Because we got to return something from gen_expr, we return the
-                    // magic number; It isn't used, so nothing lost there
-                    None => Ok(BasicValueEnum::IntValue(
-                        self.context.bool_type().const_int(1, true),
-                    )),
+            // Strings are concatenated and compared by the runtime
+            (BasicValueEnum::StructValue(lhs), BasicValueEnum::StructValue(rhs))
+                if *left.get_type() == Type::GoString =>
+            {
+                let pred = match op {
+                    Add => {
+                        let value = self.build_runtime_call(
+                            "__gostring_concat",
+                            &[lhs.into(), rhs.into()],
+                            "concattmp",
+                        );
+                        return Ok(self.root_value(value, &Type::GoString));
+                    }
+                    Eq => IntPredicate::EQ,
+                    Neq => IntPredicate::NE,
+                    Ge => IntPredicate::SGT,
+                    Le => IntPredicate::SLT,
+                    Geq => IntPredicate::SGE,
+                    Leq => IntPredicate::SLE,
+                    _ => {
+                        return Err(CodeGenError {
+                            msg: "arithmetic operation on strings (should have been caught by the type checker)",
+                            span,
+                        })
+                    }
+                };
+                Ok(self.build_string_compare(pred, lhs, rhs).into())
+            }
+            // Arrays are compared element by element, structs field by field, and pointers (and
+            // slices, to nil) by address
+            (BasicValueEnum::ArrayValue(_), BasicValueEnum::ArrayValue(_))
+            | (BasicValueEnum::StructValue(_), BasicValueEnum::StructValue(_))
+            | (BasicValueEnum::PointerValue(_), BasicValueEnum::PointerValue(_))
+                if matches!(op, Eq | Neq) =>
+            {
+                let is_equal = self.build_eq(left_gen, right_gen, left.get_type(), span)?;
+                Ok(match op {
+                    Eq => is_equal,
+                    _ => self.builder.build_not(is_equal, "neqtmp"),
                 }
+                .into())
             }
-            None => Err("undefined function passed to codegen (should have been caught by semantic checker)"),
+            _ => Err(CodeGenError {
+                msg: "binary operations on unsupported types (should have been caught by the type checker)",
+                span,
+            }),
         }
     }
 
-    fn gen_if(
-        &mut self,
-        cond: &Expression,
-        then_block: &[Statement],
-        else_block: &[Statement],
-    ) -> Result<(), &'static str> {
-        let parent = self.current_function.unwrap();
-
-        let llvm_cond = self.gen_expr(cond)?.into_int_value();
+    /// Compares two strings byte by byte, with `pred` (a signed predicate) comparing the result of
+    /// `__gostring_compare` (negative, zero or positive) with 0
+    fn build_string_compare(
+        &self,
+        pred: IntPredicate,
+        lhs: StructValue<'ctx>,
+        rhs: StructValue<'ctx>,
+    ) -> IntValue<'ctx> {
+        let order = self
+            .build_runtime_call("__gostring_compare", &[lhs.into(), rhs.into()], "order")
+            .into_int_value();
+        let zero = self.context.i64_type().const_zero();
+        self.builder.build_int_compare(pred, order, zero, "cmptmp")
+    }
 
-        let then_bb = self.context.append_basic_block(parent, "then_bb");
-        let else_bb = self.context.append_basic_block(parent, "else_bb");
+    /// Generates `left && right` or `left || right`, which only evaluate `right` if `left` doesn't
+    /// determine the result
+    fn gen_logical(
+        &self,
+        op: &BinaryOp,
+        left: &Expression,
+        right: &Expression,
+    ) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
+        let parent = self.current_function.unwrap();
+        let lhs = self.gen_expr(left)?.into_int_value();
+        let lhs_bb = self.builder.get_insert_block().unwrap();
+        let rhs_bb = self.context.append_basic_block(parent, "rhs_bb");
         let cont_bb = self.context.append_basic_block(parent, "cont_bb");
+        if *op == LogicalAnd {
+            self.builder.build_conditional_branch(lhs, rhs_bb, cont_bb);
+        } else {
+            self.builder.build_conditional_branch(lhs, cont_bb, rhs_bb);
+        }
 
-        self.builder
-            .build_conditional_branch(llvm_cond, then_bb, else_bb);
+        self.builder.position_at_end(rhs_bb);
+        let rhs = self.gen_expr(right)?;
+        // `right` may have ended in another block (e.g. if it has a logical operator itself)
+        let rhs_bb = self.builder.get_insert_block().unwrap();
+        self.builder.build_unconditional_branch(cont_bb);
+
+        // When `right` isn't evaluated, the result is `left`
+        self.builder.position_at_end(cont_bb);
+        let phi = self.builder.build_phi(self.context.bool_type(), "logicaltmp");
+        phi.add_incoming(&[(&lhs, lhs_bb), (&rhs, rhs_bb)]);
+        Ok(phi.as_basic_value())
+    }
+
+    fn gen_unary(
+        &self,
+        op: &UnaryOp,
+        expr: &Expression,
+        span: Span,
+    ) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
+        let value = self.gen_expr(expr)?;
+        Ok(match (op, value) {
+            (UnaryOp::Plus, _) => value,
+            (UnaryOp::Neg, BasicValueEnum::IntValue(int)) => {
+                BasicValueEnum::IntValue(self.builder.build_int_neg(int, "negtmp"))
+            }
+            (UnaryOp::Neg, BasicValueEnum::FloatValue(float)) => {
+                BasicValueEnum::FloatValue(self.builder.build_float_neg(float, "negtmp"))
+            }
+            // Both flip every bit (a bool is a single bit)
+            (UnaryOp::Not | UnaryOp::Complement, BasicValueEnum::IntValue(int)) => {
+                BasicValueEnum::IntValue(self.builder.build_not(int, "nottmp"))
+            }
+            _ => {
+                return Err(CodeGenError {
+                    msg: "unary operation on an unsupported type (should have been caught by the type checker)",
+                    span,
+                })
+            }
+        })
+    }
+
+    /// Calls `__gopanic` with `msg` unless `ok` is true. Code generation continues in the block
+    /// where it is
+    fn build_runtime_check(&self, ok: IntValue<'ctx>, msg: &str, name: &str) {
+        let error_msg = self.const_string(msg.as_bytes(), name);
+        self.build_check(ok, "__gopanic", &[error_msg.into()]);
+    }
+
+    /// Calls the runtime function `panic` (which never returns) with `args` unless `ok` is true.
+    /// Code generation continues in the block where it is
+    fn build_check(&self, ok: IntValue<'ctx>, panic: &str, args: &[BasicMetadataValueEnum<'ctx>]) {
+        let parent_bb = self.current_function.unwrap();
+        let panic_bb = self.context.append_basic_block(parent_bb, "panic_bb");
+        let cont_bb = self.context.append_basic_block(parent_bb, "cont_bb");
+        self.builder.build_conditional_branch(ok, cont_bb, panic_bb);
+
+        // panic_bb basic block
+        self.builder.position_at_end(panic_bb);
+        self.builder
+            .build_call(self.module.get_function(panic).unwrap(), args, "panic");
+        // Terminator instruction
+        self.builder.build_unreachable();
+
+        // If all is fine, continue at cont_bb
+        self.builder.position_at_end(cont_bb);
+    }
+
+    /// Calls the runtime function `func`, which returns a value
+    fn build_runtime_call(
+        &self,
+        func: &str,
+        args: &[BasicMetadataValueEnum<'ctx>],
+        name: &str,
+    ) -> BasicValueEnum<'ctx> {
+        self.builder
+            .build_call(self.module.get_function(func).unwrap(), args, name)
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+    }
+
+    /// A constant string of `bytes`. The bytes are stored in a private global (named `name`),
+    /// which unlike a C string isn't NUL-terminated
+    fn const_string(&self, bytes: &[u8], name: &str) -> StructValue<'ctx> {
+        let i8_type = self.context.i8_type();
+        let chars: Vec<IntValue> = bytes
+            .iter()
+            .map(|byte| i8_type.const_int(*byte as u64, false))
+            .collect();
+        let array = i8_type.const_array(&chars);
+        let global = self.module.add_global(array.get_type(), None, name);
+        global.set_initializer(&array);
+        global.set_constant(true);
+        global.set_linkage(Linkage::Private);
+        global.set_unnamed_addr(true);
+        let ptr = global
+            .as_pointer_value()
+            .const_cast(i8_type.ptr_type(AddressSpace::Generic));
+        let len = self.context.i64_type().const_int(bytes.len() as u64, false);
+        self.context.const_struct(&[ptr.into(), len.into()], false)
+    }
+
+    /// Makes a string of the `len` bytes at `ptr`
+    fn build_string(&self, ptr: PointerValue<'ctx>, len: IntValue<'ctx>) -> StructValue<'ctx> {
+        let string_type = Type::GoString.to_llvm(self.context).into_struct_type();
+        let string = self
+            .builder
+            .build_insert_value(string_type.get_undef(), ptr, 0, "strtmp")
+            .unwrap();
+        self.builder
+            .build_insert_value(string, len, 1, "strtmp")
+            .unwrap()
+            .into_struct_value()
+    }
+
+    fn build_string_ptr(&self, string: StructValue<'ctx>) -> PointerValue<'ctx> {
+        self.builder
+            .build_extract_value(string, 0, "ptr")
+            .unwrap()
+            .into_pointer_value()
+    }
+
+    fn build_string_len(&self, string: StructValue<'ctx>) -> IntValue<'ctx> {
+        self.builder
+            .build_extract_value(string, 1, "len")
+            .unwrap()
+            .into_int_value()
+    }
+
+    /// Generates the value of an index (of any integer type) as an `int`
+    fn gen_index_value(&self, index: &Expression) -> Result<IntValue<'ctx>, CodeGenError> {
+        let value = self.gen_expr(index)?;
+        Ok(self
+            .gen_conversion(value, index.get_type(), &Type::Int, index.span())?
+            .into_int_value())
+    }
+
+    /// Generates `x[i]`: the `i`th byte of the string `x`, the `i`th element of the array or
+    /// slice `x`, or the value of the key `i` in the map `x`. An index out of range panics
+    fn gen_index(
+        &self,
+        expr: &Expression,
+        index: &Expression,
+    ) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
+        if let Type::Map(..) = expr.get_type() {
+            return Ok(self.gen_map_index(expr, index)?.0);
+        }
+        if *expr.get_type() != Type::GoString {
+            let ptr = self.gen_element_ptr(expr, index)?;
+            return Ok(self.builder.build_load(ptr, "elemtmp"));
+        }
+        let string = self.gen_expr(expr)?.into_struct_value();
+        let idx = self.gen_index_value(index)?;
+        let len = self.build_string_len(string);
+        self.build_index_check(idx, len, index.span());
+        let ptr = self.build_string_ptr(string);
+        // The index was just checked
+        let byte_ptr = unsafe { self.builder.build_in_bounds_gep(ptr, &[idx], "byteptr") };
+        Ok(self.builder.build_load(byte_ptr, "bytetmp"))
+    }
+
+    /// Generates `m[k]`: the value of the key `k` in the map `m`, or the zero value if `m` doesn't
+    /// have it (or is nil), and whether it has it
+    fn gen_map_index(
+        &self,
+        map: &Expression,
+        key: &Expression,
+    ) -> Result<(BasicValueEnum<'ctx>, IntValue<'ctx>), CodeGenError> {
+        let map_value = self.gen_expr(map)?.into_pointer_value();
+        let key = self.gen_map_key(map.get_type(), key)?;
+        let value_type = match map.get_type() {
+            Type::Map(_, value_type) => value_type,
+            _ => unreachable!("map index of a non-map type"),
+        };
+        Ok(self.build_map_access(map_value, key, value_type))
+    }
+
+    /// Looks up the key at `key` (see `gen_map_key`) in `map`. Returns its value (of type
+    /// `value_type`), which is zero if it's missing, and whether it was found
+    fn build_map_access(
+        &self,
+        map: PointerValue<'ctx>,
+        key: PointerValue<'ctx>,
+        value_type: &Type,
+    ) -> (BasicValueEnum<'ctx>, IntValue<'ctx>) {
+        let llvm_type = value_type.to_llvm(self.context);
+        let ptr = self
+            .build_runtime_call("__go_mapaccess", &[map.into(), key.into()], "valueptr")
+            .into_pointer_value();
+        let found = self.builder.build_is_not_null(ptr, "found");
+        let parent = self.current_function.unwrap();
+        let missing_bb = self.builder.get_insert_block().unwrap();
+        let found_bb = self.context.append_basic_block(parent, "found_bb");
+        let cont_bb = self.context.append_basic_block(parent, "cont_bb");
+        self.builder.build_conditional_branch(found, found_bb, cont_bb);
+
+        self.builder.position_at_end(found_bb);
+        let ptr = self.builder.build_pointer_cast(
+            ptr,
+            llvm_type.ptr_type(AddressSpace::Generic),
+            "valueptr",
+        );
+        let found_value = self.builder.build_load(ptr, "value");
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.builder.position_at_end(cont_bb);
+        let value = self.builder.build_phi(llvm_type, "value");
+        value.add_incoming(&[
+            (&const_zero(llvm_type), missing_bb),
+            (&found_value, found_bb),
+        ]);
+        (value.as_basic_value(), found)
+    }
+
+    /// Stores the key `key` of the map type `map_type` in a temporary, and returns a pointer to it
+    /// as an `i8*`, which is how the runtime's map functions take keys. A key with pointers is
+    /// kept in a root, since the map may grow (and the heap be collected) before it's copied
+    fn gen_map_key(
+        &self,
+        map_type: &Type,
+        key: &Expression,
+    ) -> Result<PointerValue<'ctx>, CodeGenError> {
+        let key_type = match map_type {
+            Type::Map(key_type, _) => key_type,
+            _ => unreachable!("map key of a non-map type"),
+        };
+        let value = self.gen_expr(key)?;
+        let ptr = if self.has_pointers(key_type) {
+            self.build_root(key_type, "key")
+        } else {
+            self.build_entry_alloca(value.get_type(), "key")
+        };
+        self.builder.build_store(ptr, value);
+        let byte_ptr_type = self.context.i8_type().ptr_type(AddressSpace::Generic);
+        Ok(self.builder.build_pointer_cast(ptr, byte_ptr_type, "keyptr"))
+    }
+
+    /// A pointer to the element `x[i]` of the array or slice `x`, which panics if the index is out
+    /// of range. An array that isn't a variable (e.g. the result of a call) is stored in a
+    /// temporary first
+    fn gen_element_ptr(
+        &self,
+        expr: &Expression,
+        index: &Expression,
+    ) -> Result<PointerValue<'ctx>, CodeGenError> {
+        let i64_type = self.context.i64_type();
+        match expr.get_type() {
+            Type::Array(len, _) => {
+                let array = self.gen_value_ptr(expr)?;
+                let idx = self.gen_index_value(index)?;
+                self.build_index_check(idx, i64_type.const_int(*len, false), index.span());
+                let zero = i64_type.const_zero();
+                Ok(unsafe {
+                    self.builder
+                        .build_in_bounds_gep(array, &[zero, idx], "elemptr")
+                })
+            }
+            Type::Slice(_) => {
+                let slice = self.gen_expr(expr)?.into_struct_value();
+                let idx = self.gen_index_value(index)?;
+                let (ptr, len, _) = self.build_slice_fields(slice);
+                self.build_index_check(idx, len, index.span());
+                Ok(unsafe { self.builder.build_in_bounds_gep(ptr, &[idx], "elemptr") })
+            }
+            _ => Err(CodeGenError {
+                msg: "indexing of an unsupported type (should have been caught by the type checker)",
+                span: expr.span(),
+            }),
+        }
+    }
+
+    /// A pointer to the array or struct `expr` (or to a field of one): the storage of a variable
+    /// (or of an element or field of one, of an element of a slice, or of what a pointer points
+    /// to), or else a temporary holding its value
+    fn gen_value_ptr(&self, expr: &Expression) -> Result<PointerValue<'ctx>, CodeGenError> {
+        match expr {
+            Expression::Name { name, span, .. } => self.gen_lvalue(name, *span),
+            Expression::Index { expr, index, .. } if expr.get_type().elem().is_some() => {
+                self.gen_element_ptr(expr, index)
+            }
+            Expression::UnaryOp {
+                op: UnaryOp::Deref,
+                expr,
+                span,
+                ..
+            } => self.gen_nonnil_ptr(expr, *span),
+            // The path goes through the embedded structs the field is promoted from. The fields of
+            // a struct can also be selected through a pointer to it
+            Expression::Selector {
+                expr, path, span, ..
+            } => {
+                let mut ptr = match expr.get_type() {
+                    Type::Pointer(_) => self.gen_nonnil_ptr(expr, *span)?,
+                    _ => self.gen_value_ptr(expr)?,
+                };
+                for idx in path {
+                    ptr = self
+                        .builder
+                        .build_struct_gep(ptr, *idx as u32, "fieldptr")
+                        .unwrap();
+                }
+                Ok(ptr)
+            }
+            _ => {
+                let value = self.gen_expr(expr)?;
+                let temp = self.build_entry_alloca(value.get_type(), "value_tmp");
+                self.builder.build_store(temp, value);
+                Ok(temp)
+            }
+        }
+    }
+
+    /// The value of the pointer `expr`, which panics if it's nil (if that may happen: `span` is
+    /// that of the `*p` or `p.f`)
+    fn gen_nonnil_ptr(
+        &self,
+        expr: &Expression,
+        span: Span,
+    ) -> Result<PointerValue<'ctx>, CodeGenError> {
+        let ptr = self.gen_expr(expr)?.into_pointer_value();
+        if self.checks.is_needed(span, Check::NilDeref) {
+            let is_not_nil = self.builder.build_is_not_null(ptr, "is_not_nil");
+            self.build_runtime_check(is_not_nil, ERR_NIL_DEREF, "nil_deref");
+        }
+        Ok(ptr)
+    }
+
+    /// Generates `&x`, where `span` is that of the `&x`: the address of the variable `x` (or of an
+    /// element or field of one, or of what a pointer points to), or of a new variable holding the
+    /// composite literal `x`
+    fn gen_addr(
+        &self,
+        expr: &Expression,
+        span: Span,
+    ) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
+        if let Expression::CompositeLit { expr_type, .. } = expr {
+            let value = self.gen_expr(expr)?;
+            let ptr = self.build_new(expr_type, span);
+            self.builder.build_store(ptr, value);
+            return Ok(ptr.into());
+        }
+        Ok(self.gen_value_ptr(expr)?.into())
+    }
+
+    /// Allocates a zeroed variable of type `ty` for the `new(T)` or `&T{..}` at `span`: on the
+    /// heap if it escapes (see `escape_analysis`), and otherwise in the entry block
+    fn build_new(&self, ty: &Type, span: Span) -> PointerValue<'ctx> {
+        if self.escapes.is_heap(span) {
+            let one = self.context.i64_type().const_int(1, false);
+            let ptr = self.build_alloc(ty, one, "new");
+            self.root_value(ptr.into(), &Type::Pointer(Box::new(ty.clone())));
+            return ptr;
+        }
+        // The stack slot is reused every time the allocation is made. Like a variable, it's a root
+        // if it has pointers, since the collector doesn't follow pointers into the stack
+        let llvm_type = ty.to_llvm(self.context);
+        let ptr = if self.has_pointers(ty) {
+            self.build_root(ty, "new")
+        } else {
+            self.build_entry_alloca(llvm_type, "new")
+        };
+        self.builder.build_store(ptr, const_zero(llvm_type));
+        ptr
+    }
+
+    /// Panics unless `0 <= idx < len`, where `span` is that of the index. Constant indices into
+    /// arrays (which the type checker checked against the length) and indices known to be in range
+    /// aren't checked
+    fn build_index_check(&self, idx: IntValue<'ctx>, len: IntValue<'ctx>, span: Span) {
+        if !self.checks.is_needed(span, Check::Index) {
+            return;
+        }
+        // Negative indices are out of range too, as unsigned integers
+        let in_range = self
+            .builder
+            .build_int_compare(IntPredicate::ULT, idx, len, "in_range");
+        let msg = self.const_string(ERR_INDEX_OUT_OF_RANGE.as_bytes(), "index_out_of_range");
+        self.build_check(
+            in_range,
+            "__gopanic_index",
+            &[msg.into(), idx.into(), len.into()],
+        );
+    }
+
+    /// Generates `x[low:high]` or `x[low:high:max]`, which shares the bytes of the string `x` or
+    /// the elements of the array or slice `x`. Unless `0 <= low <= high <= max <= cap(x)` (with
+    /// `max` and `cap(x)` being the length of strings and arrays), it panics (if that may happen:
+    /// `span` is that of the slice expression)
+    fn gen_slice(
+        &self,
+        expr: &Expression,
+        low: Option<&Expression>,
+        high: Option<&Expression>,
+        max: Option<&Expression>,
+        span: Span,
+    ) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
+        let i64_type = self.context.i64_type();
+        let zero = i64_type.const_zero();
+        let (ptr, len, cap) = match expr.get_type() {
+            Type::GoString => {
+                let string = self.gen_expr(expr)?.into_struct_value();
+                let len = self.build_string_len(string);
+                (self.build_string_ptr(string), len, len)
+            }
+            Type::Array(len, _) => {
+                let array = self.gen_value_ptr(expr)?;
+                let ptr = unsafe {
+                    self.builder
+                        .build_in_bounds_gep(array, &[zero, zero], "elemptr")
+                };
+                let len = i64_type.const_int(*len, false);
+                (ptr, len, len)
+            }
+            _ => self.build_slice_fields(self.gen_expr(expr)?.into_struct_value()),
+        };
+        let low = match low {
+            Some(low) => self.gen_index_value(low)?,
+            None => zero,
+        };
+        let high = match high {
+            Some(high) => self.gen_index_value(high)?,
+            None => len,
+        };
+        let three_index = max.is_some();
+        let max = match max {
+            Some(max) => self.gen_index_value(max)?,
+            None => cap,
+        };
+        if self.checks.is_needed(span, Check::Slice) {
+            // Comparing as unsigned integers also rules out negative indices
+            let mut in_range = self
+                .builder
+                .build_int_compare(IntPredicate::ULE, max, cap, "max_in_range");
+            for (name, lhs, rhs) in [("high_in_range", high, max), ("low_in_range", low, high)] {
+                let is_in_range = self
+                    .builder
+                    .build_int_compare(IntPredicate::ULE, lhs, rhs, name);
+                in_range = self.builder.build_and(in_range, is_in_range, "in_range");
+            }
+            let bool_type = self.context.bool_type();
+            let msg = self
+                .const_string(ERR_SLICE_BOUNDS_OUT_OF_RANGE.as_bytes(), "slice_out_of_range");
+            let is_slice = matches!(expr.get_type(), Type::Slice(_));
+            self.build_check(
+                in_range,
+                "__gopanic_slice",
+                &[
+                    msg.into(),
+                    low.into(),
+                    high.into(),
+                    max.into(),
+                    cap.into(),
+                    bool_type.const_int(three_index as u64, false).into(),
+                    bool_type.const_int(is_slice as u64, false).into(),
+                ],
+            );
+        }
+        let ptr = unsafe { self.builder.build_in_bounds_gep(ptr, &[low], "sliceptr") };
+        let len = self.builder.build_int_sub(high, low, "slicelen");
+        if *expr.get_type() == Type::GoString {
+            return Ok(self.build_string(ptr, len).into());
+        }
+        let cap = self.builder.build_int_sub(max, low, "slicecap");
+        Ok(self.build_slice(ptr, len, cap).into())
+    }
+
+    /// Makes a slice of the `len` elements at `ptr`, with room for `cap`
+    fn build_slice(
+        &self,
+        ptr: PointerValue<'ctx>,
+        len: IntValue<'ctx>,
+        cap: IntValue<'ctx>,
+    ) -> StructValue<'ctx> {
+        let slice_type = self.context.struct_type(
+            &[ptr.get_type().into(), len.get_type().into(), cap.get_type().into()],
+            false,
+        );
+        let mut slice = slice_type.get_undef();
+        let fields: [BasicValueEnum; 3] = [ptr.into(), len.into(), cap.into()];
+        for (idx, field) in fields.into_iter().enumerate() {
+            slice = self
+                .builder
+                .build_insert_value(slice, field, idx as u32, "slicetmp")
+                .unwrap()
+                .into_struct_value();
+        }
+        slice
+    }
+
+    /// The pointer to the first element, the length and the capacity of a slice
+    fn build_slice_fields(
+        &self,
+        slice: StructValue<'ctx>,
+    ) -> (PointerValue<'ctx>, IntValue<'ctx>, IntValue<'ctx>) {
+        let field = |idx, name| self.builder.build_extract_value(slice, idx, name).unwrap();
+        (
+            field(0, "ptr").into_pointer_value(),
+            field(1, "len").into_int_value(),
+            field(2, "cap").into_int_value(),
+        )
+    }
+
+    /// Generates an array, slice or struct literal. The elements of a slice literal are stored in
+    /// an array allocated on the heap
+    fn gen_composite_lit(
+        &self,
+        expr_type: &Type,
+        elements: &[KeyedElement],
+        span: Span,
+    ) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
+        match expr_type {
+            Type::Named(name) => return self.gen_struct_lit(name, elements),
+            Type::Map(..) => return self.gen_map_lit(expr_type, elements),
+            _ => {}
+        }
+        // An element without a key goes after the previous one (the type checker folded the keys
+        // into literals)
+        let mut indices = vec![];
+        let mut next = 0;
+        for element in elements {
+            let index = match &element.key {
+                Some(Expression::Literal { value, .. }) => value.to_i64().unwrap() as u64,
+                Some(key) => {
+                    return Err(CodeGenError {
+                        msg: "non-constant composite literal key (should have been caught by the type checker)",
+                        span: key.span(),
+                    })
+                }
+                None => next,
+            };
+            indices.push(index);
+            next = index + 1;
+        }
+        let llvm_type = expr_type.to_llvm(self.context);
+        let elem_type = match expr_type {
+            Type::Array(..) => {
+                let mut array = llvm_type.into_array_type().const_zero();
+                for (element, index) in elements.iter().zip(indices) {
+                    let value = self.gen_expr(&element.value)?;
+                    array = self
+                        .builder
+                        .build_insert_value(array, value, index as u32, "arraytmp")
+                        .unwrap()
+                        .into_array_value();
+                }
+                return Ok(array.into());
+            }
+            Type::Slice(elem_type) => elem_type,
+            _ => {
+                return Err(CodeGenError {
+                    msg: "composite literal of an unsupported type (should have been caught by the type checker)",
+                    span,
+                })
+            }
+        };
+        let i64_type = self.context.i64_type();
+        let len = i64_type.const_int(indices.iter().max().map_or(0, |max| max + 1), false);
+        let ptr = self.build_alloc(elem_type, len, "elems");
+        // The elements may allocate too
+        self.root_value(ptr.into(), &Type::Pointer(elem_type.clone()));
+        for (element, index) in elements.iter().zip(indices) {
+            let value = self.gen_expr(&element.value)?;
+            let idx = i64_type.const_int(index, false);
+            let elem_ptr = unsafe { self.builder.build_in_bounds_gep(ptr, &[idx], "elemptr") };
+            self.builder.build_store(elem_ptr, value);
+        }
+        Ok(self.build_slice(ptr, len, len).into())
+    }
+
+    /// Generates a literal of the struct type `name`, whose elements are either all keyed by the
+    /// name of a field, or are the values of all the fields in order. The fields that aren't given
+    /// a value are zero
+    fn gen_struct_lit(
+        &self,
+        name: &str,
+        elements: &[KeyedElement],
+    ) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
+        let fields = &self.structs[name];
+        let struct_type = self.context.get_struct_type(name).unwrap();
+        let mut value = struct_type.const_zero();
+        for (idx, element) in elements.iter().enumerate() {
+            let field_idx = match &element.key {
+                Some(Expression::Name { name, span, .. }) => fields
+                    .iter()
+                    .position(|field| field.name == *name)
+                    .ok_or(CodeGenError {
+                        msg: "unknown field in struct literal (should have been caught by the type checker)",
+                        span: *span,
+                    })?,
+                Some(key) => {
+                    return Err(CodeGenError {
+                        msg: "invalid field name in struct literal (should have been caught by the type checker)",
+                        span: key.span(),
+                    })
+                }
+                None => idx,
+            };
+            let field_value = self.gen_expr(&element.value)?;
+            value = self
+                .builder
+                .build_insert_value(value, field_value, field_idx as u32, "structtmp")
+                .unwrap()
+                .into_struct_value();
+        }
+        Ok(value.into())
+    }
+
+    /// Generates a literal of the map type `map_type`, whose elements all have keys. The map is
+    /// made with room for all of them, and they're assigned in order (a later one replacing an
+    /// earlier one with an equal key)
+    fn gen_map_lit(
+        &self,
+        map_type: &Type,
+        elements: &[KeyedElement],
+    ) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
+        let len = self
+            .context
+            .i64_type()
+            .const_int(elements.len() as u64, false);
+        let map = self.build_runtime_call(
+            "__go_makemap",
+            &[self.map_type(map_type).into(), len.into()],
+            "map",
+        );
+        // The keys and values may allocate too
+        self.root_value(map, map_type);
+        for element in elements {
+            let key = element.key.as_ref().ok_or(CodeGenError {
+                msg: "map literal element without a key (should have been caught by the type checker)",
+                span: element.value.span(),
+            })?;
+            let key = self.gen_map_key(map_type, key)?;
+            let value = self.gen_expr(&element.value)?;
+            self.build_map_assign(map.into_pointer_value(), key, value);
+        }
+        Ok(map)
+    }
+
+    /// Generates `append(s, values...)`, or `append(s, t...)` if `has_dots` is set. The values are
+    /// stored after the elements of `s`, in a larger array if `s` doesn't have room for them
+    fn gen_append(
+        &self,
+        args: &[Expression],
+        has_dots: bool,
+    ) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
+        let slice_type = args[0].get_type();
+        let elem = slice_type.elem().unwrap();
+        let elem_type = elem.to_llvm(self.context);
+        let slice = self.gen_expr(&args[0])?.into_struct_value();
+        // The values are evaluated before the slice is grown
+        let mut values = vec![];
+        for arg in &args[1..] {
+            values.push(self.gen_expr(arg)?);
+        }
+        let (ptr, len, cap) = self.build_slice_fields(slice);
+        let count = match (has_dots, values.first()) {
+            // Strings and slices both have their length as their second field
+            (true, Some(src)) => self
+                .builder
+                .build_extract_value(src.into_struct_value(), 1, "count")
+                .unwrap()
+                .into_int_value(),
+            _ => self.context.i64_type().const_int(values.len() as u64, false),
+        };
+        let new_len = self.builder.build_int_add(len, count, "new_len");
+        let (ptr, cap) = self.build_grow(ptr, len, cap, new_len, elem);
+        if has_dots {
+            let dst = unsafe { self.builder.build_in_bounds_gep(ptr, &[len], "dst") };
+            let dst = self.build_slice(dst, count, count);
+            self.build_copy(dst, values[0].into_struct_value(), elem_type);
+        } else {
+            for (idx, value) in values.into_iter().enumerate() {
+                let idx = self.context.i64_type().const_int(idx as u64, false);
+                let idx = self.builder.build_int_add(len, idx, "idx");
+                let elem_ptr = unsafe { self.builder.build_in_bounds_gep(ptr, &[idx], "elemptr") };
+                self.builder.build_store(elem_ptr, value);
+            }
+        }
+        let value = self.build_slice(ptr, new_len, cap).into();
+        Ok(self.root_value(value, slice_type))
+    }
+
+    /// Makes room for `new_len` elements in the array at `ptr`, which has room for `cap`. If it's
+    /// too small, the `len` elements it holds are moved to a larger array by the runtime. Returns
+    /// the pointer to the array and its capacity
+    fn build_grow(
+        &self,
+        ptr: PointerValue<'ctx>,
+        len: IntValue<'ctx>,
+        cap: IntValue<'ctx>,
+        new_len: IntValue<'ctx>,
+        elem: &Type,
+    ) -> (PointerValue<'ctx>, IntValue<'ctx>) {
+        let elem_type = elem.to_llvm(self.context);
+        let parent = self.current_function.unwrap();
+        let fits_bb = self.builder.get_insert_block().unwrap();
+        let grow_bb = self.context.append_basic_block(parent, "grow_bb");
+        let cont_bb = self.context.append_basic_block(parent, "cont_bb");
+        let fits = self
+            .builder
+            .build_int_compare(IntPredicate::ULE, new_len, cap, "fits");
+        self.builder.build_conditional_branch(fits, cont_bb, grow_bb);
+
+        self.builder.position_at_end(grow_bb);
+        let byte_ptr_type = self.context.i8_type().ptr_type(AddressSpace::Generic);
+        let old_ptr = self.builder.build_pointer_cast(ptr, byte_ptr_type, "old_ptr");
+        let grown = self
+            .build_runtime_call(
+                "__go_growslice",
+                &[
+                    old_ptr.into(),
+                    len.into(),
+                    cap.into(),
+                    new_len.into(),
+                    elem_type.size_of().unwrap().into(),
+                    self.type_desc(elem).into(),
+                ],
+                "grown",
+            )
+            .into_struct_value();
+        let (grown_ptr, _, grown_cap) = self.build_slice_fields(grown);
+        let grown_ptr = self.builder.build_pointer_cast(
+            grown_ptr,
+            elem_type.ptr_type(AddressSpace::Generic),
+            "grown_ptr",
+        );
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.builder.position_at_end(cont_bb);
+        let new_ptr = self.builder.build_phi(ptr.get_type(), "new_ptr");
+        new_ptr.add_incoming(&[(&ptr, fits_bb), (&grown_ptr, grow_bb)]);
+        let new_cap = self.builder.build_phi(cap.get_type(), "new_cap");
+        new_cap.add_incoming(&[(&cap, fits_bb), (&grown_cap, grow_bb)]);
+        (
+            new_ptr.as_basic_value().into_pointer_value(),
+            new_cap.as_basic_value().into_int_value(),
+        )
+    }
+
+    /// Copies as many elements (of type `elem_type`) as both `dst` and `src` (a slice, or a string
+    /// if the elements are bytes) have, and returns their number. The two may overlap
+    fn build_copy(
+        &self,
+        dst: StructValue<'ctx>,
+        src: StructValue<'ctx>,
+        elem_type: BasicTypeEnum<'ctx>,
+    ) -> IntValue<'ctx> {
+        let byte_ptr_type = self.context.i8_type().ptr_type(AddressSpace::Generic);
+        let mut args: Vec<BasicMetadataValueEnum> = vec![];
+        // Like strings, slices start with their pointer and their length
+        for (slice, name) in [(dst, "dst"), (src, "src")] {
+            let ptr = self.build_string_ptr(slice);
+            args.push(self.builder.build_pointer_cast(ptr, byte_ptr_type, name).into());
+            args.push(self.build_string_len(slice).into());
+        }
+        args.push(elem_type.size_of().unwrap().into());
+        self.build_runtime_call("__go_slicecopy", &args, "copied")
+            .into_int_value()
+    }
+
+    fn gen_call(
+        &self,
+        func: &str,
+        args: &[Expression],
+        span: Span,
+    ) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
+        match self.module.get_function(func) {
+            Some(func_value) => {
+                // The results of a call can be all the arguments, as in `g(f())`
+                let compiled_args: Vec<BasicMetadataValueEnum> = match args {
+                    [arg] if matches!(arg.get_type(), Type::Tuple(_)) => self
+                        .gen_tuple_values(arg)?
+                        .into_iter()
+                        .map(|value| value.into())
+                        .collect(),
+                    _ => {
+                        let mut compiled_args = vec![];
+                        for arg in args {
+                            compiled_args.push(self.gen_expr(arg)?.into());
+                        }
+                        compiled_args
+                    }
+                };
+                match self
+                    .builder
+                    .build_call(func_value, compiled_args.as_slice(), "calltmp")
+                    .try_as_basic_value()
+                    .left()
+                {
+                    Some(value) => Ok(value),
+                    // Because we got to return something from gen_expr, we return the
+                    // magic number; It isn't used, so nothing lost there
+                    None => Ok(BasicValueEnum::IntValue(
+                        self.context.bool_type().const_int(1, true),
+                    )),
+                }
+            }
+            None => Err(CodeGenError {
+                msg: "undefined function passed to codegen (should have been caught by semantic checker)",
+                span,
+            }),
+        }
+    }
+
+    /// Generates `print(args)`, or `println(args)` if `newline` is set (which also separates the
+    /// arguments with spaces). Each argument is printed by the runtime function for its type:
+    /// integers are widened to 64 bits, `float32`s to `float64`s, slices print their length,
+    /// capacity and address, pointers and maps their address, and errors the addresses of their
+    /// type and value
+    fn gen_print(
+        &self,
+        args: &[Expression],
+        newline: bool,
+    ) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
+        let print_string = |string: &str| {
+            let string = self.const_string(string.as_bytes(), "str");
+            self.build_print("__print_gostring", string.into());
+        };
+        for (idx, arg) in args.iter().enumerate() {
+            if newline && idx > 0 {
+                print_string(" ");
+            }
+            let ty = arg.get_type();
+            if let Type::Slice(_) = ty {
+                let slice = self.gen_expr(arg)?.into_struct_value();
+                let (ptr, len, cap) = self.build_slice_fields(slice);
+                let byte_ptr_type = self.context.i8_type().ptr_type(AddressSpace::Generic);
+                let ptr = self.builder.build_pointer_cast(ptr, byte_ptr_type, "ptr");
+                self.builder.build_call(
+                    self.module.get_function("__print_slice").unwrap(),
+                    &[ptr.into(), len.into(), cap.into()],
+                    "print",
+                );
+                continue;
+            }
+            if let Type::Pointer(_) | Type::Map(..) | Type::Error = ty {
+                let ptr = self.gen_expr(arg)?.into_pointer_value();
+                let byte_ptr_type = self.context.i8_type().ptr_type(AddressSpace::Generic);
+                let ptr = self.builder.build_pointer_cast(ptr, byte_ptr_type, "ptr");
+                let func = if *ty == Type::Error {
+                    "__print_error"
+                } else {
+                    "__print_pointer"
+                };
+                self.build_print(func, ptr.into());
+                continue;
+            }
+            let (func, to) = match ty {
+                Type::Bool => ("__print_bool", Type::Bool),
+                Type::GoString => ("__print_gostring", Type::GoString),
+                _ if ty.is_unsigned() => ("__print_uint", Type::Uint64),
+                _ if ty.is_integer() => ("__print_int", Type::Int64),
+                _ => ("__print_float64", Type::Float64),
+            };
+            let value = self.gen_expr(arg)?;
+            let value = self.gen_conversion(value, ty, &to, arg.span())?;
+            self.build_print(func, value);
+        }
+        if newline {
+            print_string("\n");
+        }
+        // Like calls of functions without a result, this returns a value that's never used
+        Ok(BasicValueEnum::IntValue(
+            self.context.bool_type().const_int(1, true),
+        ))
+    }
+
+    fn build_print(&self, func: &str, value: BasicValueEnum<'ctx>) {
+        self.builder
+            .build_call(self.module.get_function(func).unwrap(), &[value.into()], "print");
+    }
+
+    /// Generates a call of a function of the `fmt` package. The arguments after the format string
+    /// are passed to the runtime as an array of `FmtArg`s and its length
+    fn gen_fmt_call(
+        &self,
+        func: FmtFunc,
+        args: &[Expression],
+    ) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
+        let mut call_args: Vec<BasicMetadataValueEnum> = vec![];
+        let mut args = args;
+        if func.has_format() {
+            call_args.push(self.gen_expr(&args[0])?.into());
+            args = &args[1..];
+        }
+        let i64_type = self.context.i64_type();
+        let zero = i64_type.const_zero();
+        let arg_type = fmt_arg_type(self.context);
+        let array = self.build_entry_alloca(
+            arg_type.array_type(args.len() as u32).into(),
+            "fmt_args",
+        );
+        for (idx, arg) in args.iter().enumerate() {
+            let ty = arg.get_type();
+            let value = self.gen_expr(arg)?;
+            let no_string = Type::GoString
+                .to_llvm(self.context)
+                .into_struct_type()
+                .const_zero()
+                .into();
+            let (kind, bits, string) = match ty {
+                Type::GoString => (FMT_STRING, zero, value),
+                Type::Bool => {
+                    let bits = self.builder.build_int_z_extend(
+                        value.into_int_value(),
+                        i64_type,
+                        "bits",
+                    );
+                    (FMT_BOOL, bits, no_string)
+                }
+                Type::Pointer(_) | Type::Error => {
+                    let bits = self.builder.build_ptr_to_int(
+                        value.into_pointer_value(),
+                        i64_type,
+                        "bits",
+                    );
+                    let kind = if *ty == Type::Error {
+                        FMT_ERROR
+                    } else {
+                        FMT_POINTER
+                    };
+                    (kind, bits, no_string)
+                }
+                _ if ty.is_integer() => {
+                    let (kind, to) = if ty.is_unsigned() {
+                        (FMT_UINT, Type::Uint64)
+                    } else {
+                        (FMT_INT, Type::Int64)
+                    };
+                    let bits = self.gen_conversion(value, ty, &to, arg.span())?;
+                    (kind, bits.into_int_value(), no_string)
+                }
+                _ => {
+                    let kind = if *ty == Type::Float32 {
+                        FMT_FLOAT32
+                    } else {
+                        FMT_FLOAT64
+                    };
+                    let float = self.gen_conversion(value, ty, &Type::Float64, arg.span())?;
+                    let bits = self.builder.build_bitcast(float, i64_type, "bits");
+                    (kind, bits.into_int_value(), no_string)
+                }
+            };
+            let type_name = self.const_string(format_type(ty).as_bytes(), "type_name");
+            let fields: [BasicValueEnum; 4] = [
+                i64_type.const_int(kind, false).into(),
+                bits.into(),
+                string,
+                type_name.into(),
+            ];
+            let mut fmt_arg = arg_type.get_undef();
+            for (field_idx, field) in fields.into_iter().enumerate() {
+                fmt_arg = self
+                    .builder
+                    .build_insert_value(fmt_arg, field, field_idx as u32, "fmt_arg")
+                    .unwrap()
+                    .into_struct_value();
+            }
+            let idx = i64_type.const_int(idx as u64, false);
+            let ptr = unsafe {
+                self.builder
+                    .build_in_bounds_gep(array, &[zero, idx], "fmt_arg_ptr")
+            };
+            self.builder.build_store(ptr, fmt_arg);
+        }
+        let first = unsafe {
+            self.builder
+                .build_in_bounds_gep(array, &[zero, zero], "fmt_args")
+        };
+        call_args.push(first.into());
+        call_args.push(i64_type.const_int(args.len() as u64, false).into());
+        let runtime_func = format!("__fmt_{}", func.name().to_lowercase());
+        let result = self.builder.build_call(
+            self.module.get_function(&runtime_func).unwrap(),
+            &call_args,
+            "fmt",
+        );
+        let result_type = if func == FmtFunc::Errorf {
+            Type::Error
+        } else {
+            Type::GoString
+        };
+        // The print functions have no result, so like calls of other functions without one, they
+        // return a value that's never used
+        Ok(match result.try_as_basic_value().left() {
+            Some(value) => self.root_value(value, &result_type),
+            None => BasicValueEnum::IntValue(self.context.bool_type().const_int(1, true)),
+        })
+    }
+
+    fn gen_if(
+        &mut self,
+        cond: &Expression,
+        then_block: &[Statement],
+        else_block: &[Statement],
+    ) -> Result<(), CodeGenError> {
+        let parent = self.current_function.unwrap();
+
+        let llvm_cond = self.gen_expr(cond)?.into_int_value();
+
+        let then_bb = self.context.append_basic_block(parent, "then_bb");
+        let else_bb = self.context.append_basic_block(parent, "else_bb");
+        let cont_bb = self.context.append_basic_block(parent, "cont_bb");
+
+        self.builder
+            .build_conditional_branch(llvm_cond, then_bb, else_bb);
 
         // Then block
         self.builder.position_at_end(then_bb);
         self.gen_block(then_block)?;
-        self.builder.build_unconditional_branch(cont_bb);
+        if !self.block_terminated() {
+            self.builder.build_unconditional_branch(cont_bb);
+        }
 
         // Else block
         self.builder.position_at_end(else_bb);
         self.gen_block(else_block)?;
-        self.builder.build_unconditional_branch(cont_bb);
+        if !self.block_terminated() {
+            self.builder.build_unconditional_branch(cont_bb);
+        }
 
         // Merge/continuation block
         self.builder.position_at_end(cont_bb);
         Ok(())
     }
+
+    /// Generates `lhs / rhs` or `lhs % rhs` for integers (the operation at `span`), where `divisor`
+    /// is the value of `rhs` if it's a constant. Dividing by zero panics, and unlike LLVM's `sdiv`
+    /// and `srem`, the smallest signed integer divided by -1 is defined: the quotient wraps around
+    /// (to the dividend itself) and the remainder is 0
+    fn build_int_division(
+        &self,
+        op: &BinaryOp,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+        divisor: Option<&Value>,
+        unsigned: bool,
+        span: Span,
+    ) -> IntValue<'ctx> {
+        let int_type = rhs.get_type();
+        // Divisors that are constants (which the type checker rejects if they're zero), or known
+        // not to be zero, aren't checked, so hot loops don't pay for the check
+        if self.checks.is_needed(span, Check::DivideByZero) {
+            let is_not_div_by_zero = self.builder.build_int_compare(
+                IntPredicate::NE,
+                rhs,
+                int_type.const_zero(),
+                "is_not_div_by_zero",
+            );
+            self.build_runtime_check(is_not_div_by_zero, ERR_DIV_BY_ZERO, "div_by_zero");
+        }
+        if unsigned {
+            return match op {
+                Div => self.builder.build_int_unsigned_div(lhs, rhs, "divtmp"),
+                _ => self.builder.build_int_unsigned_rem(lhs, rhs, "remtmp"),
+            };
+        }
+        match (op, divisor.and_then(Value::to_i64)) {
+            // x / -1 is -x (which wraps around for the smallest integer), and x % -1 is 0
+            (Div, Some(-1)) => self.builder.build_int_neg(lhs, "divtmp"),
+            (_, Some(-1)) => int_type.const_zero(),
+            (Div, Some(_)) => self.builder.build_int_signed_div(lhs, rhs, "divtmp"),
+            (_, Some(_)) => self.builder.build_int_signed_rem(lhs, rhs, "remtmp"),
+            (_, None) => {
+                // Divide by 1 instead of -1 so that the overflowing division never happens, and
+                // negate the quotient afterwards
+                let is_minus_one = self.builder.build_int_compare(
+                    IntPredicate::EQ,
+                    rhs,
+                    int_type.const_all_ones(),
+                    "is_minus_one",
+                );
+                let safe_rhs = self
+                    .builder
+                    .build_select(is_minus_one, int_type.const_int(1, false), rhs, "divisor")
+                    .into_int_value();
+                match op {
+                    Div => {
+                        let quotient = self.builder.build_int_signed_div(lhs, safe_rhs, "divtmp");
+                        let negated = self.builder.build_int_neg(lhs, "negtmp");
+                        self.builder
+                            .build_select(is_minus_one, negated, quotient, "divtmp")
+                            .into_int_value()
+                    }
+                    // x % 1 is 0, like x % -1
+                    _ => self.builder.build_int_signed_rem(lhs, safe_rhs, "remtmp"),
+                }
+            }
+        }
+    }
+
+    /// Shifts `lhs` by `rhs` (any unsigned integer). Unlike in LLVM, shifting by the width of `lhs`
+    /// or more is defined: all the bits are shifted out, so the result is 0, or -1 for a right
+    /// shift of a negative signed integer
+    fn build_shift(
+        &self,
+        op: &BinaryOp,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+        unsigned: bool,
+    ) -> IntValue<'ctx> {
+        let i64_type = self.context.i64_type();
+        let lhs_type = lhs.get_type();
+        let bits = lhs_type.get_bit_width() as u64;
+        let count = if rhs.get_type().get_bit_width() < 64 {
+            self.builder.build_int_z_extend(rhs, i64_type, "count")
+        } else {
+            rhs
+        };
+        let in_range = self.builder.build_int_compare(
+            IntPredicate::ULT,
+            count,
+            i64_type.const_int(bits, false),
+            "in_range",
+        );
+        // A right shift of a signed integer by `bits - 1` already fills it with its sign bit
+        let count = self
+            .builder
+            .build_select(in_range, count, i64_type.const_int(bits - 1, false), "count")
+            .into_int_value();
+        let count = if bits < 64 {
+            self.builder.build_int_truncate(count, lhs_type, "count")
+        } else {
+            count
+        };
+        match op {
+            Shr if !unsigned => self.builder.build_right_shift(lhs, count, true, "shrtmp"),
+            _ => {
+                let shifted = match op {
+                    Shl => self.builder.build_left_shift(lhs, count, "shltmp"),
+                    _ => self.builder.build_right_shift(lhs, count, false, "shrtmp"),
+                };
+                self.builder
+                    .build_select(in_range, shifted, lhs_type.const_zero(), "shifttmp")
+                    .into_int_value()
+            }
+        }
+    }
+
+    /// Converts `value` from type `from` to type `to` (numeric types, or the same type). Integers
+    /// are truncated or extended according to the signedness of `from`
+    fn gen_conversion(
+        &self,
+        value: BasicValueEnum<'ctx>,
+        from: &Type,
+        to: &Type,
+        span: Span,
+    ) -> Result<BasicValueEnum<'ctx>, CodeGenError> {
+        let to_llvm = to.to_llvm(self.context);
+        Ok(match value {
+            _ if from == to => value,
+            BasicValueEnum::IntValue(int) if to.is_integer() => {
+                let (from_bits, to_bits) = (from.int_bits().unwrap(), to.int_bits().unwrap());
+                let to_llvm = to_llvm.into_int_type();
+                if to_bits < from_bits {
+                    self.builder.build_int_truncate(int, to_llvm, "convtmp")
+                } else if to_bits == from_bits {
+                    int
+                } else if from.is_unsigned() {
+                    self.builder.build_int_z_extend(int, to_llvm, "convtmp")
+                } else {
+                    self.builder.build_int_s_extend(int, to_llvm, "convtmp")
+                }
+                .into()
+            }
+            BasicValueEnum::IntValue(int) if to.is_float() => {
+                let to_llvm = to_llvm.into_float_type();
+                if from.is_unsigned() {
+                    self.builder.build_unsigned_int_to_float(int, to_llvm, "convtmp")
+                } else {
+                    self.builder.build_signed_int_to_float(int, to_llvm, "convtmp")
+                }
+                .into()
+            }
+            BasicValueEnum::FloatValue(float) if to.is_integer() => {
+                let to_llvm = to_llvm.into_int_type();
+                if to.is_unsigned() {
+                    self.builder.build_float_to_unsigned_int(float, to_llvm, "convtmp")
+                } else {
+                    self.builder.build_float_to_signed_int(float, to_llvm, "convtmp")
+                }
+                .into()
+            }
+            BasicValueEnum::FloatValue(float) if to.is_float() => self
+                .builder
+                .build_float_cast(float, to_llvm.into_float_type(), "convtmp")
+                .into(),
+            // Struct types with identical fields are distinct LLVM types, so the fields are copied
+            // one by one
+            BasicValueEnum::StructValue(from_struct) if matches!(to, Type::Named(_)) => {
+                let mut converted = to_llvm.into_struct_type().get_undef();
+                for idx in 0..from_struct.get_type().count_fields() {
+                    let field = self
+                        .builder
+                        .build_extract_value(from_struct, idx, "field")
+                        .unwrap();
+                    converted = self
+                        .builder
+                        .build_insert_value(converted, field, idx, "convtmp")
+                        .unwrap()
+                        .into_struct_value();
+                }
+                converted.into()
+            }
+            _ => {
+                return Err(CodeGenError {
+                    msg: "invalid conversion (should have been caught by the type checker)",
+                    span,
+                })
+            }
+        })
+    }
+
+    /// Generates a loop (labeled `label`, if it is) of the form:
+    ///
+    /// ```text
+    ///     <init>
+    ///     br for_cond
+    /// for_cond:
+    ///     br <cond>, for_body, for_end
+    /// for_body:
+    ///     <body>
+    ///     br for_post
+    /// for_post:
+    ///     <post>
+    ///     br for_cond
+    /// for_end:
+    /// ```
+    fn gen_for(
+        &mut self,
+        label: Option<&str>,
+        init: Option<&Statement>,
+        cond: Option<&Expression>,
+        post: Option<&Statement>,
+        body: &[Statement],
+    ) -> Result<(), CodeGenError> {
+        let parent = self.current_function.unwrap();
+        // The variables declared by the init statement are scoped to the loop
+        let outer_scope = self.symbol_table.clone();
+        if let Some(init) = init {
+            self.gen_statement(init)?;
+        }
+
+        let cond_bb = self.context.append_basic_block(parent, "for_cond");
+        let body_bb = self.context.append_basic_block(parent, "for_body");
+        let post_bb = self.context.append_basic_block(parent, "for_post");
+        let end_bb = self.context.append_basic_block(parent, "for_end");
+        self.builder.build_unconditional_branch(cond_bb);
+
+        // Condition block. Without a condition, the loop is only left through a `break` (or a
+        // `return`), so `for_end` may have no predecessors
+        self.builder.position_at_end(cond_bb);
+        match cond {
+            Some(cond) => {
+                let llvm_cond = self.gen_expr(cond)?.into_int_value();
+                self.builder
+                    .build_conditional_branch(llvm_cond, body_bb, end_bb);
+            }
+            None => {
+                self.builder.build_unconditional_branch(body_bb);
+            }
+        }
+
+        // Body block
+        self.builder.position_at_end(body_bb);
+        self.targets.push(BranchTarget {
+            label: label.map(str::to_string),
+            continue_bb: Some(post_bb),
+            break_bb: end_bb,
+        });
+        self.gen_block(body)?;
+        self.targets.pop();
+        if !self.block_terminated() {
+            self.builder.build_unconditional_branch(post_bb);
+        }
+
+        // Post block
+        self.builder.position_at_end(post_bb);
+        if let Some(post) = post {
+            self.gen_statement(post)?;
+        }
+        self.builder.build_unconditional_branch(cond_bb);
+
+        self.builder.position_at_end(end_bb);
+        self.symbol_table = outer_scope;
+        Ok(())
+    }
+
+    /// Generates a loop over the runes of a string (labeled `label`, if it is). The byte index of
+    /// the next rune is kept in a variable of its own, so assigning to `key` in the body doesn't
+    /// change the iteration. ASCII bytes are decoded inline, and other runes by the runtime:
+    ///
+    /// ```text
+    ///     <expr>
+    ///     br range_cond
+    /// range_cond:
+    ///     br <next> < len, range_decode, range_end
+    /// range_decode:
+    ///     br <byte> < 0x80, range_body, range_multibyte
+    /// range_multibyte:
+    ///     __decode_rune(<expr>, <next>)
+    ///     br range_body
+    /// range_body:
+    ///     <key>, <value>, <next> = <index>, <rune>, <next index>
+    ///     <body>
+    ///     br range_cond
+    /// range_end:
+    /// ```
+    fn gen_for_range(
+        &mut self,
+        label: Option<&str>,
+        key: Option<&Expression>,
+        value: Option<&Expression>,
+        define: bool,
+        expr: &Expression,
+        body: &[Statement],
+    ) -> Result<(), CodeGenError> {
+        if let Some(elem_type) = expr.get_type().elem() {
+            return self.gen_elements_range(label, key, value, define, expr, elem_type, body);
+        }
+        if let Type::Map(..) = expr.get_type() {
+            return self.gen_map_range(label, key, value, define, expr, body);
+        }
+        let parent = self.current_function.unwrap();
+        let i64_type = self.context.i64_type();
+        let i32_type = self.context.i32_type();
+        let string = self.gen_expr(expr)?.into_struct_value();
+        let ptr = self.build_string_ptr(string);
+        let len = self.build_string_len(string);
+        let next = self.build_entry_alloca(i64_type.into(), "range_next");
+        self.builder.build_store(next, i64_type.const_zero());
+
+        // The variables declared by the range clause are scoped to the loop
+        let outer_scope = self.symbol_table.clone();
+        let targets = self.gen_range_targets(key, value, define, [&Type::Int, &Type::Int32])?;
+
+        let cond_bb = self.context.append_basic_block(parent, "range_cond");
+        let decode_bb = self.context.append_basic_block(parent, "range_decode");
+        let multibyte_bb = self.context.append_basic_block(parent, "range_multibyte");
+        let body_bb = self.context.append_basic_block(parent, "range_body");
+        let end_bb = self.context.append_basic_block(parent, "range_end");
+        self.builder.build_unconditional_branch(cond_bb);
+
+        self.builder.position_at_end(cond_bb);
+        let idx = self.builder.build_load(next, "idx").into_int_value();
+        let in_range = self
+            .builder
+            .build_int_compare(IntPredicate::ULT, idx, len, "in_range");
+        self.builder
+            .build_conditional_branch(in_range, decode_bb, end_bb);
+
+        self.builder.position_at_end(decode_bb);
+        let byte_ptr = unsafe { self.builder.build_in_bounds_gep(ptr, &[idx], "byteptr") };
+        let byte = self.builder.build_load(byte_ptr, "byte").into_int_value();
+        let is_ascii = self.builder.build_int_compare(
+            IntPredicate::ULT,
+            byte,
+            byte.get_type().const_int(0x80, false),
+            "is_ascii",
+        );
+        let ascii_rune = self.builder.build_int_z_extend(byte, i32_type, "rune");
+        let ascii_next = self
+            .builder
+            .build_int_add(idx, i64_type.const_int(1, false), "next");
+        self.builder
+            .build_conditional_branch(is_ascii, body_bb, multibyte_bb);
+
+        self.builder.position_at_end(multibyte_bb);
+        let decoded = self
+            .build_runtime_call("__decode_rune", &[string.into(), idx.into()], "decoded")
+            .into_struct_value();
+        let multibyte_rune = self.builder.build_extract_value(decoded, 0, "rune").unwrap();
+        let multibyte_next = self.builder.build_extract_value(decoded, 1, "next").unwrap();
+        self.builder.build_unconditional_branch(body_bb);
+
+        self.builder.position_at_end(body_bb);
+        let rune = self.builder.build_phi(i32_type, "rune");
+        rune.add_incoming(&[(&ascii_rune, decode_bb), (&multibyte_rune, multibyte_bb)]);
+        let next_idx = self.builder.build_phi(i64_type, "next");
+        next_idx.add_incoming(&[(&ascii_next, decode_bb), (&multibyte_next, multibyte_bb)]);
+        self.builder.build_store(next, next_idx.as_basic_value());
+        let values = [idx.into(), rune.as_basic_value()];
+        for (target, value) in targets.into_iter().zip(values) {
+            self.build_assign(target, value);
+        }
+        self.targets.push(BranchTarget {
+            label: label.map(str::to_string),
+            continue_bb: Some(cond_bb),
+            break_bb: end_bb,
+        });
+        self.gen_block(body)?;
+        self.targets.pop();
+        if !self.block_terminated() {
+            self.builder.build_unconditional_branch(cond_bb);
+        }
+
+        self.builder.position_at_end(end_bb);
+        self.symbol_table = outer_scope;
+        Ok(())
+    }
+
+    /// Generates a loop over the elements of an array or slice (labeled `label`, if it is). Like
+    /// for strings, the index of the next element is kept in a variable of its own. The range
+    /// expression is evaluated once, and an array is copied (unless only the index is needed, in
+    /// which case it's only evaluated for the calls in it):
+    ///
+    /// ```text
+    ///     <expr>
+    ///     br range_cond
+    /// range_cond:
+    ///     br <idx> < len, range_body, range_end
+    /// range_body:
+    ///     <key>, <value> = <idx>, <expr>[<idx>]
+    ///     <body>
+    ///     br range_post
+    /// range_post:
+    ///     <idx> = <idx> + 1
+    ///     br range_cond
+    /// range_end:
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    fn gen_elements_range(
+        &mut self,
+        label: Option<&str>,
+        key: Option<&Expression>,
+        value: Option<&Expression>,
+        define: bool,
+        expr: &Expression,
+        elem_type: &Type,
+        body: &[Statement],
+    ) -> Result<(), CodeGenError> {
+        let parent = self.current_function.unwrap();
+        let i64_type = self.context.i64_type();
+        let zero = i64_type.const_zero();
+        let needs_value =
+            value.is_some_and(|value| {
+                !matches!(value, Expression::Name { name, .. } if name == "_")
+            });
+        // The elements are read through a pointer to the first one
+        let (ptr, len) = match expr.get_type() {
+            Type::Array(len, _) => {
+                let len = i64_type.const_int(*len, false);
+                if needs_value {
+                    let array = self.gen_expr(expr)?;
+                    let copy = self.build_entry_alloca(array.get_type(), "range_array");
+                    self.builder.build_store(copy, array);
+                    let ptr =
+                        unsafe { self.builder.build_in_bounds_gep(copy, &[zero, zero], "ptr") };
+                    (Some(ptr), len)
+                } else {
+                    if expr.contains_call() {
+                        self.gen_expr(expr)?;
+                    }
+                    (None, len)
+                }
+            }
+            _ => {
+                let slice = self.gen_expr(expr)?.into_struct_value();
+                let (ptr, len, _) = self.build_slice_fields(slice);
+                (Some(ptr), len)
+            }
+        };
+        let next = self.build_entry_alloca(i64_type.into(), "range_next");
+        self.builder.build_store(next, zero);
+
+        let cond_bb = self.context.append_basic_block(parent, "range_cond");
+        let body_bb = self.context.append_basic_block(parent, "range_body");
+        let post_bb = self.context.append_basic_block(parent, "range_post");
+        let end_bb = self.context.append_basic_block(parent, "range_end");
+        self.builder.build_unconditional_branch(cond_bb);
+
+        self.builder.position_at_end(cond_bb);
+        let idx = self.builder.build_load(next, "idx").into_int_value();
+        let in_range = self
+            .builder
+            .build_int_compare(IntPredicate::ULT, idx, len, "in_range");
+        self.builder.build_conditional_branch(in_range, body_bb, end_bb);
+
+        // The variables declared by the range clause are scoped to the loop, and are new in each
+        // iteration (which matters for sliced arrays)
+        self.builder.position_at_end(body_bb);
+        let outer_scope = self.symbol_table.clone();
+        let targets = self.gen_range_targets(key, value, define, [&Type::Int, elem_type])?;
+        let mut values = vec![idx.into()];
+        if let (Some(ptr), true) = (ptr, needs_value) {
+            let elem_ptr = unsafe { self.builder.build_in_bounds_gep(ptr, &[idx], "elemptr") };
+            values.push(self.builder.build_load(elem_ptr, "elem"));
+        }
+        for (target, value) in targets.into_iter().zip(values) {
+            self.build_assign(target, value);
+        }
+        self.targets.push(BranchTarget {
+            label: label.map(str::to_string),
+            continue_bb: Some(post_bb),
+            break_bb: end_bb,
+        });
+        self.gen_block(body)?;
+        self.targets.pop();
+        if !self.block_terminated() {
+            self.builder.build_unconditional_branch(post_bb);
+        }
+
+        self.builder.position_at_end(post_bb);
+        let next_idx = self
+            .builder
+            .build_int_add(idx, i64_type.const_int(1, false), "next");
+        self.builder.build_store(next, next_idx);
+        self.builder.build_unconditional_branch(cond_bb);
+
+        self.builder.position_at_end(end_bb);
+        self.symbol_table = outer_scope;
+        Ok(())
+    }
+
+    /// Generates a loop over the entries of a map (labeled `label`, if it is). The runtime keeps
+    /// the state of the iteration, which is in a root since it's on the heap (it's null for an
+    /// empty map):
+    ///
+    /// ```text
+    ///     <iter> = __go_mapiterinit(<expr>)
+    ///     br range_cond
+    /// range_cond:
+    ///     <entry> = __go_mapiternext(<iter>)
+    ///     br <entry> != null, range_body, range_end
+    /// range_body:
+    ///     <key>, <value> = <entry>.key, <entry>.value
+    ///     <body>
+    ///     br range_cond
+    /// range_end:
+    /// ```
+    fn gen_map_range(
+        &mut self,
+        label: Option<&str>,
+        key: Option<&Expression>,
+        value: Option<&Expression>,
+        define: bool,
+        expr: &Expression,
+        body: &[Statement],
+    ) -> Result<(), CodeGenError> {
+        let parent = self.current_function.unwrap();
+        let map_type = expr.get_type();
+        let map = self.gen_expr(expr)?;
+        let iter_type = Type::Pointer(Box::new(Type::Uint8));
+        let iter = self.build_root(&iter_type, "range_iter");
+        let first = self.build_runtime_call("__go_mapiterinit", &[map.into()], "iter");
+        self.builder.build_store(iter, first);
+
+        let cond_bb = self.context.append_basic_block(parent, "range_cond");
+        let body_bb = self.context.append_basic_block(parent, "range_body");
+        let end_bb = self.context.append_basic_block(parent, "range_end");
+        self.builder.build_unconditional_branch(cond_bb);
+
+        self.builder.position_at_end(cond_bb);
+        let it = self.builder.build_load(iter, "it");
+        let entry = self
+            .build_runtime_call("__go_mapiternext", &[it.into()], "entry")
+            .into_pointer_value();
+        let has_next = self.builder.build_is_not_null(entry, "has_next");
+        self.builder.build_conditional_branch(has_next, body_bb, end_bb);
+
+        // The variables declared by the range clause are scoped to the loop, and are new in each
+        // iteration
+        self.builder.position_at_end(body_bb);
+        let outer_scope = self.symbol_table.clone();
+        let (key_type, value_type) = match map_type {
+            Type::Map(key_type, value_type) => (&**key_type, &**value_type),
+            _ => unreachable!("map range over a non-map type"),
+        };
+        let targets = self.gen_range_targets(key, value, define, [key_type, value_type])?;
+        let entry_type = self.map_entry_type(map_type);
+        let entry = self.builder.build_pointer_cast(
+            entry,
+            entry_type.ptr_type(AddressSpace::Generic),
+            "entry",
+        );
+        let mut values = vec![];
+        for (idx, name) in [(1, "key"), (2, "value")] {
+            let ptr = self.builder.build_struct_gep(entry, idx, name).unwrap();
+            values.push(self.builder.build_load(ptr, name));
+        }
+        for (target, value) in targets.into_iter().zip(values) {
+            self.build_assign(target, value);
+        }
+        self.targets.push(BranchTarget {
+            label: label.map(str::to_string),
+            continue_bb: Some(cond_bb),
+            break_bb: end_bb,
+        });
+        self.gen_block(body)?;
+        self.targets.pop();
+        if !self.block_terminated() {
+            self.builder.build_unconditional_branch(cond_bb);
+        }
+
+        self.builder.position_at_end(end_bb);
+        self.symbol_table = outer_scope;
+        Ok(())
+    }
+
+    /// The targets of the key and the value of a range clause, which are declared (with the types
+    /// `types`) if `define` is set. A missing variable is blank
+    fn gen_range_targets(
+        &mut self,
+        key: Option<&Expression>,
+        value: Option<&Expression>,
+        define: bool,
+        types: [&Type; 2],
+    ) -> Result<Vec<AssignTarget<'ctx>>, CodeGenError> {
+        let mut targets = vec![];
+        for (var, ty) in [key, value].into_iter().zip(types) {
+            let target = match var {
+                Some(Expression::Name { name, span, .. }) if define && name != "_" => {
+                    let ptr = self.build_var(ty, name, *span);
+                    self.symbol_table.insert(name.clone(), ptr);
+                    AssignTarget::Ptr(ptr)
+                }
+                Some(var) if !define => self.gen_assign_target(var)?,
+                _ => AssignTarget::Blank,
+            };
+            targets.push(target);
+        }
+        Ok(targets)
+    }
+
+    /// Generates a switch (labeled `label`, if it is). The tag is evaluated once, and then matched
+    /// against the cases in order. When the tag is an integer and all the cases are constants
+    /// (the semantic checker folds them into literals), this is a single LLVM `switch`:
+    ///
+    /// ```text
+    ///     <init>
+    ///     switch <tag>, default_or_end [<value>, case, ...]
+    /// case:
+    ///     <body>
+    ///     br switch_end           ; or the next case for a `fallthrough`
+    /// ...
+    /// switch_end:
+    /// ```
+    ///
+    /// and otherwise a chain of comparisons, which branches to a case as soon as one of its
+    /// values is equal to the tag
+    fn gen_switch(
+        &mut self,
+        label: Option<&str>,
+        init: Option<&Statement>,
+        tag: Option<&Expression>,
+        cases: &[CaseClause],
+    ) -> Result<(), CodeGenError> {
+        let parent = self.current_function.unwrap();
+        // The variables declared by the init statement are scoped to the switch
+        let outer_scope = self.symbol_table.clone();
+        if let Some(init) = init {
+            self.gen_statement(init)?;
+        }
+        // A switch without a tag switches on `true`
+        let (tag_value, tag_type) = match tag {
+            Some(tag) => (self.gen_expr(tag)?, tag.get_type()),
+            None => (self.context.bool_type().const_int(1, false).into(), &Type::Bool),
+        };
+
+        let case_bbs: Vec<BasicBlock> = cases
+            .iter()
+            .map(|case| match case.exprs {
+                Some(_) => self.context.append_basic_block(parent, "switch_case"),
+                None => self.context.append_basic_block(parent, "switch_default"),
+            })
+            .collect();
+        let end_bb = self.context.append_basic_block(parent, "switch_end");
+        // Where to go when no case matches
+        let default_bb = cases
+            .iter()
+            .position(|case| case.exprs.is_none())
+            .map_or(end_bb, |idx| case_bbs[idx]);
+        let values = cases
+            .iter()
+            .zip(&case_bbs)
+            .flat_map(|(case, bb)| case.exprs.iter().flatten().map(move |expr| (expr, *bb)));
+
+        let all_constants = cases
+            .iter()
+            .flat_map(|case| case.exprs.iter().flatten())
+            .all(|expr| matches!(expr, Expression::Literal { .. }));
+        if tag.is_some_and(|tag| tag.get_type().is_integer()) && all_constants {
+            let mut llvm_cases = vec![];
+            for (expr, bb) in values {
+                llvm_cases.push((self.gen_expr(expr)?.into_int_value(), bb));
+            }
+            self.builder
+                .build_switch(tag_value.into_int_value(), default_bb, &llvm_cases);
+        } else {
+            for (expr, bb) in values {
+                let value = self.gen_expr(expr)?;
+                let is_equal = self.build_eq(tag_value, value, tag_type, expr.span())?;
+                let next_bb = self.context.append_basic_block(parent, "switch_next");
+                self.builder.build_conditional_branch(is_equal, bb, next_bb);
+                self.builder.position_at_end(next_bb);
+            }
+            self.builder.build_unconditional_branch(default_bb);
+        }
+
+        self.targets.push(BranchTarget {
+            label: label.map(str::to_string),
+            continue_bb: None,
+            break_bb: end_bb,
+        });
+        for (idx, case) in cases.iter().enumerate() {
+            self.builder.position_at_end(case_bbs[idx]);
+            let (next_bb, body) = match case.body.split_last() {
+                Some((Statement::Fallthrough { .. }, body)) => (case_bbs.get(idx + 1), body),
+                _ => (None, &case.body[..]),
+            };
+            self.gen_block(body)?;
+            if !self.block_terminated() {
+                self.builder
+                    .build_unconditional_branch(*next_bb.unwrap_or(&end_bb));
+            }
+        }
+        self.targets.pop();
+
+        self.builder.position_at_end(end_bb);
+        self.symbol_table = outer_scope;
+        Ok(())
+    }
+
+    /// Compares two values of the type `ty` for equality. Structs are equal if their fields
+    /// (other than the blank ones) are, slices (which can only be compared to nil) if they point
+    /// to the same array, and maps (likewise) if they're the same map
+    fn build_eq(
+        &self,
+        lhs: BasicValueEnum<'ctx>,
+        rhs: BasicValueEnum<'ctx>,
+        ty: &Type,
+        span: Span,
+    ) -> Result<IntValue<'ctx>, CodeGenError> {
+        match (lhs, rhs) {
+            (BasicValueEnum::IntValue(lhs), BasicValueEnum::IntValue(rhs)) => Ok(self
+                .builder
+                .build_int_compare(IntPredicate::EQ, lhs, rhs, "eqtmp")),
+            (BasicValueEnum::FloatValue(lhs), BasicValueEnum::FloatValue(rhs)) => Ok(self
+                .builder
+                .build_float_compare(FloatPredicate::OEQ, lhs, rhs, "eqtmp")),
+            (BasicValueEnum::PointerValue(lhs), BasicValueEnum::PointerValue(rhs)) => {
+                Ok(self.build_ptr_eq(lhs, rhs))
+            }
+            (BasicValueEnum::StructValue(lhs), BasicValueEnum::StructValue(rhs))
+                if *ty == Type::GoString =>
+            {
+                Ok(self.build_string_compare(IntPredicate::EQ, lhs, rhs))
+            }
+            (BasicValueEnum::StructValue(lhs), BasicValueEnum::StructValue(rhs))
+                if matches!(ty, Type::Slice(_)) =>
+            {
+                let (lhs_ptr, _, _) = self.build_slice_fields(lhs);
+                let (rhs_ptr, _, _) = self.build_slice_fields(rhs);
+                Ok(self.build_ptr_eq(lhs_ptr, rhs_ptr))
+            }
+            (BasicValueEnum::StructValue(lhs), BasicValueEnum::StructValue(rhs)) => {
+                let name = match ty {
+                    Type::Named(name) => name,
+                    _ => {
+                        return Err(CodeGenError {
+                            msg: "comparison of unsupported types (should have been caught by the type checker)",
+                            span,
+                        })
+                    }
+                };
+                let mut is_equal = self.context.bool_type().const_int(1, false);
+                for (idx, field) in self.structs[name].iter().enumerate() {
+                    if field.name == "_" {
+                        continue;
+                    }
+                    let mut fields = vec![];
+                    for value in [lhs, rhs] {
+                        let field = self.builder.build_extract_value(value, idx as u32, "field");
+                        fields.push(field.unwrap());
+                    }
+                    let field_eq = self.build_eq(fields[0], fields[1], &field.field_type, span)?;
+                    is_equal = self.builder.build_and(is_equal, field_eq, "struct_eq");
+                }
+                Ok(is_equal)
+            }
+            (BasicValueEnum::ArrayValue(lhs), BasicValueEnum::ArrayValue(rhs)) => {
+                let elem_type = ty.elem().unwrap();
+                self.build_array_eq(lhs, rhs, elem_type, span)
+            }
+            _ => Err(CodeGenError {
+                msg: "comparison of unsupported types (should have been caught by the type checker)",
+                span,
+            }),
+        }
+    }
+
+    /// Whether two pointers hold the same address
+    fn build_ptr_eq(&self, lhs: PointerValue<'ctx>, rhs: PointerValue<'ctx>) -> IntValue<'ctx> {
+        let i64_type = self.context.i64_type();
+        let lhs = self.builder.build_ptr_to_int(lhs, i64_type, "lhs_addr");
+        let rhs = self.builder.build_ptr_to_int(rhs, i64_type, "rhs_addr");
+        self.builder
+            .build_int_compare(IntPredicate::EQ, lhs, rhs, "eqtmp")
+    }
+
+    /// Compares two arrays element by element, stopping at the first elements that differ:
+    ///
+    /// ```text
+    ///     br array_eq_cond
+    /// array_eq_cond:
+    ///     <idx> = phi [0, ...], [<idx> + 1, array_eq_next]
+    ///     br <idx> < len, array_eq_body, array_eq_end
+    /// array_eq_body:
+    ///     br <lhs>[<idx>] == <rhs>[<idx>], array_eq_next, array_eq_end
+    /// array_eq_next:
+    ///     br array_eq_cond
+    /// array_eq_end:
+    ///     phi [true, array_eq_cond], [false, array_eq_body]
+    /// ```
+    fn build_array_eq(
+        &self,
+        lhs: ArrayValue<'ctx>,
+        rhs: ArrayValue<'ctx>,
+        elem_type: &Type,
+        span: Span,
+    ) -> Result<IntValue<'ctx>, CodeGenError> {
+        let parent = self.current_function.unwrap();
+        let i64_type = self.context.i64_type();
+        let bool_type = self.context.bool_type();
+        let zero = i64_type.const_zero();
+        // Elements at a variable index can only be read through a pointer
+        let mut ptrs = vec![];
+        for (array, name) in [(lhs, "lhs_array"), (rhs, "rhs_array")] {
+            let ptr = self.build_entry_alloca(array.get_type().into(), name);
+            self.builder.build_store(ptr, array);
+            ptrs.push(ptr);
+        }
+        let entry_bb = self.builder.get_insert_block().unwrap();
+        let cond_bb = self.context.append_basic_block(parent, "array_eq_cond");
+        let body_bb = self.context.append_basic_block(parent, "array_eq_body");
+        let next_bb = self.context.append_basic_block(parent, "array_eq_next");
+        let end_bb = self.context.append_basic_block(parent, "array_eq_end");
+        self.builder.build_unconditional_branch(cond_bb);
+
+        self.builder.position_at_end(cond_bb);
+        let idx = self.builder.build_phi(i64_type, "idx");
+        let len = i64_type.const_int(lhs.get_type().len() as u64, false);
+        let in_range = self.builder.build_int_compare(
+            IntPredicate::ULT,
+            idx.as_basic_value().into_int_value(),
+            len,
+            "in_range",
+        );
+        self.builder.build_conditional_branch(in_range, body_bb, end_bb);
+
+        self.builder.position_at_end(body_bb);
+        let mut elems = vec![];
+        for ptr in ptrs {
+            let indices = [zero, idx.as_basic_value().into_int_value()];
+            let elem_ptr = unsafe { self.builder.build_in_bounds_gep(ptr, &indices, "elemptr") };
+            elems.push(self.builder.build_load(elem_ptr, "elem"));
+        }
+        let is_equal = self.build_eq(elems[0], elems[1], elem_type, span)?;
+        // Comparing the elements may have added blocks (e.g. for arrays of arrays)
+        let body_end_bb = self.builder.get_insert_block().unwrap();
+        self.builder.build_conditional_branch(is_equal, next_bb, end_bb);
+
+        self.builder.position_at_end(next_bb);
+        let next_idx = self.builder.build_int_add(
+            idx.as_basic_value().into_int_value(),
+            i64_type.const_int(1, false),
+            "next_idx",
+        );
+        self.builder.build_unconditional_branch(cond_bb);
+        idx.add_incoming(&[(&zero, entry_bb), (&next_idx, next_bb)]);
+
+        self.builder.position_at_end(end_bb);
+        let result = self.builder.build_phi(bool_type, "array_eq");
+        result.add_incoming(&[
+            (&bool_type.const_int(1, false), cond_bb),
+            (&bool_type.const_zero(), body_end_bb),
+        ]);
+        Ok(result.as_basic_value().into_int_value())
+    }
+}
+
+/// The zero value of an LLVM type, all of whose bits are 0
+/// The machine code is generated for, which is the host
+fn target_machine() -> TargetMachine {
+    Target::initialize_all(&InitializationConfig::default());
+    let triple = TargetMachine::get_default_triple();
+    let target = Target::from_triple(&triple).expect("Couldn't create target from target triple");
+    target
+        .create_target_machine(
+            &triple,
+            "generic",
+            "",
+            OptimizationLevel::None,
+            RelocMode::Default,
+            CodeModel::Default,
+        )
+        .expect("Unable to create target machine")
+}
+
+fn const_zero(ty: BasicTypeEnum) -> BasicValueEnum {
+    match ty {
+        BasicTypeEnum::ArrayType(ty) => ty.const_zero().into(),
+        BasicTypeEnum::FloatType(ty) => ty.const_zero().into(),
+        BasicTypeEnum::IntType(ty) => ty.const_zero().into(),
+        BasicTypeEnum::PointerType(ty) => ty.const_zero().into(),
+        BasicTypeEnum::StructType(ty) => ty.const_zero().into(),
+        BasicTypeEnum::VectorType(ty) => ty.const_zero().into(),
+    }
 }
@@ -0,0 +1,441 @@
+//! Values of constant expressions. Go evaluates constant expressions exactly: integer constants
+//! have arbitrary precision, and floating-point constants are kept as exact fractions here, so that
+//! e.g. `1 << 100 >> 98` and `1 / 3.0 * 3` give the mathematically correct result. Values are only
+//! rounded (or rejected) when they are converted to a concrete type.
+
+use crate::ast::{BinaryOp, Type, UnaryOp};
+use crate::escape;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Shift counts of constant shifts can't exceed this (the go compiler uses the same bound)
+pub const MAX_SHIFT: u64 = 1023 - 1 + 52;
+/// Exponents of floating-point literals can't exceed this, so that they stay reasonably sized
+const MAX_EXPONENT: u64 = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    /// The bytes of a string, which need not be valid UTF-8
+    String(Vec<u8>),
+    Int(BigInt),
+    Float(BigRational),
+}
+
+/// Why a constant couldn't be converted to a type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversionError {
+    /// The constant is of the wrong kind (e.g. a string converted to an int)
+    Incompatible,
+    /// A floating-point constant with a fractional part converted to an integer type
+    Truncated,
+    /// The constant is too large for the type
+    Overflows,
+}
+
+/// Parses the text of an integer literal (e.g. `0x_1F` or `0755`)
+pub fn parse_int(lit: &str) -> Option<BigInt> {
+    let lit = lit.replace('_', "");
+    let (digits, radix) = match lit.get(..2) {
+        Some("0x" | "0X") => (&lit[2..], 16),
+        Some("0b" | "0B") => (&lit[2..], 2),
+        Some("0o" | "0O") => (&lit[2..], 8),
+        _ if lit.len() > 1 && lit.starts_with('0') => (&lit[1..], 8),
+        _ => (&lit[..], 10),
+    };
+    BigInt::parse_bytes(digits.as_bytes(), radix)
+}
+
+/// Parses the text of a floating-point literal (e.g. `1.5e3` or `0x1p-2`) into an exact fraction.
+/// Returns `None` if the exponent is too large
+pub fn parse_float(lit: &str) -> Option<BigRational> {
+    let lit = lit.replace('_', "");
+    let (lit, radix, exp_chars) = match lit.get(..2) {
+        Some("0x" | "0X") => (&lit[2..], 16, ['p', 'P']),
+        _ => (&lit[..], 10, ['e', 'E']),
+    };
+    let (mantissa, exp) = match lit.find(exp_chars) {
+        Some(idx) => (&lit[..idx], lit[idx + 1..].parse::<i64>().ok()?),
+        None => (lit, 0),
+    };
+    let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{}{}", int_part, frac_part);
+    let mantissa = BigInt::parse_bytes(digits.as_bytes(), radix)?;
+    // Each hex digit after the point is 4 bits
+    let (base, exp) = if radix == 16 {
+        (2u32, exp - 4 * frac_part.len() as i64)
+    } else {
+        (10u32, exp - frac_part.len() as i64)
+    };
+    if exp.unsigned_abs() > MAX_EXPONENT {
+        return None;
+    }
+    let scale = BigInt::from(base).pow(exp.unsigned_abs() as u32);
+    Some(if exp >= 0 {
+        BigRational::from_integer(mantissa * scale)
+    } else {
+        BigRational::new(mantissa, scale)
+    })
+}
+
+impl Value {
+    pub fn to_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int(int) => int.to_i64(),
+            _ => None,
+        }
+    }
+
+    /// The two's complement bits of an integer constant that fits in 64 bits (signed or unsigned)
+    pub fn to_bits(&self) -> Option<u64> {
+        match self {
+            Value::Int(int) => int.to_i64().map(|int| int as u64).or_else(|| int.to_u64()),
+            _ => None,
+        }
+    }
+
+    pub fn to_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(int) => int.to_f64(),
+            Value::Float(float) => float.to_f64(),
+            _ => None,
+        }
+    }
+
+    pub fn string_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::String(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Converts an integer constant into the equivalent floating-point constant
+    pub fn to_float(&self) -> Value {
+        match self {
+            Value::Int(int) => Value::Float(BigRational::from_integer(int.clone())),
+            _ => self.clone(),
+        }
+    }
+
+    /// Negates a numeric constant
+    pub fn neg(&self) -> Option<Value> {
+        match self {
+            Value::Int(int) => Some(Value::Int(-int)),
+            Value::Float(float) => Some(Value::Float(-float)),
+            _ => None,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Value::Int(int) => int.is_zero(),
+            Value::Float(float) => float.is_zero(),
+            _ => false,
+        }
+    }
+
+    /// Converts the constant to a value of type `ty`, rounding floating-point values to the
+    /// precision of the type
+    pub fn convert(&self, ty: &Type) -> Result<Value, ConversionError> {
+        match (self, ty) {
+            (Value::Bool(_), Type::Bool) | (Value::String(_), Type::GoString) => Ok(self.clone()),
+            (Value::Int(int), _) if ty.is_integer() => {
+                let (min, max) = int_range(ty);
+                if *int < min || *int > max {
+                    return Err(ConversionError::Overflows);
+                }
+                Ok(self.clone())
+            }
+            (Value::Float(float), _) if ty.is_integer() => {
+                if !float.is_integer() {
+                    return Err(ConversionError::Truncated);
+                }
+                Value::Int(float.to_integer()).convert(ty)
+            }
+            (Value::Int(_), Type::Float32 | Type::Float64) => self.to_float().convert(ty),
+            (Value::Float(float), Type::Float32) => round_float(float, 24, 127).map(Value::Float),
+            (Value::Float(float), Type::Float64) => round_float(float, 53, 1023).map(Value::Float),
+            _ => Err(ConversionError::Incompatible),
+        }
+    }
+
+    /// Formats the value as a Go literal that evaluates to the same value (exactly, unless the
+    /// value is a fraction that has no finite decimal representation)
+    pub fn literal(&self) -> String {
+        match self {
+            Value::Float(float) => {
+                // Floats rounded to a float64 usually have a long exact expansion, so those are
+                // printed with the shortest digits that round back to them instead
+                let is_f64 = |f: &f64| BigRational::from_float(*f).as_ref() == Some(float);
+                let digits = match (decimal(float), float.to_f64().filter(is_f64)) {
+                    (Some((mantissa, exp)), _) if mantissa.abs() < BigInt::from(10).pow(17) => {
+                        Some((mantissa, exp))
+                    }
+                    (_, Some(f)) => parse_float(&format!("{:e}", f)).and_then(|f| decimal(&f)),
+                    (digits, None) => digits,
+                };
+                match digits {
+                    Some((mantissa, exp)) => format_decimal(&mantissa, exp),
+                    None => format!("{:e}", float.to_f64().unwrap_or(f64::INFINITY)),
+                }
+            }
+            _ => self.to_string(),
+        }
+    }
+}
+
+/// The smallest and largest values of the integer type `ty`
+fn int_range(ty: &Type) -> (BigInt, BigInt) {
+    let bits = ty.int_bits().expect("range of a non-integer type");
+    if ty.is_unsigned() {
+        (BigInt::zero(), (BigInt::one() << bits) - 1)
+    } else {
+        let half = BigInt::one() << (bits - 1);
+        (-&half, half - 1)
+    }
+}
+
+/// Rounds `float` to the nearest floating-point number with a `precision`-bit mantissa and a
+/// maximum exponent of `max_exp` (ties to even), like IEEE 754 conversions do
+fn round_float(
+    float: &BigRational,
+    precision: u64,
+    max_exp: i64,
+) -> Result<BigRational, ConversionError> {
+    if float.is_zero() {
+        return Ok(float.clone());
+    }
+    let abs = float.abs();
+    // Find `exp` such that 2^exp <= abs < 2^(exp+1)
+    let mut exp = abs.numer().bits() as i64 - abs.denom().bits() as i64;
+    if pow2(exp) > abs {
+        exp -= 1;
+    } else if pow2(exp + 1) <= abs {
+        exp += 1;
+    }
+    // Subnormal numbers have fewer bits of precision
+    let min_exp = 1 - max_exp;
+    let shift = exp.max(min_exp) - (precision as i64 - 1);
+    let scaled = &abs / pow2(shift);
+    let floor = scaled.floor();
+    let rem = &scaled - &floor;
+    let half = BigRational::new(BigInt::one(), BigInt::from(2));
+    let mut mantissa = floor.to_integer();
+    if rem > half || (rem == half && (&mantissa % 2u32).is_one()) {
+        mantissa += 1;
+    }
+    let rounded = BigRational::from_integer(mantissa) * pow2(shift);
+    if rounded >= pow2(max_exp + 1) {
+        return Err(ConversionError::Overflows);
+    }
+    Ok(if float.is_negative() {
+        -rounded
+    } else {
+        rounded
+    })
+}
+
+/// `2^exp` as a fraction
+fn pow2(exp: i64) -> BigRational {
+    let pow = BigInt::one() << exp.unsigned_abs();
+    if exp >= 0 {
+        BigRational::from_integer(pow)
+    } else {
+        BigRational::new(BigInt::one(), pow)
+    }
+}
+
+/// Writes `float` as `mantissa * 10^exp` (with no trailing zeros in `mantissa`), if it has a finite
+/// decimal representation
+fn decimal(float: &BigRational) -> Option<(BigInt, i64)> {
+    // A fraction in lowest terms has a finite decimal representation iff its denominator only has
+    // the prime factors 2 and 5
+    let mut denom = float.denom().clone();
+    let mut exp = 0;
+    let (two, five, ten) = (BigInt::from(2), BigInt::from(5), BigInt::from(10));
+    let mut mantissa = float.numer().clone();
+    while !denom.is_one() {
+        if (&denom % &ten).is_zero() {
+            denom /= &ten;
+        } else if (&denom % &two).is_zero() {
+            denom /= &two;
+            mantissa *= &five;
+        } else if (&denom % &five).is_zero() {
+            denom /= &five;
+            mantissa *= &two;
+        } else {
+            return None;
+        }
+        exp -= 1;
+    }
+    while !mantissa.is_zero() && (&mantissa % &ten).is_zero() {
+        mantissa /= &ten;
+        exp += 1;
+    }
+    Some((mantissa, exp))
+}
+
+/// Formats `mantissa * 10^exp` as a Go floating-point literal
+fn format_decimal(mantissa: &BigInt, exp: i64) -> String {
+    let sign = if mantissa.is_negative() { "-" } else { "" };
+    let digits = mantissa.abs().to_string();
+    let len = digits.len() as i64;
+    if exp >= 0 && len + exp <= 21 {
+        format!("{}{}{}.0", sign, digits, "0".repeat(exp as usize))
+    } else if exp < 0 && -exp < len {
+        let (int, frac) = digits.split_at((len + exp) as usize);
+        format!("{}{}.{}", sign, int, frac)
+    } else if exp < 0 && -exp - len < 6 {
+        format!("{}0.{}{}", sign, "0".repeat((-exp - len) as usize), digits)
+    } else {
+        // Scientific notation, e.g. `1.5e+100`
+        let (first, rest) = digits.split_at(1);
+        let point = if rest.is_empty() { "" } else { "." };
+        format!("{}{}{}{}e{:+}", sign, first, point, rest, exp + len - 1)
+    }
+}
+
+/// Formats `float` like Go's `%.6g` verb does (which is how the go compiler prints floating-point
+/// constants in errors)
+fn format_g(float: f64) -> String {
+    const PRECISION: i32 = 6;
+    let sci = format!("{:.*e}", PRECISION as usize - 1, float);
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    if !(-4..PRECISION).contains(&exp) {
+        let mantissa = trim_fraction(mantissa);
+        format!(
+            "{}e{}{:02}",
+            mantissa,
+            if exp < 0 { '-' } else { '+' },
+            exp.abs()
+        )
+    } else {
+        let fixed = format!("{:.*}", (PRECISION - 1 - exp) as usize, float);
+        trim_fraction(&fixed).to_string()
+    }
+}
+
+/// Removes the trailing zeros of the fractional part of a number (and the point, if nothing is
+/// left after it)
+fn trim_fraction(num: &str) -> &str {
+    if num.contains('.') {
+        num.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        num
+    }
+}
+
+/// Evaluates `left op right`, where `op` isn't a shift. Both operands must be of the same kind
+/// (the semantic checker converts them), and the operator must be defined on that kind
+pub fn binary_op(left: &Value, op: &BinaryOp, right: &Value) -> Result<Value, &'static str> {
+    use BinaryOp::*;
+    if matches!(op, Div | Rem) && right.is_zero() {
+        return Err("invalid operation: division by zero");
+    }
+    Ok(match (left, right) {
+        (Value::Int(l), Value::Int(r)) => match op {
+            Add => Value::Int(l + r),
+            Sub => Value::Int(l - r),
+            Mul => Value::Int(l * r),
+            // Truncated division, like Go does for integers
+            Div => Value::Int(l / r),
+            Rem => Value::Int(l % r),
+            // Bitwise operations on negative constants work as if they were infinitely wide two's
+            // complement integers
+            And => Value::Int(l & r),
+            Or => Value::Int(l | r),
+            Xor => Value::Int(l ^ r),
+            AndNot => Value::Int(l & !r),
+            _ => Value::Bool(compare(l.cmp(r), op)),
+        },
+        (Value::Float(l), Value::Float(r)) => match op {
+            Add => Value::Float(l + r),
+            Sub => Value::Float(l - r),
+            Mul => Value::Float(l * r),
+            Div => Value::Float(l / r),
+            _ => Value::Bool(compare(l.cmp(r), op)),
+        },
+        (Value::String(l), Value::String(r)) => match op {
+            Add => Value::String([l.as_slice(), r.as_slice()].concat()),
+            _ => Value::Bool(compare(l.cmp(r), op)),
+        },
+        (Value::Bool(l), Value::Bool(r)) => match op {
+            LogicalAnd => Value::Bool(*l && *r),
+            LogicalOr => Value::Bool(*l || *r),
+            _ => Value::Bool(compare(l.cmp(r), op)),
+        },
+        _ => unreachable!("binary operation on constants of different kinds"),
+    })
+}
+
+/// Evaluates `op value`, where the operator must be defined on the kind of `value`. `ty` is the
+/// type of the constant (its default type if it's untyped): the complement of an unsigned integer
+/// flips the bits of its type, rather than giving `-value - 1`
+pub fn unary_op(op: &UnaryOp, value: &Value, ty: &Type) -> Value {
+    match (op, value) {
+        (UnaryOp::Plus, _) => value.clone(),
+        (UnaryOp::Neg, _) => value.neg().expect("negation of a non-numeric constant"),
+        (UnaryOp::Not, Value::Bool(boolean)) => Value::Bool(!boolean),
+        (UnaryOp::Complement, Value::Int(int)) if ty.is_unsigned() => {
+            let (_, max) = int_range(ty);
+            Value::Int(max ^ int)
+        }
+        (UnaryOp::Complement, Value::Int(int)) => Value::Int(!int),
+        _ => unreachable!("unary operation on a constant of the wrong kind"),
+    }
+}
+
+/// Evaluates `left << count` or `left >> count`. Right shifts round towards negative infinity
+pub fn shift(left: &BigInt, op: &BinaryOp, count: u64) -> Value {
+    Value::Int(match op {
+        BinaryOp::Shl => left << count,
+        BinaryOp::Shr => left >> count,
+        _ => unreachable!("shift with a non-shift operator"),
+    })
+}
+
+fn compare(ord: Ordering, op: &BinaryOp) -> bool {
+    match op {
+        BinaryOp::Eq => ord == Ordering::Equal,
+        BinaryOp::Neq => ord != Ordering::Equal,
+        BinaryOp::Ge => ord == Ordering::Greater,
+        BinaryOp::Le => ord == Ordering::Less,
+        BinaryOp::Geq => ord != Ordering::Less,
+        BinaryOp::Leq => ord != Ordering::Greater,
+        _ => unreachable!("comparison with a non-comparison operator"),
+    }
+}
+
+impl fmt::Display for Value {
+    /// Formats the value like the go compiler does in errors (floating-point constants are
+    /// approximated)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(boolean) => write!(f, "{}", boolean),
+            Value::String(bytes) => f.write_str(&escape::quote(bytes)),
+            Value::Int(int) => write!(f, "{}", int),
+            Value::Float(float) => match float.to_f64() {
+                Some(approx) if approx.is_finite() => f.write_str(&format_g(approx)),
+                // Too large for a float64: print the first digits of the integer part
+                _ => {
+                    let int = float.to_integer();
+                    let digits = int.abs().to_string();
+                    let (mut mantissa, mut exp) = (digits[..6].parse::<BigInt>().unwrap(), 0);
+                    while (&mantissa % 10u32).is_zero() {
+                        mantissa /= 10u32;
+                        exp += 1;
+                    }
+                    if int.is_negative() {
+                        mantissa = -mantissa;
+                    }
+                    let exp = exp + digits.len() as i64 - 6;
+                    f.write_str(&format_decimal(&mantissa, exp))
+                }
+            },
+        }
+    }
+}
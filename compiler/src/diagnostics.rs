@@ -0,0 +1,390 @@
+//! Errors (and warnings) reported by every stage of the compiler. A `Diagnostic` points at the
+//! code it's about, and can be rendered for humans (with an excerpt of the source and carets
+//! under the offending code) or as JSON for editors and other tools, in the shape `go vet -json`
+//! uses.
+
+use crate::ast::Span;
+use crate::source_map::SourceMap;
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+/// What went wrong, independently of the wording of the message. Named after the error codes of
+/// the go type checker where there is one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Code {
+    /// The source couldn't be tokenized or parsed
+    SyntaxError,
+    /// Valid Go that the compiler can't handle yet
+    Unsupported,
+    /// A bug in the compiler: something the earlier passes should have rejected
+    InternalError,
+    /// The source file couldn't be read
+    ReadError,
+    /// The executable couldn't be built: writing the object file, or building or linking the
+    /// runtime, failed
+    BuildError,
+    UndeclaredName,
+    DuplicateDecl,
+    RepeatedDecl,
+    NoNewVar,
+    UnusedVar,
+    UnusedImport,
+    InvalidBlank,
+    InvalidIota,
+    InvalidPkgUse,
+    InvalidMainDecl,
+    InvalidInitCycle,
+    InvalidConstInit,
+    WrongAssignCount,
+    WrongArgCount,
+    WrongResultCount,
+    MissingReturn,
+    MisplacedBreak,
+    MisplacedContinue,
+    MisplacedLabel,
+    MisplacedFallthrough,
+    DuplicateCase,
+    DuplicateDefault,
+    UndeclaredLabel,
+    DuplicateLabel,
+    UnusedLabel,
+    JumpOverDecl,
+    JumpIntoBlock,
+    NotAnExpr,
+    UnusedExpr,
+    InvalidCall,
+    InvalidCond,
+    MismatchedTypes,
+    IncompatibleAssign,
+    UnassignableOperand,
+    NonNumericIncDec,
+    UndefinedOp,
+    InvalidConversion,
+    NumericOverflow,
+    TruncatedFloat,
+    DivByZero,
+    InvalidShiftOperand,
+    InvalidShiftCount,
+    InvalidIndex,
+    InvalidLen,
+    SwappedSliceIndices,
+    NonIndexableOperand,
+    NonSliceableOperand,
+    InvalidRangeExpr,
+    UncalledBuiltin,
+    InvalidArrayLen,
+    BadDotDotDotSyntax,
+    NonVariadicDotDotDot,
+    InvalidDotDotDot,
+    InvalidLit,
+    InvalidLitIndex,
+    OversizeArrayLit,
+    DuplicateLitKey,
+    MissingLitKey,
+    InvalidCap,
+    InvalidAppend,
+    InvalidCopy,
+    InvalidSliceExpr,
+    NotAType,
+    InvalidDeclCycle,
+    InvalidConstType,
+    MissingFieldOrMethod,
+    AmbiguousSelector,
+    MixedStructLit,
+    InvalidLitField,
+    MissingLitField,
+    DuplicateLitField,
+    InvalidStructLit,
+    UntypedLit,
+    UntypedNilUse,
+    UnaddressableOperand,
+    InvalidIndirection,
+    IncomparableMapKey,
+    InvalidMake,
+    InvalidDelete,
+    TooManyValues,
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A secondary span, e.g. the previous declaration of a redeclared name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub span: Span,
+    pub msg: String,
+}
+
+/// A fix that can be applied mechanically: replacing the code at `span` with `replacement`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    pub msg: String,
+    pub span: Span,
+    pub replacement: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    pub msg: String,
+    /// The code the diagnostic is about
+    pub span: Span,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+impl Diagnostic {
+    pub fn error(code: Code, msg: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            msg: msg.into(),
+            span,
+            labels: vec![],
+            notes: vec![],
+            suggestions: vec![],
+        }
+    }
+
+    pub fn with_label(mut self, span: Span, msg: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            msg: msg.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_suggestion(
+        mut self,
+        msg: impl Into<String>,
+        span: Span,
+        replacement: impl Into<String>,
+    ) -> Self {
+        self.suggestions.push(Suggestion {
+            msg: msg.into(),
+            span,
+            replacement: replacement.into(),
+        });
+        self
+    }
+
+    /// Renders the diagnostic for a terminal, e.g.
+    ///
+    /// ```text
+    /// error[UndeclaredName]: undefined: y
+    ///  --> main.go:4:10
+    ///   |
+    /// 4 |     var x = y
+    ///   |             ^
+    /// ```
+    pub fn render(&self, source_map: &SourceMap) -> String {
+        let mut marks: Vec<(Span, char, &str)> = vec![(self.span, '^', "")];
+        marks.extend(
+            self.labels
+                .iter()
+                .map(|label| (label.span, '-', label.msg.as_str())),
+        );
+        marks.sort_by_key(|(span, _, _)| {
+            (span.file_id != self.span.file_id, span.file_id, span.start)
+        });
+        let gutter = marks
+            .iter()
+            .map(|(span, _, _)| {
+                let line = source_map.file(span.file_id).position(span.start).line;
+                line.to_string().len()
+            })
+            .max()
+            .unwrap_or(1);
+        let blank = " ".repeat(gutter);
+
+        let mut s = format!("{}[{}]: {}\n", self.severity, self.code, self.msg);
+        s.push_str(&format!(
+            "{}--> {}\n",
+            blank,
+            source_map.describe(self.span)
+        ));
+        s.push_str(&format!("{} |\n", blank));
+        let mut file_id = self.span.file_id;
+        for (span, mark, msg) in marks {
+            if span.file_id != file_id {
+                file_id = span.file_id;
+                s.push_str(&format!("{}::: {}\n", blank, source_map.describe(span)));
+            }
+            let file = source_map.file(span.file_id);
+            let start = file.position(span.start);
+            let text = file.line(start.line);
+            // Only the first line of a span that covers several is underlined
+            let column = (start.column - 1).min(text.len());
+            let end = (span.end - (span.start - column)).clamp(column, text.len());
+            let indent = display_width(&text[..column]);
+            let width = display_width(&text[column..end]).max(1);
+            s.push_str(&format!(
+                "{:>gutter$} | {}\n",
+                start.line,
+                text.replace('\t', "    "),
+                gutter = gutter
+            ));
+            let underline = format!(
+                "{}{} {}",
+                " ".repeat(indent),
+                mark.to_string().repeat(width),
+                msg
+            );
+            s.push_str(&format!("{} | {}\n", blank, underline.trim_end()));
+        }
+        for note in &self.notes {
+            s.push_str(&format!("{} = note: {}\n", blank, note));
+        }
+        for suggestion in &self.suggestions {
+            s.push_str(&format!("{} = help: {}\n", blank, suggestion.msg));
+        }
+        s
+    }
+}
+
+/// The number of columns `text` takes up once tabs are expanded
+fn display_width(text: &str) -> usize {
+    text.chars().map(|c| if c == '\t' { 4 } else { 1 }).sum()
+}
+
+/// Renders `diagnostics` as JSON in the shape `go vet -json` uses (diagnostics grouped by package
+/// and then by code), with the additional fields `severity`, `end`, `related` and `notes`:
+///
+/// ```text
+/// {
+///     "command-line-arguments": {
+///         "UndeclaredName": [
+///             {
+///                 "posn": "main.go:4:10",
+///                 "end": "main.go:4:11",
+///                 "severity": "error",
+///                 "message": "undefined: y",
+///                 ...
+/// ```
+pub fn to_json(diagnostics: &[Diagnostic], source_map: &SourceMap) -> String {
+    let mut by_code: BTreeMap<Code, Vec<&Diagnostic>> = BTreeMap::new();
+    for diagnostic in diagnostics {
+        by_code.entry(diagnostic.code).or_default().push(diagnostic);
+    }
+    if by_code.is_empty() {
+        return "{}".to_string();
+    }
+    let end = |span: Span| {
+        source_map.describe(Span {
+            start: span.end,
+            ..span
+        })
+    };
+    let mut s = "{\n    \"command-line-arguments\": {\n".to_string();
+    let codes: Vec<String> = by_code
+        .into_iter()
+        .map(|(code, diagnostics)| {
+            let diagnostics: Vec<String> = diagnostics
+                .into_iter()
+                .map(|diagnostic| {
+                    let related: Vec<String> = diagnostic
+                        .labels
+                        .iter()
+                        .map(|label| {
+                            format!(
+                                "{{\"posn\": {}, \"end\": {}, \"message\": {}}}",
+                                json_string(&source_map.describe(label.span)),
+                                json_string(&end(label.span)),
+                                json_string(&label.msg)
+                            )
+                        })
+                        .collect();
+                    let notes: Vec<String> =
+                        diagnostic.notes.iter().map(|note| json_string(note)).collect();
+                    let fixes: Vec<String> = diagnostic
+                        .suggestions
+                        .iter()
+                        .map(|suggestion| {
+                            let span = suggestion.span;
+                            format!(
+                                "{{\"message\": {}, \"edits\": [{{\"filename\": {}, \"start\": {}, \"end\": {}, \"new\": {}}}]}}",
+                                json_string(&suggestion.msg),
+                                json_string(&source_map.file(span.file_id).name),
+                                span.start,
+                                span.end,
+                                json_string(&suggestion.replacement)
+                            )
+                        })
+                        .collect();
+                    let fields = [
+                        format!("\"posn\": {}", json_string(&source_map.describe(diagnostic.span))),
+                        format!("\"end\": {}", json_string(&end(diagnostic.span))),
+                        format!("\"severity\": {}", json_string(&diagnostic.severity.to_string())),
+                        format!("\"message\": {}", json_string(&diagnostic.msg)),
+                        format!("\"related\": [{}]", related.join(", ")),
+                        format!("\"notes\": [{}]", notes.join(", ")),
+                        format!("\"suggested_fixes\": [{}]", fixes.join(", ")),
+                    ];
+                    format!(
+                        "            {{\n                {}\n            }}",
+                        fields.join(",\n                ")
+                    )
+                })
+                .collect();
+            format!(
+                "        {}: [\n{}\n        ]",
+                json_string(&code.to_string()),
+                diagnostics.join(",\n")
+            )
+        })
+        .collect();
+    s.push_str(&codes.join(",\n"));
+    s.push_str("\n    }\n}");
+    s
+}
+
+/// Quotes and escapes `s` as a JSON string
+fn json_string(s: &str) -> String {
+    let mut quoted = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
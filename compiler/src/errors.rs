@@ -1,3 +1,8 @@
 //! A list of runtime and compile time errors that can be raised by the go.rs compiler
 
 pub const ERR_DIV_BY_ZERO: &str = "runtime error: integer divide by zero";
+pub const ERR_NEGATIVE_SHIFT: &str = "runtime error: negative shift amount";
+pub const ERR_INDEX_OUT_OF_RANGE: &str = "runtime error: index out of range";
+pub const ERR_SLICE_BOUNDS_OUT_OF_RANGE: &str = "runtime error: slice bounds out of range";
+pub const ERR_NIL_DEREF: &str = "runtime error: invalid memory address or nil pointer dereference";
+pub const ERR_NIL_MAP: &str = "assignment to entry in nil map";
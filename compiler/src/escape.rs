@@ -0,0 +1,158 @@
+//! Escape sequences in rune and string literals (`\n`, `\x41`, `é`, ...). The lexer checks
+//! them, the parser decodes the literals that contain them, and the pretty printer escapes
+//! strings again when it prints them.
+
+/// The value of an escape sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escape {
+    /// `\x` and octal escapes, which stand for a single byte
+    Byte(u8),
+    /// The other escapes, which stand for a Unicode code point (UTF-8 encoded in strings)
+    Char(char),
+}
+
+/// An invalid escape sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscapeError {
+    pub msg: &'static str,
+    /// Byte offset of the error from the start of the escape sequence (after the backslash)
+    pub offset: usize,
+}
+
+/// Decodes the escape sequence at the start of `s`, which follows a backslash in a literal
+/// delimited by `quote` (only that quote can be escaped). Returns its value and its length in
+/// bytes
+pub fn decode(s: &str, quote: char) -> Result<(Escape, usize), EscapeError> {
+    let error = |msg, offset| Err(EscapeError { msg, offset });
+    let simple = |c| Ok((Escape::Char(c), 1));
+    let (base, count, max) = match s.chars().next() {
+        Some(c) if c == quote => return simple(c),
+        Some('a') => return simple('\x07'),
+        Some('b') => return simple('\x08'),
+        Some('f') => return simple('\x0c'),
+        Some('n') => return simple('\n'),
+        Some('r') => return simple('\r'),
+        Some('t') => return simple('\t'),
+        Some('v') => return simple('\x0b'),
+        Some('\\') => return simple('\\'),
+        Some('0'..='7') => (8, 3, 255),
+        Some('x') => (16, 2, 255),
+        Some('u') => (16, 4, char::MAX as u32),
+        Some('U') => (16, 8, char::MAX as u32),
+        Some('\n') | None => return error("escape sequence not terminated", 0),
+        Some(_) => return error("unknown escape sequence", 0),
+    };
+    // Octal escapes have no prefix
+    let prefix = usize::from(base == 16);
+    let mut value: u32 = 0;
+    for (idx, c) in s[prefix..].chars().take(count).enumerate() {
+        match c.to_digit(base) {
+            Some(digit) => value = value * base + digit,
+            None if c == quote || c == '\n' => return error("escape sequence not terminated", 0),
+            None => return error("invalid character in escape sequence", prefix + idx),
+        }
+    }
+    let len = prefix + count;
+    if s.len() < len {
+        return error("escape sequence not terminated", 0);
+    }
+    if value > max && base == 8 {
+        return error("octal escape value > 255", 0);
+    }
+    match (max, char::from_u32(value)) {
+        (255, _) => Ok((Escape::Byte(value as u8), len)),
+        // Surrogate halves aren't code points of their own
+        (_, Some(c)) => Ok((Escape::Char(c), len)),
+        (_, None) => error("escape is invalid Unicode code point", 0),
+    }
+}
+
+/// Decodes a string literal, with its quotes (or backticks, for a raw string), into its bytes.
+/// The literal must have been checked by the lexer
+pub fn unquote(lit: &str) -> Vec<u8> {
+    let contents = &lit[1..lit.len() - 1];
+    if lit.starts_with('`') {
+        // Carriage returns are discarded from raw strings, so that they don't depend on how the
+        // file's lines end
+        return contents.bytes().filter(|byte| *byte != b'\r').collect();
+    }
+    let mut bytes = vec![];
+    let mut rest = contents;
+    while let Some(backslash) = rest.find('\\') {
+        bytes.extend_from_slice(&rest.as_bytes()[..backslash]);
+        let (escape, len) = decode(&rest[backslash + 1..], '"').expect("invalid escape sequence");
+        match escape {
+            Escape::Byte(byte) => bytes.push(byte),
+            Escape::Char(c) => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+        rest = &rest[backslash + 1 + len..];
+    }
+    bytes.extend_from_slice(rest.as_bytes());
+    bytes
+}
+
+/// Decodes a rune literal, with its quotes, into the code point it stands for. The literal must
+/// have been checked by the lexer
+pub fn unquote_rune(lit: &str) -> u32 {
+    let contents = &lit[1..lit.len() - 1];
+    match contents.strip_prefix('\\') {
+        Some(escape) => match decode(escape, '\'').expect("invalid escape sequence") {
+            (Escape::Byte(byte), _) => byte.into(),
+            (Escape::Char(c), _) => c.into(),
+        },
+        None => contents.chars().next().expect("empty rune literal").into(),
+    }
+}
+
+/// Quotes `bytes` as an interpreted string literal, escaping the quote, backslashes, control
+/// characters and bytes that aren't valid UTF-8, like go's `strconv.Quote`
+pub fn quote(bytes: &[u8]) -> String {
+    let mut quoted = "\"".to_string();
+    let mut rest = bytes;
+    loop {
+        let (valid, invalid) = match std::str::from_utf8(rest) {
+            Ok(valid) => (valid, &[][..]),
+            Err(err) => {
+                let (valid, invalid) = rest.split_at(err.valid_up_to());
+                let len = err.error_len().unwrap_or(invalid.len());
+                // The prefix is valid UTF-8
+                (std::str::from_utf8(valid).unwrap(), &invalid[..len])
+            }
+        };
+        for c in valid.chars() {
+            push_escaped(&mut quoted, c);
+        }
+        for byte in invalid {
+            quoted.push_str(&format!("\\x{:02x}", byte));
+        }
+        rest = &rest[valid.len() + invalid.len()..];
+        if rest.is_empty() {
+            break;
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn push_escaped(quoted: &mut String, c: char) {
+    match c {
+        '"' => quoted.push_str("\\\""),
+        '\\' => quoted.push_str("\\\\"),
+        '\x07' => quoted.push_str("\\a"),
+        '\x08' => quoted.push_str("\\b"),
+        '\x0c' => quoted.push_str("\\f"),
+        '\n' => quoted.push_str("\\n"),
+        '\r' => quoted.push_str("\\r"),
+        '\t' => quoted.push_str("\\t"),
+        '\x0b' => quoted.push_str("\\v"),
+        c if c < ' ' || c == '\x7f' => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+        c if c.is_control() || c == '\u{feff}' => {
+            if (c as u32) < 0x10000 {
+                quoted.push_str(&format!("\\u{:04x}", c as u32));
+            } else {
+                quoted.push_str(&format!("\\U{:08x}", c as u32));
+            }
+        }
+        c => quoted.push(c),
+    }
+}
@@ -0,0 +1,576 @@
+//! Decides which variables, and which values allocated by `new(T)` and `&T{..}`, live on the heap
+//! rather than in the stack frame of their function, like `go build -gcflags=-m` reports.
+//!
+//! A variable (or allocation) is moved to the heap if its address may outlive it: if it flows to
+//! the heap (it's returned, passed to a function, stored through a pointer or in a slice or a map,
+//! or stored in a variable that's on the heap itself), or to a variable declared outside of the loop
+//! it's declared in (every iteration gets a new variable, but they'd all share a single stack
+//! slot). Taking the address of a variable includes slicing it, if it's an array.
+//!
+//! The analysis is per function and ignores the order of statements: the values of a variable
+//! are everything that's ever stored in it. Values read through a pointer are approximated by the
+//! pointer itself, which can only move more variables to the heap.
+
+use crate::ast::{Builtin, Expression, FuncDef, Program, Span, Statement, Type, UnaryOp};
+use crate::pretty_printer::format_type;
+use std::collections::{HashMap, HashSet};
+
+/// What the escape analysis decided about a variable or an allocation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// The variable `name` (whose address is taken) is allocated on the heap
+    MovedToHeap(String),
+    /// The value allocated by `new(T)` or `&T{..}` (described by the string) is on the heap
+    Escapes(String),
+    /// The value allocated by `new(T)` or `&T{..}` is on the stack
+    DoesNotEscape(String),
+}
+
+impl Decision {
+    /// The decision as the go compiler prints it with `-m`
+    pub fn describe(&self) -> String {
+        match self {
+            Decision::MovedToHeap(name) => format!("moved to heap: {}", name),
+            Decision::Escapes(alloc) => format!("{} escapes to heap", alloc),
+            Decision::DoesNotEscape(alloc) => format!("{} does not escape", alloc),
+        }
+    }
+}
+
+/// The variables and allocations of a program that are on the heap, identified by the span of the
+/// variable's name (or of the parameter, or of the range clause's key or value), or of the
+/// `new(T)` or `&T{..}`
+#[derive(Default)]
+pub struct Escapes {
+    heap: HashSet<Span>,
+    /// The decisions worth reporting: the variables moved to the heap, and every allocation
+    decisions: Vec<(Span, Decision)>,
+}
+
+impl Escapes {
+    /// Whether the variable or allocation at `span` is allocated on the heap
+    pub fn is_heap(&self, span: Span) -> bool {
+        self.heap.contains(&span)
+    }
+
+    /// The decisions about the variables moved to the heap and about every allocation, in source
+    /// order
+    pub fn decisions(&self) -> Vec<(Span, Decision)> {
+        let mut decisions = self.decisions.clone();
+        decisions.sort_by_key(|(span, _)| (span.file_id, span.start));
+        decisions
+    }
+}
+
+/// Something values are stored in
+#[derive(Debug)]
+enum LocationKind {
+    /// Where everything that escapes goes
+    Heap,
+    Var(String),
+    /// `new(T)` or `&T{..}`, as the go compiler prints it
+    Alloc(String),
+}
+
+#[derive(Debug)]
+struct Location {
+    kind: LocationKind,
+    span: Span,
+    /// The number of loops (and labels, which may be jumped back to) around the declaration
+    depth: usize,
+    /// What's stored in the location
+    inflows: Vec<Flow>,
+    /// Whether it's on the heap
+    escapes: bool,
+}
+
+/// A value stored in a location, in terms of other locations
+#[derive(Debug, Clone, Copy)]
+enum Flow {
+    /// The address of the location
+    Addr(usize),
+    /// One of the values stored in the location
+    Value(usize),
+}
+
+/// The heap is the first location of every function
+const HEAP: usize = 0;
+
+struct Analysis {
+    locations: Vec<Location>,
+    /// The variables in scope, innermost scope last
+    scopes: Vec<HashMap<String, usize>>,
+    /// The number of loops and labels around the code being analyzed
+    depth: usize,
+}
+
+impl Analysis {
+    fn new() -> Self {
+        Self {
+            locations: vec![Location {
+                kind: LocationKind::Heap,
+                span: Span::default(),
+                depth: 0,
+                inflows: vec![],
+                escapes: true,
+            }],
+            scopes: vec![HashMap::new()],
+            depth: 0,
+        }
+    }
+
+    fn add_location(&mut self, kind: LocationKind, span: Span) -> usize {
+        self.locations.push(Location {
+            kind,
+            span,
+            depth: self.depth,
+            inflows: vec![],
+            escapes: false,
+        });
+        self.locations.len() - 1
+    }
+
+    fn declare(&mut self, name: &str, span: Span) -> usize {
+        let location = self.add_location(LocationKind::Var(name.to_string()), span);
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), location);
+        location
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    fn store(&mut self, flows: Vec<Flow>, location: usize) {
+        self.locations[location].inflows.extend(flows);
+    }
+
+    fn function(&mut self, func: &FuncDef) {
+        for (name, _, span) in &func.params {
+            self.declare(name, *span);
+        }
+        // Named results are variables too, and what's stored in them is returned
+        if func.named_results {
+            for (name, _, span) in &func.results {
+                let result = self.declare(name, *span);
+                self.store(vec![Flow::Value(result)], HEAP);
+            }
+        }
+        self.block(&func.code);
+    }
+
+    fn block(&mut self, block: &[Statement]) {
+        self.scopes.push(HashMap::new());
+        let depth = self.depth;
+        for stmt in block {
+            // A label may be jumped back to, which repeats the statements after it like a loop
+            if let Statement::Label { .. } = stmt {
+                self.depth += 1;
+            }
+            self.statement(stmt);
+        }
+        self.depth = depth;
+        self.scopes.pop();
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::Assignment {
+                name, expr, span, ..
+            } => {
+                let flows = self.expr(expr);
+                let var = self.declare(name, *span);
+                self.store(flows, var);
+            }
+            Statement::ShortVarDecl { vars, exprs, .. } => {
+                let flows: Vec<_> = exprs.iter().map(|expr| self.expr(expr)).collect();
+                for (var, flows) in vars.iter().zip(flows) {
+                    let location = match var.name.as_str() {
+                        "_" => continue,
+                        _ if var.is_new => self.declare(&var.name, var.span),
+                        name => match self.lookup(name) {
+                            Some(location) => location,
+                            None => continue,
+                        },
+                    };
+                    self.store(flows, location);
+                }
+            }
+            Statement::Assign { lhs, rhs, .. } => {
+                let flows: Vec<_> = rhs.iter().map(|expr| self.expr(expr)).collect();
+                for (lhs, flows) in lhs.iter().zip(flows) {
+                    if let Some(location) = self.target(lhs) {
+                        self.store(flows, location);
+                    }
+                }
+            }
+            Statement::IncDec { expr, .. } | Statement::Expression { expr, .. } => {
+                self.expr(expr);
+            }
+            Statement::If {
+                cond,
+                then_block,
+                else_block,
+                ..
+            } => {
+                self.expr(cond);
+                self.block(then_block);
+                self.block(else_block);
+            }
+            // The variables declared by the init statement (and by a range clause) are declared
+            // once, outside of the loop
+            Statement::For {
+                init,
+                cond,
+                post,
+                body,
+                ..
+            } => {
+                self.scopes.push(HashMap::new());
+                if let Some(init) = init {
+                    self.statement(init);
+                }
+                self.depth += 1;
+                if let Some(cond) = cond {
+                    self.expr(cond);
+                }
+                if let Some(post) = post {
+                    self.statement(post);
+                }
+                self.block(body);
+                self.depth -= 1;
+                self.scopes.pop();
+            }
+            Statement::ForRange {
+                key,
+                value,
+                define,
+                expr,
+                body,
+                ..
+            } => {
+                self.scopes.push(HashMap::new());
+                // The value is an element of what's ranged over, and so is the key of a map
+                let flows = self.expr(expr);
+                let flows = match expr.get_type() {
+                    Type::Map(..) => [flows.clone(), flows],
+                    _ => [vec![], flows],
+                };
+                for (var, flows) in [key, value].into_iter().zip(flows) {
+                    let location = match var {
+                        Some(Expression::Name { name, span, .. }) if *define && name != "_" => {
+                            Some(self.declare(name, *span))
+                        }
+                        Some(var) if !*define => self.target(var),
+                        _ => None,
+                    };
+                    if let Some(location) = location {
+                        self.store(flows, location);
+                    }
+                }
+                self.depth += 1;
+                self.block(body);
+                self.depth -= 1;
+                self.scopes.pop();
+            }
+            Statement::Switch {
+                init, tag, cases, ..
+            } => {
+                self.scopes.push(HashMap::new());
+                if let Some(init) = init {
+                    self.statement(init);
+                }
+                if let Some(tag) = tag {
+                    self.expr(tag);
+                }
+                for case in cases {
+                    for expr in case.exprs.iter().flatten() {
+                        self.expr(expr);
+                    }
+                    self.block(&case.body);
+                }
+                self.scopes.pop();
+            }
+            Statement::Return { exprs, .. } => {
+                for expr in exprs {
+                    let flows = self.expr(expr);
+                    self.store(flows, HEAP);
+                }
+            }
+            Statement::Const { .. }
+            | Statement::Fallthrough { .. }
+            | Statement::Break { .. }
+            | Statement::Continue { .. }
+            | Statement::Label { .. }
+            | Statement::Goto { .. } => {}
+        }
+    }
+
+    /// The location assigned to by assigning to `lhs`: a variable (for the variable, or an element
+    /// or field of it), the heap (for anything reached through a pointer, a slice or a map), or
+    /// `None` for the blank identifier
+    fn target(&mut self, lhs: &Expression) -> Option<usize> {
+        match lhs {
+            Expression::Name { name, .. } if name == "_" => None,
+            // The key is stored in the map along with the value
+            Expression::Index { expr, index, .. } if matches!(expr.get_type(), Type::Map(..)) => {
+                self.expr(expr);
+                let flows = self.expr(index);
+                self.store(flows, HEAP);
+                Some(HEAP)
+            }
+            _ => {
+                // The indices are evaluated too
+                self.expr(lhs);
+                Some(self.root(lhs).unwrap_or(HEAP))
+            }
+        }
+    }
+
+    /// The variable `expr` is part of: `a` for `a`, for the element `a[i]` of the array `a`, and
+    /// for the field `a.f` of the struct `a`. `None` if it's reached through a pointer or a slice,
+    /// or isn't stored in a variable
+    fn root(&self, expr: &Expression) -> Option<usize> {
+        match expr {
+            Expression::Name { name, .. } => self.lookup(name),
+            Expression::Index { expr, .. } if matches!(expr.get_type(), Type::Array(..)) => {
+                self.root(expr)
+            }
+            Expression::Selector { expr, .. } if !matches!(expr.get_type(), Type::Pointer(_)) => {
+                self.root(expr)
+            }
+            _ => None,
+        }
+    }
+
+    /// Analyzes `expr`, and returns the flows of its value
+    fn expr(&mut self, expr: &Expression) -> Vec<Flow> {
+        match expr {
+            Expression::Name { name, .. } => {
+                self.lookup(name).map(Flow::Value).into_iter().collect()
+            }
+            Expression::Literal { .. } | Expression::ZeroValue { .. } | Expression::Nil { .. } => {
+                vec![]
+            }
+            Expression::Make { args, .. } => {
+                for arg in args {
+                    self.expr(arg);
+                }
+                vec![]
+            }
+            Expression::New { expr_type, span } => {
+                let alloc = format!("new({})", format_pointee(expr_type));
+                vec![Flow::Addr(
+                    self.add_location(LocationKind::Alloc(alloc), *span),
+                )]
+            }
+            Expression::UnaryOp {
+                op: UnaryOp::Addr,
+                expr: inner,
+                expr_type,
+                span,
+            } => {
+                if let Expression::CompositeLit { .. } = **inner {
+                    let alloc = format!("&{}{{...}}", format_pointee(expr_type));
+                    let location = self.add_location(LocationKind::Alloc(alloc), *span);
+                    let flows = self.expr(inner);
+                    self.store(flows, location);
+                    return vec![Flow::Addr(location)];
+                }
+                self.addr(inner)
+            }
+            // What a pointer points to is approximated by the pointer
+            Expression::UnaryOp {
+                op: UnaryOp::Deref,
+                expr,
+                ..
+            }
+            | Expression::Conversion { expr, .. }
+            | Expression::Selector { expr, .. } => self.expr(expr),
+            Expression::UnaryOp { expr, .. } => {
+                self.expr(expr);
+                vec![]
+            }
+            Expression::BinaryOp { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+                vec![]
+            }
+            Expression::Index { expr, index, .. } => {
+                self.expr(index);
+                self.expr(expr)
+            }
+            Expression::Slice {
+                expr,
+                low,
+                high,
+                max,
+                ..
+            } => {
+                for index in [low, high, max].into_iter().flatten() {
+                    self.expr(index);
+                }
+                match expr.get_type() {
+                    Type::Array(..) => self.addr(expr),
+                    _ => self.expr(expr),
+                }
+            }
+            // The elements of a slice literal are stored in an array on the heap, and the keys and
+            // values of a map literal in the map
+            Expression::CompositeLit {
+                expr_type,
+                elements,
+                ..
+            } => {
+                let mut flows = vec![];
+                for element in elements {
+                    if let Some(key) = &element.key {
+                        let key_flows = self.expr(key);
+                        if let Type::Map(..) = expr_type {
+                            flows.extend(key_flows);
+                        }
+                    }
+                    flows.extend(self.expr(&element.value));
+                }
+                if let Type::Slice(_) | Type::Map(..) = expr_type {
+                    self.store(flows, HEAP);
+                    return vec![];
+                }
+                flows
+            }
+            // The values appended to a slice and those copied into one are stored in its array,
+            // which may be on the heap
+            Expression::BuiltinCall {
+                builtin: Builtin::Append,
+                args,
+                ..
+            } => {
+                let flows = self.expr(&args[0]);
+                for arg in &args[1..] {
+                    let arg_flows = self.expr(arg);
+                    self.store(arg_flows, HEAP);
+                }
+                flows
+            }
+            Expression::BuiltinCall {
+                builtin: Builtin::Copy,
+                args,
+                ..
+            } => {
+                self.expr(&args[0]);
+                let flows = self.expr(&args[1]);
+                self.store(flows, HEAP);
+                vec![]
+            }
+            Expression::BuiltinCall { args, .. } => {
+                for arg in args {
+                    self.expr(arg);
+                }
+                vec![]
+            }
+            // Nothing is known about what a function does with its arguments
+            Expression::Call { args, .. }
+            | Expression::FmtCall { args, .. }
+            | Expression::RuntimeCall { args, .. } => {
+                for arg in args {
+                    let flows = self.expr(arg);
+                    self.store(flows, HEAP);
+                }
+                vec![]
+            }
+        }
+    }
+
+    /// The flows of `&expr` (or of slicing the array `expr`): the address of a variable, or else
+    /// the pointer or slice `expr` is reached through
+    fn addr(&mut self, expr: &Expression) -> Vec<Flow> {
+        let flows = self.expr(expr);
+        match self.root(expr) {
+            Some(var) => vec![Flow::Addr(var)],
+            None => flows,
+        }
+    }
+
+    /// Moves to the heap the locations whose address flows to the heap, or to a location declared
+    /// in fewer loops. A location on the heap is reachable from anywhere, so what's stored in it
+    /// flows to the heap too
+    fn solve(&mut self) {
+        // The fewest loops around a location that the values of each location flow to, and
+        // whether they flow to the heap
+        let mut min_depth: Vec<usize> = self.locations.iter().map(|loc| loc.depth).collect();
+        let mut to_heap: Vec<bool> = self.locations.iter().map(|loc| loc.escapes).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for location in 0..self.locations.len() {
+                for flow in self.locations[location].inflows.clone() {
+                    match flow {
+                        Flow::Value(source) => {
+                            if min_depth[location] < min_depth[source] {
+                                min_depth[source] = min_depth[location];
+                                changed = true;
+                            }
+                            if to_heap[location] && !to_heap[source] {
+                                to_heap[source] = true;
+                                changed = true;
+                            }
+                        }
+                        Flow::Addr(target) => {
+                            let escapes = to_heap[location]
+                                || min_depth[location] < self.locations[target].depth;
+                            if escapes && !self.locations[target].escapes {
+                                self.locations[target].escapes = true;
+                                to_heap[target] = true;
+                                changed = true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// `T` for the pointer type `*T`
+fn format_pointee(ty: &Type) -> String {
+    match ty {
+        Type::Pointer(elem) => format_type(elem),
+        _ => format_type(ty),
+    }
+}
+
+/// Decides which variables and allocations of a program that has been type checked are on the
+/// heap
+pub fn analyze(program: &Program) -> Escapes {
+    let mut escapes = Escapes::default();
+    for func in &program.functions {
+        let mut analysis = Analysis::new();
+        analysis.function(func);
+        analysis.solve();
+        for location in &analysis.locations {
+            let decision = match &location.kind {
+                LocationKind::Heap => continue,
+                LocationKind::Var(name) if location.escapes => {
+                    Some(Decision::MovedToHeap(name.clone()))
+                }
+                LocationKind::Var(_) => None,
+                LocationKind::Alloc(alloc) if location.escapes => {
+                    Some(Decision::Escapes(alloc.clone()))
+                }
+                LocationKind::Alloc(alloc) => Some(Decision::DoesNotEscape(alloc.clone())),
+            };
+            if location.escapes {
+                escapes.heap.insert(location.span);
+            }
+            if let Some(decision) = decision {
+                escapes.decisions.push((location.span, decision));
+            }
+        }
+    }
+    escapes
+}
@@ -0,0 +1,1028 @@
+//! Turns Go source text into a stream of tokens. Each token remembers where it came from (byte
+//! offset, line and column), and semicolons are inserted automatically following the rules laid
+//! out in the Go spec, so the parser never has to care about newlines.
+
+use crate::escape;
+use std::fmt;
+
+/// A location in the source text. `offset` is a byte offset, `line` and `column` start at 1 (the
+/// column is counted in bytes, as the go toolchain does).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// Position of the first byte of the token
+    pub start: Position,
+    /// Position just past the last byte of the token
+    pub end: Position,
+}
+
+/// The kind of a token. Literals and comments store their text exactly as it appears in the
+/// source (including quotes, prefixes and underscores); it's up to the parser to interpret them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Ident(String),
+    Int(String),
+    Float(String),
+    Imaginary(String),
+    Rune(String),
+    /// An interpreted string literal (`"..."`)
+    String(String),
+    /// A raw string literal (`` `...` ``)
+    RawString(String),
+    /// Both `// ...` and `/* ... */` comments
+    Comment(String),
+
+    // Keywords
+    Break,
+    Case,
+    Chan,
+    Const,
+    Continue,
+    Default,
+    Defer,
+    Else,
+    Fallthrough,
+    For,
+    Func,
+    Go,
+    Goto,
+    If,
+    Import,
+    Interface,
+    Map,
+    Package,
+    Range,
+    Return,
+    Select,
+    Struct,
+    Switch,
+    Type,
+    Var,
+
+    // Operators
+    /// +
+    Plus,
+    /// \-
+    Minus,
+    /// \*
+    Star,
+    /// /
+    Slash,
+    /// %
+    Percent,
+    /// &
+    Amp,
+    /// |
+    Pipe,
+    /// ^
+    Caret,
+    /// <<
+    Shl,
+    /// \>>
+    Shr,
+    /// &^
+    AmpCaret,
+    /// +=
+    PlusAssign,
+    /// -=
+    MinusAssign,
+    /// *=
+    StarAssign,
+    /// /=
+    SlashAssign,
+    /// %=
+    PercentAssign,
+    /// &=
+    AmpAssign,
+    /// |=
+    PipeAssign,
+    /// ^=
+    CaretAssign,
+    /// <<=
+    ShlAssign,
+    /// \>>=
+    ShrAssign,
+    /// &^=
+    AmpCaretAssign,
+    /// &&
+    AmpAmp,
+    /// ||
+    PipePipe,
+    /// <-
+    Arrow,
+    /// ++
+    Inc,
+    /// \--
+    Dec,
+    /// ==
+    EqEq,
+    /// <
+    Lt,
+    /// \>
+    Gt,
+    /// =
+    Assign,
+    /// !
+    Bang,
+    /// ~
+    Tilde,
+    /// !=
+    NotEq,
+    /// <=
+    LtEq,
+    /// \>=
+    GtEq,
+    /// :=
+    Define,
+    /// ...
+    Ellipsis,
+
+    // Punctuation
+    LParen,
+    RParen,
+    LBrack,
+    RBrack,
+    LBrace,
+    RBrace,
+    Comma,
+    Period,
+    Colon,
+    /// `automatic` is true if the semicolon was inserted by the lexer (at a newline or the end
+    /// of the file) rather than written in the source
+    Semicolon {
+        automatic: bool,
+    },
+
+    Eof,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexError {
+    pub msg: &'static str,
+    pub pos: Position,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.pos.line, self.pos.column, self.msg)
+    }
+}
+
+impl std::error::Error for LexError {}
+
+fn keyword(ident: &str) -> Option<TokenKind> {
+    Some(match ident {
+        "break" => TokenKind::Break,
+        "case" => TokenKind::Case,
+        "chan" => TokenKind::Chan,
+        "const" => TokenKind::Const,
+        "continue" => TokenKind::Continue,
+        "default" => TokenKind::Default,
+        "defer" => TokenKind::Defer,
+        "else" => TokenKind::Else,
+        "fallthrough" => TokenKind::Fallthrough,
+        "for" => TokenKind::For,
+        "func" => TokenKind::Func,
+        "go" => TokenKind::Go,
+        "goto" => TokenKind::Goto,
+        "if" => TokenKind::If,
+        "import" => TokenKind::Import,
+        "interface" => TokenKind::Interface,
+        "map" => TokenKind::Map,
+        "package" => TokenKind::Package,
+        "range" => TokenKind::Range,
+        "return" => TokenKind::Return,
+        "select" => TokenKind::Select,
+        "struct" => TokenKind::Struct,
+        "switch" => TokenKind::Switch,
+        "type" => TokenKind::Type,
+        "var" => TokenKind::Var,
+        _ => return None,
+    })
+}
+
+impl TokenKind {
+    /// Whether a newline directly after this token should turn into a semicolon
+    fn triggers_semicolon(&self) -> bool {
+        matches!(
+            self,
+            TokenKind::Ident(_)
+                | TokenKind::Int(_)
+                | TokenKind::Float(_)
+                | TokenKind::Imaginary(_)
+                | TokenKind::Rune(_)
+                | TokenKind::String(_)
+                | TokenKind::RawString(_)
+                | TokenKind::Break
+                | TokenKind::Continue
+                | TokenKind::Fallthrough
+                | TokenKind::Return
+                | TokenKind::Inc
+                | TokenKind::Dec
+                | TokenKind::RParen
+                | TokenKind::RBrack
+                | TokenKind::RBrace
+        )
+    }
+}
+
+impl fmt::Display for TokenKind {
+    /// Formats the token the way the go compiler refers to it in syntax errors
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            TokenKind::Ident(name) => return write!(f, "name {}", name),
+            TokenKind::Int(lit)
+            | TokenKind::Float(lit)
+            | TokenKind::Imaginary(lit)
+            | TokenKind::Rune(lit)
+            | TokenKind::String(lit)
+            | TokenKind::RawString(lit) => return write!(f, "literal {}", lit),
+            TokenKind::Comment(_) => "comment",
+            TokenKind::Break => "keyword break",
+            TokenKind::Case => "keyword case",
+            TokenKind::Chan => "keyword chan",
+            TokenKind::Const => "keyword const",
+            TokenKind::Continue => "keyword continue",
+            TokenKind::Default => "keyword default",
+            TokenKind::Defer => "keyword defer",
+            TokenKind::Else => "keyword else",
+            TokenKind::Fallthrough => "keyword fallthrough",
+            TokenKind::For => "keyword for",
+            TokenKind::Func => "keyword func",
+            TokenKind::Go => "keyword go",
+            TokenKind::Goto => "keyword goto",
+            TokenKind::If => "keyword if",
+            TokenKind::Import => "keyword import",
+            TokenKind::Interface => "keyword interface",
+            TokenKind::Map => "keyword map",
+            TokenKind::Package => "keyword package",
+            TokenKind::Range => "keyword range",
+            TokenKind::Return => "keyword return",
+            TokenKind::Select => "keyword select",
+            TokenKind::Struct => "keyword struct",
+            TokenKind::Switch => "keyword switch",
+            TokenKind::Type => "keyword type",
+            TokenKind::Var => "keyword var",
+            TokenKind::Plus => "+",
+            TokenKind::Minus => "-",
+            TokenKind::Star => "*",
+            TokenKind::Slash => "/",
+            TokenKind::Percent => "%",
+            TokenKind::Amp => "&",
+            TokenKind::Pipe => "|",
+            TokenKind::Caret => "^",
+            TokenKind::Shl => "<<",
+            TokenKind::Shr => ">>",
+            TokenKind::AmpCaret => "&^",
+            TokenKind::PlusAssign => "+=",
+            TokenKind::MinusAssign => "-=",
+            TokenKind::StarAssign => "*=",
+            TokenKind::SlashAssign => "/=",
+            TokenKind::PercentAssign => "%=",
+            TokenKind::AmpAssign => "&=",
+            TokenKind::PipeAssign => "|=",
+            TokenKind::CaretAssign => "^=",
+            TokenKind::ShlAssign => "<<=",
+            TokenKind::ShrAssign => ">>=",
+            TokenKind::AmpCaretAssign => "&^=",
+            TokenKind::AmpAmp => "&&",
+            TokenKind::PipePipe => "||",
+            TokenKind::Arrow => "<-",
+            TokenKind::Inc => "++",
+            TokenKind::Dec => "--",
+            TokenKind::EqEq => "==",
+            TokenKind::Lt => "<",
+            TokenKind::Gt => ">",
+            TokenKind::Assign => "=",
+            TokenKind::Bang => "!",
+            TokenKind::Tilde => "~",
+            TokenKind::NotEq => "!=",
+            TokenKind::LtEq => "<=",
+            TokenKind::GtEq => ">=",
+            TokenKind::Define => ":=",
+            TokenKind::Ellipsis => "...",
+            TokenKind::LParen => "(",
+            TokenKind::RParen => ")",
+            TokenKind::LBrack => "[",
+            TokenKind::RBrack => "]",
+            TokenKind::LBrace => "{",
+            TokenKind::RBrace => "}",
+            TokenKind::Comma => "comma",
+            TokenKind::Period => ".",
+            TokenKind::Colon => ":",
+            TokenKind::Semicolon { automatic: true } => "newline",
+            TokenKind::Semicolon { automatic: false } => "semicolon",
+            TokenKind::Eof => "EOF",
+        };
+        f.write_str(s)
+    }
+}
+
+pub struct Lexer<'a> {
+    src: &'a str,
+    /// Byte offset of the next character to be read
+    offset: usize,
+    line: usize,
+    /// Byte offset of the first character of the current line
+    line_start: usize,
+    /// Set if a newline (or EOF) at this point should produce a semicolon
+    insert_semi: bool,
+    /// Set once `Eof` has been returned
+    done: bool,
+}
+
+/// Lexes the whole source, including comments. The last token is always `TokenKind::Eof`.
+pub fn tokenize(src: &str) -> Result<Vec<Token>, LexError> {
+    let mut lexer = Lexer::new(src);
+    let mut tokens = vec![];
+    loop {
+        let token = lexer.next_token()?;
+        let is_eof = token.kind == TokenKind::Eof;
+        tokens.push(token);
+        if is_eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(src: &'a str) -> Self {
+        // A byte order mark is only permitted as the very first character, and is ignored
+        let offset = if src.starts_with('\u{FEFF}') { 3 } else { 0 };
+        Self {
+            src,
+            offset,
+            line: 1,
+            line_start: 0,
+            insert_semi: false,
+            done: false,
+        }
+    }
+
+    fn pos(&self) -> Position {
+        Position {
+            offset: self.offset,
+            line: self.line,
+            column: self.offset - self.line_start + 1,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.offset..].chars().next()
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.src[self.offset..].chars().nth(n)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.line_start = self.offset;
+        }
+        Some(c)
+    }
+
+    /// Consumes the next character if it is `c`
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error<T>(&self, msg: &'static str, pos: Position) -> Result<T, LexError> {
+        Err(LexError { msg, pos })
+    }
+
+    /// Returns the next token. Once the end of the input has been reached, `TokenKind::Eof` is
+    /// returned on every call.
+    pub fn next_token(&mut self) -> Result<Token, LexError> {
+        // Skip whitespace, turning the newline into a semicolon if required
+        while let Some(c) = self.peek() {
+            if c == '\n' && self.insert_semi {
+                let start = self.pos();
+                self.bump();
+                self.insert_semi = false;
+                return Ok(Token {
+                    kind: TokenKind::Semicolon { automatic: true },
+                    start,
+                    end: self.pos(),
+                });
+            }
+            if !matches!(c, ' ' | '\t' | '\r' | '\n') {
+                break;
+            }
+            self.bump();
+        }
+
+        let start = self.pos();
+        let c = match self.peek() {
+            Some(c) => c,
+            None => {
+                let kind = if self.insert_semi {
+                    self.insert_semi = false;
+                    TokenKind::Semicolon { automatic: true }
+                } else {
+                    self.done = true;
+                    TokenKind::Eof
+                };
+                return Ok(Token {
+                    kind,
+                    start,
+                    end: start,
+                });
+            }
+        };
+
+        // A comment that ends the line acts like a newline
+        if c == '/' && matches!(self.peek_nth(1), Some('/') | Some('*')) {
+            if self.insert_semi && self.comment_ends_line() {
+                self.insert_semi = false;
+                return Ok(Token {
+                    kind: TokenKind::Semicolon { automatic: true },
+                    start,
+                    end: start,
+                });
+            }
+            let kind = self.lex_comment()?;
+            return Ok(Token {
+                kind,
+                start,
+                end: self.pos(),
+            });
+        }
+
+        let kind = if is_letter(c) {
+            self.lex_ident()
+        } else if c.is_ascii_digit() || (c == '.' && self.peek_nth(1).is_some_and(is_decimal)) {
+            self.lex_number()?
+        } else {
+            match c {
+                '"' => self.lex_string()?,
+                '`' => self.lex_raw_string()?,
+                '\'' => self.lex_rune()?,
+                _ => self.lex_operator()?,
+            }
+        };
+        self.insert_semi = kind.triggers_semicolon();
+        Ok(Token {
+            kind,
+            start,
+            end: self.pos(),
+        })
+    }
+
+    /// Checks whether the comment starting at the current offset runs to the end of the line
+    /// (or contains a newline), without consuming anything
+    fn comment_ends_line(&self) -> bool {
+        let rest = &self.src[self.offset..];
+        if rest.starts_with("//") {
+            return true;
+        }
+        match rest[2..].find("*/") {
+            Some(end) => {
+                let after = rest[end + 4..].trim_start_matches([' ', '\t', '\r']);
+                rest[2..end + 2].contains('\n') || after.is_empty() || after.starts_with('\n')
+            }
+            None => true,
+        }
+    }
+
+    fn lex_comment(&mut self) -> Result<TokenKind, LexError> {
+        let start = self.pos();
+        self.bump();
+        if self.eat('/') {
+            while !matches!(self.peek(), Some('\n') | None) {
+                self.bump();
+            }
+        } else {
+            self.bump(); // '*'
+            loop {
+                match self.bump() {
+                    Some('*') if self.eat('/') => break,
+                    Some(_) => {}
+                    None => return self.error("comment not terminated", start),
+                }
+            }
+        }
+        Ok(TokenKind::Comment(
+            self.src[start.offset..self.offset].to_string(),
+        ))
+    }
+
+    fn lex_ident(&mut self) -> TokenKind {
+        let start = self.offset;
+        while self.peek().is_some_and(|c| is_letter(c) || c.is_numeric()) {
+            self.bump();
+        }
+        let ident = &self.src[start..self.offset];
+        keyword(ident).unwrap_or_else(|| TokenKind::Ident(ident.to_string()))
+    }
+
+    /// Consumes digits (and `_` separators) accepted by `is_digit`. Returns whether any digit was
+    /// consumed. Underscores are only permitted between digits, or after the base prefix
+    fn lex_digits(
+        &mut self,
+        is_digit: fn(char) -> bool,
+        after_prefix: bool,
+    ) -> Result<bool, LexError> {
+        let mut seen_digit = false;
+        let mut prev_underscore = false;
+        while let Some(c) = self.peek() {
+            if c == '_' {
+                if prev_underscore || !(seen_digit || after_prefix) {
+                    return self.error("'_' must separate successive digits", self.pos());
+                }
+                prev_underscore = true;
+            } else if is_digit(c) {
+                seen_digit = true;
+                prev_underscore = false;
+            } else {
+                break;
+            }
+            self.bump();
+        }
+        if prev_underscore {
+            return self.error("'_' must separate successive digits", self.pos());
+        }
+        Ok(seen_digit)
+    }
+
+    fn lex_number(&mut self) -> Result<TokenKind, LexError> {
+        let start = self.pos();
+        let mut is_float = false;
+
+        let prefix = if self.peek() == Some('0') {
+            match self.peek_nth(1) {
+                Some('x' | 'X') => Some(16),
+                Some('b' | 'B') => Some(2),
+                Some('o' | 'O') => Some(8),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        match prefix {
+            Some(base) => {
+                self.bump();
+                self.bump();
+                let is_digit: fn(char) -> bool = match base {
+                    16 => |c| c.is_ascii_hexdigit(),
+                    8 => |c| ('0'..='7').contains(&c),
+                    _ => |c| c == '0' || c == '1',
+                };
+                let mut has_digits = self.lex_digits(is_digit, true)?;
+                if base == 16 {
+                    if self.eat('.') {
+                        is_float = true;
+                        has_digits |= self.lex_digits(is_digit, false)?;
+                    }
+                    if !has_digits {
+                        return self.error("hexadecimal literal has no digits", start);
+                    }
+                    if matches!(self.peek(), Some('p' | 'P')) {
+                        is_float = true;
+                        self.lex_exponent()?;
+                    } else if is_float {
+                        return self.error("hexadecimal mantissa requires a 'p' exponent", start);
+                    }
+                } else {
+                    if !has_digits {
+                        return self.error(
+                            if base == 8 {
+                                "octal literal has no digits"
+                            } else {
+                                "binary literal has no digits"
+                            },
+                            start,
+                        );
+                    }
+                    // Catch things like 0b102 or 0o78
+                    if self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                        return self.error(
+                            if base == 8 {
+                                "invalid digit in octal literal"
+                            } else {
+                                "invalid digit in binary literal"
+                            },
+                            self.pos(),
+                        );
+                    }
+                }
+            }
+            None => {
+                self.lex_digits(is_decimal, false)?;
+                if self.eat('.') {
+                    is_float = true;
+                    self.lex_digits(is_decimal, false)?;
+                }
+                if matches!(self.peek(), Some('e' | 'E')) {
+                    is_float = true;
+                    self.lex_exponent()?;
+                }
+                // Legacy octal literals (e.g. 0755) may only contain octal digits
+                let lit = &self.src[start.offset..self.offset];
+                if !is_float
+                    && lit.len() > 1
+                    && lit.starts_with('0')
+                    && lit.contains(['8', '9'])
+                    && self.peek() != Some('i')
+                {
+                    return self.error("invalid digit in octal literal", start);
+                }
+            }
+        }
+
+        let imaginary = self.eat('i');
+        let lit = self.src[start.offset..self.offset].to_string();
+        Ok(if imaginary {
+            TokenKind::Imaginary(lit)
+        } else if is_float {
+            TokenKind::Float(lit)
+        } else {
+            TokenKind::Int(lit)
+        })
+    }
+
+    /// Lexes an exponent (`e`, `E`, `p` or `P`) with an optional sign
+    fn lex_exponent(&mut self) -> Result<(), LexError> {
+        self.bump();
+        if !self.eat('+') {
+            self.eat('-');
+        }
+        if !self.lex_digits(is_decimal, false)? {
+            return self.error("exponent has no digits", self.pos());
+        }
+        Ok(())
+    }
+
+    /// Skips over an escape sequence, positioned right after the backslash. Its value is computed
+    /// when the literal is interpreted (see `escape`)
+    fn skip_escape(&mut self, quote: char) -> Result<(), LexError> {
+        let pos = self.pos();
+        match escape::decode(&self.src[self.offset..], quote) {
+            // Escape sequences never span lines
+            Ok((_, len)) => {
+                self.offset += len;
+                Ok(())
+            }
+            Err(err) => {
+                let pos = Position {
+                    offset: pos.offset + err.offset,
+                    column: pos.column + err.offset,
+                    ..pos
+                };
+                self.error(err.msg, pos)
+            }
+        }
+    }
+
+    fn lex_rune(&mut self) -> Result<TokenKind, LexError> {
+        let start = self.pos();
+        self.bump();
+        let mut count = 0;
+        loop {
+            match self.peek() {
+                Some('\'') => {
+                    self.bump();
+                    break;
+                }
+                Some('\n') | None => return self.error("rune literal not terminated", start),
+                Some('\\') => {
+                    self.bump();
+                    self.skip_escape('\'')?;
+                }
+                Some(_) => {
+                    self.bump();
+                }
+            }
+            count += 1;
+        }
+        match count {
+            0 => self.error("empty rune literal or unescaped ' in rune literal", start),
+            1 => Ok(TokenKind::Rune(
+                self.src[start.offset..self.offset].to_string(),
+            )),
+            _ => self.error("more than one character in rune literal", start),
+        }
+    }
+
+    fn lex_string(&mut self) -> Result<TokenKind, LexError> {
+        let start = self.pos();
+        self.bump();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.bump();
+                    break;
+                }
+                Some('\n') | None => return self.error("string literal not terminated", start),
+                Some('\\') => {
+                    self.bump();
+                    self.skip_escape('"')?;
+                }
+                Some(_) => {
+                    self.bump();
+                }
+            }
+        }
+        Ok(TokenKind::String(
+            self.src[start.offset..self.offset].to_string(),
+        ))
+    }
+
+    fn lex_raw_string(&mut self) -> Result<TokenKind, LexError> {
+        let start = self.pos();
+        self.bump();
+        loop {
+            match self.bump() {
+                Some('`') => break,
+                Some(_) => {}
+                None => return self.error("raw string literal not terminated", start),
+            }
+        }
+        Ok(TokenKind::RawString(
+            self.src[start.offset..self.offset].to_string(),
+        ))
+    }
+
+    fn lex_operator(&mut self) -> Result<TokenKind, LexError> {
+        let start = self.pos();
+        let c = self.bump().unwrap();
+        Ok(match c {
+            '(' => TokenKind::LParen,
+            ')' => TokenKind::RParen,
+            '[' => TokenKind::LBrack,
+            ']' => TokenKind::RBrack,
+            '{' => TokenKind::LBrace,
+            '}' => TokenKind::RBrace,
+            ',' => TokenKind::Comma,
+            ';' => TokenKind::Semicolon { automatic: false },
+            '~' => TokenKind::Tilde,
+            '.' => {
+                if self.peek() == Some('.') && self.peek_nth(1) == Some('.') {
+                    self.bump();
+                    self.bump();
+                    TokenKind::Ellipsis
+                } else {
+                    TokenKind::Period
+                }
+            }
+            ':' => self.pick('=', TokenKind::Define, TokenKind::Colon),
+            '+' => {
+                if self.eat('+') {
+                    TokenKind::Inc
+                } else {
+                    self.pick('=', TokenKind::PlusAssign, TokenKind::Plus)
+                }
+            }
+            '-' => {
+                if self.eat('-') {
+                    TokenKind::Dec
+                } else {
+                    self.pick('=', TokenKind::MinusAssign, TokenKind::Minus)
+                }
+            }
+            '*' => self.pick('=', TokenKind::StarAssign, TokenKind::Star),
+            '/' => self.pick('=', TokenKind::SlashAssign, TokenKind::Slash),
+            '%' => self.pick('=', TokenKind::PercentAssign, TokenKind::Percent),
+            '^' => self.pick('=', TokenKind::CaretAssign, TokenKind::Caret),
+            '=' => self.pick('=', TokenKind::EqEq, TokenKind::Assign),
+            '!' => self.pick('=', TokenKind::NotEq, TokenKind::Bang),
+            '&' => {
+                if self.eat('&') {
+                    TokenKind::AmpAmp
+                } else if self.eat('^') {
+                    self.pick('=', TokenKind::AmpCaretAssign, TokenKind::AmpCaret)
+                } else {
+                    self.pick('=', TokenKind::AmpAssign, TokenKind::Amp)
+                }
+            }
+            '|' => {
+                if self.eat('|') {
+                    TokenKind::PipePipe
+                } else {
+                    self.pick('=', TokenKind::PipeAssign, TokenKind::Pipe)
+                }
+            }
+            '<' => {
+                if self.eat('-') {
+                    TokenKind::Arrow
+                } else if self.eat('<') {
+                    self.pick('=', TokenKind::ShlAssign, TokenKind::Shl)
+                } else {
+                    self.pick('=', TokenKind::LtEq, TokenKind::Lt)
+                }
+            }
+            '>' => {
+                if self.eat('>') {
+                    self.pick('=', TokenKind::ShrAssign, TokenKind::Shr)
+                } else {
+                    self.pick('=', TokenKind::GtEq, TokenKind::Gt)
+                }
+            }
+            _ => return self.error("invalid character", start),
+        })
+    }
+
+    /// Returns `then` (consuming `next`) if the next character is `next`, else `otherwise`
+    fn pick(&mut self, next: char, then: TokenKind, otherwise: TokenKind) -> TokenKind {
+        if self.eat(next) {
+            then
+        } else {
+            otherwise
+        }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token, LexError>;
+
+    /// Yields tokens up to (and including) `TokenKind::Eof`
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            None
+        } else {
+            Some(self.next_token())
+        }
+    }
+}
+
+fn is_letter(c: char) -> bool {
+    c == '_' || c.is_alphabetic()
+}
+
+fn is_decimal(c: char) -> bool {
+    c.is_ascii_digit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEMI: TokenKind = TokenKind::Semicolon { automatic: true };
+
+    fn kinds(src: &str) -> Vec<TokenKind> {
+        tokenize(src)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .filter(|kind| !matches!(kind, TokenKind::Comment(_)))
+            .collect()
+    }
+
+    fn ident(name: &str) -> TokenKind {
+        TokenKind::Ident(name.to_string())
+    }
+
+    fn error(src: &str) -> &'static str {
+        tokenize(src).unwrap_err().msg
+    }
+
+    #[test]
+    fn semicolon_insertion() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("x++\nreturn\nf()\n}\n"),
+            [
+                ident("x"),
+                Inc,
+                SEMI,
+                Return,
+                SEMI,
+                ident("f"),
+                LParen,
+                RParen,
+                SEMI,
+                RBrace,
+                SEMI,
+                Eof
+            ]
+        );
+        // Not after operators, opening brackets or most keywords
+        assert_eq!(
+            kinds("a +\nb {\nfor\n"),
+            [ident("a"), Plus, ident("b"), LBrace, For, Eof]
+        );
+        // At the end of the file, and at comments that end the line
+        assert_eq!(kinds("a"), [ident("a"), SEMI, Eof]);
+        assert_eq!(
+            kinds("a // c\nb"),
+            [ident("a"), SEMI, ident("b"), SEMI, Eof]
+        );
+        assert_eq!(
+            kinds("a /* c\n */ b"),
+            [ident("a"), SEMI, ident("b"), SEMI, Eof]
+        );
+        assert_eq!(kinds("a /* c */ b"), [ident("a"), ident("b"), SEMI, Eof]);
+        // Explicit semicolons aren't automatic
+        assert_eq!(
+            kinds("a; b"),
+            [
+                ident("a"),
+                Semicolon { automatic: false },
+                ident("b"),
+                SEMI,
+                Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn positions() {
+        let tokens = tokenize("a\n  bc").unwrap();
+        let bc = &tokens[2];
+        assert_eq!(bc.kind, ident("bc"));
+        assert_eq!(
+            (bc.start, bc.end),
+            (
+                Position {
+                    offset: 4,
+                    line: 2,
+                    column: 3
+                },
+                Position {
+                    offset: 6,
+                    line: 2,
+                    column: 5
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn number_literals() {
+        use TokenKind::*;
+        let number = |src: &str| kinds(src).remove(0);
+        for lit in [
+            "42",
+            "0x1F",
+            "0X_ff",
+            "0b101",
+            "0o17",
+            "017",
+            "1_000_000",
+            "0",
+        ] {
+            assert_eq!(number(lit), Int(lit.to_string()));
+        }
+        for lit in [
+            "1.5", ".5", "1.", "1e10", "1E-3", "0x1p-2", "0x1.8P+1", "089.5",
+        ] {
+            assert_eq!(number(lit), Float(lit.to_string()));
+        }
+        for lit in ["1i", "0x1p4i", "089i"] {
+            assert_eq!(number(lit), Imaginary(lit.to_string()));
+        }
+        assert_eq!(error("0x"), "hexadecimal literal has no digits");
+        assert_eq!(error("0b"), "binary literal has no digits");
+        assert_eq!(error("0b102"), "invalid digit in binary literal");
+        assert_eq!(error("0o78"), "invalid digit in octal literal");
+        assert_eq!(error("089"), "invalid digit in octal literal");
+        assert_eq!(error("1e"), "exponent has no digits");
+        assert_eq!(
+            error("0x1.5"),
+            "hexadecimal mantissa requires a 'p' exponent"
+        );
+    }
+
+    #[test]
+    fn escape_literals() {
+        use TokenKind::*;
+        let lit = r#""a\n\t\"\x41\101é\U0001F600\\""#;
+        assert_eq!(kinds(lit)[0], String(lit.to_string()));
+        assert_eq!(escape::unquote(lit), "a\n\t\"AAé😀\\".as_bytes());
+        assert_eq!(escape::unquote(r#""\xff""#), [0xff]);
+        assert_eq!(escape::unquote("`a\\n\r`"), b"a\\n");
+        assert_eq!(kinds(r"'\''")[0], Rune(r"'\''".to_string()));
+        assert_eq!(escape::unquote_rune(r"'\x41'"), 0x41);
+        assert_eq!(escape::unquote_rune("'é'"), 'é' as u32);
+
+        assert_eq!(error(r#""\q""#), "unknown escape sequence");
+        assert_eq!(error(r#""\'""#), "unknown escape sequence");
+        assert_eq!(error(r#""\x4""#), "escape sequence not terminated");
+        assert_eq!(error(r#""\xg0""#), "invalid character in escape sequence");
+        assert_eq!(error(r#""\400""#), "octal escape value > 255");
+        assert_eq!(error(r#""\uD800""#), "escape is invalid Unicode code point");
+        assert_eq!(error("\"abc\n\""), "string literal not terminated");
+        assert_eq!(error("'ab'"), "more than one character in rune literal");
+        assert_eq!(
+            error("''"),
+            "empty rune literal or unescaped ' in rune literal"
+        );
+        assert_eq!(error("/* a"), "comment not terminated");
+    }
+}
@@ -1,20 +1,33 @@
 //! Ties all parts of the compiler together
 
 pub mod ast;
+pub mod checks;
 pub mod codegen;
+pub mod constant;
+pub mod diagnostics;
 pub mod errors;
+pub mod escape;
+pub mod escape_analysis;
+pub mod lexer;
+pub mod parser;
 pub mod pretty_printer;
+pub mod sema;
+pub mod source_map;
 use ast::*;
+use checks::Check;
 use codegen::CodeGen;
+use diagnostics::{Code, Diagnostic};
+use escape_analysis::Decision;
 use inkwell::module::Module;
 use inkwell::{context::Context, module::Linkage};
+use inkwell::types::BasicType;
 use inkwell::{AddressSpace, OptimizationLevel};
+use source_map::SourceMap;
 use std::fs;
 use std::process::Command;
 
 // TODO: Clean this steaming piece of garbage up. This includes, but isn't limited to:
 //     - Handle debug and release filepaths while linking with runtime
-//     - For the love of god, please handle stderr while compiling and linking runtime
 //     - Add a nice command line interface
 
 /// Adds an extern (runtime) function to the module. All the types must be `context.<my_type>()`.
@@ -35,12 +48,118 @@ macro_rules! add_runtime_func {
 }
 
 fn add_runtime<'a>(module: &Module<'a>, context: &'a Context) {
+    let string_type = Type::GoString.to_llvm(context);
     add_runtime_func!(module, "__flush_stdout", context.void_type(), []);
+    add_runtime_func!(module, "__gopanic", context.void_type(), [string_type]);
+    // Take the message, and the index and length (or the indices and the bound they exceed)
     add_runtime_func!(
         module,
-        "__gopanic",
+        "__gopanic_index",
         context.void_type(),
-        [context.i8_type().ptr_type(AddressSpace::Generic)],
+        [string_type, context.i64_type(), context.i64_type()],
+    );
+    add_runtime_func!(
+        module,
+        "__gopanic_slice",
+        context.void_type(),
+        [
+            string_type,
+            context.i64_type(),
+            context.i64_type(),
+            context.i64_type(),
+            context.i64_type(),
+            context.bool_type(),
+            context.bool_type(),
+        ],
+    );
+    // The heap is garbage collected by the runtime. Allocations take the size and the type
+    // descriptor (as an `i8*`) of what they hold, and functions push their frames onto
+    // `__go_frames` (see `CodeGen::build_frame`)
+    let byte_ptr_type = context.i8_type().ptr_type(AddressSpace::Generic);
+    add_runtime_func!(
+        module,
+        "__go_alloc",
+        byte_ptr_type,
+        [context.i64_type(), byte_ptr_type],
+    );
+    add_runtime_func!(module, "__go_gc", context.void_type(), []);
+    let frames = module.add_global(byte_ptr_type, None, "__go_frames");
+    frames.set_linkage(Linkage::External);
+    // The slice functions take and return element pointers as `i8*`s, along with the size of an
+    // element
+    add_runtime_func!(
+        module,
+        "__go_growslice",
+        Type::Slice(Box::new(Type::Uint8)).to_llvm(context),
+        [
+            byte_ptr_type,
+            context.i64_type(),
+            context.i64_type(),
+            context.i64_type(),
+            context.i64_type(),
+            byte_ptr_type,
+        ],
+    );
+    add_runtime_func!(
+        module,
+        "__go_slicecopy",
+        context.i64_type(),
+        [
+            byte_ptr_type,
+            context.i64_type(),
+            byte_ptr_type,
+            context.i64_type(),
+            context.i64_type(),
+        ],
+    );
+    // Maps, iterations over them, their types (see `CodeGen::map_type`) and their entries are all
+    // `i8*`s. Keys are passed by pointer, and lookups and assignments return pointers to values
+    // (null if the key is missing)
+    add_runtime_func!(
+        module,
+        "__go_makemap",
+        byte_ptr_type,
+        [byte_ptr_type, context.i64_type()],
+    );
+    add_runtime_func!(
+        module,
+        "__go_mapaccess",
+        byte_ptr_type,
+        [byte_ptr_type, byte_ptr_type],
+    );
+    add_runtime_func!(
+        module,
+        "__go_mapassign",
+        byte_ptr_type,
+        [byte_ptr_type, byte_ptr_type],
+    );
+    add_runtime_func!(
+        module,
+        "__go_mapdelete",
+        context.void_type(),
+        [byte_ptr_type, byte_ptr_type],
+    );
+    add_runtime_func!(module, "__go_maplen", context.i64_type(), [byte_ptr_type]);
+    add_runtime_func!(module, "__go_mapiterinit", byte_ptr_type, [byte_ptr_type]);
+    add_runtime_func!(module, "__go_mapiternext", byte_ptr_type, [byte_ptr_type]);
+    add_runtime_func!(
+        module,
+        "__gostring_concat",
+        string_type,
+        [string_type, string_type],
+    );
+    add_runtime_func!(
+        module,
+        "__gostring_compare",
+        context.i64_type(),
+        [string_type, string_type],
+    );
+    // Returns the rune starting at a byte index, and the index of the next one
+    add_runtime_func!(
+        module,
+        "__decode_rune",
+        context.struct_type(&[context.i32_type().into(), context.i64_type().into()], false),
+        [string_type, context.i64_type()],
     );
     add_runtime_func!(
         module,
@@ -54,6 +173,12 @@ fn add_runtime<'a>(module: &Module<'a>, context: &'a Context) {
         context.void_type(),
         [context.i64_type()],
     );
+    add_runtime_func!(
+        module,
+        "__print_uint",
+        context.void_type(),
+        [context.i64_type()],
+    );
     add_runtime_func!(
         module,
         "__print_bool",
@@ -76,56 +201,158 @@ fn add_runtime<'a>(module: &Module<'a>, context: &'a Context) {
         module,
         "__print_gostring",
         context.void_type(),
-        [context.i8_type().ptr_type(AddressSpace::Generic)],
+        [string_type],
+    );
+    add_runtime_func!(
+        module,
+        "__print_pointer",
+        context.void_type(),
+        [byte_ptr_type],
+    );
+    add_runtime_func!(
+        module,
+        "__print_error",
+        context.void_type(),
+        [byte_ptr_type],
     );
+    add_runtime_func!(
+        module,
+        "__print_slice",
+        context.void_type(),
+        [byte_ptr_type, context.i64_type(), context.i64_type()],
+    );
+    // The fmt functions take their arguments (after the format) as an array of `FmtArg`s, and its
+    // length
+    let args_type = codegen::fmt_arg_type(context).ptr_type(AddressSpace::Generic);
+    let len_type = context.i64_type();
+    add_runtime_func!(module, "__fmt_print", context.void_type(), [args_type, len_type]);
+    add_runtime_func!(module, "__fmt_println", context.void_type(), [args_type, len_type]);
+    add_runtime_func!(
+        module,
+        "__fmt_printf",
+        context.void_type(),
+        [string_type, args_type, len_type],
+    );
+    add_runtime_func!(module, "__fmt_sprint", string_type, [args_type, len_type]);
+    add_runtime_func!(module, "__fmt_sprintln", string_type, [args_type, len_type]);
+    add_runtime_func!(
+        module,
+        "__fmt_sprintf",
+        string_type,
+        [string_type, args_type, len_type],
+    );
+    add_runtime_func!(
+        module,
+        "__fmt_errorf",
+        byte_ptr_type,
+        [string_type, args_type, len_type],
+    );
+}
+
+/// Runs `command` (which does `what`), returning what it wrote to stderr if it fails
+fn run(command: &mut Command, what: &str) -> Result<(), String> {
+    match command.output() {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => Err(format!(
+            "{} failed: {}",
+            what,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+        Err(err) => Err(format!("{}: {}", what, err)),
+    }
 }
 
-// NOTE: Once the parser is implemented, `program` will be a &str for file path
-pub fn compile_aot(program: &Program, out_path: &str) -> String {
+/// Reads and parses the Go source file at `path`, registering it in `source_map`
+pub fn parse_file(source_map: &mut SourceMap, path: &str) -> Result<Program, Box<Diagnostic>> {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            // The file is registered empty, so that the diagnostic can point at it
+            let file_id = source_map.add_file(path, "");
+            let span = Span {
+                file_id,
+                start: 0,
+                end: 0,
+            };
+            let msg = format!("open {}: {}", path, err);
+            return Err(Box::new(Diagnostic::error(Code::ReadError, msg, span)));
+        }
+    };
+    let file_id = source_map.add_file(path, &source);
+    parser::parse(&source, file_id)
+}
+
+/// What compiling a program produces, besides the executable
+pub struct Output {
+    /// The LLVM IR of the program
+    pub ir: String,
+    /// The runtime checks left in the program (those that may fail), in source order
+    pub checks: Vec<(Span, Check)>,
+    /// What the escape analysis decided about the variables moved to the heap and about every
+    /// allocation, in source order
+    pub escapes: Vec<(Span, Decision)>,
+}
+
+/// Compiles a parsed program to an executable at `out_path`. The diagnostics returned on failure
+/// point into the `SourceMap` the program was parsed with
+pub fn compile_aot(mut program: Program, out_path: &str) -> Result<Output, Vec<Diagnostic>> {
+    sema::check_program(&mut program)?;
+    // Leave out the runtime checks that can't fail
+    let checks = checks::find_checks(&program);
+    let remaining_checks = checks.remaining();
+    // Decide which variables live on the heap
+    let escapes = escape_analysis::analyze(&program);
+    let decisions = escapes.decisions();
     let context = Context::create();
     // Add global (external) decelerations
-    let mut codegen = CodeGen::new(&context);
+    let mut codegen = CodeGen::new(&context, checks, escapes);
     add_runtime(&codegen.module, &context);
-    if let Err(err) = codegen.gen_program(program) {
-        panic!("{}", err);
+    if let Err(err) = codegen.gen_program(&program) {
+        return Err(vec![err.into()]);
     }
     codegen.optimize(OptimizationLevel::Aggressive);
 
+    // Failures to build the executable aren't about any code in particular, so they point at the
+    // package clause
+    let build_error = |msg: String| {
+        vec![Diagnostic::error(
+            Code::BuildError,
+            msg,
+            program.package_span,
+        )]
+    };
     // Create directory `output` if it doesn't already exist
-    fs::create_dir_all("output/").expect("unable to create output/");
+    if let Err(err) = fs::create_dir_all("output/") {
+        return Err(build_error(format!("mkdir output/: {}", err)));
+    }
     // Generate object file
-    codegen.to_object_file(&format!("output/{}.o", program.package_name));
-    // Compile runtime to object file
-    let compile_runtime = Command::new("cargo")
-        .args(&[
-            "build",
-            "--release",
-            "--manifest-path",
-            "./runtime/Cargo.toml",
-        ])
-        .output();
-    match compile_runtime {
-        Ok(_) => {}
-        Err(err) => panic!("{}", err),
+    let obj_path = format!("output/{}.o", program.package_name);
+    if let Err(err) = codegen.to_object_file(&obj_path) {
+        return Err(build_error(format!("write {}: {}", obj_path, err)));
     }
+    // Compile runtime to object file
+    let mut compile_runtime = Command::new("cargo");
+    compile_runtime.args([
+        "build",
+        "--release",
+        "--manifest-path",
+        "./runtime/Cargo.toml",
+    ]);
+    run(&mut compile_runtime, "building the runtime").map_err(build_error)?;
     // Link runtime and package`
-    let link_runtime = Command::new("gcc")
-        .args(&[
-            "-flto",
-            "-dead_strip",
-            "runtime/target/release/libruntime.a",
-            &format!("output/{}.o", program.package_name),
-            "-o",
-            out_path,
-        ])
-        .output();
-    match link_runtime {
-        Ok(result) => {
-            if !result.stderr.is_empty() {
-                panic!("{}", std::str::from_utf8(&result.stderr).unwrap().trim());
-            }
-        }
-        Err(err) => panic!("{}", err),
-    }
-    codegen.module.print_to_string().to_string() // Return LLVM IR
+    let mut link_runtime = Command::new("gcc");
+    link_runtime.args([
+        "-flto",
+        "-dead_strip",
+        "runtime/target/release/libruntime.a",
+        &obj_path,
+        "-o",
+        out_path,
+    ]);
+    run(&mut link_runtime, "linking").map_err(build_error)?;
+    Ok(Output {
+        ir: codegen.module.print_to_string().to_string(),
+        checks: remaining_checks,
+        escapes: decisions,
+    })
 }
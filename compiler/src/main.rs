@@ -1,134 +1,73 @@
-use compiler::{ast::*, compile_aot, pretty_printer::format_program};
+use compiler::diagnostics::{self, Diagnostic};
+use compiler::{compile_aot, parse_file, pretty_printer::format_program, source_map::SourceMap};
+use std::env;
+use std::process;
+
+fn usage() -> ! {
+    eprintln!("usage: compiler [-json] [-d=check_report] [-m] <file.go>");
+    process::exit(2);
+}
 
 fn main() {
-    let program = Program {
-        package_name: "main".to_string(),
-        imports: vec![],
-        functions: vec![FuncDef {
-            name: "main".to_string(),
-            params: vec![],
-            return_type: None,
-            code: vec![
-                Statement::Assignment {
-                    name: "x".to_string(),
-                    var_type: Type::Int,
-                    expr: Expression::Literal {
-                        expr_type: Type::Int,
-                        value: "10".to_string(),
-                    },
-                },
-                Statement::Assignment {
-                    name: "y".to_string(),
-                    var_type: Type::Int,
-                    expr: Expression::BinaryOp {
-                        expr_type: Type::Int,
-                        op: BinaryOp::Div,
-                        left: Box::new(Expression::Literal {
-                            expr_type: Type::Int,
-                            value: "5".to_string(),
-                        }),
-                        right: Box::new(Expression::Name {
-                            expr_type: Type::Int,
-                            name: "x".to_string(),
-                        }),
-                    },
-                },
-                Statement::Assignment {
-                    name: "lit".to_string(),
-                    var_type: Type::GoString,
-                    expr: Expression::Literal {
-                        expr_type: Type::GoString,
-                        value: "hello world! my number is ".to_string(),
-                    },
-                },
-                Statement::Expression {
-                    expr: Expression::Call {
-                        expr_type: None,
-                        func: "__print_gostring".to_string(),
-                        args: vec![Expression::Name {
-                            expr_type: Type::GoString,
-                            name: "lit".to_string(),
-                        }],
-                    },
-                },
-                Statement::Expression {
-                    expr: Expression::Call {
-                        expr_type: None,
-                        func: "__print_int".to_string(),
-                        args: vec![Expression::Name {
-                            expr_type: Type::GoString,
-                            name: "y".to_string(),
-                        }],
-                    },
-                },
-                Statement::Expression {
-                    expr: Expression::Call {
-                        expr_type: None,
-                        func: "__print_gostring".to_string(),
-                        args: vec![Expression::Literal {
-                            expr_type: Type::GoString,
-                            value: "\\n".to_string(),
-                        }],
-                    },
-                },
-                Statement::If {
-                    cond: Expression::BinaryOp {
-                        expr_type: Type::Bool,
-                        op: BinaryOp::Eq,
-                        left: Box::new(Expression::Literal {
-                            expr_type: Type::Float32,
-                            value: "5".to_string(),
-                        }),
-                        right: Box::new(Expression::Literal {
-                            expr_type: Type::Float32,
-                            value: "5".to_string(),
-                        }),
-                    },
-                    then_block: vec![
-                        Statement::Expression {
-                            expr: Expression::Call {
-                                expr_type: None,
-                                func: "__print_gostring".to_string(),
-                                args: vec![Expression::Literal {
-                                    expr_type: Type::GoString,
-                                    value: "good ".to_string(),
-                                }],
-                            },
-                        },
-                        Statement::Expression {
-                            expr: Expression::Call {
-                                expr_type: None,
-                                func: "__print_gostring".to_string(),
-                                args: vec![Expression::Literal {
-                                    expr_type: Type::GoString,
-                                    value: "✓\\n".to_string(),
-                                }],
-                            },
-                        },
-                    ],
-                    else_block: vec![Statement::Expression {
-                        expr: Expression::Call {
-                            expr_type: None,
-                            func: "__print_gostring".to_string(),
-                            args: vec![Expression::Literal {
-                                expr_type: Type::GoString,
-                                value: "oops\\n".to_string(),
-                            }],
-                        },
-                    }],
-                },
-                // Statement::Expression {
-                //     expr: Expression::Call {
-                //         expr_type: None,
-                //         func: "__flush_stdout".to_string(),
-                //         args: vec![],
-                //     },
-                // },
-            ],
-        }],
+    // With `-json`, the diagnostics are printed to stdout as JSON (see `diagnostics::to_json`)
+    // instead of being rendered to stderr, and nothing else is printed. With `-d=check_report`,
+    // the runtime checks left in the program (those that may fail) are listed on stderr, and with
+    // `-m`, the escape analysis decisions (like `go build -gcflags=-m` prints them)
+    let mut json = false;
+    let mut check_report = false;
+    let mut escape_report = false;
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "-json" => json = true,
+            "-d=check_report" => check_report = true,
+            "-m" => escape_report = true,
+            _ if arg.starts_with('-') || path.is_some() => usage(),
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+
+    let mut source_map = SourceMap::new();
+    let result = parse_file(&mut source_map, &path)
+        .map_err(|diagnostic| vec![*diagnostic])
+        .and_then(|program| {
+            if !json {
+                println!("------- GO CODE: -------");
+                println!("{}", format_program(&program));
+            }
+            compile_aot(program, "output/main")
+        });
+    let diagnostics: Vec<Diagnostic> = match result {
+        Ok(output) => {
+            if !json {
+                println!("------- LLVM IR: -------");
+                println!("{}", output.ir);
+            }
+            if check_report {
+                for (span, check) in output.checks {
+                    let location = source_map.describe(span);
+                    eprintln!("{}: check for {}", location, check.describe());
+                }
+            }
+            if escape_report {
+                for (span, decision) in output.escapes {
+                    let location = source_map.describe(span);
+                    eprintln!("{}: {}", location, decision.describe());
+                }
+            }
+            vec![]
+        }
+        Err(diagnostics) => diagnostics,
     };
-    println!("------- GO CODE: -------");
-    println!("{}", format_program(&program));
-    println!("------- LLVM IR: -------");
-    println!("{}", compile_aot(&program, "output/main"));
+    if json {
+        println!("{}", diagnostics::to_json(&diagnostics, &source_map));
+    } else {
+        for diagnostic in &diagnostics {
+            eprintln!("{}", diagnostic.render(&source_map));
+        }
+    }
+    if !diagnostics.is_empty() {
+        process::exit(1);
+    }
 }
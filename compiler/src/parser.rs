@@ -0,0 +1,1845 @@
+//! A recursive-descent parser that turns the token stream produced by the lexer into the AST.
+//!
+//! The parser is purely syntactic: the types of names, calls, operators and variables declared
+//! without a type aren't known at this point, so they are tagged with `Type::Unknown` until the
+//! semantic checker fills them in. Literals are tagged with their default type.
+
+use crate::ast::*;
+use crate::constant::{self, Value};
+use crate::diagnostics::{Code, Diagnostic};
+use crate::escape;
+use crate::lexer::{self, LexError, Position, Token, TokenKind};
+use crate::pretty_printer::{format_expression, format_type};
+
+type ParseResult<T> = Result<T, Box<Diagnostic>>;
+
+/// The `init; cond; post` of a `for` loop
+type ForHeader = (
+    Option<Box<Statement>>,
+    Option<Expression>,
+    Option<Box<Statement>>,
+);
+
+/// Parses a whole source file. `file_id` is the id of the file in the `SourceMap`, which ends up
+/// in the spans of the AST nodes
+pub fn parse(src: &str, file_id: usize) -> ParseResult<Program> {
+    let tokens = lexer::tokenize(src)
+        .map_err(|err: LexError| {
+            let span = Span {
+                file_id,
+                start: err.pos.offset,
+                end: err.pos.offset,
+            };
+            Box::new(Diagnostic::error(Code::SyntaxError, err.msg, span))
+        })?
+        .into_iter()
+        .filter(|token| !matches!(token.kind, TokenKind::Comment(_)))
+        .collect();
+    Parser {
+        tokens,
+        pos: 0,
+        file_id,
+        header: false,
+    }
+    .parse_program()
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    /// Index of the current token. The token stream always ends with `Eof`, which is never
+    /// consumed, so this always points to a valid token
+    pos: usize,
+    file_id: usize,
+    /// Whether an expression in the header of an `if`, `for` or `switch` statement is being
+    /// parsed, where `T {` starts the body rather than a composite literal (unless it's nested in
+    /// parentheses or brackets)
+    header: bool,
+}
+
+impl Parser {
+    fn peek(&self) -> &TokenKind {
+        &self.tokens[self.pos].kind
+    }
+
+    fn peek_nth(&self, n: usize) -> &TokenKind {
+        let idx = (self.pos + n).min(self.tokens.len() - 1);
+        &self.tokens[idx].kind
+    }
+
+    fn position(&self) -> Position {
+        self.tokens[self.pos].start
+    }
+
+    /// The span from `start` up to the end of the last consumed token
+    fn span_from(&self, start: Position) -> Span {
+        Span {
+            file_id: self.file_id,
+            start: start.offset,
+            end: self.tokens[self.pos.saturating_sub(1)]
+                .end
+                .offset
+                .max(start.offset),
+        }
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn at(&self, kind: &TokenKind) -> bool {
+        self.peek() == kind
+    }
+
+    fn at_semicolon(&self) -> bool {
+        matches!(self.peek(), TokenKind::Semicolon { .. })
+    }
+
+    /// Consumes the current token if it is `kind`
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.at(kind) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn current_span(&self) -> Span {
+        let token = &self.tokens[self.pos];
+        Span {
+            file_id: self.file_id,
+            start: token.start.offset,
+            end: token.end.offset,
+        }
+    }
+
+    /// Reports a syntax error at the current token
+    fn error<T>(&self, msg: impl Into<String>) -> ParseResult<T> {
+        Err(self.error_at(Code::SyntaxError, msg, self.current_span()))
+    }
+
+    /// Reports valid Go that isn't supported yet at the current token
+    fn unsupported<T>(&self, msg: impl Into<String>) -> ParseResult<T> {
+        Err(self.error_at(Code::Unsupported, msg, self.current_span()))
+    }
+
+    fn error_at(&self, code: Code, msg: impl Into<String>, span: Span) -> Box<Diagnostic> {
+        Box::new(Diagnostic::error(code, msg, span))
+    }
+
+    /// Reports the current token as unexpected; `expected` describes what should have been there
+    fn unexpected<T>(&self, expected: &str) -> ParseResult<T> {
+        self.error(format!(
+            "syntax error: unexpected {}, expected {}",
+            self.peek(),
+            expected
+        ))
+    }
+
+    fn expect(&mut self, kind: &TokenKind) -> ParseResult<()> {
+        if self.eat(kind) {
+            Ok(())
+        } else {
+            self.unexpected(&kind.to_string())
+        }
+    }
+
+    fn expect_ident(&mut self) -> ParseResult<String> {
+        match self.peek() {
+            TokenKind::Ident(name) => {
+                let name = name.clone();
+                self.next();
+                Ok(name)
+            }
+            _ => self.unexpected("name"),
+        }
+    }
+
+    /// Runs `parse` with `header` (see `Parser`) set to `header`, restoring it afterwards
+    fn with_header<T>(
+        &mut self,
+        header: bool,
+        parse: impl FnOnce(&mut Self) -> ParseResult<T>,
+    ) -> ParseResult<T> {
+        let outer = std::mem::replace(&mut self.header, header);
+        let result = parse(self);
+        self.header = outer;
+        result
+    }
+
+    /// Expects the end of a statement or declaration. A semicolon may be omitted before a closing
+    /// `)` or `}`
+    fn expect_semicolon(&mut self, after: &str) -> ParseResult<()> {
+        match self.peek() {
+            TokenKind::Semicolon { .. } => {
+                self.next();
+                Ok(())
+            }
+            TokenKind::RParen | TokenKind::RBrace | TokenKind::Eof => Ok(()),
+            tok => self.error(format!("syntax error: unexpected {} after {}", tok, after)),
+        }
+    }
+
+    fn parse_program(&mut self) -> ParseResult<Program> {
+        if !self.at(&TokenKind::Package) {
+            return self.error("syntax error: package statement must be first");
+        }
+        self.next();
+        let start = self.position();
+        let package_name = self.expect_ident()?;
+        let package_span = self.span_from(start);
+        self.expect_semicolon("package clause")?;
+
+        let mut imports = vec![];
+        while self.eat(&TokenKind::Import) {
+            if self.eat(&TokenKind::LParen) {
+                while !self.at(&TokenKind::RParen) {
+                    imports.push(self.parse_import_spec()?);
+                    self.expect_semicolon("import path")?;
+                }
+                self.next();
+            } else {
+                imports.push(self.parse_import_spec()?);
+            }
+            self.expect_semicolon("import declaration")?;
+        }
+
+        let mut types = vec![];
+        let mut functions = vec![];
+        let mut consts = vec![];
+        loop {
+            match self.peek() {
+                TokenKind::Func => functions.push(self.parse_funcdef()?),
+                TokenKind::Const => consts.push(self.parse_const_decl()?),
+                TokenKind::Type => self.parse_type_decl(&mut types)?,
+                TokenKind::Import => {
+                    return self
+                        .error("syntax error: imports must appear before other declarations")
+                }
+                TokenKind::Var => {
+                    return self.unsupported("package-level var declarations are not supported yet")
+                }
+                TokenKind::Eof => break,
+                _ => {
+                    return self
+                        .error("syntax error: non-declaration statement outside function body")
+                }
+            }
+            if !self.at(&TokenKind::Eof) {
+                self.expect_semicolon("top level declaration")?;
+            }
+        }
+
+        Ok(Program {
+            package_name,
+            package_span,
+            imports,
+            types,
+            consts,
+            functions,
+        })
+    }
+
+    fn parse_import_spec(&mut self) -> ParseResult<(String, Span)> {
+        if matches!(self.peek(), TokenKind::Ident(_) | TokenKind::Period) {
+            return self.unsupported("named and dot imports are not supported yet");
+        }
+        let start = self.position();
+        match self.peek() {
+            TokenKind::String(lit) | TokenKind::RawString(lit) => {
+                let path = String::from_utf8_lossy(&escape::unquote(lit)).into_owned();
+                self.next();
+                Ok((path, self.span_from(start)))
+            }
+            _ => self.unexpected("import path"),
+        }
+    }
+
+    /// `type T struct { .. }` and `type ( .. )`, appending the declared types to `types`
+    fn parse_type_decl(&mut self, types: &mut Vec<TypeDecl>) -> ParseResult<()> {
+        self.expect(&TokenKind::Type)?;
+        if self.eat(&TokenKind::LParen) {
+            while !self.at(&TokenKind::RParen) {
+                types.push(self.parse_type_spec()?);
+                self.expect_semicolon("type declaration")?;
+            }
+            self.next();
+        } else {
+            types.push(self.parse_type_spec()?);
+        }
+        Ok(())
+    }
+
+    fn parse_type_spec(&mut self) -> ParseResult<TypeDecl> {
+        let start = self.position();
+        let name = self.expect_ident()?;
+        let span = self.span_from(start);
+        match self.peek() {
+            TokenKind::Struct => {}
+            TokenKind::Assign => return self.unsupported("type aliases are not supported yet"),
+            _ => return self.unsupported("only struct types can be declared for now"),
+        }
+        let fields = self.parse_struct_fields()?;
+        Ok(TypeDecl { name, fields, span })
+    }
+
+    /// Parses `struct { a, b T; U }`, returning its fields. A string after a field declaration
+    /// (its tag) is ignored
+    fn parse_struct_fields(&mut self) -> ParseResult<Vec<Field>> {
+        self.expect(&TokenKind::Struct)?;
+        self.expect(&TokenKind::LBrace)?;
+        let mut fields = vec![];
+        while !self.at(&TokenKind::RBrace) {
+            let start = self.position();
+            let name = match self.peek() {
+                TokenKind::Ident(name) => name.clone(),
+                TokenKind::Star => {
+                    return self.unsupported("embedded pointer fields are not supported yet")
+                }
+                _ => return self.unexpected("field name or embedded type"),
+            };
+            self.next();
+            let embedded = matches!(
+                self.peek(),
+                TokenKind::Semicolon { .. }
+                    | TokenKind::RBrace
+                    | TokenKind::String(_)
+                    | TokenKind::RawString(_)
+            );
+            if embedded {
+                fields.push(Field {
+                    field_type: named_type(&name),
+                    name,
+                    embedded,
+                    span: self.span_from(start),
+                });
+            } else if self.at(&TokenKind::Period) {
+                return self.unsupported("qualified types are not supported yet");
+            } else {
+                let mut names = vec![(name, self.span_from(start))];
+                while self.eat(&TokenKind::Comma) {
+                    let start = self.position();
+                    let name = self.expect_ident()?;
+                    names.push((name, self.span_from(start)));
+                }
+                let field_type = self.parse_type()?;
+                for (name, span) in names {
+                    fields.push(Field {
+                        name,
+                        field_type: field_type.clone(),
+                        embedded,
+                        span,
+                    });
+                }
+            }
+            if let TokenKind::String(_) | TokenKind::RawString(_) = self.peek() {
+                self.next();
+            }
+            self.expect_semicolon("field declaration")?;
+        }
+        self.next();
+        Ok(fields)
+    }
+
+    fn parse_funcdef(&mut self) -> ParseResult<FuncDef> {
+        let start = self.position();
+        self.expect(&TokenKind::Func)?;
+        if self.at(&TokenKind::LParen) {
+            return self.unsupported("methods are not supported yet");
+        }
+        let name = self.expect_ident()?;
+        let (params, _) = self.parse_params()?;
+        let (results, named_results) = self.parse_results()?;
+        let span = self.span_from(start);
+        if !self.at(&TokenKind::LBrace) {
+            return self.error(format!("missing function body for {}", name));
+        }
+        let code = self.parse_block()?;
+        Ok(FuncDef {
+            name,
+            params,
+            results,
+            named_results,
+            code,
+            span,
+        })
+    }
+
+    /// Parses `(a, b int, c string)` or `(int, string)`, and returns whether the parameters are
+    /// named. Unnamed parameters are named `_`
+    fn parse_params(&mut self) -> ParseResult<(Params, bool)> {
+        self.expect(&TokenKind::LParen)?;
+        // Each entry is either `name type`, or a lone name or type, which can't be told apart
+        // until the whole list has been seen
+        // The span is that of the name if there is one, else that of the type
+        let mut entries: Vec<(Span, Option<String>, Option<Type>)> = vec![];
+        while !self.at(&TokenKind::RParen) {
+            let start = self.position();
+            if self.at(&TokenKind::Ellipsis) {
+                return self.unsupported("variadic functions are not supported yet");
+            }
+            match self.peek() {
+                TokenKind::Ident(name) => {
+                    let name = name.clone();
+                    if matches!(self.peek_nth(1), TokenKind::Comma | TokenKind::RParen) {
+                        self.next();
+                        entries.push((self.span_from(start), Some(name), None));
+                    } else if self.peek_nth(1) == &TokenKind::Period {
+                        let r#type = self.parse_type()?;
+                        entries.push((self.span_from(start), None, Some(r#type)));
+                    } else {
+                        self.next();
+                        let span = self.span_from(start);
+                        if self.at(&TokenKind::Ellipsis) {
+                            return self.unsupported("variadic functions are not supported yet");
+                        }
+                        entries.push((span, Some(name), Some(self.parse_type()?)));
+                    }
+                }
+                _ => {
+                    let r#type = self.parse_type()?;
+                    entries.push((self.span_from(start), None, Some(r#type)));
+                }
+            }
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(&TokenKind::RParen)?;
+
+        let named = entries
+            .iter()
+            .any(|(_, name, r#type)| name.is_some() && r#type.is_some());
+        let mut params = vec![];
+        if named {
+            // `a, b int`: names without a type take the type of the next entry that has one
+            let mut pending = vec![];
+            for (span, name, r#type) in entries {
+                match (name, r#type) {
+                    (Some(name), None) => pending.push((name, span)),
+                    (Some(name), Some(r#type)) => {
+                        for (pending_name, pending_span) in pending.drain(..) {
+                            params.push((pending_name, r#type.clone(), pending_span));
+                        }
+                        params.push((name, r#type, span));
+                    }
+                    (None, _) => {
+                        return Err(self.error_at(
+                            Code::SyntaxError,
+                            "syntax error: mixed named and unnamed parameters",
+                            span,
+                        ))
+                    }
+                }
+            }
+            if !pending.is_empty() {
+                return self.error("syntax error: mixed named and unnamed parameters");
+            }
+        } else {
+            for (span, name, r#type) in entries {
+                let r#type = match (name, r#type) {
+                    (_, Some(r#type)) => r#type,
+                    (Some(name), None) => named_type(&name),
+                    (None, None) => unreachable!(),
+                };
+                params.push(("_".to_string(), r#type, span));
+            }
+        }
+        Ok((params, named))
+    }
+
+    /// Parses the (optional) results of a function signature, which are a lone type or a list
+    /// like the parameters, and returns whether they're named
+    fn parse_results(&mut self) -> ParseResult<(Params, bool)> {
+        match self.peek() {
+            TokenKind::LParen => self.parse_params(),
+            TokenKind::LBrace | TokenKind::Semicolon { .. } => Ok((vec![], false)),
+            _ => {
+                let start = self.position();
+                let r#type = self.parse_type()?;
+                Ok((
+                    vec![("_".to_string(), r#type, self.span_from(start))],
+                    false,
+                ))
+            }
+        }
+    }
+
+    fn parse_type(&mut self) -> ParseResult<Type> {
+        match self.peek() {
+            TokenKind::Ident(name) => {
+                let name = name.clone();
+                self.next();
+                if self.at(&TokenKind::Period) {
+                    return self.unsupported("qualified types are not supported yet");
+                }
+                Ok(named_type(&name))
+            }
+            TokenKind::LParen => {
+                self.next();
+                let r#type = self.parse_type()?;
+                self.expect(&TokenKind::RParen)?;
+                Ok(r#type)
+            }
+            TokenKind::LBrack => Ok(self.parse_array_type(false)?.0),
+            TokenKind::Struct => {
+                self.unsupported("struct types can only be used in type declarations for now")
+            }
+            TokenKind::Star => {
+                self.next();
+                Ok(Type::Pointer(Box::new(self.parse_type()?)))
+            }
+            TokenKind::Map => {
+                self.next();
+                self.expect(&TokenKind::LBrack)?;
+                let key = self.parse_type()?;
+                self.expect(&TokenKind::RBrack)?;
+                let value = self.parse_type()?;
+                Ok(Type::Map(Box::new(key), Box::new(value)))
+            }
+            TokenKind::Chan | TokenKind::Func | TokenKind::Interface => {
+                self.unsupported(format!("{} types are not supported yet", self.peek()))
+            }
+            _ => self.unexpected("type"),
+        }
+    }
+
+    /// Parses `[]T` or `[N]T`, where `N` is an integer literal, or `[...]T` if `allow_dots` is set
+    /// (in a composite literal). Returns the type and whether it's `[...]T`, whose length is left
+    /// at 0
+    fn parse_array_type(&mut self, allow_dots: bool) -> ParseResult<(Type, bool)> {
+        let start = self.position();
+        self.expect(&TokenKind::LBrack)?;
+        if self.eat(&TokenKind::RBrack) {
+            return Ok((Type::Slice(Box::new(self.parse_type()?)), false));
+        }
+        if self.eat(&TokenKind::Ellipsis) {
+            self.expect(&TokenKind::RBrack)?;
+            let elem = self.parse_type()?;
+            if !allow_dots {
+                return Err(self.error_at(
+                    Code::BadDotDotDotSyntax,
+                    "invalid use of [...] array (outside a composite literal)",
+                    self.span_from(start),
+                ));
+            }
+            return Ok((Type::Array(0, Box::new(elem)), true));
+        }
+        let len_span = self.current_span();
+        let len = match self.next().kind {
+            TokenKind::Int(lit) if self.at(&TokenKind::RBrack) => {
+                let len = constant::parse_int(&lit).unwrap();
+                if i64::try_from(&len).is_err() {
+                    let msg = format!("invalid array length {} (untyped int constant)", lit);
+                    return Err(self.error_at(Code::InvalidArrayLen, msg, len_span));
+                }
+                u32::try_from(&len).map_err(|_| {
+                    let msg = format!("arrays of {} elements are not supported", lit);
+                    self.error_at(Code::Unsupported, msg, len_span)
+                })?
+            }
+            _ => {
+                return Err(self.error_at(
+                    Code::Unsupported,
+                    "array lengths other than integer literals are not supported yet",
+                    len_span,
+                ))
+            }
+        };
+        self.expect(&TokenKind::RBrack)?;
+        Ok((Type::Array(len.into(), Box::new(self.parse_type()?)), false))
+    }
+
+    fn parse_block(&mut self) -> ParseResult<CodeBlock> {
+        self.expect(&TokenKind::LBrace)?;
+        let mut block = vec![];
+        while !self.at(&TokenKind::RBrace) {
+            if self.at(&TokenKind::Eof) {
+                return self.unexpected("}");
+            }
+            self.parse_statement(&mut block)?;
+            if !self.at(&TokenKind::RBrace) {
+                self.expect_semicolon("statement")?;
+            }
+        }
+        self.next();
+        Ok(block)
+    }
+
+    /// Parses a single statement, appending the result to `block`. A statement can expand into
+    /// several AST statements (e.g. `var a, b = 1, 2`), or into none at all (`;`)
+    fn parse_statement(&mut self, block: &mut CodeBlock) -> ParseResult<()> {
+        let start = self.position();
+        match self.peek() {
+            TokenKind::Semicolon { .. } => Ok(()),
+            TokenKind::Var => self.parse_var_decl(block),
+            TokenKind::Const => {
+                block.push(self.parse_const_decl()?);
+                Ok(())
+            }
+            TokenKind::If => {
+                block.push(self.parse_if()?);
+                Ok(())
+            }
+            TokenKind::For => {
+                block.push(self.parse_for()?);
+                Ok(())
+            }
+            TokenKind::Switch => {
+                block.push(self.parse_switch()?);
+                Ok(())
+            }
+            TokenKind::Fallthrough => {
+                self.next();
+                block.push(Statement::Fallthrough {
+                    span: self.span_from(start),
+                });
+                Ok(())
+            }
+            TokenKind::Break | TokenKind::Continue => {
+                let is_break = self.at(&TokenKind::Break);
+                self.next();
+                let label = match self.peek() {
+                    TokenKind::Ident(_) => Some(self.expect_ident()?),
+                    _ => None,
+                };
+                let span = self.span_from(start);
+                block.push(if is_break {
+                    Statement::Break { label, span }
+                } else {
+                    Statement::Continue { label, span }
+                });
+                Ok(())
+            }
+            TokenKind::Goto => {
+                self.next();
+                let label = self.expect_ident()?;
+                block.push(Statement::Goto {
+                    label,
+                    span: self.span_from(start),
+                });
+                Ok(())
+            }
+            TokenKind::Ident(_) if self.tokens[self.pos + 1].kind == TokenKind::Colon => {
+                let name = self.expect_ident()?;
+                block.push(Statement::Label {
+                    name,
+                    span: self.span_from(start),
+                });
+                self.next();
+                // The labeled statement is empty at the end of a block (or of a case)
+                if matches!(
+                    self.peek(),
+                    TokenKind::RBrace | TokenKind::Case | TokenKind::Default
+                ) {
+                    return Ok(());
+                }
+                self.parse_statement(block)
+            }
+            TokenKind::Return => {
+                self.next();
+                let exprs = if self.at_semicolon() || self.at(&TokenKind::RBrace) {
+                    vec![]
+                } else {
+                    self.parse_expr_list()?
+                };
+                block.push(Statement::Return {
+                    exprs,
+                    span: self.span_from(start),
+                });
+                Ok(())
+            }
+            TokenKind::LBrace => self.unsupported("nested blocks are not supported yet"),
+            TokenKind::Type
+            | TokenKind::Select
+            | TokenKind::Go
+            | TokenKind::Defer
+            | TokenKind::Func => {
+                self.unsupported(format!("{} statements are not supported yet", self.peek()))
+            }
+            _ => {
+                block.push(self.parse_simple_stmt()?);
+                Ok(())
+            }
+        }
+    }
+
+    /// A statement that can also appear in the header of a `for` loop or a switch: an expression,
+    /// `:=`, an assignment, or `++`/`--`
+    fn parse_simple_stmt(&mut self) -> ParseResult<Statement> {
+        let start = self.position();
+        let lhs = self.parse_expr_list()?;
+        let token = self.peek().clone();
+        match token {
+            TokenKind::Define => {
+                self.next();
+                let mut vars = vec![];
+                for expr in lhs {
+                    match expr {
+                        Expression::Name { name, span, .. } => vars.push(ShortVar {
+                            name,
+                            var_type: Type::Unknown,
+                            is_new: false,
+                            span,
+                        }),
+                        _ => {
+                            let msg = format!(
+                                "syntax error: non-name {} on left side of :=",
+                                format_expression(&expr)
+                            );
+                            return Err(self.error_at(Code::SyntaxError, msg, expr.span()));
+                        }
+                    }
+                }
+                let exprs = self.parse_assign_rhs(vars.len())?;
+                Ok(Statement::ShortVarDecl {
+                    vars,
+                    exprs,
+                    span: self.span_from(start),
+                })
+            }
+            TokenKind::Assign => {
+                self.next();
+                let rhs = self.parse_assign_rhs(lhs.len())?;
+                Ok(Statement::Assign {
+                    lhs,
+                    op: None,
+                    rhs,
+                    span: self.span_from(start),
+                })
+            }
+            _ if lhs.len() > 1 => self.unexpected(":= or = or comma"),
+            _ if assign_op(&token).is_some() => {
+                let op = assign_op(&token).unwrap();
+                self.next();
+                let rhs = self.parse_expr()?;
+                Ok(Statement::Assign {
+                    lhs,
+                    op: Some(op),
+                    rhs: vec![rhs],
+                    span: self.span_from(start),
+                })
+            }
+            TokenKind::Inc | TokenKind::Dec => {
+                self.next();
+                Ok(Statement::IncDec {
+                    expr: lhs.into_iter().next().unwrap(),
+                    op: if token == TokenKind::Inc {
+                        BinaryOp::Add
+                    } else {
+                        BinaryOp::Sub
+                    },
+                    span: self.span_from(start),
+                })
+            }
+            TokenKind::Colon => self.error("syntax error: unexpected :"),
+            _ => Ok(Statement::Expression {
+                expr: lhs.into_iter().next().unwrap(),
+                span: self.span_from(start),
+            }),
+        }
+    }
+
+    /// The right-hand side of `=` or `:=`, which must have a value for each of the `vars`
+    /// variables on the left. An index expression alone may give two values (`v, ok := m[k]`,
+    /// which the semantic checker makes sure indexes a map)
+    fn parse_assign_rhs(&mut self, vars: usize) -> ParseResult<Vec<Expression>> {
+        let start = self.position();
+        let exprs = self.parse_expr_list()?;
+        if exprs.len() != vars && !is_multi_value(vars, &exprs) {
+            return Err(self.error_at(
+                Code::WrongAssignCount,
+                assignment_mismatch(vars, exprs.len()),
+                self.span_from(start),
+            ));
+        }
+        Ok(exprs)
+    }
+
+    /// `var x T = e`, `var x = e`, `var x T`, `var a, b = 1, 2` and `var ( ... )`
+    fn parse_var_decl(&mut self, block: &mut CodeBlock) -> ParseResult<()> {
+        self.expect(&TokenKind::Var)?;
+        if self.eat(&TokenKind::LParen) {
+            while !self.at(&TokenKind::RParen) {
+                self.parse_var_spec(block)?;
+                self.expect_semicolon("var declaration")?;
+            }
+            self.next();
+            Ok(())
+        } else {
+            self.parse_var_spec(block)
+        }
+    }
+
+    fn parse_var_spec(&mut self, block: &mut CodeBlock) -> ParseResult<()> {
+        let mut names = vec![];
+        loop {
+            let start = self.position();
+            let name = self.expect_ident()?;
+            names.push((name, self.span_from(start)));
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        let var_type = if self.at(&TokenKind::Assign) {
+            None
+        } else {
+            Some(self.parse_type()?)
+        };
+
+        let exprs = if self.eat(&TokenKind::Assign) {
+            let start = self.position();
+            let exprs = self.parse_assign_rhs(names.len())?;
+            if exprs.len() != names.len() {
+                let msg = "var declarations with fewer values than names are not supported yet";
+                return Err(self.error_at(Code::Unsupported, msg, self.span_from(start)));
+            }
+            exprs
+        } else {
+            // Without an initializer, the variables start out with the zero value of their type
+            let var_type = var_type.as_ref().unwrap();
+            names
+                .iter()
+                .map(|(_, span)| zero_value(var_type, *span))
+                .collect()
+        };
+
+        for ((name, name_span), expr) in names.into_iter().zip(exprs) {
+            let span = Span {
+                end: expr.span().end.max(name_span.end),
+                ..name_span
+            };
+            block.push(Statement::Assignment {
+                name,
+                var_type: var_type.clone().unwrap_or(Type::Unknown),
+                expr,
+                span,
+            });
+        }
+        Ok(())
+    }
+
+    /// `const x T = e`, `const x = e`, `const a, b = 1, 2` and `const ( ... )`. In a parenthesized
+    /// declaration, specs without expressions repeat the type and expressions of the previous one
+    fn parse_const_decl(&mut self) -> ParseResult<Statement> {
+        let start = self.position();
+        self.expect(&TokenKind::Const)?;
+        let mut specs: Vec<ConstSpec> = vec![];
+        if self.eat(&TokenKind::LParen) {
+            while !self.at(&TokenKind::RParen) {
+                let spec = self.parse_const_spec(specs.last(), specs.len() as u64)?;
+                specs.push(spec);
+                self.expect_semicolon("const declaration")?;
+            }
+            self.next();
+        } else {
+            specs.push(self.parse_const_spec(None, 0)?);
+        }
+        Ok(Statement::Const {
+            specs,
+            span: self.span_from(start),
+        })
+    }
+
+    fn parse_const_spec(&mut self, prev: Option<&ConstSpec>, iota: u64) -> ParseResult<ConstSpec> {
+        let start = self.position();
+        let mut names = vec![];
+        loop {
+            let start = self.position();
+            let name = self.expect_ident()?;
+            names.push((name, self.span_from(start)));
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        let (const_type, exprs) = if self.at_semicolon() || self.at(&TokenKind::RParen) {
+            match prev {
+                Some(prev) => (prev.const_type.clone(), prev.exprs.clone()),
+                None => {
+                    let span = self.current_span();
+                    let msg = "missing init expr for const declaration";
+                    return Err(self.error_at(Code::WrongAssignCount, msg, span));
+                }
+            }
+        } else {
+            let const_type = if self.at(&TokenKind::Assign) {
+                Type::Unknown
+            } else {
+                self.parse_type()?
+            };
+            self.expect(&TokenKind::Assign)?;
+            (const_type, self.parse_expr_list()?)
+        };
+        if exprs.len() != names.len() {
+            return Err(self.error_at(
+                Code::WrongAssignCount,
+                if exprs.len() < names.len() {
+                    "missing init expr for const declaration"
+                } else {
+                    "extra init expr"
+                },
+                self.span_from(start),
+            ));
+        }
+        Ok(ConstSpec {
+            names,
+            const_type,
+            exprs,
+            iota,
+        })
+    }
+
+    /// `if cond { .. } else if cond { .. } else { .. }`
+    fn parse_if(&mut self) -> ParseResult<Statement> {
+        let start = self.position();
+        self.expect(&TokenKind::If)?;
+        if self.at(&TokenKind::LBrace) {
+            return self.error("missing condition in if statement");
+        }
+        // The header is parsed as a simple statement, so that an init statement is recognized as
+        // such rather than as a malformed condition
+        let header_start = self.position();
+        let cond = match self.with_header(true, Self::parse_simple_stmt)? {
+            _ if self.at_semicolon() => {
+                let msg = "if statements with an init statement are not supported yet";
+                return Err(self.error_at(Code::Unsupported, msg, self.span_from(header_start)));
+            }
+            Statement::Expression { expr, .. } => expr,
+            _ => {
+                let msg = "missing condition in if statement";
+                return Err(self.error_at(Code::SyntaxError, msg, self.span_from(header_start)));
+            }
+        };
+        let then_block = self.parse_block()?;
+        let else_block = if self.eat(&TokenKind::Else) {
+            match self.peek() {
+                TokenKind::If => vec![self.parse_if()?],
+                TokenKind::LBrace => self.parse_block()?,
+                _ => return self.unexpected("if statement or block"),
+            }
+        } else {
+            vec![]
+        };
+        Ok(Statement::If {
+            cond,
+            then_block,
+            else_block,
+            span: self.span_from(start),
+        })
+    }
+
+    /// `for { .. }`, `for cond { .. }` and `for init; cond; post { .. }`
+    fn parse_for(&mut self) -> ParseResult<Statement> {
+        let start = self.position();
+        self.expect(&TokenKind::For)?;
+        if let Some(stmt) = self.parse_for_range(start)? {
+            return Ok(stmt);
+        }
+        let (init, cond, post) = self.with_header(true, Self::parse_for_header)?;
+        let body = self.parse_block()?;
+        Ok(Statement::For {
+            init,
+            cond,
+            post,
+            body,
+            span: self.span_from(start),
+        })
+    }
+
+    /// The `init; cond; post` (or just `cond`) of a `for` loop, each of which may be missing
+    fn parse_for_header(&mut self) -> ParseResult<ForHeader> {
+        let (mut init, mut cond, mut post) = (None, None, None);
+        if self.at(&TokenKind::LBrace) {
+            return Ok((init, cond, post));
+        }
+        let first = if self.at_semicolon() {
+            None
+        } else {
+            Some(self.parse_simple_stmt()?)
+        };
+        if self.at_semicolon() {
+            // `for init; cond; post`, where each part is optional
+            self.next();
+            init = first.map(Box::new);
+            if !self.at_semicolon() {
+                cond = Some(self.parse_expr()?);
+            }
+            self.expect_semicolon("for loop condition")?;
+            if !self.at(&TokenKind::LBrace) {
+                let stmt = self.parse_simple_stmt()?;
+                if let Statement::ShortVarDecl { span, .. } = stmt {
+                    let msg = "syntax error: cannot declare in post statement of for loop";
+                    return Err(self.error_at(Code::SyntaxError, msg, span));
+                }
+                post = Some(Box::new(stmt));
+            }
+        } else {
+            cond = match first {
+                Some(Statement::Expression { expr, .. }) => Some(expr),
+                _ => return self.unexpected("for loop condition"),
+            };
+        }
+        Ok((init, cond, post))
+    }
+
+    /// The rest of `for range x { .. }`, `for k, v := range x { .. }` or `for k, v = range x { .. }`
+    /// (after `for`). Returns `None`, without consuming anything, if the loop isn't over a range
+    fn parse_for_range(&mut self, start: Position) -> ParseResult<Option<Statement>> {
+        let saved = self.pos;
+        let (lhs, define) = if self.at(&TokenKind::Range) {
+            (vec![], false)
+        } else if self.at(&TokenKind::LBrace) || self.at_semicolon() {
+            return Ok(None);
+        } else {
+            let lhs = self.with_header(true, Self::parse_expr_list)?;
+            let define = match (self.peek(), self.peek_nth(1)) {
+                (TokenKind::Define, TokenKind::Range) => true,
+                (TokenKind::Assign, TokenKind::Range) => false,
+                _ => {
+                    // Something else, which is parsed again as the header of a regular loop
+                    self.pos = saved;
+                    return Ok(None);
+                }
+            };
+            self.next();
+            (lhs, define)
+        };
+        self.expect(&TokenKind::Range)?;
+        if lhs.len() > 2 {
+            let msg = "range clause permits at most two iteration variables";
+            return Err(self.error_at(Code::SyntaxError, msg, lhs[2].span()));
+        }
+        if define {
+            if let Some(expr) = lhs
+                .iter()
+                .find(|expr| !matches!(expr, Expression::Name { .. }))
+            {
+                let msg = format!(
+                    "syntax error: non-name {} on left side of :=",
+                    format_expression(expr)
+                );
+                return Err(self.error_at(Code::SyntaxError, msg, expr.span()));
+            }
+        }
+        let expr = self.with_header(true, Self::parse_expr)?;
+        let body = self.parse_block()?;
+        let mut lhs = lhs.into_iter();
+        Ok(Some(Statement::ForRange {
+            key: lhs.next(),
+            value: lhs.next(),
+            define,
+            expr: Box::new(expr),
+            body,
+            span: self.span_from(start),
+        }))
+    }
+
+    /// `switch { .. }`, `switch tag { .. }` and `switch init; tag { .. }` (where the tag is
+    /// optional)
+    fn parse_switch(&mut self) -> ParseResult<Statement> {
+        let start = self.position();
+        self.expect(&TokenKind::Switch)?;
+        let (init, tag) = self.with_header(true, Self::parse_switch_header)?;
+        self.expect(&TokenKind::LBrace)?;
+        let mut cases = vec![];
+        while !self.at(&TokenKind::RBrace) {
+            let case_start = self.position();
+            let exprs = match self.peek() {
+                TokenKind::Case => {
+                    self.next();
+                    Some(self.parse_expr_list()?)
+                }
+                TokenKind::Default => {
+                    self.next();
+                    None
+                }
+                _ => return self.unexpected("case or default or }"),
+            };
+            self.expect(&TokenKind::Colon)?;
+            let mut body = vec![];
+            while !matches!(
+                self.peek(),
+                TokenKind::Case | TokenKind::Default | TokenKind::RBrace
+            ) {
+                if self.at(&TokenKind::Eof) {
+                    return self.unexpected("}");
+                }
+                self.parse_statement(&mut body)?;
+                if !matches!(
+                    self.peek(),
+                    TokenKind::Case | TokenKind::Default | TokenKind::RBrace
+                ) {
+                    self.expect_semicolon("statement")?;
+                }
+            }
+            cases.push(CaseClause {
+                exprs,
+                body,
+                span: self.span_from(case_start),
+            });
+        }
+        self.next();
+        Ok(Statement::Switch {
+            init,
+            tag,
+            cases,
+            span: self.span_from(start),
+        })
+    }
+
+    /// The `init; tag` of a switch, both of which may be missing
+    fn parse_switch_header(&mut self) -> ParseResult<(Option<Box<Statement>>, Option<Expression>)> {
+        let (mut init, mut tag) = (None, None);
+        if self.at(&TokenKind::LBrace) {
+            return Ok((init, tag));
+        }
+        let first = if self.at_semicolon() {
+            None
+        } else {
+            Some(self.parse_simple_stmt()?)
+        };
+        if self.at_semicolon() {
+            self.next();
+            init = first.map(Box::new);
+            if !self.at(&TokenKind::LBrace) {
+                tag = Some(self.parse_expr()?);
+            }
+        } else {
+            tag = match first {
+                Some(Statement::Expression { expr, .. }) => Some(expr),
+                _ => return self.unexpected("switch expression"),
+            };
+        }
+        Ok((init, tag))
+    }
+
+    fn parse_expr_list(&mut self) -> ParseResult<Vec<Expression>> {
+        let mut exprs = vec![self.parse_expr()?];
+        while self.eat(&TokenKind::Comma) {
+            exprs.push(self.parse_expr()?);
+        }
+        Ok(exprs)
+    }
+
+    fn parse_expr(&mut self) -> ParseResult<Expression> {
+        self.parse_binary_expr(1)
+    }
+
+    /// Precedence climbing over Go's five binary precedence levels
+    fn parse_binary_expr(&mut self, min_prec: u8) -> ParseResult<Expression> {
+        let start = self.position();
+        let mut left = self.parse_unary_expr()?;
+        loop {
+            let prec = precedence(self.peek());
+            let op = match binary_op(self.peek()) {
+                Some(op) if prec >= min_prec => op,
+                _ => return Ok(left),
+            };
+            self.next();
+            let right = self.parse_binary_expr(prec + 1)?;
+            left = Expression::BinaryOp {
+                expr_type: Type::Unknown,
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+                span: self.span_from(start),
+            };
+        }
+    }
+
+    fn parse_unary_expr(&mut self) -> ParseResult<Expression> {
+        let start = self.position();
+        match self.peek() {
+            TokenKind::Plus
+            | TokenKind::Minus
+            | TokenKind::Bang
+            | TokenKind::Caret
+            | TokenKind::Amp
+            | TokenKind::Star => {
+                let op = match self.next().kind {
+                    TokenKind::Plus => UnaryOp::Plus,
+                    TokenKind::Minus => UnaryOp::Neg,
+                    TokenKind::Bang => UnaryOp::Not,
+                    TokenKind::Caret => UnaryOp::Complement,
+                    TokenKind::Amp => UnaryOp::Addr,
+                    _ => UnaryOp::Deref,
+                };
+                let expr = self.parse_unary_expr()?;
+                Ok(Expression::UnaryOp {
+                    expr_type: Type::Unknown,
+                    op,
+                    expr: Box::new(expr),
+                    span: self.span_from(start),
+                })
+            }
+            TokenKind::Arrow => self.unsupported(format!(
+                "unary operator {} is not supported yet",
+                self.peek()
+            )),
+            _ => self.parse_primary_expr(),
+        }
+    }
+
+    /// Parses the parenthesized arguments of a call, and whether the last one is followed by `...`
+    fn parse_call_args(&mut self) -> ParseResult<(Vec<Expression>, bool)> {
+        self.expect(&TokenKind::LParen)?;
+        let mut args = vec![];
+        let mut has_dots = false;
+        while !self.at(&TokenKind::RParen) {
+            args.push(self.with_header(false, Self::parse_expr)?);
+            // Only the last argument can be followed by `...`
+            has_dots = self.eat(&TokenKind::Ellipsis);
+            if !self.eat(&TokenKind::Comma) || has_dots {
+                break;
+            }
+        }
+        self.expect(&TokenKind::RParen)?;
+        Ok((args, has_dots))
+    }
+
+    /// Makes the conversion `expr_type(args)` (where the type is written `name`), which must have
+    /// a single argument
+    fn conversion(
+        &self,
+        expr_type: Type,
+        name: &str,
+        mut args: Vec<Expression>,
+        has_dots: bool,
+        span: Span,
+    ) -> ParseResult<Expression> {
+        if args.len() != 1 {
+            let msg = format!(
+                "{} in conversion to {}",
+                if args.is_empty() {
+                    "missing argument"
+                } else {
+                    "too many arguments"
+                },
+                name
+            );
+            return Err(self.error_at(Code::WrongArgCount, msg, span));
+        }
+        if has_dots {
+            let msg = format!("invalid use of ... in conversion to {}", name);
+            return Err(self.error_at(Code::BadDotDotDotSyntax, msg, span));
+        }
+        Ok(Expression::Conversion {
+            expr_type,
+            expr: Box::new(args.pop().unwrap()),
+            span,
+        })
+    }
+
+    /// Parses an operand that starts with an array, slice or map type: a composite literal, or a
+    /// conversion
+    fn parse_type_operand(&mut self) -> ParseResult<Expression> {
+        let start = self.position();
+        let (expr_type, infer_len) = if self.at(&TokenKind::Map) {
+            (self.parse_type()?, false)
+        } else {
+            self.parse_array_type(true)?
+        };
+        let type_span = self.span_from(start);
+        match self.peek() {
+            TokenKind::LBrace => {
+                let syntax = if infer_len {
+                    LitType::InferredLen
+                } else {
+                    LitType::Explicit
+                };
+                self.parse_composite_lit(expr_type, syntax, start)
+            }
+            _ if infer_len => Err(self.error_at(
+                Code::BadDotDotDotSyntax,
+                "invalid use of [...] array (outside a composite literal)",
+                type_span,
+            )),
+            TokenKind::LParen => {
+                let (args, has_dots) = self.parse_call_args()?;
+                let name = format_type(&expr_type);
+                self.conversion(expr_type, &name, args, has_dots, self.span_from(start))
+            }
+            _ => {
+                let msg = format!("{} (type) is not an expression", format_type(&expr_type));
+                Err(self.error_at(Code::NotAnExpr, msg, type_span))
+            }
+        }
+    }
+
+    /// Parses the `{<elements>}` of a composite literal of type `expr_type` (which starts at
+    /// `start`)
+    fn parse_composite_lit(
+        &mut self,
+        expr_type: Type,
+        syntax: LitType,
+        start: Position,
+    ) -> ParseResult<Expression> {
+        self.expect(&TokenKind::LBrace)?;
+        let mut elements = vec![];
+        while !self.at(&TokenKind::RBrace) {
+            let value = self.parse_element()?;
+            elements.push(if self.eat(&TokenKind::Colon) {
+                KeyedElement {
+                    key: Some(value),
+                    value: self.parse_element()?,
+                }
+            } else {
+                KeyedElement { key: None, value }
+            });
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        // A newline after the last element ends it with a semicolon, unless it has a comma
+        if self.at(&TokenKind::Semicolon { automatic: true }) {
+            return self.error(
+                "syntax error: unexpected newline in composite literal; possibly missing comma or }",
+            );
+        }
+        self.expect(&TokenKind::RBrace)?;
+        Ok(Expression::CompositeLit {
+            expr_type,
+            syntax,
+            elements,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parses an element (or the key of one) of a composite literal, which can be a composite
+    /// literal whose type is elided
+    fn parse_element(&mut self) -> ParseResult<Expression> {
+        if self.at(&TokenKind::LBrace) {
+            let start = self.position();
+            self.parse_composite_lit(Type::Unknown, LitType::Elided, start)
+        } else {
+            self.with_header(false, Self::parse_expr)
+        }
+    }
+
+    fn parse_primary_expr(&mut self) -> ParseResult<Expression> {
+        let start = self.position();
+        if self.at(&TokenKind::LBrack) || self.at(&TokenKind::Map) {
+            let expr = self.parse_type_operand()?;
+            return self.parse_postfix(expr, start);
+        }
+        let token = self.next();
+        let token_span = self.span_from(start);
+        let expr = match token.kind {
+            TokenKind::Ident(name) => match name.as_str() {
+                "true" | "false" => Expression::Literal {
+                    expr_type: Type::Bool,
+                    value: Value::Bool(name == "true"),
+                    span: token_span,
+                },
+                // `new` takes a type rather than an expression
+                "new" if self.at(&TokenKind::LParen) => {
+                    self.next();
+                    let elem = self.with_header(false, Self::parse_type)?;
+                    self.expect(&TokenKind::RParen)?;
+                    Expression::New {
+                        expr_type: Type::Pointer(Box::new(elem)),
+                        span: self.span_from(start),
+                    }
+                }
+                // So does `make`, whose other arguments are expressions
+                "make" if self.at(&TokenKind::LParen) => {
+                    self.next();
+                    let expr_type = self.with_header(false, Self::parse_type)?;
+                    let mut args = vec![];
+                    while self.eat(&TokenKind::Comma) && !self.at(&TokenKind::RParen) {
+                        args.push(self.with_header(false, Self::parse_expr)?);
+                    }
+                    self.expect(&TokenKind::RParen)?;
+                    Expression::Make {
+                        expr_type,
+                        args,
+                        span: self.span_from(start),
+                    }
+                }
+                _ if self.at(&TokenKind::LParen) => {
+                    let (args, has_dots) = self.parse_call_args()?;
+                    let span = self.span_from(start);
+                    match predeclared_type(&name) {
+                        // A "call" of a type is a conversion
+                        Some(expr_type) => {
+                            self.conversion(expr_type, &name, args, has_dots, span)?
+                        }
+                        None => Expression::Call {
+                            expr_type: Some(Type::Unknown),
+                            package: None,
+                            func: name,
+                            func_span: token_span,
+                            args,
+                            has_dots,
+                            span,
+                        },
+                    }
+                }
+                // A composite literal of a declared type, which in the header of a statement must
+                // be parenthesized: `T {` starts the body there
+                _ if self.at(&TokenKind::LBrace) && !self.header => {
+                    self.parse_composite_lit(named_type(&name), LitType::Explicit, start)?
+                }
+                // A qualified identifier like `fmt.Println`, which can only be called for now
+                _ if self.at(&TokenKind::Period)
+                    && matches!(self.peek_nth(1), TokenKind::Ident(_))
+                    && *self.peek_nth(2) == TokenKind::LParen =>
+                {
+                    self.next();
+                    let func_start = self.position();
+                    let func = self.expect_ident()?;
+                    let func_span = self.span_from(func_start);
+                    let (args, has_dots) = self.parse_call_args()?;
+                    Expression::Call {
+                        expr_type: Some(Type::Unknown),
+                        package: Some((name, token_span)),
+                        func,
+                        func_span,
+                        args,
+                        has_dots,
+                        span: self.span_from(start),
+                    }
+                }
+                _ => Expression::Name {
+                    expr_type: Type::Unknown,
+                    name,
+                    span: token_span,
+                },
+            },
+            TokenKind::Int(lit) => Expression::Literal {
+                expr_type: Type::Int,
+                value: Value::Int(constant::parse_int(&lit).unwrap()),
+                span: token_span,
+            },
+            TokenKind::Float(lit) => Expression::Literal {
+                expr_type: Type::Float64,
+                value: Value::Float(constant::parse_float(&lit).ok_or_else(|| {
+                    self.error_at(
+                        Code::NumericOverflow,
+                        format!("constant {} is too large", lit),
+                        token_span,
+                    )
+                })?),
+                span: token_span,
+            },
+            TokenKind::Rune(lit) => Expression::Literal {
+                expr_type: Type::Int32,
+                value: Value::Int(escape::unquote_rune(&lit).into()),
+                span: token_span,
+            },
+            TokenKind::String(lit) | TokenKind::RawString(lit) => Expression::Literal {
+                expr_type: Type::GoString,
+                value: Value::String(escape::unquote(&lit)),
+                span: token_span,
+            },
+            TokenKind::Imaginary(_) => {
+                return Err(self.error_at(
+                    Code::Unsupported,
+                    "complex numbers are not supported",
+                    token_span,
+                ))
+            }
+            TokenKind::LParen => {
+                let expr = self.with_header(false, Self::parse_expr)?;
+                self.expect(&TokenKind::RParen)?;
+                expr
+            }
+            kind => {
+                return Err(self.error_at(
+                    Code::SyntaxError,
+                    format!("syntax error: unexpected {}, expected expression", kind),
+                    token_span,
+                ))
+            }
+        };
+        self.parse_postfix(expr, start)
+    }
+
+    /// Parses the index, slice and selector expressions applied to `expr` (which starts at
+    /// `start`)
+    fn parse_postfix(&mut self, mut expr: Expression, start: Position) -> ParseResult<Expression> {
+        loop {
+            match self.peek() {
+                TokenKind::LBrack => {
+                    self.next();
+                    let low = if self.at(&TokenKind::Colon) {
+                        None
+                    } else {
+                        Some(Box::new(self.with_header(false, Self::parse_expr)?))
+                    };
+                    if self.eat(&TokenKind::Colon) {
+                        let high = if self.at(&TokenKind::RBrack) || self.at(&TokenKind::Colon) {
+                            None
+                        } else {
+                            Some(Box::new(self.with_header(false, Self::parse_expr)?))
+                        };
+                        // In a 3-index slice, only the first index is optional
+                        let max = if self.at(&TokenKind::Colon) {
+                            if high.is_none() {
+                                return self.error("middle index required in 3-index slice");
+                            }
+                            self.next();
+                            if self.at(&TokenKind::RBrack) {
+                                return self.error("final index required in 3-index slice");
+                            }
+                            Some(Box::new(self.with_header(false, Self::parse_expr)?))
+                        } else {
+                            None
+                        };
+                        self.expect(&TokenKind::RBrack)?;
+                        expr = Expression::Slice {
+                            expr_type: Type::Unknown,
+                            expr: Box::new(expr),
+                            low,
+                            high,
+                            max,
+                            span: self.span_from(start),
+                        };
+                    } else {
+                        let index = match low {
+                            Some(index) => index,
+                            None => return self.unexpected("operand"),
+                        };
+                        self.expect(&TokenKind::RBrack)?;
+                        expr = Expression::Index {
+                            expr_type: Type::Unknown,
+                            expr: Box::new(expr),
+                            index,
+                            span: self.span_from(start),
+                        };
+                    }
+                }
+                TokenKind::Period => {
+                    self.next();
+                    let field = match self.peek() {
+                        TokenKind::Ident(_) => self.expect_ident()?,
+                        TokenKind::LParen => {
+                            return self.unsupported("type assertions are not supported yet")
+                        }
+                        _ => return self.unexpected("name or ("),
+                    };
+                    expr = Expression::Selector {
+                        expr_type: Type::Unknown,
+                        expr: Box::new(expr),
+                        field,
+                        path: vec![],
+                        span: self.span_from(start),
+                    };
+                }
+                TokenKind::LParen => return self.unsupported("only named functions can be called"),
+                _ => return Ok(expr),
+            }
+        }
+    }
+}
+
+/// The precedence of a binary operator, or 0 if the token isn't one
+fn precedence(kind: &TokenKind) -> u8 {
+    match kind {
+        TokenKind::PipePipe => 1,
+        TokenKind::AmpAmp => 2,
+        TokenKind::EqEq
+        | TokenKind::NotEq
+        | TokenKind::Lt
+        | TokenKind::LtEq
+        | TokenKind::Gt
+        | TokenKind::GtEq => 3,
+        TokenKind::Plus | TokenKind::Minus | TokenKind::Pipe | TokenKind::Caret => 4,
+        TokenKind::Star
+        | TokenKind::Slash
+        | TokenKind::Percent
+        | TokenKind::Shl
+        | TokenKind::Shr
+        | TokenKind::Amp
+        | TokenKind::AmpCaret => 5,
+        _ => 0,
+    }
+}
+
+fn binary_op(kind: &TokenKind) -> Option<BinaryOp> {
+    Some(match kind {
+        TokenKind::Plus => BinaryOp::Add,
+        TokenKind::Minus => BinaryOp::Sub,
+        TokenKind::Star => BinaryOp::Mul,
+        TokenKind::Slash => BinaryOp::Div,
+        TokenKind::Percent => BinaryOp::Rem,
+        TokenKind::EqEq => BinaryOp::Eq,
+        TokenKind::NotEq => BinaryOp::Neq,
+        TokenKind::Gt => BinaryOp::Ge,
+        TokenKind::Lt => BinaryOp::Le,
+        TokenKind::GtEq => BinaryOp::Geq,
+        TokenKind::LtEq => BinaryOp::Leq,
+        TokenKind::Shl => BinaryOp::Shl,
+        TokenKind::Shr => BinaryOp::Shr,
+        TokenKind::Amp => BinaryOp::And,
+        TokenKind::Pipe => BinaryOp::Or,
+        TokenKind::Caret => BinaryOp::Xor,
+        TokenKind::AmpCaret => BinaryOp::AndNot,
+        TokenKind::AmpAmp => BinaryOp::LogicalAnd,
+        TokenKind::PipePipe => BinaryOp::LogicalOr,
+        _ => return None,
+    })
+}
+
+/// The type a predeclared type name (e.g. `int` or `byte`) stands for
+fn predeclared_type(name: &str) -> Option<Type> {
+    Some(match name {
+        "int" => Type::Int,
+        "int8" => Type::Int8,
+        "int16" => Type::Int16,
+        "int32" | "rune" => Type::Int32,
+        "int64" => Type::Int64,
+        "uint" => Type::Uint,
+        "uint8" | "byte" => Type::Uint8,
+        "uint16" => Type::Uint16,
+        "uint32" => Type::Uint32,
+        "uint64" => Type::Uint64,
+        "uintptr" => Type::Uintptr,
+        "bool" => Type::Bool,
+        "float32" => Type::Float32,
+        "float64" => Type::Float64,
+        "string" => Type::GoString,
+        "error" => Type::Error,
+        _ => return None,
+    })
+}
+
+/// The type a type name stands for: a predeclared type, or else a declared one (which the semantic
+/// checker makes sure exists)
+fn named_type(name: &str) -> Type {
+    predeclared_type(name).unwrap_or_else(|| Type::Named(name.to_string()))
+}
+
+/// The operator of an assignment like `+=`
+fn assign_op(kind: &TokenKind) -> Option<BinaryOp> {
+    Some(match kind {
+        TokenKind::PlusAssign => BinaryOp::Add,
+        TokenKind::MinusAssign => BinaryOp::Sub,
+        TokenKind::StarAssign => BinaryOp::Mul,
+        TokenKind::SlashAssign => BinaryOp::Div,
+        TokenKind::PercentAssign => BinaryOp::Rem,
+        TokenKind::AmpAssign => BinaryOp::And,
+        TokenKind::PipeAssign => BinaryOp::Or,
+        TokenKind::CaretAssign => BinaryOp::Xor,
+        TokenKind::ShlAssign => BinaryOp::Shl,
+        TokenKind::ShrAssign => BinaryOp::Shr,
+        TokenKind::AmpCaretAssign => BinaryOp::AndNot,
+        _ => return None,
+    })
+}
+
+fn assignment_mismatch(vars: usize, values: usize) -> String {
+    format!(
+        "assignment mismatch: {} variable{} but {} value{}",
+        vars,
+        if vars == 1 { "" } else { "s" },
+        values,
+        if values == 1 { "" } else { "s" },
+    )
+}
+
+/// Whether `exprs` may be a single expression that gives the values of all the `vars`: the index
+/// expression of `v, ok = m[k]`, or a call of a function with several results
+fn is_multi_value(vars: usize, exprs: &[Expression]) -> bool {
+    match exprs {
+        [Expression::Index { .. }] => vars == 2,
+        [Expression::Call { .. }] => vars > 1,
+        _ => false,
+    }
+}
+
+/// The value a variable declared without an initializer starts out with: a literal, except for
+/// arrays, slices, structs, pointers, maps and errors
+fn zero_value(r#type: &Type, span: Span) -> Expression {
+    if let Type::Array(..)
+    | Type::Slice(_)
+    | Type::Named(_)
+    | Type::Pointer(_)
+    | Type::Map(..)
+    | Type::Error = r#type
+    {
+        return Expression::ZeroValue {
+            expr_type: r#type.clone(),
+            span,
+        };
+    }
+    Expression::Literal {
+        expr_type: r#type.clone(),
+        value: match r#type {
+            _ if r#type.is_integer() => Value::Int(0.into()),
+            Type::Float32 | Type::Float64 => Value::Int(0.into()).to_float(),
+            Type::Bool => Value::Bool(false),
+            _ => Value::String(vec![]),
+        },
+        span,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pretty_printer::{format_expression, format_program};
+
+    /// Parses `body` as the body of `main`
+    fn parse_main(body: &str) -> ParseResult<Program> {
+        parse(&format!("package main\nfunc main() {{\n{}\n}}\n", body), 0)
+    }
+
+    fn error(src: &str) -> (Code, String) {
+        let err = parse(src, 0).unwrap_err();
+        (err.code, err.msg)
+    }
+
+    fn main_error(body: &str) -> (Code, String) {
+        let err = parse_main(body).unwrap_err();
+        (err.code, err.msg)
+    }
+
+    /// Parses `expr` and prints it again, with the parentheses the pretty printer needs
+    fn reformat(expr: &str) -> String {
+        let program = parse_main(&format!("_ = {}", expr)).unwrap();
+        match &program.functions[0].code[0] {
+            Statement::Assign { rhs, .. } => format_expression(&rhs[0]),
+            stmt => panic!("unexpected statement {:?}", stmt),
+        }
+    }
+
+    #[test]
+    fn program() {
+        let src = "package main
+
+import \"fmt\"
+
+const (
+	A = iota
+	B
+)
+
+func f(a, b int) (int, bool) { return a + b, !true }
+
+func main() {
+	var x, y int
+	z := -x; x, y = y, x
+	for i := 0; i < 3; i++ {
+		if i == 1 {
+			continue
+		} else if i > 2 {
+			break
+		}
+	}
+	s := []int{1, 2}[1:]
+	fmt.Println(s, z, 'a', \"\\n\", 0x10, 1.5)
+}
+";
+        let expected = "package main
+
+import (
+    \"fmt\"
+)
+
+const (
+    A = iota
+    B = iota
+)
+
+func f(a int, b int) (int, bool) {
+    return a + b, !true
+}
+
+func main() {
+    var x int = 0
+    var y int = 0
+    z := -x
+    x, y = y, x
+    for i := 0; i < 3; i++ {
+        if i == 1 {
+            continue
+        } else if i > 2 {
+            break
+        }
+    }
+    s := []int{1, 2}[1:]
+    fmt.Println(s, z, 97, \"\\n\", 16, 1.5)
+}
+";
+        assert_eq!(format_program(&parse(src, 0).unwrap()), expected);
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(reformat("a + b*c"), "a + b * c");
+        assert_eq!(reformat("(a + b) * c"), "(a + b) * c");
+        assert_eq!(reformat("a - b - c"), "a - b - c");
+        assert_eq!(reformat("a - (b - c)"), "a - (b - c)");
+        assert_eq!(reformat("a || b && c == d"), "a || b && c == d");
+        assert_eq!(reformat("(a || b) && c"), "(a || b) && c");
+        assert_eq!(reformat("-a[i] << 2 | b"), "-a[i] << 2 | b");
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let syntax = |msg: &str| (Code::SyntaxError, msg.to_string());
+        assert_eq!(
+            error("func main() {}"),
+            syntax("syntax error: package statement must be first")
+        );
+        assert_eq!(
+            main_error("x := 1 y := 2"),
+            syntax("syntax error: unexpected name y after statement")
+        );
+        assert_eq!(
+            main_error("if {\n}"),
+            syntax("missing condition in if statement")
+        );
+        assert_eq!(
+            main_error("if x := 1 {\n}"),
+            syntax("missing condition in if statement")
+        );
+        assert_eq!(
+            error("package main\nfunc main()\n{\n}\n"),
+            syntax("missing function body for main")
+        );
+        // Lexer errors are syntax errors too
+        assert_eq!(
+            main_error("x := 08"),
+            syntax("invalid digit in octal literal")
+        );
+        assert_eq!(
+            main_error(r#"x := "\q""#),
+            syntax("unknown escape sequence")
+        );
+        assert_eq!(
+            main_error("var a, b int = 1"),
+            (
+                Code::WrongAssignCount,
+                "assignment mismatch: 2 variables but 1 value".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        let unsupported = |msg: &str| (Code::Unsupported, msg.to_string());
+        assert_eq!(
+            error("package main\nvar x int\n"),
+            unsupported("package-level var declarations are not supported yet")
+        );
+        assert_eq!(
+            main_error("var a, b = f()"),
+            unsupported("var declarations with fewer values than names are not supported yet")
+        );
+        for header in ["x := 1; x > 0", "f(); true", "x++; x > 0"] {
+            assert_eq!(
+                main_error(&format!("if {} {{\n}}", header)),
+                unsupported("if statements with an init statement are not supported yet")
+            );
+        }
+        // The span covers the init statement
+        let err = parse_main("if x := 1; x > 0 {\n}").unwrap_err();
+        assert_eq!((err.span.start, err.span.end), (30, 36));
+    }
+}
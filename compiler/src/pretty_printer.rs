@@ -1,19 +1,41 @@
 // Prints the AST as (well formatted) Go code
 
 use crate::ast::*;
+use crate::escape;
 
-fn format_type(r#type: &Type) -> String {
+pub fn format_type(r#type: &Type) -> String {
     match r#type {
+        Type::Array(len, elem) => return format!("[{}]{}", len, format_type(elem)),
+        Type::Slice(elem) => return format!("[]{}", format_type(elem)),
+        Type::Named(name) => return name.clone(),
+        Type::Pointer(elem) => return format!("*{}", format_type(elem)),
+        Type::Map(key, value) => return format!("map[{}]{}", format_type(key), format_type(value)),
+        Type::Tuple(types) => {
+            let types: Vec<String> = types.iter().map(format_type).collect();
+            return format!("({})", types.join(", "));
+        }
         Type::Int => "int",
+        Type::Int8 => "int8",
+        Type::Int16 => "int16",
+        Type::Int32 => "int32",
+        Type::Int64 => "int64",
+        Type::Uint => "uint",
+        Type::Uint8 => "uint8",
+        Type::Uint16 => "uint16",
+        Type::Uint32 => "uint32",
+        Type::Uint64 => "uint64",
+        Type::Uintptr => "uintptr",
         Type::Float32 => "float32",
         Type::Float64 => "float64",
         Type::Bool => "bool",
         Type::GoString => "string",
+        Type::Error => "error",
+        Type::Unknown => "<unknown>",
     }
     .to_string()
 }
 
-fn format_name_type((name, r#type): &(String, Type)) -> String {
+fn format_name_type((name, r#type, _): &(String, Type, Span)) -> String {
     format!("{} {}", name, format_type(r#type))
 }
 
@@ -25,22 +47,65 @@ fn format_params(params: &Params) -> String {
         .join(", ")
 }
 
-fn format_bop(bop: &BinaryOp) -> String {
+pub fn format_bop(bop: &BinaryOp) -> String {
     match bop {
         BinaryOp::Add => "+",
         BinaryOp::Sub => "-",
         BinaryOp::Mul => "*",
         BinaryOp::Div => "/",
+        BinaryOp::Rem => "%",
         BinaryOp::Eq => "==",
         BinaryOp::Neq => "!=",
         BinaryOp::Ge => ">",
         BinaryOp::Le => "<",
         BinaryOp::Geq => ">=",
         BinaryOp::Leq => "<=",
+        BinaryOp::Shl => "<<",
+        BinaryOp::Shr => ">>",
+        BinaryOp::And => "&",
+        BinaryOp::Or => "|",
+        BinaryOp::Xor => "^",
+        BinaryOp::AndNot => "&^",
+        BinaryOp::LogicalAnd => "&&",
+        BinaryOp::LogicalOr => "||",
+    }
+    .to_string()
+}
+
+pub fn format_uop(uop: &UnaryOp) -> String {
+    match uop {
+        UnaryOp::Plus => "+",
+        UnaryOp::Neg => "-",
+        UnaryOp::Not => "!",
+        UnaryOp::Complement => "^",
+        UnaryOp::Addr => "&",
+        UnaryOp::Deref => "*",
     }
     .to_string()
 }
 
+/// Go's precedence level of a binary operator (higher binds tighter)
+fn bop_precedence(bop: &BinaryOp) -> u8 {
+    match bop {
+        BinaryOp::Mul
+        | BinaryOp::Div
+        | BinaryOp::Rem
+        | BinaryOp::Shl
+        | BinaryOp::Shr
+        | BinaryOp::And
+        | BinaryOp::AndNot => 5,
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Or | BinaryOp::Xor => 4,
+        BinaryOp::Eq
+        | BinaryOp::Neq
+        | BinaryOp::Ge
+        | BinaryOp::Le
+        | BinaryOp::Geq
+        | BinaryOp::Leq => 3,
+        BinaryOp::LogicalAnd => 2,
+        BinaryOp::LogicalOr => 1,
+    }
+}
+
 fn push_indent(indent: usize, s: &mut String) {
     s.push_str(&" ".repeat(indent));
 }
@@ -48,7 +113,19 @@ fn push_indent(indent: usize, s: &mut String) {
 pub fn format_program(program: &Program) -> String {
     let mut s = format!("package {}\n\n", program.package_name);
     if !program.imports.is_empty() {
-        s.push_str(&format!("import (\"{}\")\n\n", program.imports.join(", ")));
+        s.push_str("import (\n");
+        for (import, _) in &program.imports {
+            s.push_str(&format!("    {}\n", escape::quote(import.as_bytes())));
+        }
+        s.push_str(")\n\n");
+    }
+    for decl in &program.types {
+        s.push_str(&format_type_decl(decl));
+        s.push_str("\n\n");
+    }
+    for decl in &program.consts {
+        s.push_str(&format_statement(decl, 0));
+        s.push_str("\n\n");
     }
     s.push_str(
         &program
@@ -61,16 +138,39 @@ pub fn format_program(program: &Program) -> String {
     s
 }
 
+fn format_type_decl(decl: &TypeDecl) -> String {
+    let mut s = format!("type {} struct {{\n", decl.name);
+    for field in &decl.fields {
+        push_indent(4, &mut s);
+        if field.embedded {
+            s.push_str(&format_type(&field.field_type));
+        } else {
+            s.push_str(&format!("{} {}", field.name, format_type(&field.field_type)));
+        }
+        s.push('\n');
+    }
+    s.push('}');
+    s
+}
+
 fn format_funcdef(funcdef: &FuncDef) -> String {
     let FuncDef {
         name,
         params,
-        return_type,
+        results,
+        named_results,
         code,
+        ..
     } = funcdef;
     let mut s = format!("func {}({}) ", name, format_params(params));
-    if let Some(r#type) = return_type {
-        s.push_str(&(format_type(r#type) + " "));
+    match results.as_slice() {
+        [] => {}
+        _ if *named_results => s.push_str(&format!("({}) ", format_params(results))),
+        [(_, r#type, _)] => s.push_str(&(format_type(r#type) + " ")),
+        _ => {
+            let types: Vec<String> = results.iter().map(|(_, ty, _)| format_type(ty)).collect();
+            s.push_str(&format!("({}) ", types.join(", ")));
+        }
     }
     s.push_str(&format_code_block(code, 0));
     s.push('\n');
@@ -86,57 +186,369 @@ fn format_code_block(code: &Vec<Statement>, indent: usize) -> String {
     }
 
     push_indent(indent, &mut s);
-    s.push_str("}");
+    s.push('}');
     s
 }
 
 fn format_statement(statement: &Statement, indent: usize) -> String {
+    if let Statement::Label { name, .. } = statement {
+        // Like gofmt, labels are outdented by one level
+        return format!("{}{}:", " ".repeat(indent.saturating_sub(4)), name);
+    }
     " ".repeat(indent)
         + &match statement {
+            Statement::Assignment {
+                name,
+                var_type: Type::Unknown,
+                expr,
+                ..
+            } => format!("var {} = {}", name, format_expression(expr)),
+            Statement::Assignment {
+                name,
+                var_type,
+                expr: Expression::ZeroValue { .. },
+                ..
+            } => format!("var {} {}", name, format_type(var_type)),
             Statement::Assignment {
                 name,
                 var_type,
                 expr,
+                ..
             } => format!(
                 "var {} {} = {}",
                 name,
                 format_type(var_type),
                 format_expression(expr)
             ),
-            Statement::If { cond, then_block, else_block } => format!(
-                "if {} {} else {}\n",
-                format_expression(cond),
-                format_code_block(then_block, indent),
-                format_code_block(else_block, indent)
+            Statement::ShortVarDecl { vars, exprs, .. } => {
+                let names: Vec<&str> = vars.iter().map(|var| var.name.as_str()).collect();
+                format!("{} := {}", names.join(", "), format_expressions(exprs))
+            }
+            Statement::Assign { lhs, op, rhs, .. } => format!(
+                "{} {}= {}",
+                format_expressions(lhs),
+                op.as_ref().map_or(String::new(), format_bop),
+                format_expressions(rhs)
             ),
-            Statement::Return { expr } => format!("return {}", format_expression(expr)),
-            Statement::Expression { expr } => format_expression(expr),
+            Statement::IncDec { expr, op, .. } => {
+                let op = if *op == BinaryOp::Add { "++" } else { "--" };
+                format!("{}{}", format_expression(expr), op)
+            }
+            Statement::If { .. } => format_if(statement, indent),
+            Statement::For {
+                init,
+                cond,
+                post,
+                body,
+                ..
+            } => {
+                let header = match (init, cond, post) {
+                    (None, None, None) => String::new(),
+                    (None, Some(cond), None) => format_expression(cond) + " ",
+                    _ => format!(
+                        "{}; {}; {} ",
+                        init.as_ref().map_or(String::new(), |init| format_statement(init, 0)),
+                        cond.as_ref().map_or(String::new(), format_expression),
+                        post.as_ref().map_or(String::new(), |post| format_statement(post, 0))
+                    ),
+                };
+                format!("for {}{}", header, format_code_block(body, indent))
+            }
+            Statement::ForRange {
+                key,
+                value,
+                define,
+                expr,
+                body,
+                ..
+            } => {
+                let vars: Vec<String> = key.iter().chain(value).map(format_expression).collect();
+                let assign = match (vars.is_empty(), define) {
+                    (true, _) => String::new(),
+                    (false, true) => format!("{} := ", vars.join(", ")),
+                    (false, false) => format!("{} = ", vars.join(", ")),
+                };
+                format!(
+                    "for {}range {} {}",
+                    assign,
+                    format_expression(expr),
+                    format_code_block(body, indent)
+                )
+            }
+            Statement::Switch {
+                init, tag, cases, ..
+            } => format_switch(init.as_deref(), tag.as_ref(), cases, indent),
+            Statement::Fallthrough { .. } => "fallthrough".to_string(),
+            Statement::Break { label: None, .. } => "break".to_string(),
+            Statement::Break {
+                label: Some(label), ..
+            } => format!("break {}", label),
+            Statement::Continue { label: None, .. } => "continue".to_string(),
+            Statement::Continue {
+                label: Some(label), ..
+            } => format!("continue {}", label),
+            Statement::Label { .. } => unreachable!(),
+            Statement::Goto { label, .. } => format!("goto {}", label),
+            Statement::Return { exprs, .. } if exprs.is_empty() => "return".to_string(),
+            Statement::Return { exprs, .. } => format!("return {}", format_expressions(exprs)),
+            Statement::Expression { expr, .. } => format_expression(expr),
+            Statement::Const { specs, .. } => format_const(specs, indent),
+        }
+}
+
+/// Formats a constant declaration (without leading indentation). Specs that repeated the previous
+/// spec's expressions are printed with the expressions spelled out
+fn format_const(specs: &[ConstSpec], indent: usize) -> String {
+    let format_spec = |spec: &ConstSpec| {
+        let names = spec
+            .names
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<String>>()
+            .join(", ");
+        let r#type = match &spec.const_type {
+            Type::Unknown => String::new(),
+            r#type => format_type(r#type) + " ",
+        };
+        format!("{} {}= {}", names, r#type, format_expressions(&spec.exprs))
+    };
+    match specs {
+        [spec] => format!("const {}", format_spec(spec)),
+        _ => {
+            let mut s = "const (\n".to_string();
+            for spec in specs {
+                push_indent(indent + 4, &mut s);
+                s.push_str(&format_spec(spec));
+                s.push('\n');
+            }
+            push_indent(indent, &mut s);
+            s.push(')');
+            s
         }
+    }
 }
 
-fn format_expression(expr: &Expression) -> String {
+/// Formats a switch statement (without leading indentation). Like gofmt, the cases are indented
+/// as much as the switch itself
+fn format_switch(
+    init: Option<&Statement>,
+    tag: Option<&Expression>,
+    cases: &[CaseClause],
+    indent: usize,
+) -> String {
+    let mut s = "switch ".to_string();
+    if let Some(init) = init {
+        s.push_str(&format_statement(init, 0));
+        s.push_str("; ");
+    }
+    if let Some(tag) = tag {
+        s.push_str(&format_expression(tag));
+        s.push(' ');
+    }
+    s.push_str("{\n");
+    for case in cases {
+        push_indent(indent, &mut s);
+        match &case.exprs {
+            Some(exprs) => s.push_str(&format!("case {}:\n", format_expressions(exprs))),
+            None => s.push_str("default:\n"),
+        }
+        for statement in &case.body {
+            s.push_str(&format_statement(statement, indent + 4));
+            s.push('\n');
+        }
+    }
+    push_indent(indent, &mut s);
+    s.push('}');
+    s
+}
+
+/// Formats an if statement (without leading indentation), turning an else block that only holds
+/// another if statement into `else if`
+fn format_if(statement: &Statement, indent: usize) -> String {
+    let (cond, then_block, else_block) = match statement {
+        Statement::If {
+            cond,
+            then_block,
+            else_block,
+            ..
+        } => (cond, then_block, else_block),
+        _ => unreachable!(),
+    };
+    let mut s = format!(
+        "if {} {}",
+        format_expression(cond),
+        format_code_block(then_block, indent)
+    );
+    match &else_block[..] {
+        [] => {}
+        [nested @ Statement::If { .. }] => {
+            s.push_str(" else ");
+            s.push_str(&format_if(nested, indent));
+        }
+        _ => {
+            s.push_str(" else ");
+            s.push_str(&format_code_block(else_block, indent));
+        }
+    }
+    s
+}
+
+/// Formats the operand of a binary operator with precedence `prec`, adding parentheses where they
+/// are needed to preserve the structure of the tree (operators are left associative)
+fn format_operand(expr: &Expression, prec: u8, is_right: bool) -> String {
+    match expr {
+        Expression::BinaryOp { op, .. }
+            if bop_precedence(op) < prec || (is_right && bop_precedence(op) == prec) =>
+        {
+            format!("({})", format_expression(expr))
+        }
+        _ => format_expression(expr),
+    }
+}
+
+pub fn format_expression(expr: &Expression) -> String {
     match expr {
         Expression::Name { name, .. } => name.clone(),
-        Expression::Literal { expr_type, value } => match expr_type {
-            Type::Bool => (if value == "1" { "true" } else { "false" }).to_string(),
-            Type::GoString => format!("\"{}\"", value),
-            _ => value.clone(),
-        },
+        Expression::Literal { value, .. } => value.literal(),
         Expression::BinaryOp {
             op, left, right, ..
         } => format!(
             "{} {} {}",
-            format_expression(left),
+            format_operand(left, bop_precedence(op), false),
             format_bop(op),
-            format_expression(right)
+            format_operand(right, bop_precedence(op), true)
         ),
-        Expression::Call { func, args, .. } => format!(
-            "{}({})",
+        Expression::UnaryOp { op, expr, .. } => {
+            let operand = format_expression(expr);
+            // Unary operators bind tighter than binary ones, and `-(-x)` mustn't become `--x`
+            let parenthesize = matches!(**expr, Expression::BinaryOp { .. })
+                || (matches!(op, UnaryOp::Plus | UnaryOp::Neg) && operand.starts_with(['+', '-']));
+            if parenthesize {
+                format!("{}({})", format_uop(op), operand)
+            } else {
+                format!("{}{}", format_uop(op), operand)
+            }
+        }
+        Expression::Call {
+            package,
             func,
-            args.iter()
-                .map(format_expression)
-                .collect::<Vec<String>>()
-                .join(", ")
-        ),
+            args,
+            has_dots,
+            ..
+        } => match package {
+            Some((package, _)) => format!("{}.{}({})", package, func, format_args(args, *has_dots)),
+            None => format!("{}({})", func, format_args(args, *has_dots)),
+        },
+        Expression::BuiltinCall {
+            builtin,
+            args,
+            has_dots,
+            ..
+        } => format!("{}({})", builtin.name(), format_args(args, *has_dots)),
+        Expression::FmtCall { func, args, .. } => {
+            format!("fmt.{}({})", func.name(), format_expressions(args))
+        }
+        Expression::RuntimeCall { func, args, .. } => {
+            format!("runtime.{}({})", func.name(), format_expressions(args))
+        }
+        Expression::Index { expr, index, .. } => {
+            format!("{}[{}]", format_primary(expr), format_expression(index))
+        }
+        Expression::Slice {
+            expr,
+            low,
+            high,
+            max,
+            ..
+        } => {
+            let indices: Vec<String> = [low, high]
+                .into_iter()
+                .chain(max.is_some().then_some(max))
+                .map(|index| index.as_deref().map_or(String::new(), format_expression))
+                .collect();
+            format!("{}[{}]", format_primary(expr), indices.join(":"))
+        }
+        Expression::Selector { expr, field, .. } => format!("{}.{}", format_primary(expr), field),
+        Expression::CompositeLit {
+            expr_type,
+            syntax,
+            elements,
+            ..
+        } => {
+            let elements: Vec<String> = elements
+                .iter()
+                .map(|element| match &element.key {
+                    Some(key) => format!(
+                        "{}: {}",
+                        format_expression(key),
+                        format_expression(&element.value)
+                    ),
+                    None => format_expression(&element.value),
+                })
+                .collect();
+            let r#type = match (syntax, expr_type) {
+                (LitType::Explicit, _) => format_type(expr_type),
+                (LitType::InferredLen, Type::Array(_, elem)) => {
+                    format!("[...]{}", format_type(elem))
+                }
+                _ => String::new(),
+            };
+            format!("{}{{{}}}", r#type, elements.join(", "))
+        }
+        // Only arrays, slices, structs, pointers, maps and errors have zero values that aren't
+        // literals
+        Expression::ZeroValue { expr_type, .. } => match expr_type {
+            Type::Slice(_) | Type::Map(..) | Type::Error => {
+                format!("{}(nil)", format_type(expr_type))
+            }
+            Type::Pointer(_) => format!("({})(nil)", format_type(expr_type)),
+            _ => format!("{}{{}}", format_type(expr_type)),
+        },
+        Expression::Nil { .. } => "nil".to_string(),
+        Expression::New {
+            expr_type: Type::Pointer(elem),
+            ..
+        } => format!("new({})", format_type(elem)),
+        Expression::New { .. } => "new(<unknown>)".to_string(),
+        Expression::Make {
+            expr_type, args, ..
+        } => {
+            let args: Vec<String> = std::iter::once(format_type(expr_type))
+                .chain(args.iter().map(format_expression))
+                .collect();
+            format!("make({})", args.join(", "))
+        }
+        Expression::Conversion {
+            expr_type, expr, ..
+        } => format!("{}({})", format_type(expr_type), format_expression(expr)),
+    }
+}
+
+/// Formats the operand of an index, slice or selector expression, which is parenthesized if it's
+/// an operation
+fn format_primary(expr: &Expression) -> String {
+    match expr {
+        Expression::BinaryOp { .. } | Expression::UnaryOp { .. } => {
+            format!("({})", format_expression(expr))
+        }
+        _ => format_expression(expr),
     }
 }
+
+/// Formats the arguments of a call, the last of which may be followed by `...`
+fn format_args(args: &[Expression], has_dots: bool) -> String {
+    let mut s = format_expressions(args);
+    if has_dots {
+        s.push_str("...");
+    }
+    s
+}
+
+/// Formats a comma-separated list of expressions
+fn format_expressions(exprs: &[Expression]) -> String {
+    exprs
+        .iter()
+        .map(format_expression)
+        .collect::<Vec<String>>()
+        .join(", ")
+}
@@ -0,0 +1,4300 @@
+//! The semantic checker. Resolves every name in the program against the lexical scopes defined by
+//! the Go spec (universe, package, file, function and block scopes) and type checks it, filling in
+//! the `expr_type` of every expression and the type of every variable declared without one. It
+//! reports the errors the go compiler reports: undefined, redeclared and unused identifiers, unused
+//! imports, and type errors. Programs that fail these checks never make it to codegen.
+//!
+//! Constant expressions are evaluated exactly (see `constant`), and replaced with a literal of
+//! their value once their type is known. Untyped constants take on the type their context requires
+//! (e.g. the `1` in `x + 1` becomes a `float32` if `x` is one), and their default type otherwise.
+
+mod labels;
+
+use crate::ast::*;
+use crate::constant::{self, ConversionError, Value};
+use crate::diagnostics::{Code, Diagnostic};
+use crate::pretty_printer::{format_bop, format_expression, format_type, format_uop};
+use num_traits::{Signed, ToPrimitive};
+use std::collections::{HashMap, HashSet};
+
+/// Functions provided by the runtime, which are callable from every package. Of the form
+/// `(name, param_types, result_type)`
+const RUNTIME_FUNCTIONS: &[(&str, &[Type], Option<Type>)] = &[
+    ("__flush_stdout", &[], None),
+    ("__gopanic", &[Type::GoString], None),
+    ("add", &[Type::Int, Type::Int], Some(Type::Int)),
+    ("__print_int", &[Type::Int], None),
+    ("__print_bool", &[Type::Bool], None),
+    ("__print_float32", &[Type::Float32], None),
+    ("__print_float64", &[Type::Float64], None),
+    ("__print_gostring", &[Type::GoString], None),
+];
+
+/// The predeclared functions, which are declared in the universe scope
+const BUILTINS: &[Builtin] = &[
+    Builtin::Append,
+    Builtin::Cap,
+    Builtin::Copy,
+    Builtin::Delete,
+    Builtin::Len,
+    Builtin::Make,
+    Builtin::New,
+    Builtin::Print,
+    Builtin::Println,
+];
+
+#[derive(Debug, Clone)]
+enum SymbolKind {
+    Var(Type),
+    /// A constant (always in mode `Constant` or `Untyped`), or `None` if its declaration had an
+    /// error
+    Const(Option<Operand>),
+    Func {
+        params: Vec<Type>,
+        result: Option<Type>,
+    },
+    /// A predeclared function like `len`, which can only be called
+    Builtin(Builtin),
+    /// A declared (struct) type, `Type::Named`
+    TypeName,
+    /// An imported package, with its import path
+    Package(String),
+    /// The predeclared `iota`, which is only valid in constant declarations
+    Iota,
+    /// The predeclared `nil`
+    Nil,
+}
+
+#[derive(Debug)]
+struct Symbol {
+    kind: SymbolKind,
+    /// Where the symbol was declared. Predeclared symbols have a default span
+    span: Span,
+    used: bool,
+}
+
+/// What kind of value an expression evaluates to
+#[derive(Debug, Clone, PartialEq)]
+enum Mode {
+    /// A variable
+    Variable,
+    /// An element of a map, which can be assigned to like a variable but isn't addressable
+    MapIndex,
+    /// The result of a computation
+    Value,
+    /// A typed constant
+    Constant(Value),
+    /// An untyped constant. The operand's type is the constant's default type
+    Untyped(Value),
+    /// The result of calling a function without a result
+    NoValue,
+    /// `nil`, which has no type until it's used as a pointer, a slice or a map (the operand's type
+    /// is `Unknown`)
+    Nil,
+}
+
+#[derive(Debug, Clone)]
+struct Operand {
+    mode: Mode,
+    ty: Type,
+}
+
+impl Operand {
+    fn value(&self) -> Option<&Value> {
+        match &self.mode {
+            Mode::Constant(value) | Mode::Untyped(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn is_untyped(&self) -> bool {
+        matches!(self.mode, Mode::Untyped(_))
+    }
+
+    /// Converts the operand to type `target`. Only untyped constants can change their type
+    fn convert(&self, target: &Type) -> Result<Operand, ConversionError> {
+        match &self.mode {
+            Mode::Untyped(value) => Ok(Operand {
+                mode: Mode::Constant(value.convert(target)?),
+                ty: target.clone(),
+            }),
+            Mode::Nil
+                if matches!(
+                    target,
+                    Type::Pointer(_) | Type::Slice(_) | Type::Map(..) | Type::Error
+                ) =>
+            {
+                Ok(Operand {
+                    mode: Mode::Value,
+                    ty: target.clone(),
+                })
+            }
+            Mode::Nil => Err(ConversionError::Incompatible),
+            _ if self.ty == *target => Ok(self.clone()),
+            _ => Err(ConversionError::Incompatible),
+        }
+    }
+}
+
+/// The result of looking up a field by name in a struct type
+enum FieldLookup {
+    /// The field, with the indices of the fields to go through to get to it (see
+    /// `Expression::Selector`)
+    Found(Vec<usize>, Field),
+    Missing,
+    /// Several embedded structs at the same depth have a field with the name
+    Ambiguous,
+}
+
+/// The left-hand side of an assignment
+enum Lhs {
+    /// The blank identifier, which discards the value assigned to it
+    Blank,
+    Var(Type),
+    /// An error was reported
+    Invalid,
+}
+
+/// Checks the whole program, returning all the errors found (sorted by position)
+pub fn check_program(program: &mut Program) -> Result<(), Vec<Diagnostic>> {
+    let mut checker = Checker::new();
+    checker.check_program(program);
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        checker
+            .errors
+            .sort_by_key(|err| (err.span.file_id, err.span.start));
+        Err(checker.errors)
+    }
+}
+
+struct Checker {
+    /// The innermost scope is last. The first scope is the universe scope
+    scopes: Vec<HashMap<String, Symbol>>,
+    /// The fields of the declared struct types
+    structs: HashMap<String, Vec<Field>>,
+    /// The key types of the maps in the fields of the struct types being declared, which can only
+    /// be checked for comparability once all the structs are known
+    pending_keys: Option<Vec<(Type, Span)>>,
+    /// The result types of the function being checked
+    results: Vec<Type>,
+    /// Whether the results of the function being checked are named, so that it may `return`
+    /// without values
+    named_results: bool,
+    /// The value of `iota` in the constant spec being checked, if any
+    iota: Option<u64>,
+    /// The number of loops around the statement being checked
+    loop_depth: usize,
+    /// The number of switch statements around the statement being checked
+    switch_depth: usize,
+    errors: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn new() -> Self {
+        let mut universe: HashMap<String, Symbol> = RUNTIME_FUNCTIONS
+            .iter()
+            .map(|(name, params, result)| {
+                (
+                    name.to_string(),
+                    Symbol {
+                        kind: SymbolKind::Func {
+                            params: params.to_vec(),
+                            result: result.clone(),
+                        },
+                        span: Span::default(),
+                        used: false,
+                    },
+                )
+            })
+            .collect();
+        for &builtin in BUILTINS {
+            universe.insert(
+                builtin.name().to_string(),
+                Symbol {
+                    kind: SymbolKind::Builtin(builtin),
+                    span: Span::default(),
+                    used: false,
+                },
+            );
+        }
+        for (name, kind) in [("iota", SymbolKind::Iota), ("nil", SymbolKind::Nil)] {
+            universe.insert(
+                name.to_string(),
+                Symbol {
+                    kind,
+                    span: Span::default(),
+                    used: false,
+                },
+            );
+        }
+        Self {
+            scopes: vec![universe],
+            structs: HashMap::new(),
+            pending_keys: None,
+            results: vec![],
+            named_results: false,
+            iota: None,
+            loop_depth: 0,
+            switch_depth: 0,
+            errors: vec![],
+        }
+    }
+
+    fn error(&mut self, code: Code, msg: impl Into<String>, span: Span) {
+        self.report(Diagnostic::error(code, msg, span));
+    }
+
+    fn report(&mut self, diagnostic: Diagnostic) {
+        self.errors.push(diagnostic);
+    }
+
+    fn open_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// Pops the innermost scope, reporting the variables and imports declared in it that were
+    /// never used
+    fn close_scope(&mut self) {
+        let scope = self.scopes.pop().expect("closed the universe scope");
+        for (name, symbol) in scope {
+            if symbol.used {
+                continue;
+            }
+            match symbol.kind {
+                // The declaration had an error, which was already reported
+                SymbolKind::Var(Type::Unknown) => {}
+                SymbolKind::Var(_) => self.error(
+                    Code::UnusedVar,
+                    format!("declared and not used: {}", name),
+                    symbol.span,
+                ),
+                SymbolKind::Package(path) => self.error(
+                    Code::UnusedImport,
+                    format!("\"{}\" imported and not used", path),
+                    symbol.span,
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    /// Declares `name` in the innermost scope. If it is already declared there, returns the span
+    /// of the other declaration instead. The blank identifier is never declared
+    fn try_declare(&mut self, name: &str, kind: SymbolKind, span: Span) -> Result<(), Span> {
+        if name == "_" {
+            return Ok(());
+        }
+        let scope = self.scopes.last_mut().unwrap();
+        if let Some(other) = scope.get(name) {
+            return Err(other.span);
+        }
+        scope.insert(
+            name.to_string(),
+            Symbol {
+                kind,
+                span,
+                used: false,
+            },
+        );
+        Ok(())
+    }
+
+    fn declare(&mut self, name: &str, kind: SymbolKind, span: Span) {
+        if let Err(other) = self.try_declare(name, kind, span) {
+            let msg = format!("{} redeclared in this block", name);
+            let label = format!("other declaration of {}", name);
+            self.report(Diagnostic::error(Code::DuplicateDecl, msg, span).with_label(other, label));
+        }
+    }
+
+    /// Looks up `name` from the innermost scope outwards, marking it as used
+    fn resolve(&mut self, name: &str, span: Span) -> Option<SymbolKind> {
+        if name == "_" {
+            self.error(Code::InvalidBlank, "cannot use _ as value", span);
+            return None;
+        }
+        let symbol = self.lookup(name, span)?;
+        symbol.used = true;
+        Some(symbol.kind.clone())
+    }
+
+    /// Looks up `name` from the innermost scope outwards, reporting it if it's undefined
+    fn lookup(&mut self, name: &str, span: Span) -> Option<&mut Symbol> {
+        if let Some(idx) = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.contains_key(name))
+        {
+            return self.scopes[idx].get_mut(name);
+        }
+        let mut diagnostic =
+            Diagnostic::error(Code::UndeclaredName, format!("undefined: {}", name), span);
+        if let Some(similar) = self.similar_name(name) {
+            diagnostic = diagnostic.with_suggestion(
+                format!("a similar name is in scope: `{}`", similar),
+                span,
+                similar,
+            );
+        }
+        self.report(diagnostic);
+        None
+    }
+
+    /// Finds the visible name closest to the undefined `name`, if one is close enough to be a typo
+    fn similar_name(&self, name: &str) -> Option<String> {
+        let max_distance = (name.chars().count() / 3).max(1);
+        self.scopes
+            .iter()
+            .flat_map(|scope| scope.keys())
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min()
+            .map(|(_, candidate)| candidate.clone())
+    }
+
+    fn check_program(&mut self, program: &mut Program) {
+        // Package scope: types and functions may be referenced before they are declared
+        self.open_scope();
+        self.check_type_decls(&mut program.types);
+        for func in &mut program.functions {
+            if func.name == "main" && (!func.params.is_empty() || !func.results.is_empty()) {
+                self.error(
+                    Code::InvalidMainDecl,
+                    "func main must have no arguments and no return values",
+                    func.span,
+                );
+            }
+            for (_, ty, span) in func.params.iter_mut().chain(&mut func.results) {
+                self.check_type(ty, *span);
+            }
+            let kind = SymbolKind::Func {
+                params: func.params.iter().map(|(_, ty, _)| ty.clone()).collect(),
+                result: func.result_type(),
+            };
+            self.declare(&func.name, kind, func.span);
+        }
+        if program.package_name == "main"
+            && !program.functions.iter().any(|func| func.name == "main")
+        {
+            self.error(
+                Code::InvalidMainDecl,
+                "function main is undeclared in the main package",
+                Span::default(),
+            );
+        }
+        self.check_package_consts(&mut program.consts);
+
+        // File scope: imports are only visible in the file that imports them
+        self.open_scope();
+        for (path, span) in &program.imports {
+            let name = path.rsplit('/').next().unwrap();
+            self.declare(name, SymbolKind::Package(path.clone()), *span);
+        }
+
+        for func in &mut program.functions {
+            self.check_function(func);
+        }
+        self.close_scope();
+        self.close_scope();
+    }
+
+    /// Declares the struct types of the package, and checks their fields. The fields may refer to
+    /// types declared after them
+    fn check_type_decls(&mut self, decls: &mut [TypeDecl]) {
+        for decl in decls.iter() {
+            self.declare(&decl.name, SymbolKind::TypeName, decl.span);
+        }
+        self.pending_keys = Some(vec![]);
+        for decl in decls.iter_mut() {
+            let mut names: HashMap<&str, Span> = HashMap::new();
+            for field in &mut decl.fields {
+                self.check_type(&mut field.field_type, field.span);
+                if field.name == "_" {
+                    continue;
+                }
+                if let Some(other) = names.insert(&field.name, field.span) {
+                    let msg = format!("{} redeclared", field.name);
+                    let label = format!("other declaration of {}", field.name);
+                    self.report(
+                        Diagnostic::error(Code::DuplicateDecl, msg, field.span)
+                            .with_label(other, label),
+                    );
+                }
+            }
+            // A redeclared type was reported, and keeps its first fields
+            self.structs
+                .entry(decl.name.clone())
+                .or_insert_with(|| decl.fields.clone());
+        }
+        let mut done = HashSet::new();
+        for decl in decls.iter() {
+            self.check_type_cycle(&decl.name, &mut vec![], &mut done, decls);
+        }
+        // The cycles are broken, so the keys' fields can be looked into
+        for (key, span) in self.pending_keys.take().unwrap() {
+            self.check_map_key(&key, span);
+        }
+    }
+
+    /// Reports the struct types that contain themselves (directly, or through the fields of other
+    /// structs or the elements of arrays), whose values would be infinitely large. `path` is the
+    /// types whose fields are being looked into, and `done` those already checked. The fields that
+    /// close a cycle are made `Unknown`, so that the later checks don't go around it forever
+    fn check_type_cycle(
+        &mut self,
+        name: &str,
+        path: &mut Vec<String>,
+        done: &mut HashSet<String>,
+        decls: &[TypeDecl],
+    ) {
+        if done.contains(name) {
+            return;
+        }
+        if let Some(start) = path.iter().position(|other| other == name) {
+            let span = |name: &str| {
+                decls
+                    .iter()
+                    .find(|decl| decl.name == name)
+                    .map_or(Span::default(), |decl| decl.span)
+            };
+            // The cycle is reported at the type declared first
+            let mut cycle = path[start..].to_vec();
+            let first = (0..cycle.len())
+                .min_by_key(|idx| span(&cycle[*idx]).start)
+                .unwrap();
+            cycle.rotate_left(first);
+            let diagnostic = if cycle.len() == 1 {
+                let msg = format!("invalid recursive type: {} refers to itself", name);
+                Diagnostic::error(Code::InvalidDeclCycle, msg, span(name))
+            } else {
+                let msg = format!("invalid recursive type {}", cycle[0]);
+                let mut diagnostic =
+                    Diagnostic::error(Code::InvalidDeclCycle, msg, span(&cycle[0]));
+                for (idx, name) in cycle.iter().enumerate() {
+                    let next = &cycle[(idx + 1) % cycle.len()];
+                    let label = format!("{} refers to {}", name, next);
+                    diagnostic = diagnostic.with_label(span(name), label);
+                }
+                diagnostic
+            };
+            self.report(diagnostic);
+            let last = path.last().unwrap();
+            for field in self.structs.get_mut(last).unwrap() {
+                if contained_struct(&field.field_type) == Some(name) {
+                    field.field_type = Type::Unknown;
+                }
+            }
+            return;
+        }
+        path.push(name.to_string());
+        // The fields are looked up again each time, as a cycle found through one may have been
+        // broken at another
+        let count = self.structs.get(name).map_or(0, Vec::len);
+        for idx in 0..count {
+            let field_type = self.structs[name][idx].field_type.clone();
+            if let Some(inner) = contained_struct(&field_type) {
+                self.check_type_cycle(inner, path, done, decls);
+            }
+        }
+        path.pop();
+        done.insert(name.to_string());
+    }
+
+    /// Checks that the type names in `ty` refer to declared types, and that the key types of maps
+    /// are comparable, returning whether they do and are. An invalid type (which was reported)
+    /// becomes `Unknown`
+    fn check_type(&mut self, ty: &mut Type, span: Span) -> bool {
+        let valid = match ty {
+            Type::Array(_, elem) | Type::Slice(elem) | Type::Pointer(elem) => {
+                self.check_type(elem, span)
+            }
+            Type::Map(key, value) => {
+                let valid_key = self.check_type(key, span);
+                let valid_value = self.check_type(value, span);
+                valid_key && valid_value && self.check_map_key(key, span)
+            }
+            Type::Named(name) => match self.lookup(name, span) {
+                Some(symbol) => {
+                    symbol.used = true;
+                    let is_type = matches!(symbol.kind, SymbolKind::TypeName);
+                    if !is_type {
+                        self.error(Code::NotAType, format!("{} is not a type", name), span);
+                    }
+                    is_type
+                }
+                None => false,
+            },
+            _ => true,
+        };
+        if !valid {
+            *ty = Type::Unknown;
+        }
+        valid
+    }
+
+    /// Reports `key` if its values can't be compared, which the keys of a map must be. Returns
+    /// whether it can be the key type of a map (or it will be checked later)
+    fn check_map_key(&mut self, key: &Type, span: Span) -> bool {
+        if let Some(pending) = &mut self.pending_keys {
+            pending.push((key.clone(), span));
+            return true;
+        }
+        if self.incomparable_cause(key).is_none() {
+            return true;
+        }
+        let msg = format!("invalid map key type {}", format_type(key));
+        self.error(Code::IncomparableMapKey, msg, span);
+        false
+    }
+
+    /// Package-level constants may refer to each other regardless of the order they are declared
+    /// in, so each spec is only checked once all the constants it refers to have been
+    fn check_package_consts(&mut self, decls: &mut [Statement]) {
+        let mut pending: Vec<&mut ConstSpec> = decls
+            .iter_mut()
+            .flat_map(|decl| match decl {
+                Statement::Const { specs, .. } => specs.iter_mut(),
+                _ => unreachable!("non-constant package-level declaration"),
+            })
+            .collect();
+        let names: HashSet<String> = pending
+            .iter()
+            .flat_map(|spec| spec.names.iter().map(|(name, _)| name.clone()))
+            .collect();
+        loop {
+            let (ready, waiting): (Vec<_>, Vec<_>) = pending.into_iter().partition(|spec| {
+                let mut refs = vec![];
+                for expr in &spec.exprs {
+                    referenced_names(expr, &mut refs);
+                }
+                refs.iter()
+                    .all(|name| !names.contains(*name) || self.scopes[1].contains_key(*name))
+            });
+            pending = waiting;
+            if ready.is_empty() {
+                break;
+            }
+            for spec in ready {
+                self.check_const_spec(spec);
+            }
+        }
+        for spec in pending {
+            for (name, span) in &spec.names {
+                self.error(
+                    Code::InvalidInitCycle,
+                    format!("initialization cycle for {}", name),
+                    *span,
+                );
+                self.declare(name, SymbolKind::Const(None), *span);
+            }
+        }
+    }
+
+    fn check_function(&mut self, func: &mut FuncDef) {
+        self.results = func.results.iter().map(|(_, ty, _)| ty.clone()).collect();
+        self.named_results = func.named_results;
+        // The parameters, the named results and the outermost block of the body share a scope
+        self.open_scope();
+        for (name, ty, span) in func.params.iter().chain(&func.results) {
+            if let Err(other) = self.try_declare(name, SymbolKind::Var(ty.clone()), *span) {
+                let msg = format!("duplicate argument {}", name);
+                let label = format!("other declaration of {}", name);
+                self.report(
+                    Diagnostic::error(Code::DuplicateDecl, msg, *span).with_label(other, label),
+                );
+            }
+            // Unused parameters and results are fine
+            if let Some(symbol) = self.scopes.last_mut().unwrap().get_mut(name) {
+                symbol.used = true;
+            }
+        }
+        for stmt in &mut func.code {
+            self.check_statement(stmt);
+        }
+        self.close_scope();
+        self.errors.extend(labels::check_labels(&func.code));
+        if !func.results.is_empty() && !is_terminating_block(&func.code) {
+            self.error(Code::MissingReturn, "missing return", func.span);
+        }
+    }
+
+    fn check_block(&mut self, block: &mut [Statement]) {
+        self.open_scope();
+        for stmt in block {
+            self.check_statement(stmt);
+        }
+        self.close_scope();
+    }
+
+    fn check_statement(&mut self, stmt: &mut Statement) {
+        match stmt {
+            Statement::Assignment {
+                name,
+                var_type,
+                expr,
+                span,
+            } => {
+                // The statement's span starts at the variable's name
+                let name_span = Span {
+                    end: span.start + name.len(),
+                    ..*span
+                };
+                let valid_type = self.check_type(var_type, name_span);
+                if let Expression::ZeroValue { expr_type, .. } = expr {
+                    *expr_type = var_type.clone();
+                }
+                // The variable isn't in scope in its own initializer
+                if !valid_type {
+                    self.check_expr(expr);
+                } else if let Some(operand) = self.check_value(expr) {
+                    // Without a type, the variable takes the type of its initializer (the default
+                    // type, if it's an untyped constant)
+                    let target = match var_type {
+                        Type::Unknown => operand.ty.clone(),
+                        _ => var_type.clone(),
+                    };
+                    if self.assign_operand(expr, operand, &target, "variable declaration") {
+                        *var_type = target;
+                    }
+                }
+                self.declare(name, SymbolKind::Var(var_type.clone()), name_span);
+            }
+            Statement::ShortVarDecl { vars, exprs, span } => {
+                self.check_short_var_decl(vars, exprs, *span)
+            }
+            Statement::Assign {
+                lhs,
+                op: None,
+                rhs,
+                span,
+            } if lhs.len() != rhs.len() => {
+                let lhs: Vec<Lhs> = lhs.iter_mut().map(|lhs| self.check_lhs(lhs)).collect();
+                let operands = self.check_multi_assign(&mut rhs[0], lhs.len(), *span);
+                for (lhs, operand) in lhs.into_iter().zip(operands.into_iter().flatten()) {
+                    let target = match lhs {
+                        Lhs::Blank => operand.ty.clone(),
+                        Lhs::Var(ty) => ty,
+                        Lhs::Invalid => continue,
+                    };
+                    self.assign_operand(&mut rhs[0], operand, &target, "assignment");
+                }
+            }
+            Statement::Assign {
+                lhs, op: None, rhs, ..
+            } => {
+                for (lhs, rhs) in lhs.iter_mut().zip(rhs) {
+                    self.check_assign(lhs, rhs);
+                }
+            }
+            Statement::Assign {
+                lhs,
+                op: Some(op),
+                rhs,
+                span,
+            } => self.check_assign_op(&mut lhs[0], op, &mut rhs[0], *span),
+            Statement::IncDec { expr, op, .. } => {
+                if let Some(ty) = self.check_modifiable(expr) {
+                    if !ty.is_numeric() {
+                        let msg = format!(
+                            "invalid operation: {}{} (non-numeric type {})",
+                            format_expression(expr),
+                            if *op == BinaryOp::Add { "++" } else { "--" },
+                            format_type(&ty)
+                        );
+                        self.error(Code::NonNumericIncDec, msg, expr.span());
+                    }
+                }
+            }
+            Statement::If {
+                cond,
+                then_block,
+                else_block,
+                ..
+            } => {
+                self.check_cond(cond, "if statement");
+                self.check_block(then_block);
+                self.check_block(else_block);
+            }
+            Statement::For {
+                init,
+                cond,
+                post,
+                body,
+                ..
+            } => {
+                // Variables declared by the init statement are scoped to the loop
+                self.open_scope();
+                if let Some(init) = init {
+                    self.check_statement(init);
+                }
+                if let Some(cond) = cond {
+                    self.check_cond(cond, "for statement");
+                }
+                if let Some(post) = post {
+                    self.check_statement(post);
+                }
+                self.loop_depth += 1;
+                self.check_block(body);
+                self.loop_depth -= 1;
+                self.close_scope();
+            }
+            Statement::ForRange {
+                key,
+                value,
+                define,
+                expr,
+                body,
+                ..
+            } => self.check_for_range(key.as_mut(), value.as_mut(), *define, expr, body),
+            // Labels, and the statements that refer to them, are checked once the whole function
+            // has been (see `labels`)
+            Statement::Label { .. }
+            | Statement::Goto { .. }
+            | Statement::Break { label: Some(_), .. }
+            | Statement::Continue { label: Some(_), .. } => {}
+            Statement::Switch {
+                init, tag, cases, ..
+            } => self.check_switch(init.as_deref_mut(), tag.as_mut(), cases),
+            // A `fallthrough` ending a case is checked along with the switch
+            Statement::Fallthrough { span } => {
+                let msg = "fallthrough statement out of place";
+                self.error(Code::MisplacedFallthrough, msg, *span);
+            }
+            Statement::Break { label: None, span } => {
+                if self.loop_depth == 0 && self.switch_depth == 0 {
+                    let msg = "break is not in a loop, switch, or select";
+                    self.error(Code::MisplacedBreak, msg, *span);
+                }
+            }
+            Statement::Continue { label: None, span } => {
+                if self.loop_depth == 0 {
+                    self.error(Code::MisplacedContinue, "continue is not in a loop", *span);
+                }
+            }
+            Statement::Return { exprs, span } => self.check_return(exprs, *span),
+            Statement::Expression { expr, .. } => {
+                // Calls of functions (but not of most builtins) may discard their results
+                let operand = match expr {
+                    Expression::Call { package: None, .. } => self.check_call(expr),
+                    _ => self.check_expr(expr),
+                };
+                let operand = operand.filter(|operand| {
+                    operand.mode != Mode::NoValue
+                        && !matches!(
+                            expr,
+                            Expression::Call { .. }
+                                | Expression::FmtCall { .. }
+                                | Expression::RuntimeCall { .. }
+                                | Expression::BuiltinCall {
+                                    builtin: Builtin::Copy,
+                                    ..
+                                }
+                        )
+                });
+                if let Some(operand) = operand {
+                    let msg = format!("{} is not used", describe(expr, &operand));
+                    self.error(Code::UnusedExpr, msg, expr.span());
+                }
+            }
+            Statement::Const { specs, .. } => {
+                for spec in specs {
+                    self.check_const_spec(spec);
+                }
+            }
+        }
+    }
+
+    /// Checks `vars := exprs`. The variables that aren't declared in the innermost scope yet are
+    /// declared with the types of their values, and the others are assigned to
+    fn check_short_var_decl(
+        &mut self,
+        vars: &mut [ShortVar],
+        exprs: &mut [Expression],
+        span: Span,
+    ) {
+        // The new variables aren't in scope in the values
+        let operands: Vec<Option<Operand>> = if vars.len() == exprs.len() {
+            exprs
+                .iter_mut()
+                .map(|expr| self.check_value(expr))
+                .collect()
+        } else {
+            match self.check_multi_assign(&mut exprs[0], vars.len(), span) {
+                Some(operands) => operands.into_iter().map(Some).collect(),
+                None => vec![None; vars.len()],
+            }
+        };
+        let mut names = HashSet::new();
+        let mut any_new = false;
+        let last = exprs.len() - 1;
+        for (idx, (var, operand)) in vars.iter_mut().zip(operands).enumerate() {
+            // All the values of a call with several results (or of a comma-ok expression) come
+            // from it
+            let expr = &mut exprs[idx.min(last)];
+            if var.name != "_" && !names.insert(var.name.clone()) {
+                let msg = format!("{} repeated on left side of :=", var.name);
+                self.error(Code::RepeatedDecl, msg, var.span);
+                continue;
+            }
+            let existing = self.scopes.last().unwrap().get(&var.name);
+            let lhs = match existing.map(|symbol| &symbol.kind) {
+                _ if var.name == "_" => Lhs::Blank,
+                None => {
+                    var.is_new = true;
+                    any_new = true;
+                    Lhs::Blank
+                }
+                Some(SymbolKind::Var(Type::Unknown)) => Lhs::Invalid,
+                Some(SymbolKind::Var(ty)) => Lhs::Var(ty.clone()),
+                Some(_) => self.unassignable_name(&var.name, var.span),
+            };
+            if let Some(operand) = operand {
+                // Like the variables declared without a type, new variables take the (default)
+                // type of their value
+                let (target, context) = match lhs {
+                    Lhs::Blank if var.is_new => (operand.ty.clone(), "variable declaration"),
+                    Lhs::Blank => (operand.ty.clone(), "assignment"),
+                    Lhs::Var(ty) => (ty, "assignment"),
+                    Lhs::Invalid => continue,
+                };
+                if self.assign_operand(expr, operand, &target, context) {
+                    var.var_type = target;
+                }
+            }
+            if var.is_new {
+                let kind = SymbolKind::Var(var.var_type.clone());
+                self.declare(&var.name, kind, var.span);
+            }
+        }
+        if !any_new {
+            self.error(Code::NoNewVar, "no new variables on left side of :=", span);
+        }
+    }
+
+    /// Checks the expression `expr` that gives the values of `vars` variables, more than the one
+    /// value an expression usually has. A call of a function with as many results can, and so can
+    /// an index expression of a map: `v, ok := m[k]` gives the element (or the zero value) and
+    /// whether there was one
+    fn check_multi_assign(
+        &mut self,
+        expr: &mut Expression,
+        vars: usize,
+        span: Span,
+    ) -> Option<Vec<Operand>> {
+        let mut operands = self.check_multi_value(expr)?;
+        if vars == 2 && operands.len() == 1 && operands[0].mode == Mode::MapIndex {
+            operands.push(Operand {
+                mode: Mode::Value,
+                ty: Type::Bool,
+            });
+        }
+        if operands.len() == vars {
+            return Some(operands);
+        }
+        let values = match operands.len() {
+            1 => "1 value".to_string(),
+            len => format!("{} values", len),
+        };
+        let msg = match expr {
+            Expression::Call { package, func, .. } => format!(
+                "assignment mismatch: {} variables but {}{} returns {}",
+                vars,
+                package
+                    .as_ref()
+                    .map_or(String::new(), |(package, _)| format!("{}.", package)),
+                func,
+                values
+            ),
+            Expression::BuiltinCall { builtin, .. } => format!(
+                "assignment mismatch: {} variables but {} returns {}",
+                vars,
+                builtin.name(),
+                values
+            ),
+            _ => format!("assignment mismatch: {} variables but {}", vars, values),
+        };
+        self.error(Code::WrongAssignCount, msg, span);
+        None
+    }
+
+    /// Checks `lhs = rhs`
+    fn check_assign(&mut self, lhs: &mut Expression, rhs: &mut Expression) {
+        let lhs = self.check_lhs(lhs);
+        if let Some(operand) = self.check_value(rhs) {
+            // A value assigned to the blank identifier still needs a type
+            let target = match lhs {
+                Lhs::Blank => operand.ty.clone(),
+                Lhs::Var(ty) => ty,
+                Lhs::Invalid => return,
+            };
+            self.assign_operand(rhs, operand, &target, "assignment");
+        }
+    }
+
+    /// Checks the left-hand side of an assignment. Assigning to a variable doesn't count as using
+    /// it
+    fn check_lhs(&mut self, lhs: &mut Expression) -> Lhs {
+        match lhs {
+            Expression::Name { name, .. } if name == "_" => Lhs::Blank,
+            Expression::Name {
+                expr_type,
+                name,
+                span,
+            } => match self.lookup(name, *span).map(|symbol| symbol.kind.clone()) {
+                None | Some(SymbolKind::Var(Type::Unknown)) => Lhs::Invalid,
+                Some(SymbolKind::Var(ty)) => {
+                    *expr_type = ty.clone();
+                    Lhs::Var(ty)
+                }
+                Some(_) => self.unassignable_name(name, *span),
+            },
+            // An element of a slice, of an array variable or of a map
+            _ => match self.check_expr(lhs) {
+                Some(operand) if matches!(operand.mode, Mode::Variable | Mode::MapIndex) => {
+                    Lhs::Var(operand.ty)
+                }
+                Some(_) => self.unassignable(lhs),
+                None => Lhs::Invalid,
+            },
+        }
+    }
+
+    /// Checks `lhs op= rhs`, which assigns `lhs op rhs` to `lhs`
+    fn check_assign_op(
+        &mut self,
+        lhs: &mut Expression,
+        op: &BinaryOp,
+        rhs: &mut Expression,
+        span: Span,
+    ) {
+        if self.check_modifiable(lhs).is_none() {
+            self.check_value(rhs);
+            return;
+        }
+        // The right-hand side is checked (and converted) as the right operand of `op`
+        let placeholder = Expression::Name {
+            expr_type: Type::Unknown,
+            name: "_".to_string(),
+            span,
+        };
+        let mut binop = Expression::BinaryOp {
+            expr_type: Type::Unknown,
+            op: op.clone(),
+            left: Box::new(lhs.clone()),
+            right: Box::new(std::mem::replace(rhs, placeholder)),
+            span,
+        };
+        self.check_binop(&mut binop);
+        if let Expression::BinaryOp { right, .. } = binop {
+            *rhs = *right;
+        }
+    }
+
+    /// Checks the operand of `op=`, `++` or `--`, which must be a variable (or an element of a
+    /// map), returning its type
+    fn check_modifiable(&mut self, expr: &mut Expression) -> Option<Type> {
+        let operand = self.check_value(expr)?;
+        if !matches!(operand.mode, Mode::Variable | Mode::MapIndex) {
+            self.unassignable(expr);
+            return None;
+        }
+        Some(operand.ty)
+    }
+
+    /// Reports that the operand `expr` can't be assigned to. The fields of a struct in a map are
+    /// pointed out, as they're the likeliest to be mistaken for variables
+    fn unassignable(&mut self, expr: &Expression) -> Lhs {
+        let mut inner = expr;
+        while let Expression::Selector { expr, .. } = inner {
+            inner = expr;
+        }
+        match (expr, inner) {
+            (Expression::Selector { .. }, Expression::Index { expr: map, .. })
+                if matches!(map.get_type(), Type::Map(..)) =>
+            {
+                let msg = format!(
+                    "cannot assign to struct field {} in map",
+                    format_expression(expr)
+                );
+                self.error(Code::UnassignableOperand, msg, expr.span());
+                Lhs::Invalid
+            }
+            _ => self.unassignable_name(&format_expression(expr), expr.span()),
+        }
+    }
+
+    /// Reports that the operand `text` can't be assigned to
+    fn unassignable_name(&mut self, text: &str, span: Span) -> Lhs {
+        let msg = format!(
+            "cannot assign to {} (neither addressable nor a map index expression)",
+            text
+        );
+        self.error(Code::UnassignableOperand, msg, span);
+        Lhs::Invalid
+    }
+
+    /// Checks `for key, value := range expr` (or `=`). Ranging over a string gives the byte index
+    /// of each rune (an `int`) and the rune itself (a `rune`), ranging over an array or a slice
+    /// gives the index and the value of each element, and ranging over a map gives the key and the
+    /// value of each entry
+    fn check_for_range(
+        &mut self,
+        key: Option<&mut Expression>,
+        value: Option<&mut Expression>,
+        define: bool,
+        expr: &mut Expression,
+        body: &mut CodeBlock,
+    ) {
+        // The variables aren't in scope in the range expression
+        let types = match self.check_value(expr) {
+            Some(operand) => match operand.ty.clone() {
+                Type::GoString => {
+                    let operand = operand.convert(&Type::GoString).unwrap();
+                    fold(expr, &operand);
+                    Some((Type::Int, Type::Int32))
+                }
+                Type::Array(_, elem) | Type::Slice(elem) => Some((Type::Int, *elem)),
+                Type::Map(key, value) => Some((*key, *value)),
+                _ => {
+                    let msg = format!("cannot range over {}", describe(expr, &operand));
+                    self.error(Code::InvalidRangeExpr, msg, expr.span());
+                    None
+                }
+            },
+            None => None,
+        };
+        let valid = types.is_some();
+        // If the range expression had an error, so do the variables
+        let (key_type, value_type) = types.unwrap_or((Type::Unknown, Type::Unknown));
+        // Variables declared by the range clause are scoped to the loop
+        self.open_scope();
+        let mut names = HashSet::new();
+        for (var, ty) in [(key, key_type), (value, value_type)] {
+            let var = match var {
+                Some(var) => var,
+                None => continue,
+            };
+            if !define {
+                if let (Lhs::Var(target), true) = (self.check_lhs(var), valid) {
+                    let operand = Operand {
+                        mode: Mode::Value,
+                        ty,
+                    };
+                    self.assign_operand(var, operand, &target, "range clause");
+                }
+                continue;
+            }
+            // The parser only accepts names on the left of `:=`
+            if let Expression::Name {
+                expr_type,
+                name,
+                span,
+            } = var
+            {
+                if name != "_" && !names.insert(name.clone()) {
+                    let msg = format!("{} repeated on left side of :=", name);
+                    self.error(Code::RepeatedDecl, msg, *span);
+                    continue;
+                }
+                *expr_type = ty.clone();
+                self.declare(name, SymbolKind::Var(ty), *span);
+            }
+        }
+        self.loop_depth += 1;
+        self.check_block(body);
+        self.loop_depth -= 1;
+        self.close_scope();
+    }
+
+    fn check_switch(
+        &mut self,
+        init: Option<&mut Statement>,
+        mut tag: Option<&mut Expression>,
+        cases: &mut [CaseClause],
+    ) {
+        // Variables declared by the init statement are scoped to the switch
+        self.open_scope();
+        if let Some(init) = init {
+            self.check_statement(init);
+        }
+        // An untyped constant tag is converted to its default type, and a switch without a tag
+        // switches on `true`
+        let tag_type = match tag.as_deref_mut() {
+            Some(tag) => self.check_value(tag).and_then(|operand| {
+                let ty = operand.ty.clone();
+                self.assign_operand(tag, operand, &ty, "switch expression")
+                    .then_some(ty)
+            }),
+            None => Some(Type::Bool),
+        };
+        let tag = tag.as_deref();
+
+        let mut values = vec![];
+        let mut default: Option<Span> = None;
+        let last = cases.len().saturating_sub(1);
+        for (idx, case) in cases.iter_mut().enumerate() {
+            match &mut case.exprs {
+                Some(exprs) => {
+                    for expr in exprs {
+                        self.check_case(expr, tag, tag_type.as_ref(), &mut values);
+                    }
+                }
+                None => {
+                    let span = Span {
+                        end: case.span.start + "default".len(),
+                        ..case.span
+                    };
+                    match default {
+                        Some(first) => {
+                            let diagnostic = Diagnostic::error(
+                                Code::DuplicateDefault,
+                                "multiple defaults in switch",
+                                span,
+                            )
+                            .with_label(first, "first default");
+                            self.report(diagnostic);
+                        }
+                        None => default = Some(span),
+                    }
+                }
+            }
+            // Only the last statement of a case can be a `fallthrough`
+            let body = match case.body.split_last_mut() {
+                Some((Statement::Fallthrough { span }, body)) => {
+                    if idx == last {
+                        let msg = "cannot fallthrough final case in switch";
+                        self.error(Code::MisplacedFallthrough, msg, *span);
+                    }
+                    body
+                }
+                _ => &mut case.body[..],
+            };
+            self.switch_depth += 1;
+            self.check_block(body);
+            self.switch_depth -= 1;
+        }
+        self.close_scope();
+    }
+
+    /// Checks a value in a case of a switch on `tag` (of type `tag_type`), which is compared with
+    /// the tag. `values` are the constant values of the cases before it, which must all be
+    /// different
+    fn check_case(
+        &mut self,
+        expr: &mut Expression,
+        tag: Option<&Expression>,
+        tag_type: Option<&Type>,
+        values: &mut Vec<(Value, Span)>,
+    ) {
+        let (operand, tag_type) = match (self.check_value(expr), tag_type) {
+            (Some(operand), Some(tag_type)) => (operand, tag_type),
+            _ => return,
+        };
+        let text = format_expression(expr);
+        if let Some(cause) = self.incomparable_cause(tag_type) {
+            let msg = match tag {
+                Some(tag) => format!(
+                    "invalid case {} in switch on {} ({})",
+                    text,
+                    format_expression(tag),
+                    cause
+                ),
+                None => unreachable!("a switch without a tag is on a bool"),
+            };
+            self.error(Code::UndefinedOp, msg, expr.span());
+            return;
+        }
+        match operand.convert(tag_type) {
+            Ok(converted) => {
+                fold(expr, &converted);
+                let value = match converted.value() {
+                    Some(value) => value,
+                    None => return,
+                };
+                match values.iter().find(|(other, _)| other == value) {
+                    Some((_, other)) => {
+                        let msg = format!("duplicate case {} in expression switch", text);
+                        let diagnostic = Diagnostic::error(Code::DuplicateCase, msg, expr.span())
+                            .with_label(*other, "previous case");
+                        self.report(diagnostic);
+                    }
+                    None => values.push((value.clone(), expr.span())),
+                }
+            }
+            Err(err) => {
+                let reason = match err {
+                    ConversionError::Incompatible => format!(
+                        "mismatched types {} and {}",
+                        type_name(&operand),
+                        format_type(tag_type)
+                    ),
+                    ConversionError::Truncated => format!("truncated to {}", format_type(tag_type)),
+                    ConversionError::Overflows => format!("overflows {}", format_type(tag_type)),
+                };
+                let msg = match tag {
+                    Some(tag) => format!(
+                        "invalid case {} in switch on {} ({})",
+                        text,
+                        format_expression(tag),
+                        reason
+                    ),
+                    None => format!("invalid case {} in switch ({})", text, reason),
+                };
+                self.error(Code::MismatchedTypes, msg, expr.span());
+            }
+        }
+    }
+
+    /// Evaluates the constants of `spec`, and declares them
+    fn check_const_spec(&mut self, spec: &mut ConstSpec) {
+        // The spec has no span of its own, so errors about its type are reported at its first name
+        let name_span = spec.names[0].1;
+        let mut valid_type = self.check_type(&mut spec.const_type, name_span);
+        if valid_type
+            && matches!(
+                spec.const_type,
+                Type::Array(..) | Type::Slice(_) | Type::Named(_)
+            )
+        {
+            let msg = format!("invalid constant type {}", format_type(&spec.const_type));
+            self.error(Code::InvalidConstType, msg, name_span);
+            valid_type = false;
+        }
+        self.iota = Some(spec.iota);
+        let mut operands = vec![];
+        for expr in &mut spec.exprs {
+            let operand = self.check_value(expr).and_then(|operand| {
+                if !valid_type {
+                    return None;
+                }
+                if operand.value().is_none() {
+                    let msg = format!("{} is not constant", describe(expr, &operand));
+                    self.error(Code::InvalidConstInit, msg, expr.span());
+                    return None;
+                }
+                if spec.const_type == Type::Unknown {
+                    return Some(operand);
+                }
+                match operand.convert(&spec.const_type) {
+                    Ok(converted) => Some(converted),
+                    Err(err) => {
+                        let msg = format!(
+                            "cannot use {} as {} value in constant declaration{}",
+                            describe(expr, &operand),
+                            format_type(&spec.const_type),
+                            conversion_reason(err)
+                        );
+                        self.error(conversion_code(err), msg, expr.span());
+                        None
+                    }
+                }
+            });
+            operands.push(operand);
+        }
+        self.iota = None;
+        for ((name, span), operand) in spec.names.iter().zip(operands) {
+            self.declare(name, SymbolKind::Const(operand), *span);
+        }
+    }
+
+    /// Checks that `cond` is a boolean condition of a `kind` statement
+    fn check_cond(&mut self, cond: &mut Expression, kind: &str) {
+        let operand = match self.check_value(cond) {
+            Some(operand) => operand,
+            None => return,
+        };
+        match operand.convert(&Type::Bool) {
+            Ok(operand) => fold(cond, &operand),
+            Err(_) => self.error(
+                Code::InvalidCond,
+                format!("non-boolean condition in {}", kind),
+                cond.span(),
+            ),
+        }
+    }
+
+    /// Checks `return exprs`. There must be a value for each result of the function, and they may
+    /// all come from a single call. A bare `return` returns the named results, if they're named
+    fn check_return(&mut self, exprs: &mut [Expression], span: Span) {
+        let results = self.results.clone();
+        if exprs.is_empty() && (results.is_empty() || self.named_results) {
+            return;
+        }
+        let is_multi_value = results.len() > 1 && exprs.len() == 1;
+        if exprs.len() == results.len() {
+            for (expr, result) in exprs.iter_mut().zip(&results) {
+                self.assign(expr, result, "return statement");
+            }
+            return;
+        }
+        let have = if is_multi_value {
+            self.check_multi_value(&mut exprs[0])
+        } else {
+            exprs
+                .iter_mut()
+                .map(|expr| self.check_value(expr))
+                .collect::<Vec<_>>()
+                .into_iter()
+                .collect()
+        };
+        let have = match have {
+            Some(have) => have,
+            None => return,
+        };
+        if have.len() == results.len() {
+            for (operand, result) in have.into_iter().zip(&results) {
+                self.assign_operand(&mut exprs[0], operand, result, "return statement");
+            }
+            return;
+        }
+        let (msg, span) = match exprs.get(results.len()) {
+            _ if have.len() < results.len() => ("not enough return values", span),
+            Some(extra) => ("too many return values", extra.span()),
+            None => ("too many return values", exprs[0].span()),
+        };
+        let have: Vec<String> = have.iter().map(signature_name).collect();
+        let want: Vec<String> = results.iter().map(format_type).collect();
+        let diagnostic = Diagnostic::error(Code::WrongResultCount, msg, span)
+            .with_note(format!("have ({})", have.join(", ")))
+            .with_note(format!("want ({})", want.join(", ")));
+        self.report(diagnostic);
+    }
+
+    /// Checks that `expr` can be assigned to a variable of type `target`, converting it to that
+    /// type if it's an untyped constant. `context` describes where the assignment happens
+    fn assign(&mut self, expr: &mut Expression, target: &Type, context: &str) {
+        if let Some(operand) = self.check_value(expr) {
+            self.assign_operand(expr, operand, target, context);
+        }
+    }
+
+    /// Like `assign`, for an expression that was already checked (giving `operand`). Returns
+    /// whether the assignment is valid
+    fn assign_operand(
+        &mut self,
+        expr: &mut Expression,
+        operand: Operand,
+        target: &Type,
+        context: &str,
+    ) -> bool {
+        // `nil` can't give its type to the target
+        if operand.mode == Mode::Nil && *target == Type::Unknown {
+            let msg = format!("use of untyped nil in {}", context);
+            self.error(Code::UntypedNilUse, msg, expr.span());
+            return false;
+        }
+        // The target's type was invalid, which was reported
+        if *target == Type::Unknown {
+            return false;
+        }
+        let err = match operand.convert(target) {
+            Ok(converted) => {
+                fold(expr, &converted);
+                return true;
+            }
+            Err(err) => err,
+        };
+        let msg = format!(
+            "cannot use {} as {} value in {}{}",
+            describe(expr, &operand),
+            format_type(target),
+            context,
+            conversion_reason(err)
+        );
+        self.error(conversion_code(err), msg, expr.span());
+        false
+    }
+
+    /// Like `check_expr`, but the expression must produce a value
+    fn check_value(&mut self, expr: &mut Expression) -> Option<Operand> {
+        let operand = self.check_expr(expr)?;
+        self.expect_value(expr, operand)
+    }
+
+    /// Like `check_value`, but a call of a function with several results gives all of them, e.g.
+    /// for `a, b := f()` or `g(f())`
+    fn check_multi_value(&mut self, expr: &mut Expression) -> Option<Vec<Operand>> {
+        if let Expression::Call { package: None, .. } = expr {
+            let operand = self.check_call(expr)?;
+            return match operand.ty {
+                Type::Tuple(types) => Some(
+                    types
+                        .into_iter()
+                        .map(|ty| Operand {
+                            mode: Mode::Value,
+                            ty,
+                        })
+                        .collect(),
+                ),
+                _ => Some(vec![self.expect_value(expr, operand)?]),
+            };
+        }
+        Some(vec![self.check_value(expr)?])
+    }
+
+    /// Reports an error if `operand` (of `expr`) isn't a value, e.g. the call of a function
+    /// without results
+    fn expect_value(&mut self, expr: &Expression, operand: Operand) -> Option<Operand> {
+        if operand.mode == Mode::NoValue {
+            let msg = format!("{} used as value", describe(expr, &operand));
+            self.error(Code::NotAnExpr, msg, expr.span());
+            return None;
+        }
+        Some(operand)
+    }
+
+    /// Type checks `expr`, filling in its type. Returns `None` if an error was reported
+    fn check_expr(&mut self, expr: &mut Expression) -> Option<Operand> {
+        match expr {
+            Expression::Name {
+                expr_type,
+                name,
+                span,
+            } => match self.resolve(name, *span)? {
+                // The declaration had an error, which was already reported
+                SymbolKind::Var(Type::Unknown) | SymbolKind::Const(None) => None,
+                SymbolKind::Var(ty) => {
+                    *expr_type = ty.clone();
+                    Some(Operand {
+                        mode: Mode::Variable,
+                        ty,
+                    })
+                }
+                SymbolKind::Const(Some(operand)) => {
+                    *expr_type = operand.ty.clone();
+                    Some(operand)
+                }
+                SymbolKind::Nil => {
+                    *expr = Expression::Nil {
+                        expr_type: Type::Unknown,
+                        span: *span,
+                    };
+                    Some(Operand {
+                        mode: Mode::Nil,
+                        ty: Type::Unknown,
+                    })
+                }
+                SymbolKind::Iota => match self.iota {
+                    Some(iota) => {
+                        *expr_type = Type::Int;
+                        Some(Operand {
+                            mode: Mode::Untyped(Value::Int(iota.into())),
+                            ty: Type::Int,
+                        })
+                    }
+                    None => {
+                        self.error(
+                            Code::InvalidIota,
+                            "cannot use iota outside constant declaration",
+                            *span,
+                        );
+                        None
+                    }
+                },
+                SymbolKind::Func { .. } => {
+                    self.error(
+                        Code::Unsupported,
+                        format!("functions as values are not supported yet: {}", name),
+                        *span,
+                    );
+                    None
+                }
+                SymbolKind::Builtin(_) => {
+                    let msg = format!("{} (built-in function {}) must be called", name, name);
+                    self.error(Code::UncalledBuiltin, msg, *span);
+                    None
+                }
+                SymbolKind::Package(_) => {
+                    let msg = format!("use of package {} without selector", name);
+                    self.error(Code::InvalidPkgUse, msg, *span);
+                    None
+                }
+                SymbolKind::TypeName => {
+                    let msg = format!("{} (type) is not an expression", name);
+                    self.error(Code::NotAnExpr, msg, *span);
+                    None
+                }
+            },
+            Expression::Literal {
+                expr_type, value, ..
+            } => Some(Operand {
+                mode: Mode::Untyped(value.clone()),
+                ty: expr_type.clone(),
+            }),
+            Expression::BinaryOp { .. } => self.check_binop(expr),
+            Expression::UnaryOp { .. } => self.check_unary(expr),
+            Expression::Conversion { .. } => self.check_conversion(expr),
+            Expression::Index { .. } => self.check_index(expr),
+            Expression::Slice { .. } => self.check_slice(expr),
+            Expression::Selector { .. } => self.check_selector(expr),
+            Expression::CompositeLit { .. } => self.check_composite_lit(expr),
+            Expression::ZeroValue { expr_type, .. } => Some(Operand {
+                mode: Mode::Value,
+                ty: expr_type.clone(),
+            }),
+            // `nil` is only checked again once it has a type
+            Expression::Nil { expr_type, .. } => Some(match expr_type {
+                Type::Unknown => Operand {
+                    mode: Mode::Nil,
+                    ty: Type::Unknown,
+                },
+                ty => Operand {
+                    mode: Mode::Value,
+                    ty: ty.clone(),
+                },
+            }),
+            Expression::New { .. } => self.check_new(expr),
+            Expression::Make { .. } => self.check_make(expr),
+            Expression::BuiltinCall { .. } => self.check_builtin(expr),
+            Expression::FmtCall { .. } => self.check_fmt_call(expr),
+            Expression::RuntimeCall { .. } => self.check_runtime_call(expr),
+            Expression::Call {
+                package: Some(_), ..
+            } => self.check_qualified_call(expr),
+            Expression::Call { package: None, .. } => {
+                let operand = self.check_call(expr)?;
+                if let Type::Tuple(_) = operand.ty {
+                    let msg = format!(
+                        "multiple-value {} in single-value context",
+                        describe(expr, &operand)
+                    );
+                    self.error(Code::TooManyValues, msg, expr.span());
+                    return None;
+                }
+                Some(operand)
+            }
+        }
+    }
+
+    /// Checks a call `f(args...)` of a function of the package (or of a builtin, or a conversion,
+    /// which it turns into). The call of a function with several results gives a tuple, which is
+    /// only allowed where several values are
+    fn check_call(&mut self, expr: &mut Expression) -> Option<Operand> {
+        let (expr_type, func, args, has_dots, span) = match expr {
+            Expression::Call {
+                expr_type,
+                package: None,
+                func,
+                args,
+                has_dots,
+                span,
+                ..
+            } => (expr_type, func.clone(), args, *has_dots, *span),
+            _ => unreachable!(),
+        };
+        let kind = self.resolve(&func, span);
+        if let Some(SymbolKind::Builtin(builtin)) = kind {
+            *expr = Expression::BuiltinCall {
+                expr_type: None,
+                builtin,
+                args: std::mem::take(args),
+                has_dots,
+                span,
+            };
+            return self.check_builtin(expr);
+        }
+        if let Some(SymbolKind::TypeName) = kind {
+            if args.len() != 1 {
+                let msg = format!(
+                    "{} in conversion to {}",
+                    if args.is_empty() {
+                        "missing argument"
+                    } else {
+                        "too many arguments"
+                    },
+                    func
+                );
+                self.error(Code::WrongArgCount, msg, span);
+                return None;
+            }
+            if has_dots {
+                let msg = format!("invalid use of ... in conversion to {}", func);
+                self.error(Code::BadDotDotDotSyntax, msg, span);
+                return None;
+            }
+            *expr = Expression::Conversion {
+                expr_type: Type::Named(func.clone()),
+                expr: Box::new(args.pop().unwrap()),
+                span,
+            };
+            return self.check_conversion(expr);
+        }
+        let (params, result) = match kind {
+            Some(SymbolKind::Func { params, result }) => (params, result),
+            _ => {
+                match kind {
+                    Some(SymbolKind::Func { .. }) | None => {}
+                    Some(SymbolKind::Package(_)) => self.error(
+                        Code::InvalidPkgUse,
+                        format!("use of package {} without selector", func),
+                        span,
+                    ),
+                    Some(_) => self.error(
+                        Code::InvalidCall,
+                        format!("invalid operation: cannot call non-function {}", func),
+                        span,
+                    ),
+                }
+                for arg in args {
+                    self.check_expr(arg);
+                }
+                return None;
+            }
+        };
+        if has_dots {
+            let msg = format!("cannot use ... in call to non-variadic {}", func);
+            self.error(Code::NonVariadicDotDotDot, msg, span);
+            for arg in args {
+                self.check_expr(arg);
+            }
+            return None;
+        }
+        let context = format!("argument to {}", func);
+        let is_multi_value = params.len() > 1 && args.len() == 1;
+        if args.len() == params.len() {
+            for (arg, param) in args.iter_mut().zip(&params) {
+                self.assign(arg, param, &context);
+            }
+        } else {
+            // The results of a call can be all the arguments of another, as in `g(f())`
+            let have = if is_multi_value {
+                self.check_multi_value(&mut args[0])?
+            } else {
+                let mut have = vec![];
+                for arg in args.iter_mut() {
+                    have.push(self.check_value(arg)?);
+                }
+                have
+            };
+            if have.len() == params.len() {
+                for (operand, param) in have.into_iter().zip(&params) {
+                    self.assign_operand(&mut args[0], operand, param, &context);
+                }
+                *expr_type = result.clone();
+                return Some(call_operand(result));
+            }
+            let (msg, span) = match args.get(params.len()) {
+                _ if have.len() < params.len() => ("not enough", span),
+                Some(extra) => ("too many", extra.span()),
+                None => ("too many", args[0].span()),
+            };
+            let have: Vec<String> = have.iter().map(signature_name).collect();
+            let want: Vec<String> = params.iter().map(format_type).collect();
+            let msg = format!("{} arguments in call to {}", msg, func);
+            let diagnostic = Diagnostic::error(Code::WrongArgCount, msg, span)
+                .with_note(format!("have ({})", have.join(", ")))
+                .with_note(format!("want ({})", want.join(", ")));
+            self.report(diagnostic);
+        }
+        *expr_type = result.clone();
+        Some(call_operand(result))
+    }
+
+    /// Checks a call of a predeclared function. `len(s)` is constant if `s` is a constant string
+    /// or an array (unless computing it involves a call), `print` and `println` take any number of
+    /// arguments of any type, and `append` and `copy` work on slices
+    fn check_builtin(&mut self, expr: &mut Expression) -> Option<Operand> {
+        let text = format_expression(expr);
+        let (expr_type, builtin, args, has_dots, span) = match expr {
+            Expression::BuiltinCall {
+                expr_type,
+                builtin,
+                args,
+                has_dots,
+                span,
+            } => (expr_type, *builtin, args, *has_dots, *span),
+            _ => unreachable!(),
+        };
+        if has_dots && builtin != Builtin::Append {
+            let msg = format!("invalid use of ... with built-in {}", builtin.name());
+            self.error(Code::InvalidDotDotDot, msg, span);
+            for arg in args {
+                self.check_expr(arg);
+            }
+            return None;
+        }
+        let mut operands = vec![];
+        for arg in args.iter_mut() {
+            operands.push(self.check_value(arg)?);
+        }
+        match builtin {
+            Builtin::Print | Builtin::Println => {
+                // Untyped constants are printed as values of their default type
+                let context = format!("argument to built-in {}", builtin.name());
+                let mut valid = true;
+                for (arg, operand) in args.iter_mut().zip(operands) {
+                    if let Type::Array(..) | Type::Named(_) = operand.ty {
+                        let msg = format!("illegal types for operand: {}", builtin.name());
+                        let diagnostic = Diagnostic::error(Code::UndefinedOp, msg, arg.span())
+                            .with_note(format_type(&operand.ty));
+                        self.report(diagnostic);
+                        valid = false;
+                        continue;
+                    }
+                    let ty = operand.ty.clone();
+                    valid &= self.assign_operand(arg, operand, &ty, &context);
+                }
+                if !valid {
+                    return None;
+                }
+                Some(Operand {
+                    mode: Mode::NoValue,
+                    ty: Type::Unknown,
+                })
+            }
+            Builtin::Len | Builtin::Cap => {
+                if !self.check_arg_count(&text, args, 1, span) {
+                    return None;
+                }
+                let (arg, operand) = (&mut args[0], operands.remove(0));
+                let valid = match operand.ty {
+                    Type::GoString | Type::Map(..) => builtin == Builtin::Len,
+                    Type::Array(..) | Type::Slice(_) => true,
+                    _ => false,
+                };
+                if !valid {
+                    let msg = format!(
+                        "invalid argument: {} for built-in {}",
+                        describe(arg, &operand),
+                        builtin.name()
+                    );
+                    let code = match builtin {
+                        Builtin::Len => Code::InvalidLen,
+                        _ => Code::InvalidCap,
+                    };
+                    self.error(code, msg, arg.span());
+                    return None;
+                }
+                let ty = operand.ty.clone();
+                let operand = operand.convert(&ty).unwrap();
+                fold(arg, &operand);
+                *expr_type = Some(Type::Int);
+                // The length of an array is part of its type, but calls must still be made
+                let value = match operand.ty {
+                    Type::Array(len, _) if !arg.contains_call() => Some(Value::Int(len.into())),
+                    _ => operand
+                        .value()
+                        .and_then(Value::string_bytes)
+                        .map(|bytes| Value::Int(bytes.len().into())),
+                };
+                match value {
+                    Some(value) => self.typed_constant(value, Type::Int, span),
+                    None => Some(Operand {
+                        mode: Mode::Value,
+                        ty: Type::Int,
+                    }),
+                }
+            }
+            Builtin::Append => {
+                if args.is_empty() {
+                    self.check_arg_count(&text, args, 1, span);
+                    return None;
+                }
+                let slice_type = operands[0].ty.clone();
+                let elem = match &slice_type {
+                    Type::Slice(elem) => (**elem).clone(),
+                    _ => {
+                        let msg = format!(
+                            "invalid argument: {} is not a slice",
+                            describe(&args[0], &operands[0])
+                        );
+                        self.error(Code::InvalidAppend, msg, args[0].span());
+                        return None;
+                    }
+                };
+                let context = "argument to append";
+                let rest = &mut args[1..];
+                let mut valid = true;
+                if has_dots {
+                    // `append(s, t...)` appends the elements of the slice `t` (or the bytes of the
+                    // string `t`, if `s` is a `[]byte`)
+                    if rest.len() != 1 {
+                        let msg = if rest.is_empty() {
+                            "not enough arguments in call to append"
+                        } else {
+                            "too many arguments in call to append"
+                        };
+                        let have: Vec<String> = operands.iter().map(signature_name).collect();
+                        let diagnostic = Diagnostic::error(Code::WrongArgCount, msg, span)
+                            .with_note(format!("have ({})", have.join(", ")))
+                            .with_note(format!(
+                                "want ({}, ...{})",
+                                format_type(&slice_type),
+                                format_type(&elem)
+                            ));
+                        self.report(diagnostic);
+                        return None;
+                    }
+                    let operand = operands.remove(1);
+                    if elem == Type::Uint8 && operand.ty == Type::GoString {
+                        let operand = operand.convert(&Type::GoString).unwrap();
+                        fold(&mut rest[0], &operand);
+                    } else {
+                        valid = self.assign_operand(&mut rest[0], operand, &slice_type, context);
+                    }
+                } else {
+                    for (arg, operand) in rest.iter_mut().zip(operands.into_iter().skip(1)) {
+                        valid &= self.assign_operand(arg, operand, &elem, context);
+                    }
+                }
+                if !valid {
+                    return None;
+                }
+                *expr_type = Some(slice_type.clone());
+                Some(Operand {
+                    mode: Mode::Value,
+                    ty: slice_type,
+                })
+            }
+            Builtin::Copy => {
+                if !self.check_arg_count(&text, args, 2, span) {
+                    return None;
+                }
+                let (dst, src) = (&operands[0], &operands[1]);
+                let dst_elem = match &dst.ty {
+                    Type::Slice(elem) => Some(&**elem),
+                    _ => None,
+                };
+                // The source may be a string, whose bytes are copied
+                let src_elem = match &src.ty {
+                    Type::Slice(elem) => Some(&**elem),
+                    Type::GoString => Some(&Type::Uint8),
+                    _ => None,
+                };
+                let (dst_elem, src_elem) = match (dst_elem, src_elem) {
+                    (Some(dst_elem), Some(src_elem)) => (dst_elem, src_elem),
+                    _ => {
+                        let msg = format!(
+                            "invalid argument: copy expects slice arguments; found {} and {}",
+                            describe(&args[0], dst),
+                            describe(&args[1], src)
+                        );
+                        self.error(Code::InvalidCopy, msg, args[0].span());
+                        return None;
+                    }
+                };
+                if dst_elem != src_elem {
+                    let msg = format!(
+                        "invalid argument: arguments to copy {} and {} have different element \
+                         types {} and {}",
+                        describe(&args[0], dst),
+                        describe(&args[1], src),
+                        format_type(dst_elem),
+                        format_type(src_elem)
+                    );
+                    self.error(Code::InvalidCopy, msg, args[0].span());
+                    return None;
+                }
+                let src = operands.remove(1);
+                let src = src.convert(&src.ty.clone()).unwrap();
+                fold(&mut args[1], &src);
+                *expr_type = Some(Type::Int);
+                Some(Operand {
+                    mode: Mode::Value,
+                    ty: Type::Int,
+                })
+            }
+            Builtin::Delete => {
+                if !self.check_arg_count(&text, args, 2, span) {
+                    return None;
+                }
+                let key = match &operands[0].ty {
+                    Type::Map(key, _) => (**key).clone(),
+                    _ => {
+                        let msg = format!(
+                            "invalid argument: {} is not a map",
+                            describe(&args[0], &operands[0])
+                        );
+                        self.error(Code::InvalidDelete, msg, args[0].span());
+                        return None;
+                    }
+                };
+                let operand = operands.remove(1);
+                if !self.assign_operand(&mut args[1], operand, &key, "argument to delete") {
+                    return None;
+                }
+                Some(Operand {
+                    mode: Mode::NoValue,
+                    ty: Type::Unknown,
+                })
+            }
+            Builtin::New => unreachable!("new(T) should have been parsed as Expression::New"),
+            Builtin::Make => unreachable!("make(T) should have been parsed as Expression::Make"),
+        }
+    }
+
+    /// Checks `new(T)`, which allocates a zeroed `T` and gives a pointer to it
+    fn check_new(&mut self, expr: &mut Expression) -> Option<Operand> {
+        let (expr_type, span) = match expr {
+            Expression::New { expr_type, span } => (expr_type, *span),
+            _ => unreachable!(),
+        };
+        match self.resolve("new", span)? {
+            SymbolKind::Builtin(Builtin::New) => {}
+            _ => {
+                let msg = "calls of a shadowed new are not supported yet";
+                self.error(Code::Unsupported, msg, span);
+                return None;
+            }
+        }
+        if !self.check_type(expr_type, span) {
+            return None;
+        }
+        Some(Operand {
+            mode: Mode::Value,
+            ty: expr_type.clone(),
+        })
+    }
+
+    /// Checks `make(T, args...)`, which makes a map `T` with room for `args[0]` elements (if
+    /// given). Slices and channels can't be made yet
+    fn check_make(&mut self, expr: &mut Expression) -> Option<Operand> {
+        let text = format_expression(expr);
+        let (expr_type, args, span) = match expr {
+            Expression::Make {
+                expr_type,
+                args,
+                span,
+            } => (expr_type, args, *span),
+            _ => unreachable!(),
+        };
+        match self.resolve("make", span)? {
+            SymbolKind::Builtin(Builtin::Make) => {}
+            _ => {
+                let msg = "calls of a shadowed make are not supported yet";
+                self.error(Code::Unsupported, msg, span);
+                return None;
+            }
+        }
+        let valid_type = self.check_type(expr_type, span);
+        let mut operands = vec![];
+        for arg in args.iter_mut() {
+            operands.push(self.check_value(arg)?);
+        }
+        if !valid_type {
+            return None;
+        }
+        match expr_type {
+            Type::Map(..) => {}
+            Type::Slice(_) => {
+                self.error(
+                    Code::Unsupported,
+                    "make of slices is not supported yet",
+                    span,
+                );
+                return None;
+            }
+            ty => {
+                let msg = format!(
+                    "invalid argument: cannot make {}; type must be slice, map, or channel",
+                    format_type(ty)
+                );
+                self.error(Code::InvalidMake, msg, span);
+                return None;
+            }
+        }
+        if args.len() > 1 {
+            let msg = format!(
+                "invalid operation: {} expects 1 or 2 arguments; found {}",
+                text,
+                args.len() + 1
+            );
+            self.error(Code::WrongArgCount, msg, span);
+            return None;
+        }
+        // The size is checked like an index, which can't be negative either
+        for (arg, operand) in args.iter_mut().zip(operands) {
+            self.check_index_value(arg, operand, None)?;
+        }
+        Some(Operand {
+            mode: Mode::Value,
+            ty: expr_type.clone(),
+        })
+    }
+
+    /// Checks a call `pkg.F(args...)` of a function of an imported package. Only the `fmt` and
+    /// `runtime` packages are supported, whose calls are turned into `Expression::FmtCall`s and
+    /// `Expression::RuntimeCall`s
+    fn check_qualified_call(&mut self, expr: &mut Expression) -> Option<Operand> {
+        let (package, package_span, func, func_span, args, has_dots, span) = match expr {
+            Expression::Call {
+                package: Some((package, package_span)),
+                func,
+                func_span,
+                args,
+                has_dots,
+                span,
+                ..
+            } => (
+                package.clone(),
+                *package_span,
+                func.clone(),
+                *func_span,
+                args,
+                *has_dots,
+                *span,
+            ),
+            _ => unreachable!(),
+        };
+        let fmt_func = match self.resolve(&package, package_span) {
+            Some(SymbolKind::Package(path)) if path == "fmt" => {
+                let fmt_func = FmtFunc::ALL.iter().find(|fmt_func| fmt_func.name() == func);
+                if fmt_func.is_none() {
+                    let msg = format!("undefined: fmt.{}", func);
+                    self.error(Code::UndeclaredName, msg, span);
+                }
+                fmt_func.copied()
+            }
+            Some(SymbolKind::Package(path)) if path == "runtime" => {
+                match RuntimeFunc::ALL
+                    .iter()
+                    .find(|runtime_func| runtime_func.name() == func)
+                {
+                    Some(runtime_func) if !has_dots => {
+                        *expr = Expression::RuntimeCall {
+                            expr_type: None,
+                            func: *runtime_func,
+                            args: std::mem::take(args),
+                            span,
+                        };
+                        return self.check_runtime_call(expr);
+                    }
+                    Some(_) => {
+                        let msg =
+                            format!("cannot use ... in call to non-variadic runtime.{}", func);
+                        self.error(Code::NonVariadicDotDotDot, msg, span);
+                    }
+                    None => {
+                        let msg = format!("runtime.{} is not supported yet", func);
+                        self.error(Code::Unsupported, msg, span);
+                    }
+                }
+                for arg in args.iter_mut() {
+                    self.check_expr(arg);
+                }
+                None
+            }
+            Some(SymbolKind::Package(path)) => {
+                let msg = format!("package {} is not supported yet", path);
+                self.error(Code::Unsupported, msg, span);
+                None
+            }
+            // A method call, which can't be of a field
+            Some(_) => {
+                let mut selector = Expression::Selector {
+                    expr_type: Type::Unknown,
+                    expr: Box::new(Expression::Name {
+                        expr_type: Type::Unknown,
+                        name: package.clone(),
+                        span: package_span,
+                    }),
+                    field: func.clone(),
+                    path: vec![],
+                    span: Span {
+                        end: func_span.end,
+                        ..package_span
+                    },
+                };
+                if let Some(operand) = self.check_selector(&mut selector) {
+                    let msg = format!(
+                        "invalid operation: cannot call non-function {}",
+                        describe(&selector, &operand)
+                    );
+                    self.error(Code::InvalidCall, msg, span);
+                }
+                None
+            }
+            None => None,
+        };
+        match fmt_func {
+            // The variadic arguments of the fmt functions are a `[]any`, which can't be written yet
+            Some(func) if has_dots => {
+                let mut operands = vec![];
+                for arg in args.iter_mut() {
+                    operands.push(self.check_value(arg));
+                }
+                if let (Some(arg), Some(Some(operand))) = (args.last(), operands.last()) {
+                    let msg = format!(
+                        "cannot use {} as []any value in argument to fmt.{}",
+                        describe(arg, operand),
+                        func.name()
+                    );
+                    self.error(Code::IncompatibleAssign, msg, arg.span());
+                }
+                None
+            }
+            Some(func) => {
+                *expr = Expression::FmtCall {
+                    expr_type: None,
+                    func,
+                    args: std::mem::take(args),
+                    span,
+                };
+                self.check_fmt_call(expr)
+            }
+            None => {
+                for arg in args {
+                    self.check_expr(arg);
+                }
+                None
+            }
+        }
+    }
+
+    /// Checks a call of a function of the `fmt` package. They take any number of arguments of any
+    /// type (after the format string, for `Printf`, `Sprintf` and `Errorf`), the `Sprint`
+    /// functions return a string, and `Errorf` returns an `error`
+    fn check_fmt_call(&mut self, expr: &mut Expression) -> Option<Operand> {
+        let (expr_type, func, args, span) = match expr {
+            Expression::FmtCall {
+                expr_type,
+                func,
+                args,
+                span,
+            } => (expr_type, *func, args, *span),
+            _ => unreachable!(),
+        };
+        let mut operands = vec![];
+        for arg in args.iter_mut() {
+            operands.push(self.check_value(arg)?);
+        }
+        let context = format!("argument to fmt.{}", func.name());
+        let mut args = &mut args[..];
+        if func.has_format() {
+            if args.is_empty() {
+                let msg = format!("not enough arguments in call to fmt.{}", func.name());
+                let diagnostic = Diagnostic::error(Code::WrongArgCount, msg, span)
+                    .with_note("have ()")
+                    .with_note("want (string, ...any)");
+                self.report(diagnostic);
+                return None;
+            }
+            let (format, rest) = args.split_first_mut().unwrap();
+            if !self.assign_operand(format, operands.remove(0), &Type::GoString, &context) {
+                return None;
+            }
+            args = rest;
+        }
+        // Untyped constants are formatted as values of their default type
+        let mut valid = true;
+        for (arg, operand) in args.iter_mut().zip(operands) {
+            if operand.ty.elem().is_some() {
+                let msg = "formatting arrays and slices with fmt is not supported yet";
+                self.error(Code::Unsupported, msg, arg.span());
+                valid = false;
+                continue;
+            }
+            let unsupported = match (&operand.mode, &operand.ty) {
+                (_, Type::Named(_)) => Some("structs"),
+                (Mode::Nil, _) => Some("nil"),
+                (_, Type::Map(..)) => Some("maps"),
+                _ => None,
+            };
+            if let Some(kind) = unsupported {
+                let msg = format!("formatting {} with fmt is not supported yet", kind);
+                self.error(Code::Unsupported, msg, arg.span());
+                valid = false;
+                continue;
+            }
+            let ty = operand.ty.clone();
+            valid &= self.assign_operand(arg, operand, &ty, &context);
+        }
+        if !valid {
+            return None;
+        }
+        match func {
+            FmtFunc::Sprint | FmtFunc::Sprintln | FmtFunc::Sprintf => {
+                *expr_type = Some(Type::GoString);
+                Some(Operand {
+                    mode: Mode::Value,
+                    ty: Type::GoString,
+                })
+            }
+            FmtFunc::Errorf => {
+                *expr_type = Some(Type::Error);
+                Some(Operand {
+                    mode: Mode::Value,
+                    ty: Type::Error,
+                })
+            }
+            FmtFunc::Print | FmtFunc::Println | FmtFunc::Printf => Some(Operand {
+                mode: Mode::NoValue,
+                ty: Type::Unknown,
+            }),
+        }
+    }
+
+    /// Checks a call of a function of the `runtime` package, none of which take arguments or
+    /// return a value
+    fn check_runtime_call(&mut self, expr: &mut Expression) -> Option<Operand> {
+        let (func, args) = match expr {
+            Expression::RuntimeCall { func, args, .. } => (*func, args),
+            _ => unreachable!(),
+        };
+        if let Some(first) = args.first() {
+            let span = first.span();
+            let mut have = vec![];
+            for arg in args.iter_mut() {
+                have.push(self.check_value(arg)?);
+            }
+            let have: Vec<String> = have.iter().map(signature_name).collect();
+            let msg = format!("too many arguments in call to runtime.{}", func.name());
+            let diagnostic = Diagnostic::error(Code::WrongArgCount, msg, span)
+                .with_note(format!("have ({})", have.join(", ")))
+                .with_note("want ()");
+            self.report(diagnostic);
+            return None;
+        }
+        Some(Operand {
+            mode: Mode::NoValue,
+            ty: Type::Unknown,
+        })
+    }
+
+    /// Checks that the call `text` of a builtin has the `expected` number of arguments
+    fn check_arg_count(
+        &mut self,
+        text: &str,
+        args: &[Expression],
+        expected: usize,
+        span: Span,
+    ) -> bool {
+        if args.len() == expected {
+            return true;
+        }
+        let (msg, span) = if args.len() < expected {
+            ("not enough", span)
+        } else {
+            ("too many", args[expected].span())
+        };
+        let msg = format!(
+            "{} arguments for {} (expected {}, found {})",
+            msg,
+            text,
+            expected,
+            args.len()
+        );
+        self.error(Code::WrongArgCount, msg, span);
+        false
+    }
+
+    /// Checks `x[i]`, which gives the `i`th byte of the string `x`, the `i`th element of the array
+    /// or slice `x`, or the element of the map `x` with the key `i`. Elements of slices and of
+    /// array variables are variables themselves
+    fn check_index(&mut self, expr: &mut Expression) -> Option<Operand> {
+        let (expr_type, inner, index) = match expr {
+            Expression::Index {
+                expr_type,
+                expr,
+                index,
+                ..
+            } => (expr_type, expr, index),
+            _ => unreachable!(),
+        };
+        let operand = self.check_value(inner);
+        let index_operand = self.check_value(index);
+        let (operand, index_operand) = (operand?, index_operand?);
+        if let Type::Map(key, value) = &operand.ty {
+            if !self.assign_operand(index, index_operand, key, "map index") {
+                return None;
+            }
+            *expr_type = (**value).clone();
+            return Some(Operand {
+                mode: Mode::MapIndex,
+                ty: (**value).clone(),
+            });
+        }
+        let (length, ty, mode) = match &operand.ty {
+            Type::GoString => {
+                let operand = operand.convert(&Type::GoString).unwrap();
+                fold(inner, &operand);
+                // Indexing a constant string doesn't give a constant
+                let length = operand
+                    .value()
+                    .and_then(Value::string_bytes)
+                    .map(|bytes| bytes.len());
+                (length, Type::Uint8, Mode::Value)
+            }
+            Type::Array(len, elem) => {
+                let mode = match operand.mode {
+                    Mode::Variable => Mode::Variable,
+                    _ => Mode::Value,
+                };
+                (Some(*len as usize), (**elem).clone(), mode)
+            }
+            Type::Slice(elem) => (None, (**elem).clone(), Mode::Variable),
+            _ => {
+                let msg = format!(
+                    "invalid operation: cannot index {}",
+                    describe(inner, &operand)
+                );
+                self.error(Code::NonIndexableOperand, msg, inner.span());
+                return None;
+            }
+        };
+        self.check_index_value(index, index_operand, length)?;
+        *expr_type = ty.clone();
+        Some(Operand { mode, ty })
+    }
+
+    /// Checks `x.f`, which selects the field `f` of the struct `x` (or of the struct `x` points
+    /// to), or of a struct embedded in it (see `lookup_field`). The fields of a variable are
+    /// variables themselves
+    fn check_selector(&mut self, expr: &mut Expression) -> Option<Operand> {
+        let (expr_type, inner, field, path, span) = match expr {
+            Expression::Selector {
+                expr_type,
+                expr,
+                field,
+                path,
+                span,
+            } => (expr_type, expr, field.clone(), path, *span),
+            _ => unreachable!(),
+        };
+        let field_span = Span {
+            start: span.end - field.len(),
+            ..span
+        };
+        // Only the functions of packages can be selected, and only to call them
+        if let Expression::Name { name, .. } = &**inner {
+            let symbol = self.scopes.iter().rev().find_map(|scope| scope.get(name));
+            if let Some(Symbol {
+                kind: SymbolKind::Package(import_path),
+                ..
+            }) = symbol
+            {
+                let import_path = import_path.clone();
+                let name = name.clone();
+                self.resolve(&name, span);
+                let (code, msg) = match import_path.as_str() {
+                    "fmt" if FmtFunc::ALL.iter().any(|func| func.name() == field) => (
+                        Code::Unsupported,
+                        format!("functions as values are not supported yet: fmt.{}", field),
+                    ),
+                    "fmt" => (Code::UndeclaredName, format!("undefined: fmt.{}", field)),
+                    "runtime" if RuntimeFunc::ALL.iter().any(|func| func.name() == field) => (
+                        Code::Unsupported,
+                        format!(
+                            "functions as values are not supported yet: runtime.{}",
+                            field
+                        ),
+                    ),
+                    "runtime" => (
+                        Code::Unsupported,
+                        format!("runtime.{} is not supported yet", field),
+                    ),
+                    _ => (
+                        Code::Unsupported,
+                        format!("package {} is not supported yet", import_path),
+                    ),
+                };
+                self.error(code, msg, span);
+                return None;
+            }
+        }
+        let operand = self.check_value(inner)?;
+        if field == "_" {
+            let msg = "cannot refer to blank field or method";
+            self.error(Code::InvalidBlank, msg, field_span);
+            return None;
+        }
+        if operand.ty == Type::Error && field == "Error" {
+            let msg = format!(
+                "methods are not supported yet: {}.Error",
+                format_expression(inner)
+            );
+            self.error(Code::Unsupported, msg, field_span);
+            return None;
+        }
+        // The fields of a struct can also be selected through a pointer to it
+        let struct_name = match &operand.ty {
+            Type::Named(name) => Some(name.clone()),
+            Type::Pointer(elem) => match &**elem {
+                Type::Named(name) => Some(name.clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+        let lookup = match &struct_name {
+            Some(name) => self.lookup_field(name, &field, false),
+            None => FieldLookup::Missing,
+        };
+        let (field_path, ty) = match lookup {
+            FieldLookup::Found(field_path, found) => (field_path, found.field_type),
+            FieldLookup::Missing => {
+                // A field whose name only differs in case is pointed out
+                let alternative = match &struct_name {
+                    Some(name) => match self.lookup_field(name, &field, true) {
+                        FieldLookup::Found(_, found) => {
+                            format!(", but does have field {}", found.name)
+                        }
+                        _ => String::new(),
+                    },
+                    _ => String::new(),
+                };
+                let msg = format!(
+                    "{}.{} undefined (type {} has no field or method {}{})",
+                    format_expression(inner),
+                    field,
+                    format_type(&operand.ty),
+                    field,
+                    alternative
+                );
+                self.error(Code::MissingFieldOrMethod, msg, field_span);
+                return None;
+            }
+            FieldLookup::Ambiguous => {
+                let msg = format!("ambiguous selector {}.{}", format_expression(inner), field);
+                self.error(Code::AmbiguousSelector, msg, field_span);
+                return None;
+            }
+        };
+        // The field's type was invalid, which was reported
+        if ty == Type::Unknown {
+            return None;
+        }
+        *path = field_path;
+        *expr_type = ty.clone();
+        let mode = match (&operand.mode, &operand.ty) {
+            (Mode::Variable, _) | (_, Type::Pointer(_)) => Mode::Variable,
+            _ => Mode::Value,
+        };
+        Some(Operand { mode, ty })
+    }
+
+    /// Looks up the field `field` of the struct type `name`. A field of a struct embedded in it is
+    /// promoted, unless it has a field with the same name at a shallower depth: the fields of the
+    /// struct itself are at depth 0, those of the structs it embeds at depth 1, and so on. A name
+    /// that several fields at the shallowest depth have is ambiguous. With `fold_case`, the case
+    /// of the names doesn't matter
+    fn lookup_field(&self, name: &str, field: &str, fold_case: bool) -> FieldLookup {
+        // The structs at the current depth, with the path to each of them. A struct embedded at
+        // several depths only counts at the shallowest one
+        let mut structs = vec![(name.to_string(), vec![])];
+        let mut seen = HashSet::new();
+        while !structs.is_empty() {
+            let mut found = vec![];
+            let mut embedded = vec![];
+            for (name, path) in &structs {
+                let fields = match self.structs.get(name) {
+                    Some(fields) if !seen.contains(name) => fields,
+                    _ => continue,
+                };
+                for (idx, candidate) in fields.iter().enumerate() {
+                    let mut field_path = path.clone();
+                    field_path.push(idx);
+                    if candidate.name == field
+                        || fold_case && candidate.name.eq_ignore_ascii_case(field)
+                    {
+                        found.push((field_path.clone(), candidate.clone()));
+                    }
+                    if let (true, Type::Named(inner)) = (candidate.embedded, &candidate.field_type)
+                    {
+                        embedded.push((inner.clone(), field_path));
+                    }
+                }
+            }
+            match found.len() {
+                0 => {}
+                1 => {
+                    let (path, field) = found.pop().unwrap();
+                    return FieldLookup::Found(path, field);
+                }
+                _ => return FieldLookup::Ambiguous,
+            }
+            seen.extend(structs.into_iter().map(|(name, _)| name));
+            structs = embedded;
+        }
+        FieldLookup::Missing
+    }
+
+    /// Why `==` isn't defined on values of a type, if it isn't comparable: slices and maps aren't,
+    /// and neither are arrays and structs of them
+    fn incomparable_cause(&self, ty: &Type) -> Option<String> {
+        match ty {
+            Type::Slice(_) => Some("slice can only be compared to nil".to_string()),
+            Type::Map(..) => Some("map can only be compared to nil".to_string()),
+            Type::Array(_, elem) => self
+                .incomparable_cause(elem)
+                .map(|_| format!("{} cannot be compared", format_type(ty))),
+            Type::Named(name) => self.structs[name].iter().find_map(|field| {
+                self.incomparable_cause(&field.field_type).map(|_| {
+                    format!(
+                        "struct containing {} cannot be compared",
+                        format_type(&field.field_type)
+                    )
+                })
+            }),
+            _ => None,
+        }
+    }
+
+    /// Checks `x[low:high]` or `x[low:high:max]`. Slicing a string gives the bytes from `low` (0
+    /// by default) up to `high` (`len(x)` by default); slicing an array variable or a slice gives a
+    /// slice of the elements, whose capacity ends at `max` (`cap(x)` by default)
+    fn check_slice(&mut self, expr: &mut Expression) -> Option<Operand> {
+        let (expr_type, inner, low, high, max_index, span) = match expr {
+            Expression::Slice {
+                expr_type,
+                expr,
+                low,
+                high,
+                max,
+                span,
+            } => (expr_type, expr, low, high, max, *span),
+            _ => unreachable!(),
+        };
+        let operand = self.check_value(inner)?;
+        // The indices of a slice may be equal to the length
+        let (ty, max) = match &operand.ty {
+            Type::GoString => {
+                if max_index.is_some() {
+                    let msg = "invalid operation: 3-index slice of string";
+                    self.error(Code::InvalidSliceExpr, msg, span);
+                    return None;
+                }
+                let operand = operand.convert(&Type::GoString).unwrap();
+                fold(inner, &operand);
+                let max = operand
+                    .value()
+                    .and_then(Value::string_bytes)
+                    .map(|bytes| bytes.len() + 1);
+                (Type::GoString, max)
+            }
+            Type::Array(len, elem) => {
+                if operand.mode != Mode::Variable {
+                    let msg = format!(
+                        "invalid operation: {} (slice of unaddressable value)",
+                        describe(inner, &operand)
+                    );
+                    self.error(Code::NonSliceableOperand, msg, inner.span());
+                    return None;
+                }
+                (Type::Slice(elem.clone()), Some(*len as usize + 1))
+            }
+            Type::Slice(_) => (operand.ty.clone(), None),
+            _ => {
+                let msg = format!(
+                    "invalid operation: cannot slice {}",
+                    describe(inner, &operand)
+                );
+                self.error(Code::NonSliceableOperand, msg, inner.span());
+                return None;
+            }
+        };
+        let mut values = vec![];
+        let mut valid = true;
+        for index in [low, high, max_index] {
+            let index = match index {
+                Some(index) => index,
+                None => continue,
+            };
+            match self
+                .check_value(index)
+                .and_then(|operand| self.check_index_value(index, operand, max))
+            {
+                Some(operand) => values.push(match operand.value() {
+                    Some(Value::Int(value)) => Some((value.clone(), index.span())),
+                    _ => None,
+                }),
+                None => valid = false,
+            }
+        }
+        // Constant indices must be in order (only the first pair that isn't is reported)
+        let swapped = values.iter().enumerate().find_map(|(i, a)| {
+            let (a, _) = a.as_ref()?;
+            values[i + 1..]
+                .iter()
+                .flatten()
+                .find(|(b, _)| b < a)
+                .map(|b| (a, b))
+        });
+        if let Some((a, (b, span))) = swapped {
+            let msg = format!("invalid slice indices: {} < {}", b, a);
+            self.error(Code::SwappedSliceIndices, msg, *span);
+            return None;
+        }
+        if !valid {
+            return None;
+        }
+        *expr_type = ty.clone();
+        Some(Operand {
+            mode: Mode::Value,
+            ty,
+        })
+    }
+
+    /// Checks an index into a string or array (of `max` - 1 bytes or elements, if it's known;
+    /// `max` is the first invalid index), which must be an integer, and isn't negative or out of
+    /// bounds if it's constant. An untyped constant index becomes an `int`
+    fn check_index_value(
+        &mut self,
+        index: &mut Expression,
+        operand: Operand,
+        max: Option<usize>,
+    ) -> Option<Operand> {
+        let operand = match operand.convert(&Type::Int) {
+            Ok(converted) if operand.is_untyped() => converted,
+            Err(err @ (ConversionError::Truncated | ConversionError::Overflows)) => {
+                let reason = match err {
+                    ConversionError::Truncated => "truncated to",
+                    _ => "overflows",
+                };
+                let msg = format!("{} {} int", describe(index, &operand), reason);
+                self.error(conversion_code(err), msg, index.span());
+                return None;
+            }
+            _ => operand,
+        };
+        if !operand.ty.is_integer() {
+            let msg = format!(
+                "invalid argument: index {} must be integer",
+                describe(index, &operand)
+            );
+            self.error(Code::InvalidIndex, msg, index.span());
+            return None;
+        }
+        if let Some(Value::Int(value)) = operand.value() {
+            if value.is_negative() {
+                let msg = format!(
+                    "invalid argument: index {} must not be negative",
+                    describe(index, &operand)
+                );
+                self.error(Code::InvalidIndex, msg, index.span());
+                return None;
+            }
+            if let Some(max) = max {
+                if *value >= max.into() {
+                    let msg = format!(
+                        "invalid argument: index {} out of bounds [0:{}]",
+                        value, max
+                    );
+                    self.error(Code::InvalidIndex, msg, index.span());
+                    return None;
+                }
+            }
+        }
+        fold(index, &operand);
+        Some(operand)
+    }
+
+    /// Checks an array or slice literal `T{elements}`. Each element is assigned to the element type,
+    /// at the index given by its key (which must be constant), or at the index after the previous
+    /// element's. The elements of `[...]T{...}` decide its length. Struct and map literals are
+    /// checked by `check_struct_lit` and `check_map_lit`
+    fn check_composite_lit(&mut self, expr: &mut Expression) -> Option<Operand> {
+        let (expr_type, syntax, elements, lit_span) = match expr {
+            Expression::CompositeLit {
+                expr_type,
+                syntax,
+                elements,
+                span,
+            } => (expr_type, *syntax, elements, *span),
+            _ => unreachable!(),
+        };
+        self.check_type(expr_type, lit_span);
+        let (len, elem) = match &*expr_type {
+            Type::Array(_, elem) if syntax == LitType::InferredLen => (None, (**elem).clone()),
+            Type::Array(len, elem) => (Some(*len), (**elem).clone()),
+            Type::Slice(elem) => (None, (**elem).clone()),
+            Type::Named(name) => {
+                let name = name.clone();
+                self.check_struct_lit(&name, elements, lit_span);
+                // Errors in the elements don't change the type of the literal
+                return Some(Operand {
+                    mode: Mode::Value,
+                    ty: expr_type.clone(),
+                });
+            }
+            Type::Map(key, value) => {
+                let (key, value) = ((**key).clone(), (**value).clone());
+                self.check_map_lit(&key, &value, elements);
+                return Some(Operand {
+                    mode: Mode::Value,
+                    ty: expr_type.clone(),
+                });
+            }
+            ty => {
+                // The elided type of the elements of an invalid literal is unknown
+                if *ty != Type::Unknown {
+                    let msg = format!("invalid composite literal type {}", format_type(ty));
+                    self.error(Code::InvalidLit, msg, lit_span);
+                }
+                for element in elements {
+                    if let Some(key) = &mut element.key {
+                        self.check_expr(key);
+                    }
+                    self.check_expr(&mut element.value);
+                }
+                return None;
+            }
+        };
+        // Errors in the elements don't change the type of the literal
+        let mut indices = HashSet::new();
+        let (mut index, mut length) = (0, 0);
+        for element in elements.iter_mut() {
+            let span = match &element.key {
+                Some(key) => Span {
+                    end: element.value.span().end,
+                    ..key.span()
+                },
+                None => element.value.span(),
+            };
+            let mut valid_index = true;
+            if let Some(key) = &mut element.key {
+                let max = len.map(|len| len as usize);
+                match self
+                    .check_value(key)
+                    .and_then(|operand| self.check_index_value(key, operand, max))
+                {
+                    Some(operand) => match operand.value() {
+                        Some(Value::Int(value)) => index = value.to_u64().unwrap(),
+                        _ => {
+                            let msg = format!(
+                                "index {} must be integer constant",
+                                format_expression(key)
+                            );
+                            self.error(Code::InvalidLitIndex, msg, key.span());
+                            valid_index = false;
+                        }
+                    },
+                    None => valid_index = false,
+                }
+            } else if len.is_some_and(|len| index >= len) {
+                let msg = format!("index {} is out of bounds (>= {})", index, len.unwrap());
+                self.error(Code::OversizeArrayLit, msg, span);
+                valid_index = false;
+            }
+            if valid_index && !indices.insert(index) {
+                let msg = format!("duplicate index {} in array or slice literal", index);
+                self.error(Code::DuplicateLitKey, msg, span);
+            }
+            index += 1;
+            length = length.max(index);
+            // `{...}` elements are literals of the element type
+            if let Expression::CompositeLit {
+                expr_type,
+                syntax: LitType::Elided,
+                ..
+            } = &mut element.value
+            {
+                *expr_type = elem.clone();
+            }
+            if let Some(operand) = self.check_value(&mut element.value) {
+                self.assign_operand(&mut element.value, operand, &elem, "array or slice literal");
+            }
+        }
+        if syntax == LitType::InferredLen {
+            if length > u32::MAX.into() {
+                let msg = format!("arrays of {} elements are not supported", length);
+                self.error(Code::Unsupported, msg, lit_span);
+                return None;
+            }
+            *expr_type = Type::Array(length, Box::new(elem));
+        }
+        Some(Operand {
+            mode: Mode::Value,
+            ty: expr_type.clone(),
+        })
+    }
+
+    /// Checks the elements of a literal of a map with keys of type `key` and values of type
+    /// `value`. Every element needs a key, and constant keys can't be repeated
+    fn check_map_lit(&mut self, key: &Type, value: &Type, elements: &mut [KeyedElement]) {
+        let mut keys = vec![];
+        for element in elements.iter_mut() {
+            match &mut element.key {
+                Some(key_expr) => {
+                    let span = Span {
+                        end: element.value.span().end,
+                        ..key_expr.span()
+                    };
+                    // `{...}` keys are literals of the key type
+                    if let Expression::CompositeLit {
+                        expr_type,
+                        syntax: LitType::Elided,
+                        ..
+                    } = key_expr
+                    {
+                        *expr_type = key.clone();
+                    }
+                    let text = format_expression(key_expr);
+                    if let Some(operand) = self.check_value(key_expr) {
+                        if self.assign_operand(key_expr, operand, key, "map literal") {
+                            if let Expression::Literal { value: lit, .. } = &*key_expr {
+                                if keys.contains(lit) {
+                                    let msg = format!("duplicate key {} in map literal", text);
+                                    self.error(Code::DuplicateLitKey, msg, span);
+                                } else {
+                                    keys.push(lit.clone());
+                                }
+                            }
+                        }
+                    }
+                }
+                None => {
+                    let msg = "missing key in map literal";
+                    self.error(Code::MissingLitKey, msg, element.value.span());
+                }
+            }
+            // `{...}` values are literals of the value type
+            if let Expression::CompositeLit {
+                expr_type,
+                syntax: LitType::Elided,
+                ..
+            } = &mut element.value
+            {
+                *expr_type = value.clone();
+            }
+            if let Some(operand) = self.check_value(&mut element.value) {
+                self.assign_operand(&mut element.value, operand, value, "map literal");
+            }
+        }
+    }
+
+    /// Checks the elements of a literal of the struct type `name`, which either all have a key
+    /// (the name of the field they're assigned to) or none (one for each field, in order). `span`
+    /// is that of the literal
+    fn check_struct_lit(&mut self, name: &str, elements: &mut [KeyedElement], span: Span) {
+        let fields = self.structs[name].clone();
+        let keyed = elements
+            .first()
+            .is_some_and(|element| element.key.is_some());
+        let mut assigned = HashSet::new();
+        for (idx, element) in elements.iter_mut().enumerate() {
+            let element_span = match &element.key {
+                Some(key) => Span {
+                    end: element.value.span().end,
+                    ..key.span()
+                },
+                None => element.value.span(),
+            };
+            let field = match (&element.key, keyed) {
+                (Some(_), false) | (None, true) => {
+                    let msg = "mixture of field:value and value elements in struct literal";
+                    self.error(Code::MixedStructLit, msg, element_span);
+                    None
+                }
+                (
+                    Some(Expression::Name {
+                        name: key, span, ..
+                    }),
+                    true,
+                ) => match fields.iter().position(|field| field.name == *key) {
+                    Some(idx) if !assigned.insert(idx) => {
+                        let msg = format!("duplicate field name {} in struct literal", key);
+                        self.error(Code::DuplicateLitField, msg, *span);
+                        None
+                    }
+                    Some(idx) => Some(&fields[idx]),
+                    None => {
+                        let alternative = fields
+                            .iter()
+                            .find(|field| field.name.eq_ignore_ascii_case(key))
+                            .map_or(String::new(), |field| {
+                                format!(", but does have {}", field.name)
+                            });
+                        let msg = format!(
+                            "unknown field {} in struct literal of type {}{}",
+                            key, name, alternative
+                        );
+                        self.error(Code::MissingLitField, msg, *span);
+                        None
+                    }
+                },
+                (Some(key), true) => {
+                    let msg = format!(
+                        "invalid field name {} in struct literal",
+                        format_expression(key)
+                    );
+                    self.error(Code::InvalidLitField, msg, key.span());
+                    None
+                }
+                (None, false) if idx >= fields.len() => {
+                    let msg = format!("too many values in struct literal of type {}", name);
+                    self.error(Code::InvalidStructLit, msg, element_span);
+                    self.check_expr(&mut element.value);
+                    break;
+                }
+                (None, false) => Some(&fields[idx]),
+            };
+            // Only the elements of arrays and slices can have their type elided
+            if let Expression::CompositeLit {
+                syntax: LitType::Elided,
+                span,
+                ..
+            } = &element.value
+            {
+                if field.is_some() {
+                    let span = *span;
+                    self.error(Code::UntypedLit, "missing type in composite literal", span);
+                }
+                continue;
+            }
+            let operand = self.check_value(&mut element.value);
+            if let (Some(field), Some(operand)) = (field, operand) {
+                let ty = field.field_type.clone();
+                self.assign_operand(&mut element.value, operand, &ty, "struct literal");
+            }
+        }
+        if !keyed && !elements.is_empty() && elements.len() < fields.len() {
+            // Reported at the closing brace
+            let span = Span {
+                start: span.end - 1,
+                ..span
+            };
+            let msg = format!("too few values in struct literal of type {}", name);
+            self.error(Code::InvalidStructLit, msg, span);
+        }
+    }
+
+    /// Checks `T(x)`. Constants are converted exactly, and must be representable by `T` (except
+    /// that floating-point constants are rounded); other values can be converted between numeric
+    /// types
+    fn check_conversion(&mut self, expr: &mut Expression) -> Option<Operand> {
+        let (ty, inner, span) = match expr {
+            Expression::Conversion {
+                expr_type,
+                expr,
+                span,
+            } => {
+                if !self.check_type(expr_type, *span) {
+                    self.check_expr(expr);
+                    return None;
+                }
+                (expr_type.clone(), expr, *span)
+            }
+            _ => unreachable!(),
+        };
+        let operand = self.check_value(inner)?;
+        if ty == Type::GoString && operand.ty.is_integer() {
+            let msg = "conversions from integers to strings are not supported yet";
+            self.error(Code::Unsupported, msg, span);
+            return None;
+        }
+        let is_bytes_or_runes = |ty: &Type| matches!(ty, Type::Slice(elem) if matches!(**elem, Type::Uint8 | Type::Int32));
+        if ty == Type::GoString && is_bytes_or_runes(&operand.ty)
+            || is_bytes_or_runes(&ty) && operand.ty == Type::GoString
+        {
+            let msg = "conversions between strings and slices are not supported yet";
+            self.error(Code::Unsupported, msg, span);
+            return None;
+        }
+        if operand.mode == Mode::Nil {
+            if let Ok(converted) = operand.convert(&ty) {
+                fold(inner, &converted);
+                return Some(converted);
+            }
+        } else if let Some(value) = operand.value() {
+            return match value.convert(&ty) {
+                Ok(value) => Some(Operand {
+                    mode: Mode::Constant(value),
+                    ty,
+                }),
+                Err(err) => {
+                    let msg = match (value, err) {
+                        (Value::Int(_), ConversionError::Overflows) if ty.is_integer() => {
+                            format!("constant {} overflows {}", value, format_type(&ty))
+                        }
+                        _ => format!(
+                            "cannot convert {} to type {}{}",
+                            describe(inner, &operand),
+                            format_type(&ty),
+                            conversion_reason(err)
+                        ),
+                    };
+                    self.error(Code::InvalidConversion, msg, span);
+                    None
+                }
+            };
+        }
+        // Struct types with identical fields can be converted to each other
+        let identical_structs = match (&ty, &operand.ty) {
+            (Type::Named(a), Type::Named(b)) => {
+                identical_fields(&self.structs[a], &self.structs[b])
+            }
+            _ => false,
+        };
+        if ty.is_numeric() && operand.ty.is_numeric() || ty == operand.ty || identical_structs {
+            return Some(Operand {
+                mode: Mode::Value,
+                ty,
+            });
+        }
+        let msg = format!(
+            "cannot convert {} to type {}",
+            describe(inner, &operand),
+            format_type(&ty)
+        );
+        self.error(Code::InvalidConversion, msg, span);
+        None
+    }
+
+    /// Checks `+x`, `-x` (numeric operands), `!x` (booleans) and `^x` (integers). The result has
+    /// the type of `x`, and is constant if `x` is
+    fn check_unary(&mut self, expr: &mut Expression) -> Option<Operand> {
+        let (op, inner, span) = match expr {
+            Expression::UnaryOp { op, expr, span, .. } => (op.clone(), expr, *span),
+            _ => unreachable!(),
+        };
+        let operand = self.check_value(inner)?;
+        if let UnaryOp::Addr | UnaryOp::Deref = op {
+            let operand = self.check_pointer_op(&op, inner, operand, span)?;
+            if let Expression::UnaryOp { expr_type, .. } = expr {
+                *expr_type = operand.ty.clone();
+            }
+            return Some(operand);
+        }
+        let defined = match op {
+            UnaryOp::Plus | UnaryOp::Neg => operand.ty.is_numeric(),
+            UnaryOp::Not => operand.ty == Type::Bool,
+            UnaryOp::Complement => operand.ty.is_integer(),
+            UnaryOp::Addr | UnaryOp::Deref => unreachable!(),
+        };
+        if !defined {
+            let msg = format!(
+                "invalid operation: operator {} not defined on {}",
+                format_uop(&op),
+                describe(inner, &operand)
+            );
+            self.error(Code::UndefinedOp, msg, span);
+            return None;
+        }
+        if let Expression::UnaryOp { expr_type, .. } = expr {
+            *expr_type = operand.ty.clone();
+        }
+        let ty = operand.ty.clone();
+        match operand.value() {
+            Some(value) => {
+                let value = constant::unary_op(&op, value, &ty);
+                if operand.is_untyped() {
+                    Some(Operand {
+                        mode: Mode::Untyped(value),
+                        ty,
+                    })
+                } else {
+                    self.typed_constant(value, ty, span)
+                }
+            }
+            None => Some(Operand {
+                mode: Mode::Value,
+                ty,
+            }),
+        }
+    }
+
+    /// Checks `&x`, which takes the address of a variable (or allocates a composite literal), or
+    /// `*p`, which is the variable `p` points to
+    fn check_pointer_op(
+        &mut self,
+        op: &UnaryOp,
+        inner: &Expression,
+        operand: Operand,
+        span: Span,
+    ) -> Option<Operand> {
+        match op {
+            UnaryOp::Addr
+                if operand.mode != Mode::Variable
+                    && !matches!(inner, Expression::CompositeLit { .. }) =>
+            {
+                let msg = format!(
+                    "invalid operation: cannot take address of {}",
+                    describe(inner, &operand)
+                );
+                self.error(Code::UnaddressableOperand, msg, span);
+                None
+            }
+            UnaryOp::Addr => Some(Operand {
+                mode: Mode::Value,
+                ty: Type::Pointer(Box::new(operand.ty)),
+            }),
+            _ => match operand.ty {
+                Type::Pointer(elem) => Some(Operand {
+                    mode: Mode::Variable,
+                    ty: *elem,
+                }),
+                _ => {
+                    let msg = format!(
+                        "invalid operation: cannot indirect {}",
+                        describe(inner, &operand)
+                    );
+                    self.error(Code::InvalidIndirection, msg, span);
+                    None
+                }
+            },
+        }
+    }
+
+    fn check_binop(&mut self, expr: &mut Expression) -> Option<Operand> {
+        let (op, left, right) = match expr {
+            Expression::BinaryOp {
+                op, left, right, ..
+            } => (op.clone(), left, right),
+            _ => unreachable!(),
+        };
+        let lhs = self.check_value(left);
+        let rhs = self.check_value(right);
+        let (lhs, rhs) = (lhs?, rhs?);
+        if matches!(op, BinaryOp::Shl | BinaryOp::Shr) {
+            return self.check_shift(expr, lhs, rhs);
+        }
+
+        // Bring both operands to the same type, converting untyped constants (and `nil`) to the
+        // type of the other operand
+        let nils = [&lhs, &rhs]
+            .iter()
+            .filter(|operand| operand.mode == Mode::Nil)
+            .count();
+        let untyped = |operand: &Operand| operand.is_untyped() || operand.mode == Mode::Nil;
+        let converted = match (untyped(&lhs), untyped(&rhs)) {
+            (true, true) => match (untyped_rank(&lhs.ty), untyped_rank(&rhs.ty)) {
+                _ if lhs.ty == rhs.ty => Ok((lhs.clone(), rhs.clone())),
+                // Numeric constants of different kinds take the kind that comes last of integer,
+                // rune and floating-point
+                (Some(l), Some(r)) if l < r => Ok((untyped_as(&lhs, &rhs.ty), rhs.clone())),
+                (Some(_), Some(_)) => Ok((lhs.clone(), untyped_as(&rhs, &lhs.ty))),
+                _ => Err((ConversionError::Incompatible, left)),
+            },
+            (true, false) => match lhs.convert(&rhs.ty) {
+                Ok(converted) => Ok((converted, rhs.clone())),
+                Err(err) => Err((err, left)),
+            },
+            (false, true) => match rhs.convert(&lhs.ty) {
+                Ok(converted) => Ok((lhs.clone(), converted)),
+                Err(err) => Err((err, right)),
+            },
+            (false, false) if lhs.ty == rhs.ty => Ok((lhs.clone(), rhs.clone())),
+            (false, false) => Err((ConversionError::Incompatible, left)),
+        };
+        let (lhs, rhs) = match converted {
+            Ok(operands) => operands,
+            Err((err, operand_expr)) => {
+                let (untyped, typed) = if lhs.is_untyped() {
+                    (&lhs, &rhs)
+                } else {
+                    (&rhs, &lhs)
+                };
+                let code = match err {
+                    ConversionError::Incompatible => Code::MismatchedTypes,
+                    err => conversion_code(err),
+                };
+                let msg = match err {
+                    ConversionError::Incompatible => format!(
+                        "invalid operation: {} (mismatched types {} and {})",
+                        format_expression(expr),
+                        type_name(&lhs),
+                        type_name(&rhs)
+                    ),
+                    ConversionError::Truncated => format!(
+                        "{} truncated to {}",
+                        describe(operand_expr, untyped),
+                        format_type(&typed.ty)
+                    ),
+                    ConversionError::Overflows => format!(
+                        "{} overflows {}",
+                        describe(operand_expr, untyped),
+                        format_type(&typed.ty)
+                    ),
+                };
+                self.error(code, msg, expr.span());
+                return None;
+            }
+        };
+
+        let ty = lhs.ty.clone();
+        let numeric = ty.is_numeric();
+        let (defined, result) = match op {
+            BinaryOp::Add => (numeric || ty == Type::GoString, ty.clone()),
+            BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => (numeric, ty.clone()),
+            BinaryOp::Rem | BinaryOp::And | BinaryOp::Or | BinaryOp::Xor | BinaryOp::AndNot => {
+                (ty.is_integer(), ty.clone())
+            }
+            BinaryOp::LogicalAnd | BinaryOp::LogicalOr => (ty == Type::Bool, ty.clone()),
+            // Slices can be compared to `nil`, but `nil` can't be compared to itself
+            BinaryOp::Eq | BinaryOp::Neq => (
+                nils == 1 || nils == 0 && self.incomparable_cause(&ty).is_none(),
+                Type::Bool,
+            ),
+            _ => (numeric || ty == Type::GoString, Type::Bool),
+        };
+        if !defined {
+            let msg = if is_comparison(&op) {
+                let reason = match op {
+                    _ if nils == 2 => format!("operator {} not defined on nil", format_bop(&op)),
+                    BinaryOp::Eq | BinaryOp::Neq => self.incomparable_cause(&ty).unwrap(),
+                    _ => format!(
+                        "operator {} not defined on {}",
+                        format_bop(&op),
+                        kind_name(&ty)
+                    ),
+                };
+                format!(
+                    "invalid operation: {} ({})",
+                    format_expression(expr),
+                    reason
+                )
+            } else {
+                let left = match &*expr {
+                    Expression::BinaryOp { left, .. } => left,
+                    _ => unreachable!(),
+                };
+                format!(
+                    "invalid operation: operator {} not defined on {}",
+                    format_bop(&op),
+                    describe(left, &lhs)
+                )
+            };
+            self.error(Code::UndefinedOp, msg, expr.span());
+            return None;
+        }
+
+        let (expr_type, left, right, span) = match expr {
+            Expression::BinaryOp {
+                expr_type,
+                left,
+                right,
+                span,
+                ..
+            } => (expr_type, left, right, *span),
+            _ => unreachable!(),
+        };
+        // Dividing by a constant zero is an error even if the dividend isn't constant, unless it's a
+        // floating-point number (which gives an infinity or NaN at runtime)
+        let is_division = matches!(op, BinaryOp::Div | BinaryOp::Rem);
+        if is_division
+            && rhs.value().is_some_and(Value::is_zero)
+            && (ty.is_integer() || lhs.value().is_some())
+        {
+            self.error(
+                Code::DivByZero,
+                "invalid operation: division by zero",
+                right.span(),
+            );
+            return None;
+        }
+        *expr_type = result.clone();
+        let (lvalue, rvalue) = match (lhs.value(), rhs.value()) {
+            (Some(lvalue), Some(rvalue)) => (lvalue, rvalue),
+            _ => {
+                // Only one side is constant (if any): it's replaced with its value
+                fold(left, &lhs);
+                fold(right, &rhs);
+                return Some(Operand {
+                    mode: Mode::Value,
+                    ty: result,
+                });
+            }
+        };
+        let value = match constant::binary_op(lvalue, &op, rvalue) {
+            Ok(value) => value,
+            Err(msg) => {
+                self.error(Code::DivByZero, msg, span);
+                return None;
+            }
+        };
+        if is_comparison(&op) {
+            // Comparing constants gives an untyped boolean constant
+            Some(Operand {
+                mode: Mode::Untyped(value),
+                ty: Type::Bool,
+            })
+        } else if lhs.is_untyped() && rhs.is_untyped() {
+            Some(Operand {
+                mode: Mode::Untyped(value),
+                ty: result,
+            })
+        } else {
+            self.typed_constant(value, result, span)
+        }
+    }
+
+    /// Checks `left << right` or `left >> right`. The result has the type of `left`, and is only
+    /// constant if both operands are
+    fn check_shift(
+        &mut self,
+        expr: &mut Expression,
+        lhs: Operand,
+        rhs: Operand,
+    ) -> Option<Operand> {
+        let (op, left, right, span) = match &*expr {
+            Expression::BinaryOp {
+                op,
+                left,
+                right,
+                span,
+                ..
+            } => (op.clone(), left, right, *span),
+            _ => unreachable!(),
+        };
+        // The shifted operand must be an integer (an untyped constant one may be larger than any
+        // int), and so must the count
+        let shifted = match &lhs.mode {
+            Mode::Untyped(Value::Int(int)) => Some(int.clone()),
+            Mode::Untyped(Value::Float(float)) if float.is_integer() => Some(float.to_integer()),
+            Mode::Constant(Value::Int(int)) => Some(int.clone()),
+            Mode::Variable | Mode::Value if lhs.ty.is_integer() => None,
+            _ => {
+                let msg = format!(
+                    "invalid operation: shifted operand {} must be integer",
+                    describe(left, &lhs)
+                );
+                self.error(Code::InvalidShiftOperand, msg, span);
+                return None;
+            }
+        };
+        let count = match rhs.value() {
+            Some(Value::Int(count)) => Some(count.clone()),
+            Some(Value::Float(count)) if rhs.is_untyped() && count.is_integer() => {
+                Some(count.to_integer())
+            }
+            None if rhs.ty.is_integer() => None,
+            _ => {
+                let msg = format!(
+                    "invalid operation: shift count {} must be integer",
+                    describe(right, &rhs)
+                );
+                self.error(Code::InvalidShiftCount, msg, span);
+                return None;
+            }
+        };
+        if let Some(count) = &count {
+            if count.is_negative() {
+                let msg = format!(
+                    "invalid operation: negative shift count {}",
+                    describe(right, &rhs)
+                );
+                self.error(Code::InvalidShiftCount, msg, span);
+                return None;
+            }
+        }
+        let invalid_count = |checker: &mut Self| {
+            let msg = format!(
+                "invalid operation: invalid shift count {}",
+                describe(right, &rhs)
+            );
+            checker.error(Code::InvalidShiftCount, msg, span);
+            None
+        };
+
+        let (shifted, count) = match (shifted, count) {
+            (Some(shifted), Some(count)) => match count.to_u64() {
+                Some(count) if count <= constant::MAX_SHIFT => (shifted, count),
+                _ => return invalid_count(self),
+            },
+            (_, count) => {
+                // An untyped constant shifted by a non-constant count takes its default type, and
+                // an untyped constant count becomes a uint
+                let ty = lhs.ty.clone();
+                if !ty.is_integer() {
+                    let msg = format!(
+                        "invalid operation: shifted operand {} (type {}) must be integer",
+                        format_expression(left),
+                        format_type(&ty)
+                    );
+                    self.error(Code::InvalidShiftOperand, msg, span);
+                    return None;
+                }
+                let lhs = match lhs.convert(&ty) {
+                    Ok(lhs) => lhs,
+                    Err(_) => {
+                        let value = lhs.value().unwrap();
+                        let msg = format!("constant {} overflows {}", value, format_type(&ty));
+                        self.error(Code::NumericOverflow, msg, left.span());
+                        return None;
+                    }
+                };
+                let rhs = match (count, rhs.is_untyped()) {
+                    (Some(_), true) => match rhs.convert(&Type::Uint) {
+                        Ok(rhs) => rhs,
+                        Err(_) => return invalid_count(self),
+                    },
+                    _ => rhs,
+                };
+                if let Expression::BinaryOp {
+                    expr_type,
+                    left,
+                    right,
+                    ..
+                } = expr
+                {
+                    *expr_type = ty.clone();
+                    fold(left, &lhs);
+                    fold(right, &rhs);
+                }
+                return Some(Operand {
+                    mode: Mode::Value,
+                    ty,
+                });
+            }
+        };
+
+        // Shifting an untyped floating-point constant gives an untyped integer constant
+        let ty = match lhs.ty {
+            Type::Float64 if lhs.is_untyped() => Type::Int,
+            ref ty => ty.clone(),
+        };
+        if let Expression::BinaryOp { expr_type, .. } = expr {
+            *expr_type = ty.clone();
+        }
+        let value = constant::shift(&shifted, &op, count);
+        if lhs.is_untyped() {
+            Some(Operand {
+                mode: Mode::Untyped(value),
+                ty,
+            })
+        } else {
+            self.typed_constant(value, ty, span)
+        }
+    }
+
+    /// Makes an operand for the result of an operation on typed constants, which must be
+    /// representable by their type
+    fn typed_constant(&mut self, value: Value, ty: Type, span: Span) -> Option<Operand> {
+        match value.convert(&ty) {
+            Ok(value) => Some(Operand {
+                mode: Mode::Constant(value),
+                ty,
+            }),
+            Err(_) => {
+                self.error(
+                    Code::NumericOverflow,
+                    format!("constant {} overflows {}", value, format_type(&ty)),
+                    span,
+                );
+                None
+            }
+        }
+    }
+}
+
+fn is_comparison(op: &BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Eq | BinaryOp::Neq | BinaryOp::Ge | BinaryOp::Le | BinaryOp::Geq | BinaryOp::Leq
+    )
+}
+
+/// The order of the kinds of untyped numeric constants (integer, rune and floating-point, whose
+/// default types are `int`, `rune` and `float64`), or `None` for other constants
+fn untyped_rank(ty: &Type) -> Option<u8> {
+    match ty {
+        Type::Int => Some(0),
+        Type::Int32 => Some(1),
+        Type::Float64 => Some(2),
+        _ => None,
+    }
+}
+
+/// Converts an untyped numeric constant into an untyped constant of a later kind (see
+/// `untyped_rank`), whose default type is `ty`
+fn untyped_as(operand: &Operand, ty: &Type) -> Operand {
+    let value = operand.value().unwrap();
+    Operand {
+        mode: Mode::Untyped(match ty {
+            Type::Float64 => value.to_float(),
+            _ => value.clone(),
+        }),
+        ty: ty.clone(),
+    }
+}
+
+/// Replaces `expr` with a literal of its value, if it is a (typed) constant. `nil` takes the
+/// type it was converted to
+fn fold(expr: &mut Expression, operand: &Operand) {
+    match (&operand.mode, expr) {
+        (Mode::Constant(value), expr) => {
+            *expr = Expression::Literal {
+                expr_type: operand.ty.clone(),
+                value: value.clone(),
+                span: expr.span(),
+            };
+        }
+        (Mode::Value, Expression::Nil { expr_type, .. }) => *expr_type = operand.ty.clone(),
+        _ => {}
+    }
+}
+
+/// Collects the names `expr` refers to
+fn referenced_names<'a>(expr: &'a Expression, names: &mut Vec<&'a str>) {
+    match expr {
+        Expression::Name { name, .. } => names.push(name),
+        Expression::Literal { .. } => {}
+        Expression::BinaryOp { left, right, .. } => {
+            referenced_names(left, names);
+            referenced_names(right, names);
+        }
+        Expression::Call {
+            package,
+            func,
+            args,
+            ..
+        } => {
+            names.push(package.as_ref().map_or(func, |(package, _)| package));
+            for arg in args {
+                referenced_names(arg, names);
+            }
+        }
+        Expression::BuiltinCall { args, .. }
+        | Expression::FmtCall { args, .. }
+        | Expression::RuntimeCall { args, .. } => {
+            for arg in args {
+                referenced_names(arg, names);
+            }
+        }
+        Expression::Index { expr, index, .. } => {
+            referenced_names(expr, names);
+            referenced_names(index, names);
+        }
+        Expression::Slice {
+            expr,
+            low,
+            high,
+            max,
+            ..
+        } => {
+            referenced_names(expr, names);
+            for index in [low, high, max].into_iter().flatten() {
+                referenced_names(index, names);
+            }
+        }
+        Expression::CompositeLit { elements, .. } => {
+            for element in elements {
+                if let Some(key) = &element.key {
+                    referenced_names(key, names);
+                }
+                referenced_names(&element.value, names);
+            }
+        }
+        Expression::ZeroValue { .. } | Expression::Nil { .. } | Expression::New { .. } => {}
+        Expression::Make { args, .. } => {
+            for arg in args {
+                referenced_names(arg, names);
+            }
+        }
+        Expression::UnaryOp { expr, .. }
+        | Expression::Conversion { expr, .. }
+        | Expression::Selector { expr, .. } => referenced_names(expr, names),
+    }
+}
+
+/// The struct type values of type `ty` contain: `ty` itself if it's a struct, or the element
+/// type of an array of structs
+fn contained_struct(ty: &Type) -> Option<&str> {
+    match ty {
+        Type::Named(name) => Some(name),
+        Type::Array(_, elem) => contained_struct(elem),
+        _ => None,
+    }
+}
+
+/// Whether two struct types have the same field names and types (in the same order)
+fn identical_fields(a: &[Field], b: &[Field]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|(a, b)| {
+            a.name == b.name && a.field_type == b.field_type && a.embedded == b.embedded
+        })
+}
+
+/// The kind of a type in errors about undefined operators: `array`, `slice`, `struct`, `pointer`
+/// or `map`, or the name of other types
+fn kind_name(ty: &Type) -> String {
+    match ty {
+        Type::Array(..) => "array".to_string(),
+        Type::Slice(_) => "slice".to_string(),
+        Type::Map(..) => "map".to_string(),
+        Type::Named(_) => "struct".to_string(),
+        Type::Pointer(_) => "pointer".to_string(),
+        _ => format_type(ty),
+    }
+}
+
+/// The explanation appended to errors about a failed conversion
+fn conversion_reason(err: ConversionError) -> &'static str {
+    match err {
+        ConversionError::Incompatible => "",
+        ConversionError::Truncated => " (truncated)",
+        ConversionError::Overflows => " (overflows)",
+    }
+}
+
+/// The code of the error reported when a conversion fails
+fn conversion_code(err: ConversionError) -> Code {
+    match err {
+        ConversionError::Incompatible => Code::IncompatibleAssign,
+        ConversionError::Truncated => Code::TruncatedFloat,
+        ConversionError::Overflows => Code::NumericOverflow,
+    }
+}
+
+/// The number of single character insertions, deletions and substitutions needed to turn `a`
+/// into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != *cb);
+            cur.push(substitution.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// The name of an operand's type, as the go compiler prints it (e.g. `untyped float`)
+fn type_name(operand: &Operand) -> String {
+    if operand.mode == Mode::Nil {
+        return "untyped nil".to_string();
+    }
+    if !operand.is_untyped() {
+        return format_type(&operand.ty);
+    }
+    let kind = match operand.ty {
+        Type::Int32 => "rune".to_string(),
+        Type::Float64 => "float".to_string(),
+        ref ty => format_type(ty),
+    };
+    format!("untyped {}", kind)
+}
+
+/// The operand of a call of a function with `result` (a tuple if it has several results)
+fn call_operand(result: Option<Type>) -> Operand {
+    match result {
+        Some(ty) => Operand {
+            mode: Mode::Value,
+            ty,
+        },
+        None => Operand {
+            mode: Mode::NoValue,
+            ty: Type::Unknown,
+        },
+    }
+}
+
+/// The name of an operand's type in the `have (..)` list of a call or return error
+fn signature_name(operand: &Operand) -> String {
+    match (&operand.mode, &operand.ty) {
+        (Mode::Untyped(_), Type::Int | Type::Int32 | Type::Float64) => "number".to_string(),
+        (_, ty) => format_type(ty),
+    }
+}
+
+/// Describes an operand for an error message, e.g. `x (variable of type int)`. The value of a
+/// constant is included, unless it reads the same as the expression (e.g. a literal)
+fn describe(expr: &Expression, operand: &Operand) -> String {
+    let text = format_expression(expr);
+    let value = match operand.value() {
+        Some(value) if value.to_string() != text => format!(" {}", value),
+        _ => String::new(),
+    };
+    // Like the go compiler, the kind of a declared type is spelled out
+    let type_text = match &operand.ty {
+        Type::Named(_) => format!("struct type {}", format_type(&operand.ty)),
+        ty => format!("type {}", format_type(ty)),
+    };
+    match operand.mode {
+        Mode::Variable => format!("{} (variable of {})", text, type_text),
+        Mode::MapIndex => format!("{} (map index expression of {})", text, type_text),
+        Mode::Value => format!("{} (value of {})", text, type_text),
+        Mode::Constant(_) => format!("{} (constant{} of {})", text, value, type_text),
+        Mode::Untyped(_) => format!("{} ({} constant{})", text, type_name(operand), value),
+        Mode::NoValue => format!("{} (no value)", text),
+        Mode::Nil => text,
+    }
+}
+
+/// Whether control can't flow past the end of `block` (a "terminating statement list" in the spec)
+pub fn is_terminating_block(block: &[Statement]) -> bool {
+    match block {
+        [.., Statement::Label { name, .. }, last] => is_terminating(last, Some(name)),
+        [.., last] => is_terminating(last, None),
+        [] => false,
+    }
+}
+
+/// Whether `stmt` (labeled `label`, if it is) is a terminating statement
+fn is_terminating(stmt: &Statement, label: Option<&str>) -> bool {
+    match stmt {
+        Statement::Return { .. } | Statement::Goto { .. } => true,
+        Statement::If {
+            then_block,
+            else_block,
+            ..
+        } => is_terminating_block(then_block) && is_terminating_block(else_block),
+        // An infinite loop can only be left by a `break`
+        Statement::For {
+            cond: None, body, ..
+        } => !has_break(body, label, false),
+        // Without a default case, a switch can be left when no case matches
+        Statement::Switch { cases, .. } => {
+            cases.iter().any(|case| case.exprs.is_none())
+                && cases.iter().all(|case| {
+                    !has_break(&case.body, label, false)
+                        && (is_terminating_block(&case.body)
+                            || matches!(case.body.last(), Some(Statement::Fallthrough { .. })))
+                })
+        }
+        _ => false,
+    }
+}
+
+/// Whether `block` (in the body of the loop or switch labeled `label`, if it is) contains a
+/// `break` out of it. An unlabeled `break` in a loop or switch nested in it (`nested`) exits that
+/// instead
+fn has_break(block: &[Statement], label: Option<&str>, nested: bool) -> bool {
+    block.iter().any(|stmt| match stmt {
+        Statement::Break { label: None, .. } => !nested,
+        Statement::Break {
+            label: Some(target),
+            ..
+        } => Some(target.as_str()) == label,
+        Statement::If {
+            then_block,
+            else_block,
+            ..
+        } => has_break(then_block, label, nested) || has_break(else_block, label, nested),
+        Statement::For { body, .. } | Statement::ForRange { body, .. } => {
+            has_break(body, label, true)
+        }
+        Statement::Switch { cases, .. } => {
+            cases.iter().any(|case| has_break(&case.body, label, true))
+        }
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    /// The diagnostics for the program `src`, with their codes
+    fn errors(src: &str) -> Vec<(Code, String)> {
+        let mut program = parser::parse(src, 0).unwrap();
+        match check_program(&mut program) {
+            Ok(()) => vec![],
+            Err(errors) => errors.into_iter().map(|err| (err.code, err.msg)).collect(),
+        }
+    }
+
+    /// The diagnostics for a program whose `main` function has the body `body`
+    fn main_errors(body: &str) -> Vec<(Code, String)> {
+        errors(&format!("package main\n\nfunc main() {{\n{}\n}}\n", body))
+    }
+
+    fn error(code: Code, msg: &str) -> Vec<(Code, String)> {
+        vec![(code, msg.to_string())]
+    }
+
+    #[test]
+    fn names_and_scopes() {
+        assert_eq!(main_errors("x := 1\n_ = x"), []);
+        assert_eq!(
+            main_errors("_ = y"),
+            error(Code::UndeclaredName, "undefined: y")
+        );
+        assert_eq!(
+            main_errors("f()"),
+            error(Code::UndeclaredName, "undefined: f")
+        );
+        assert_eq!(
+            main_errors("var x int\nvar x int\n_ = x"),
+            error(Code::DuplicateDecl, "x redeclared in this block")
+        );
+        assert_eq!(
+            errors("package main\n\nfunc f() {}\nfunc f() {}\nfunc main() {}\n"),
+            error(Code::DuplicateDecl, "f redeclared in this block")
+        );
+        // Functions are declared in the package scope, which the function body may shadow
+        assert_eq!(main_errors("main := 1\n_ = main"), []);
+        assert_eq!(
+            errors("package main\n\nfunc f() {}\n\nfunc main() {\n\tf()\n}\n"),
+            []
+        );
+    }
+
+    #[test]
+    fn unused_variables_and_imports() {
+        assert_eq!(
+            main_errors("x := 1"),
+            error(Code::UnusedVar, "declared and not used: x")
+        );
+        // Assigning to a variable doesn't use it
+        assert_eq!(
+            main_errors("var x int\nx = 1"),
+            error(Code::UnusedVar, "declared and not used: x")
+        );
+        assert_eq!(
+            errors("package main\n\nimport \"fmt\"\n\nfunc main() {}\n"),
+            error(Code::UnusedImport, "\"fmt\" imported and not used")
+        );
+        assert_eq!(
+            errors("package main\n\nimport \"fmt\"\n\nfunc main() {\n\tfmt.Println()\n}\n"),
+            []
+        );
+        // Parameters needn't be used
+        assert_eq!(
+            errors("package main\n\nfunc f(a int) {}\n\nfunc main() {\n\tf(1)\n}\n"),
+            []
+        );
+    }
+
+    #[test]
+    fn types() {
+        assert_eq!(
+            main_errors("var x int = \"a\"\n_ = x"),
+            error(
+                Code::IncompatibleAssign,
+                "cannot use \"a\" (untyped string constant) as int value in variable declaration"
+            )
+        );
+        assert_eq!(
+            main_errors("var a float32\nvar b float64\n_ = a + b"),
+            error(
+                Code::MismatchedTypes,
+                "invalid operation: a + b (mismatched types float32 and float64)"
+            )
+        );
+        assert_eq!(
+            main_errors("x := 1 + \"a\"\n_ = x"),
+            error(
+                Code::MismatchedTypes,
+                "invalid operation: 1 + \"a\" (mismatched types untyped int and untyped string)"
+            )
+        );
+        assert_eq!(main_errors("var f float32 = 1\n_ = f + 2.5"), []);
+    }
+
+    #[test]
+    fn calls_and_returns() {
+        let src = "package main
+
+func f(a int) int { return a }
+
+func main() {
+	f(1, 2)
+	f(\"a\")
+	_ = f()
+	_ = f(1) + 1
+}
+";
+        assert_eq!(
+            errors(src),
+            [
+                (
+                    Code::WrongArgCount,
+                    "too many arguments in call to f".to_string()
+                ),
+                (
+                    Code::IncompatibleAssign,
+                    "cannot use \"a\" (untyped string constant) as int value in argument to f"
+                        .to_string()
+                ),
+                (
+                    Code::WrongArgCount,
+                    "not enough arguments in call to f".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            errors("package main\n\nfunc f() int { return \"a\" }\n\nfunc main() {}\n"),
+            error(
+                Code::IncompatibleAssign,
+                "cannot use \"a\" (untyped string constant) as int value in return statement"
+            )
+        );
+    }
+
+    #[test]
+    fn missing_return() {
+        let missing = |body: &str| {
+            let src = format!(
+                "package main\n\nfunc f(x int) int {{\n{}\n}}\n\nfunc main() {{}}\n",
+                body
+            );
+            errors(&src) == error(Code::MissingReturn, "missing return")
+        };
+        assert!(missing(""));
+        assert!(!missing("return 1"));
+        assert!(!missing("for {\n}"));
+        assert!(!missing("if x > 0 {\n\treturn 1\n} else {\n\treturn 2\n}"));
+        assert!(missing("if x > 0 {\n\treturn 1\n}"));
+        assert!(missing("for x > 0 {\n}"));
+        assert!(missing("for {\n\tbreak\n}"));
+        // Breaking out of an inner loop leaves the outer one running
+        assert!(!missing("for {\n\tfor {\n\t\tbreak\n\t}\n}"));
+    }
+
+    #[test]
+    fn terminating_blocks() {
+        let body = |body: &str| {
+            let src = format!("package main\n\nfunc main() {{\n{}\n}}\n", body);
+            parser::parse(&src, 0).unwrap().functions.remove(0).code
+        };
+        assert!(is_terminating_block(&body("return")));
+        assert!(is_terminating_block(&body("x := 1\n_ = x\nreturn")));
+        assert!(!is_terminating_block(&body("return\nx := 1\n_ = x")));
+        assert!(!is_terminating_block(&body("")));
+        assert!(is_terminating_block(&body("for {\n}")));
+    }
+
+    #[test]
+    fn untyped_constants() {
+        let overflow = |msg: &str| error(Code::NumericOverflow, msg);
+        // Constants are exact, and only need to fit where they're used
+        assert_eq!(main_errors("const x = 1 << 62\n_ = x"), []);
+        assert_eq!(
+            main_errors("const big = 1 << 100\nvar x int = big >> 98\n_ = x"),
+            []
+        );
+        assert_eq!(main_errors("var f float32 = 1 / 3.0\n_ = f"), []);
+        assert_eq!(main_errors("var i int = 1e3\n_ = i"), []);
+        assert_eq!(main_errors("const f = 1 << 2.0\n_ = f"), []);
+        assert_eq!(
+            main_errors("var b int8 = 128\n_ = b"),
+            overflow(
+                "cannot use 128 (untyped int constant) as int8 value in variable declaration \
+                 (overflows)"
+            )
+        );
+        assert_eq!(
+            main_errors("var u uint = -1\n_ = u"),
+            overflow(
+                "cannot use -1 (untyped int constant) as uint value in variable declaration \
+                 (overflows)"
+            )
+        );
+        assert_eq!(
+            main_errors("x := 1 << 64\n_ = x"),
+            overflow(
+                "cannot use 1 << 64 (untyped int constant 18446744073709551616) as int value in \
+                 variable declaration (overflows)"
+            )
+        );
+        assert_eq!(
+            main_errors("var f float32 = 1e39\n_ = f"),
+            overflow(
+                "cannot use 1e+39 (untyped float constant) as float32 value in variable \
+                 declaration (overflows)"
+            )
+        );
+        assert_eq!(
+            main_errors("const d int8 = 100\n_ = d * 2"),
+            overflow("constant 200 overflows int8")
+        );
+        assert_eq!(
+            main_errors("var i int = 1.5\n_ = i"),
+            error(
+                Code::TruncatedFloat,
+                "cannot use 1.5 (untyped float constant) as int value in variable declaration \
+                 (truncated)"
+            )
+        );
+        assert_eq!(
+            main_errors("const h = 'a' + 1\nvar s string = h\n_ = s"),
+            error(
+                Code::IncompatibleAssign,
+                "cannot use h (untyped rune constant 98) as string value in variable declaration"
+            )
+        );
+    }
+
+    #[test]
+    fn constant_conversions_and_expressions() {
+        assert_eq!(
+            main_errors("_ = uint8(256)"),
+            error(Code::InvalidConversion, "constant 256 overflows uint8")
+        );
+        assert_eq!(
+            main_errors("var i int = int(2.5)\n_ = i"),
+            error(
+                Code::InvalidConversion,
+                "cannot convert 2.5 (untyped float constant) to type int (truncated)"
+            )
+        );
+        assert_eq!(
+            main_errors("_ = 1 / 0"),
+            error(Code::DivByZero, "invalid operation: division by zero")
+        );
+        assert_eq!(
+            main_errors("const g = 1 << 2.5\n_ = g"),
+            error(
+                Code::InvalidShiftCount,
+                "invalid operation: shift count 2.5 (untyped float constant) must be integer"
+            )
+        );
+        // iota counts the specs of a const declaration, which repeat the previous expressions
+        assert_eq!(
+            main_errors(
+                "const (\n\ta = iota * 10\n\tb\n\tc\n)\nvar x int8 = c * 7\n_, _, _ = a, b, x"
+            ),
+            error(
+                Code::NumericOverflow,
+                "cannot use c * 7 (untyped int constant 140) as int8 value in variable \
+                 declaration (overflows)"
+            )
+        );
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(
+            main_errors("x := 0\nL:\n\tx++\n\tif x < 3 {\n\t\tgoto L\n\t}"),
+            []
+        );
+        assert_eq!(
+            main_errors("L:\n\tfor {\n\t\tfor {\n\t\t\tcontinue L\n\t\t}\n\t}"),
+            []
+        );
+        assert_eq!(
+            main_errors("goto L\nvar x int\n_ = x\nL:"),
+            error(Code::JumpOverDecl, "goto L jumps over variable declaration")
+        );
+        // Assigning isn't declaring
+        assert_eq!(main_errors("var x int\ngoto L\nx = 1\nL:\n\t_ = x"), []);
+        assert_eq!(
+            main_errors("goto L\nif true {\nL:\n}"),
+            error(Code::JumpIntoBlock, "goto L jumps into block")
+        );
+        assert_eq!(
+            main_errors("goto M"),
+            error(Code::UndeclaredLabel, "label M not defined")
+        );
+        assert_eq!(
+            main_errors("for {\n\tcontinue L\n}"),
+            error(Code::UndeclaredLabel, "continue label not defined: L")
+        );
+        assert_eq!(
+            main_errors("L:\n\tx := 1\n\t_ = x"),
+            error(Code::UnusedLabel, "label L defined and not used")
+        );
+        assert_eq!(
+            main_errors("L:\n\tfor {\n\t\tbreak L\n\t}\nL:\n\tfor {\n\t\tbreak L\n\t}"),
+            error(Code::DuplicateLabel, "label L already defined")
+        );
+        assert_eq!(
+            main_errors("L:\n\tif true {\n\t\tcontinue L\n\t}"),
+            error(Code::MisplacedLabel, "invalid continue label L")
+        );
+        assert_eq!(
+            main_errors("L:\n\tx := 1\n\t_ = x\n\tfor {\n\t\tbreak L\n\t}"),
+            error(Code::MisplacedLabel, "invalid break label L")
+        );
+    }
+
+    #[test]
+    fn switches() {
+        let switch = |tag: &str, cases: &str| {
+            main_errors(&format!("x := 1\nswitch {} {{\n{}\n}}", tag, cases))
+        };
+        assert_eq!(switch("x", "case 1, 2:\ncase 3:\ndefault:"), []);
+        // Each case clause is a scope of its own
+        assert_eq!(switch("x", "case 1:\n\tx := 2\n\t_ = x\ncase 2:"), []);
+        assert_eq!(
+            switch("x", "case 1, 2:\ncase 1:"),
+            error(Code::DuplicateCase, "duplicate case 1 in expression switch")
+        );
+        assert_eq!(
+            switch("", "case x > 0:\ncase true:\ncase true:"),
+            error(
+                Code::DuplicateCase,
+                "duplicate case true in expression switch"
+            )
+        );
+        assert_eq!(
+            main_errors("switch s := \"a\"; s {\ncase \"a\":\ncase \"a\":\n}"),
+            error(
+                Code::DuplicateCase,
+                "duplicate case \"a\" in expression switch"
+            )
+        );
+        assert_eq!(
+            switch("x", "default:\ndefault:"),
+            error(Code::DuplicateDefault, "multiple defaults in switch")
+        );
+        assert_eq!(
+            switch("x", "case \"a\":"),
+            error(
+                Code::MismatchedTypes,
+                "invalid case \"a\" in switch on x (mismatched types untyped string and int)"
+            )
+        );
+    }
+
+    #[test]
+    fn fallthrough() {
+        assert_eq!(
+            main_errors("x := 1\nswitch x {\ncase 1:\n\tfallthrough\ndefault:\n}"),
+            []
+        );
+        assert_eq!(
+            main_errors("x := 1\nswitch x {\ncase 1:\ndefault:\n\tfallthrough\n}"),
+            error(
+                Code::MisplacedFallthrough,
+                "cannot fallthrough final case in switch"
+            )
+        );
+        assert_eq!(
+            main_errors("fallthrough"),
+            error(
+                Code::MisplacedFallthrough,
+                "fallthrough statement out of place"
+            )
+        );
+    }
+
+    #[test]
+    fn terminating_switches() {
+        let missing = |cases: &str| {
+            let src = format!(
+                "package main\n\nfunc f(x int) int {{\n\tswitch x {{\n{}\n\t}}\n}}\n\nfunc main() {{}}\n",
+                cases
+            );
+            errors(&src) == error(Code::MissingReturn, "missing return")
+        };
+        assert!(!missing("case 1:\n\treturn 1\ndefault:\n\treturn 2"));
+        assert!(!missing("case 1:\n\tfallthrough\ndefault:\n\treturn 2"));
+        // Without a default, no case may run
+        assert!(missing("case 1:\n\treturn 1"));
+        assert!(missing("case 1:\ndefault:\n\treturn 2"));
+        assert!(missing("case 1:\n\tbreak\ndefault:\n\treturn 2"));
+    }
+
+    #[test]
+    fn short_variable_declarations() {
+        assert_eq!(
+            main_errors("a, b := 1, 2\na, c := 3, 4\n_, _, _ = a, b, c"),
+            []
+        );
+        assert_eq!(
+            main_errors("x := 1\nx := 2\n_ = x"),
+            error(Code::NoNewVar, "no new variables on left side of :=")
+        );
+        assert_eq!(
+            main_errors("_ := 1"),
+            error(Code::NoNewVar, "no new variables on left side of :=")
+        );
+        // Assigning to the old variable doesn't use it
+        assert_eq!(
+            main_errors("x := 1\nx, y := 2, \"a\"\n_ = y"),
+            error(Code::UnusedVar, "declared and not used: x")
+        );
+        assert_eq!(
+            main_errors("goto L\nx := 1\n_ = x\nL:"),
+            error(Code::JumpOverDecl, "goto L jumps over variable declaration")
+        );
+        assert_eq!(
+            main_errors("x := 1\ngoto L\nx, y := 2, 3\n_ = y\nL:\n\t_ = x"),
+            error(Code::JumpOverDecl, "goto L jumps over variable declaration")
+        );
+        // Without new variables, `:=` is an error of its own rather than a declaration
+        assert_eq!(
+            main_errors("x := 1\ngoto L\nx := 2\nL:\n\t_ = x"),
+            error(Code::NoNewVar, "no new variables on left side of :=")
+        );
+    }
+
+    #[test]
+    fn assignments() {
+        assert_eq!(
+            main_errors("x := 1\ny := 2\nx, y = y, x\nx++\nx += y\n_ = x"),
+            []
+        );
+        assert_eq!(main_errors("f := 1.5\nf *= 2\n_ = f"), []);
+        assert_eq!(
+            main_errors("const c = 1\nc = 2"),
+            error(
+                Code::UnassignableOperand,
+                "cannot assign to c (neither addressable nor a map index expression)"
+            )
+        );
+        assert_eq!(
+            main_errors("s := \"a\"\ns++"),
+            error(
+                Code::NonNumericIncDec,
+                "invalid operation: s++ (non-numeric type string)"
+            )
+        );
+        assert_eq!(
+            main_errors("x := 1\nx -= 1.5"),
+            error(
+                Code::TruncatedFloat,
+                "1.5 (untyped float constant) truncated to int"
+            )
+        );
+        assert_eq!(
+            main_errors("x := 1\nx += \"a\""),
+            error(
+                Code::MismatchedTypes,
+                "invalid operation: x + \"a\" (mismatched types int and untyped string)"
+            )
+        );
+    }
+
+    #[test]
+    fn errors_from_errorf() {
+        let src = "package main
+
+import \"fmt\"
+
+func check(n int) error {
+\tif n < 0 {
+\t\treturn fmt.Errorf(\"negative: %d\", n)
+\t}
+\treturn nil
+}
+
+func main() {
+\terr := check(-1)
+\tvar wrapped error = fmt.Errorf(\"check: %w\", err)
+\tprintln(err == nil, err != wrapped, wrapped == error(nil))
+\tfmt.Println(err, wrapped)
+}
+";
+        assert_eq!(errors(src), []);
+        let errorf_errors = |body: &str| {
+            errors(&format!(
+                "package main\n\nimport \"fmt\"\n\nfunc main() {{\n{}\n}}\n",
+                body
+            ))
+        };
+        assert_eq!(
+            errorf_errors("var s string = fmt.Errorf(\"x\")\n_ = s"),
+            error(
+                Code::IncompatibleAssign,
+                "cannot use fmt.Errorf(\"x\") (value of type error) as string value in variable declaration"
+            )
+        );
+        assert_eq!(
+            errorf_errors("_ = fmt.Errorf(1)"),
+            error(
+                Code::IncompatibleAssign,
+                "cannot use 1 (untyped int constant) as string value in argument to fmt.Errorf"
+            )
+        );
+        assert_eq!(
+            errorf_errors("err := fmt.Errorf(\"x\")\n_ = err < err"),
+            error(
+                Code::UndefinedOp,
+                "invalid operation: err < err (operator < not defined on error)"
+            )
+        );
+        assert_eq!(
+            errorf_errors("err := fmt.Errorf(\"x\")\n_ = err.Error()"),
+            error(
+                Code::Unsupported,
+                "methods are not supported yet: err.Error"
+            )
+        );
+        assert_eq!(
+            main_errors("var err error = 1\n_ = err"),
+            error(
+                Code::IncompatibleAssign,
+                "cannot use 1 (untyped int constant) as error value in variable declaration"
+            )
+        );
+    }
+}
@@ -0,0 +1,261 @@
+//! Checks the labels of a function body, and the statements that refer to them: `goto`, and
+//! labeled `break` and `continue`. Labels have function scope, must all be used, and a `goto` must
+//! neither jump over a variable declaration nor into a block.
+
+use crate::ast::*;
+use crate::diagnostics::{Code, Diagnostic};
+use std::collections::HashMap;
+
+/// Checks the labels of `body`, returning the errors found. The statements of `body` must have been
+/// checked already
+pub fn check_labels(body: &[Statement]) -> Vec<Diagnostic> {
+    let mut checker = LabelChecker::default();
+    checker.declare_labels(body, None);
+    let jumps = checker.check_block(body);
+    // The jumps left never reached their label: it's either in a block the `goto` isn't in, or
+    // it doesn't exist at all
+    for jump in jumps {
+        let diagnostic = match checker.labels.get_mut(&jump.label) {
+            Some(label) => {
+                label.used = true;
+                let msg = format!("goto {} jumps into block", jump.label);
+                let diagnostic = Diagnostic::error(Code::JumpIntoBlock, msg, jump.span);
+                match label.block {
+                    Some(block) => diagnostic.with_label(block, "block starts here"),
+                    None => diagnostic,
+                }
+            }
+            None => {
+                let msg = format!("label {} not defined", jump.label);
+                Diagnostic::error(Code::UndeclaredLabel, msg, jump.span)
+            }
+        };
+        checker.errors.push(diagnostic);
+    }
+    for (name, label) in &checker.labels {
+        if !label.used {
+            let msg = format!("label {} defined and not used", name);
+            checker
+                .errors
+                .push(Diagnostic::error(Code::UnusedLabel, msg, label.span));
+        }
+    }
+    checker.errors
+}
+
+/// What a labeled statement is, as the target of a `break` or `continue`
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Loop,
+    Switch,
+    Other,
+}
+
+struct Label {
+    span: Span,
+    /// The statement whose block the label is in, or `None` for the function body
+    block: Option<Span>,
+    used: bool,
+}
+
+/// A forward `goto`, whose label hasn't been reached yet
+struct Jump {
+    label: String,
+    /// The span of the label in the `goto` statement
+    span: Span,
+}
+
+#[derive(Default)]
+struct LabelChecker {
+    labels: HashMap<String, Label>,
+    /// The labels declared so far in the blocks around the statement being checked. A `goto` to
+    /// one of them jumps backwards
+    visible: Vec<String>,
+    /// The labeled statements around the statement being checked, innermost last
+    enclosing: Vec<(String, Target)>,
+    errors: Vec<Diagnostic>,
+}
+
+impl LabelChecker {
+    /// Declares all the labels in `block` and the blocks nested in it
+    fn declare_labels(&mut self, block: &[Statement], block_span: Option<Span>) {
+        for stmt in block {
+            match stmt {
+                Statement::Label { name, span } => match self.labels.get(name) {
+                    Some(other) => {
+                        let msg = format!("label {} already defined", name);
+                        let diagnostic = Diagnostic::error(Code::DuplicateLabel, msg, *span)
+                            .with_label(other.span, "previous definition");
+                        self.errors.push(diagnostic);
+                    }
+                    None => {
+                        let label = Label {
+                            span: *span,
+                            block: block_span,
+                            used: false,
+                        };
+                        self.labels.insert(name.clone(), label);
+                    }
+                },
+                Statement::If {
+                    then_block,
+                    else_block,
+                    span,
+                    ..
+                } => {
+                    self.declare_labels(then_block, Some(*span));
+                    self.declare_labels(else_block, Some(*span));
+                }
+                Statement::For { body, span, .. } | Statement::ForRange { body, span, .. } => {
+                    self.declare_labels(body, Some(*span))
+                }
+                Statement::Switch { cases, span, .. } => {
+                    for case in cases {
+                        self.declare_labels(&case.body, Some(*span));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn use_label(&mut self, name: &str) {
+        if let Some(label) = self.labels.get_mut(name) {
+            label.used = true;
+        }
+    }
+
+    /// Checks the labels and jumps in `block`, returning the forward jumps out of it that haven't
+    /// reached their label yet
+    fn check_block(&mut self, block: &[Statement]) -> Vec<Jump> {
+        let outer_visible = self.visible.len();
+        let mut jumps: Vec<Jump> = vec![];
+        // The last variable declared in the block so far
+        let mut var_decl: Option<Span> = None;
+        // The label of the statement being checked
+        let mut stmt_label: Option<&String> = None;
+        for stmt in block {
+            let label = stmt_label.take();
+            match stmt {
+                Statement::Label { name, .. } => {
+                    // The forward jumps to the label end here
+                    let (arrived, pending) =
+                        jumps.into_iter().partition(|jump| jump.label == *name);
+                    jumps = pending;
+                    for jump in arrived {
+                        match var_decl {
+                            Some(decl) if jump.span.start < decl.start => {
+                                let msg = format!("goto {} jumps over variable declaration", name);
+                                let diagnostic =
+                                    Diagnostic::error(Code::JumpOverDecl, msg, jump.span)
+                                        .with_label(decl, "variable declared here");
+                                self.errors.push(diagnostic);
+                            }
+                            _ => {}
+                        }
+                        self.use_label(name);
+                    }
+                    self.visible.push(name.clone());
+                    stmt_label = Some(name);
+                }
+                Statement::Assignment { name, span, .. } => {
+                    var_decl = Some(Span {
+                        end: span.start + name.len(),
+                        ..*span
+                    })
+                }
+                // The checker has marked the variables that `:=` declares, rather than assigns
+                Statement::ShortVarDecl { vars, .. } => {
+                    if let Some(var) = vars.iter().find(|var| var.is_new) {
+                        var_decl = Some(var.span);
+                    }
+                }
+                Statement::Goto { label, span } => {
+                    let span = label_span(label, *span);
+                    if self.visible.contains(label) {
+                        self.use_label(label);
+                    } else {
+                        jumps.push(Jump {
+                            label: label.clone(),
+                            span,
+                        });
+                    }
+                }
+                Statement::Break {
+                    label: Some(label),
+                    span,
+                } => self.check_target(label, "break", label_span(label, *span)),
+                Statement::Continue {
+                    label: Some(label),
+                    span,
+                } => self.check_target(label, "continue", label_span(label, *span)),
+                Statement::If {
+                    then_block,
+                    else_block,
+                    ..
+                } => {
+                    if let Some(label) = label {
+                        self.enclosing.push((label.clone(), Target::Other));
+                    }
+                    jumps.extend(self.check_block(then_block));
+                    jumps.extend(self.check_block(else_block));
+                    if label.is_some() {
+                        self.enclosing.pop();
+                    }
+                }
+                Statement::For { body, .. } | Statement::ForRange { body, .. } => {
+                    if let Some(label) = label {
+                        self.enclosing.push((label.clone(), Target::Loop));
+                    }
+                    jumps.extend(self.check_block(body));
+                    if label.is_some() {
+                        self.enclosing.pop();
+                    }
+                }
+                Statement::Switch { cases, .. } => {
+                    if let Some(label) = label {
+                        self.enclosing.push((label.clone(), Target::Switch));
+                    }
+                    for case in cases {
+                        jumps.extend(self.check_block(&case.body));
+                    }
+                    if label.is_some() {
+                        self.enclosing.pop();
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.visible.truncate(outer_visible);
+        jumps
+    }
+
+    /// Checks that `label` is the label of a loop around a `break` or `continue` (`kind`), or of a
+    /// switch around a `break`
+    fn check_target(&mut self, label: &str, kind: &str, span: Span) {
+        if !self.labels.contains_key(label) {
+            let msg = format!("{} label not defined: {}", kind, label);
+            self.errors
+                .push(Diagnostic::error(Code::UndeclaredLabel, msg, span));
+            return;
+        }
+        // The label counts as used even if it isn't a valid target
+        self.use_label(label);
+        let msg = match self.enclosing.iter().rev().find(|(name, _)| name == label) {
+            Some((_, Target::Loop)) => return,
+            Some((_, Target::Switch)) if kind == "break" => return,
+            // Including labels that exist but aren't around the statement
+            _ => format!("invalid {} label {}", kind, label),
+        };
+        self.errors
+            .push(Diagnostic::error(Code::MisplacedLabel, msg, span));
+    }
+}
+
+/// The span of `label` in a statement ending with it (e.g. `goto label`)
+fn label_span(label: &str, stmt_span: Span) -> Span {
+    Span {
+        start: stmt_span.end - label.len(),
+        ..stmt_span
+    }
+}
@@ -0,0 +1,77 @@
+//! Keeps track of the source files being compiled, so that the spans stored in the AST can be
+//! turned back into file names, lines and columns.
+
+use crate::ast::Span;
+use crate::lexer::Position;
+
+pub struct SourceFile {
+    pub name: String,
+    pub src: String,
+    /// Byte offset at which each line starts
+    line_starts: Vec<usize>,
+}
+
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceFile {
+    fn new(name: String, src: String) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        Self {
+            name,
+            src,
+            line_starts,
+        }
+    }
+
+    /// Converts a byte offset into a position (lines and columns start at 1)
+    pub fn position(&self, offset: usize) -> Position {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1,
+        };
+        Position {
+            offset,
+            line: line + 1,
+            column: offset - self.line_starts[line] + 1,
+        }
+    }
+
+    /// Returns the text of a line (starting at 1), without the trailing newline
+    pub fn line(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.src.len(), |next| next - 1);
+        self.src[start..end].trim_end_matches('\r')
+    }
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a file, returning the id that spans into it should use
+    pub fn add_file(&mut self, name: &str, src: &str) -> usize {
+        self.files
+            .push(SourceFile::new(name.to_string(), src.to_string()));
+        self.files.len() - 1
+    }
+
+    pub fn file(&self, file_id: usize) -> &SourceFile {
+        &self.files[file_id]
+    }
+
+    /// Formats the start of `span` as `file:line:column`, like the go toolchain does
+    pub fn describe(&self, span: Span) -> String {
+        let file = self.file(span.file_id);
+        let pos = file.position(span.start);
+        format!("{}:{}:{}", file.name, pos.line, pos.column)
+    }
+}
@@ -0,0 +1,14 @@
+package main
+
+func main() {
+	var x int = 10
+	var y int = 5 / x
+	var lit string = "hello world! my number is "
+	print(lit)
+	println(y)
+	if 5.0 == 5.0 {
+		println("good", "✓")
+	} else {
+		println("oops")
+	}
+}
@@ -0,0 +1,1177 @@
+//! The printing functions of go's `fmt` package. The compiler passes the operands as an array of
+//! `FmtArg`s, which carry their type along with their value, and the formatting follows go's rules
+//! for those types (the verbs, flags, width and precision, and the `%!verb(type=value)` errors).
+//! Explicit argument indexes (`%[1]d`) aren't supported.
+//!
+//! The only `error`s are the ones `Errorf` makes: pointers to an `ErrorString`, like the
+//! `*errors.errorString`s of go.
+
+use crate::{
+    decode_rune,
+    gc::{__go_alloc, with_root, StaticTypeDesc},
+    GoString,
+};
+use std::io::{self, Write};
+use std::mem::size_of;
+use std::{ptr, slice};
+
+// The kinds of operands, which must match the `FMT_*` constants of the compiler's codegen
+const BOOL: i64 = 0;
+const INT: i64 = 1;
+const UINT: i64 = 2;
+const FLOAT32: i64 = 3;
+const FLOAT64: i64 = 4;
+const STRING: i64 = 5;
+const POINTER: i64 = 6;
+const ERROR: i64 = 7;
+
+/// What a non-nil `error` points to: its message
+#[repr(C)]
+struct ErrorString {
+    msg: GoString,
+}
+
+static ERROR_STRING_TYPE: StaticTypeDesc<1> = StaticTypeDesc::new(size_of::<ErrorString>(), [0]);
+
+/// The name `%T` gives a non-nil `error`
+const ERROR_TYPE_NAME: &[u8] = b"*errors.errorString";
+
+/// An operand of a `fmt` function: its kind, its bits (for all kinds but strings, with floats
+/// widened to a float64, and pointers and `error`s converted to their address), its value if it's
+/// a string, and the name of its type
+#[repr(C)]
+pub struct FmtArg {
+    kind: i64,
+    bits: u64,
+    string: GoString,
+    type_name: GoString,
+}
+
+#[derive(Clone, Copy)]
+enum Value {
+    Bool(bool),
+    Int(i64),
+    Uint(u64),
+    /// A float and its size in bits
+    Float(f64, u32),
+    String(&'static [u8]),
+    /// The address a pointer holds
+    Pointer(u64),
+    /// The address an `error` holds, and its message (empty for a nil `error`)
+    Error(u64, &'static [u8]),
+}
+
+#[derive(Clone, Copy)]
+struct Arg {
+    value: Value,
+    type_name: &'static [u8],
+}
+
+/// # Safety
+///
+/// `args` must point to `len` valid arguments
+unsafe fn read_args(args: *const FmtArg, len: i64) -> Vec<Arg> {
+    if len == 0 {
+        // The pointer to an empty array may be anything
+        return vec![];
+    }
+    let args = slice::from_raw_parts(args, len as usize);
+    args.iter()
+        .map(|arg| match arg.kind {
+            // A nil `error` has no type, and so no type name either
+            ERROR if arg.bits == 0 => Arg {
+                value: Value::Error(0, &[]),
+                type_name: b"<nil>",
+            },
+            ERROR => Arg {
+                value: Value::Error(arg.bits, error_message(arg.bits as *const ErrorString)),
+                type_name: ERROR_TYPE_NAME,
+            },
+            _ => Arg {
+                value: match arg.kind {
+                    BOOL => Value::Bool(arg.bits != 0),
+                    INT => Value::Int(arg.bits as i64),
+                    UINT => Value::Uint(arg.bits),
+                    FLOAT32 => Value::Float(f64::from_bits(arg.bits), 32),
+                    FLOAT64 => Value::Float(f64::from_bits(arg.bits), 64),
+                    STRING => Value::String(arg.string.as_bytes()),
+                    POINTER => Value::Pointer(arg.bits),
+                    kind => unreachable!("invalid kind of fmt argument: {}", kind),
+                },
+                type_name: arg.type_name.as_bytes(),
+            },
+        })
+        .collect()
+}
+
+/// # Safety
+///
+/// `error` must point to an `ErrorString` made by `__fmt_errorf`
+unsafe fn error_message(error: *const ErrorString) -> &'static [u8] {
+    (*error).msg.as_bytes()
+}
+
+/// The flags, width and precision of a verb
+#[derive(Default, Clone, Copy)]
+struct Flags {
+    plus: bool,
+    minus: bool,
+    sharp: bool,
+    space: bool,
+    zero: bool,
+    /// `%#v`, which formats values with go syntax
+    sharp_v: bool,
+    width: Option<usize>,
+    precision: Option<usize>,
+}
+
+/// Widths and precisions can't exceed this
+const MAX_WIDTH: i64 = 1_000_000;
+
+#[derive(Default)]
+struct Printer {
+    buf: Vec<u8>,
+    flags: Flags,
+    /// Whether `%w` formats `error`s, as it does in `Errorf`
+    wrap_errs: bool,
+}
+
+impl Printer {
+    fn write(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn write_padding(&mut self, len: usize) {
+        let byte = if self.flags.zero { b'0' } else { b' ' };
+        self.buf.resize(self.buf.len() + len, byte);
+    }
+
+    /// Writes `bytes`, padded to the width on the left (or on the right, with the `-` flag)
+    fn pad(&mut self, bytes: &[u8]) {
+        let padding = self
+            .flags
+            .width
+            .unwrap_or(0)
+            .saturating_sub(rune_count(bytes));
+        if self.flags.minus {
+            self.write(bytes);
+            self.write_padding(padding);
+        } else {
+            self.write_padding(padding);
+            self.write(bytes);
+        }
+    }
+
+    /// Like `pad`, but always pads with spaces
+    fn pad_spaces(&mut self, bytes: &[u8]) {
+        let zero = self.flags.zero;
+        self.flags.zero = false;
+        self.pad(bytes);
+        self.flags.zero = zero;
+    }
+
+    /// Writes the operands like `fmt.Print`: spaces are added between operands when neither is a
+    /// string
+    fn print(&mut self, args: &[Arg]) {
+        let mut prev_string = false;
+        for (idx, arg) in args.iter().enumerate() {
+            let is_string = matches!(arg.value, Value::String(_));
+            if idx > 0 && !is_string && !prev_string {
+                self.write(b" ");
+            }
+            self.print_arg(*arg, 'v');
+            prev_string = is_string;
+        }
+    }
+
+    /// Writes the operands like `fmt.Println`: separated by spaces, and followed by a newline
+    fn println(&mut self, args: &[Arg]) {
+        for (idx, arg) in args.iter().enumerate() {
+            if idx > 0 {
+                self.write(b" ");
+            }
+            self.print_arg(*arg, 'v');
+        }
+        self.write(b"\n");
+    }
+
+    /// Writes the operands as `format` describes, like `fmt.Printf`
+    fn printf(&mut self, format: &[u8], args: &[Arg]) {
+        let end = format.len();
+        let mut arg_num = 0;
+        let mut i = 0;
+        while i < end {
+            let start = i;
+            while i < end && format[i] != b'%' {
+                i += 1;
+            }
+            self.write(&format[start..i]);
+            if i >= end {
+                break;
+            }
+            // Skip the %
+            i += 1;
+
+            self.flags = Flags::default();
+            while i < end {
+                match format[i] {
+                    b'#' => self.flags.sharp = true,
+                    // Zeros only pad on the left
+                    b'0' => self.flags.zero = !self.flags.minus,
+                    b'+' => self.flags.plus = true,
+                    b'-' => {
+                        self.flags.minus = true;
+                        self.flags.zero = false;
+                    }
+                    b' ' => self.flags.space = true,
+                    _ => break,
+                }
+                i += 1;
+            }
+
+            if i < end && format[i] == b'*' {
+                i += 1;
+                let (width, next) = int_from_arg(args, arg_num);
+                arg_num = next;
+                match width {
+                    // A negative width pads on the right
+                    Some(width) if width < 0 => {
+                        self.flags.width = Some(width.unsigned_abs() as usize);
+                        self.flags.minus = true;
+                        self.flags.zero = false;
+                    }
+                    Some(width) => self.flags.width = Some(width as usize),
+                    None => self.write(b"%!(BADWIDTH)"),
+                }
+            } else {
+                (self.flags.width, i) = parse_num(format, i);
+            }
+
+            if i < end && format[i] == b'.' {
+                i += 1;
+                if i < end && format[i] == b'*' {
+                    i += 1;
+                    let (precision, next) = int_from_arg(args, arg_num);
+                    arg_num = next;
+                    match precision {
+                        Some(precision) if precision >= 0 => {
+                            self.flags.precision = Some(precision as usize)
+                        }
+                        _ => self.write(b"%!(BADPREC)"),
+                    }
+                } else {
+                    // A missing precision means 0
+                    let (precision, next) = parse_num(format, i);
+                    self.flags.precision = Some(precision.unwrap_or(0));
+                    i = next;
+                }
+            }
+
+            if i >= end {
+                self.write(b"%!(NOVERB)");
+                break;
+            }
+            let (verb, len) = decode_rune(&format[i..]);
+            i += len;
+            match verb {
+                // Percent signs don't take an operand, and ignore the flags
+                '%' => self.write(b"%"),
+                _ if arg_num >= args.len() => self.write(format!("%!{}(MISSING)", verb).as_bytes()),
+                _ => {
+                    if verb == 'v' {
+                        self.flags.sharp_v = self.flags.sharp;
+                        self.flags.sharp = false;
+                        self.flags.plus = false;
+                    }
+                    self.print_arg(args[arg_num], verb);
+                    arg_num += 1;
+                }
+            }
+        }
+
+        if arg_num < args.len() {
+            self.flags = Flags::default();
+            self.write(b"%!(EXTRA ");
+            for (idx, arg) in args[arg_num..].iter().enumerate() {
+                if idx > 0 {
+                    self.write(b", ");
+                }
+                self.write_typed(*arg);
+            }
+            self.write(b")");
+        }
+    }
+
+    fn print_arg(&mut self, arg: Arg, verb: char) {
+        let ok = match (verb, arg.value) {
+            ('T', _) => {
+                self.fmt_s(arg.type_name);
+                true
+            }
+            (_, Value::Error(addr, msg)) => self.fmt_error(addr, msg, verb),
+            (_, Value::Pointer(ptr)) => self.fmt_pointer(ptr, arg.type_name, verb),
+            ('p', _) => false,
+            (_, Value::Bool(boolean)) => self.fmt_bool(boolean, verb),
+            (_, Value::Int(int)) => self.fmt_integer(int as u64, true, verb),
+            (_, Value::Uint(uint)) => self.fmt_integer(uint, false, verb),
+            (_, Value::Float(float, size)) => self.fmt_float(float, size, verb),
+            (_, Value::String(string)) => self.fmt_string(string, verb),
+        };
+        if !ok {
+            self.bad_verb(arg, verb);
+        }
+    }
+
+    /// Writes an operand the verb isn't defined on, e.g. `%!d(string=hi)`
+    fn bad_verb(&mut self, arg: Arg, verb: char) {
+        self.write(format!("%!{}(", verb).as_bytes());
+        self.write_typed(arg);
+        self.write(b")");
+    }
+
+    /// Writes an operand with its type, e.g. `string=hi`, or just `<nil>` for a nil `error`
+    fn write_typed(&mut self, arg: Arg) {
+        if let Value::Error(0, _) = arg.value {
+            self.write(b"<nil>");
+        } else {
+            self.write(arg.type_name);
+            self.write(b"=");
+            self.print_arg(arg, 'v');
+        }
+    }
+
+    /// Writes an `error`: its message for the string verbs (and for `%w`, in `Errorf`), `<nil>`
+    /// for a nil `%v`, and otherwise what go writes for an `*errors.errorString`, a pointer to a
+    /// struct with the message as its only field
+    fn fmt_error(&mut self, addr: u64, msg: &'static [u8], verb: char) -> bool {
+        match verb {
+            'v' if addr == 0 => self.pad_spaces(b"<nil>"),
+            _ if addr == 0 => return false,
+            'v' if self.flags.sharp_v => {
+                self.write(b"&errors.errorString{s:");
+                self.fmt_q(msg);
+                self.write(b"}");
+            }
+            'w' if !self.wrap_errs => return false,
+            'v' | 's' | 'x' | 'X' | 'q' | 'w' => {
+                let verb = if verb == 'w' { 'v' } else { verb };
+                self.fmt_string(msg, verb);
+            }
+            'p' => return self.fmt_pointer(addr, ERROR_TYPE_NAME, verb),
+            _ => {
+                self.write(b"&{");
+                let field = Arg {
+                    value: Value::String(msg),
+                    type_name: b"string",
+                };
+                self.print_arg(field, verb);
+                self.write(b"}");
+            }
+        }
+        true
+    }
+
+    fn fmt_bool(&mut self, boolean: bool, verb: char) -> bool {
+        if !matches!(verb, 't' | 'v') {
+            return false;
+        }
+        self.pad_spaces(if boolean { b"true" } else { b"false" });
+        true
+    }
+
+    fn fmt_integer(&mut self, int: u64, signed: bool, verb: char) -> bool {
+        match verb {
+            // Go syntax for unsigned integers is hexadecimal
+            'v' if self.flags.sharp_v && !signed => {
+                self.flags.sharp = true;
+                self.fmt_integer_base(int, 16, signed, verb);
+            }
+            'v' | 'd' => self.fmt_integer_base(int, 10, signed, verb),
+            'b' => self.fmt_integer_base(int, 2, signed, verb),
+            'o' | 'O' => self.fmt_integer_base(int, 8, signed, verb),
+            'x' | 'X' => self.fmt_integer_base(int, 16, signed, verb),
+            'c' => {
+                let rune = to_rune(int);
+                self.pad(rune.encode_utf8(&mut [0; 4]).as_bytes());
+            }
+            'q' => {
+                let quoted = quote_rune(to_rune(int), self.flags.plus);
+                self.pad(quoted.as_bytes());
+            }
+            'U' => self.fmt_unicode(int),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Writes `int` in `base`. A precision is the minimum number of digits, and the `0` flag pads
+    /// with zeros after the sign
+    fn fmt_integer_base(&mut self, int: u64, base: u32, signed: bool, verb: char) {
+        let negative = signed && (int as i64) < 0;
+        let int = if negative { int.wrapping_neg() } else { int };
+        let min_digits = match self.flags.precision {
+            // A precision of 0 and a value of 0 print nothing but the padding
+            Some(0) if int == 0 => {
+                self.pad_spaces(b"");
+                return;
+            }
+            Some(precision) => precision,
+            None if self.flags.zero && self.flags.width.is_some() => {
+                let sign = negative || self.flags.plus || self.flags.space;
+                self.flags.width.unwrap().saturating_sub(usize::from(sign))
+            }
+            None => 0,
+        };
+        let mut digits = match (base, verb) {
+            (2, _) => format!("{:b}", int),
+            (8, _) => format!("{:o}", int),
+            (16, 'X') => format!("{:X}", int),
+            (16, _) => format!("{:x}", int),
+            _ => int.to_string(),
+        };
+        if digits.len() < min_digits {
+            digits.insert_str(0, &"0".repeat(min_digits - digits.len()));
+        }
+        if self.flags.sharp {
+            match base {
+                2 => digits.insert_str(0, "0b"),
+                8 if !digits.starts_with('0') => digits.insert(0, '0'),
+                16 if verb == 'X' => digits.insert_str(0, "0X"),
+                16 => digits.insert_str(0, "0x"),
+                _ => {}
+            }
+        }
+        if verb == 'O' {
+            digits.insert_str(0, "0o");
+        }
+        if negative {
+            digits.insert(0, '-');
+        } else if self.flags.plus {
+            digits.insert(0, '+');
+        } else if self.flags.space {
+            digits.insert(0, ' ');
+        }
+        // The zeros were already added as digits
+        self.pad_spaces(digits.as_bytes());
+    }
+
+    /// Writes `int` like `U+0041`, followed by the character with the `#` flag (`U+0041 'A'`)
+    fn fmt_unicode(&mut self, int: u64) {
+        let min_digits = self.flags.precision.unwrap_or(0).max(4);
+        let mut unicode = format!("U+{:01$X}", int, min_digits);
+        let printable = u32::try_from(int)
+            .ok()
+            .and_then(char::from_u32)
+            .filter(|rune| is_print(*rune));
+        if let (true, Some(rune)) = (self.flags.sharp, printable) {
+            unicode.push_str(&format!(" '{}'", rune));
+        }
+        self.pad_spaces(unicode.as_bytes());
+    }
+
+    /// Writes a pointer of type `type_name`: in hexadecimal with a leading `0x` for `%p` and `%v`
+    /// (without it for `%#p`, and as `<nil>` for a nil `%v`), in go syntax for `%#v`, and as an
+    /// integer for the integer verbs
+    fn fmt_pointer(&mut self, ptr: u64, type_name: &[u8], verb: char) -> bool {
+        match verb {
+            'v' if self.flags.sharp_v => {
+                let addr = if ptr == 0 {
+                    "nil".to_string()
+                } else {
+                    format!("0x{:x}", ptr)
+                };
+                let type_name = String::from_utf8_lossy(type_name);
+                self.pad_spaces(format!("({})({})", type_name, addr).as_bytes());
+            }
+            'v' if ptr == 0 => self.pad_spaces(b"<nil>"),
+            'v' | 'p' => {
+                self.flags.sharp = !self.flags.sharp;
+                self.fmt_integer_base(ptr, 16, false, 'x');
+            }
+            'b' | 'o' | 'd' | 'x' | 'X' => return self.fmt_integer(ptr, false, verb),
+            _ => return false,
+        }
+        true
+    }
+
+    fn fmt_float(&mut self, float: f64, size: u32, verb: char) -> bool {
+        // The shortest representation is the default, except for %e and %f
+        let precision = match verb {
+            'v' | 'g' | 'G' => None,
+            'e' | 'E' | 'f' | 'F' => Some(6),
+            _ => return false,
+        };
+        let verb = if verb == 'v' { 'g' } else { verb };
+        let precision = self.flags.precision.or(precision);
+        let mut num = format_float(float, size, verb, precision).into_bytes();
+        // Make room for a sign, which is then only written if needed
+        if num[0] != b'-' && num[0] != b'+' {
+            num.insert(0, b'+');
+        }
+        if self.flags.space && num[0] == b'+' && !self.flags.plus {
+            num[0] = b' ';
+        }
+        // Infinities and NaN aren't padded with zeros, and NaN only has a sign if asked to
+        if num[1] == b'I' || num[1] == b'N' {
+            if num[1] == b'N' && !self.flags.space && !self.flags.plus {
+                num.remove(0);
+            }
+            self.pad_spaces(&num);
+            return true;
+        }
+        if self.flags.plus || num[0] != b'+' {
+            // Zeros go between the sign and the digits
+            let width = self.flags.width.unwrap_or(0);
+            if self.flags.zero && width > num.len() {
+                self.write(&num[..1]);
+                self.write_padding(width - num.len());
+                self.write(&num[1..]);
+            } else {
+                self.pad(&num);
+            }
+            return true;
+        }
+        self.pad(&num[1..]);
+        true
+    }
+
+    fn fmt_string(&mut self, string: &[u8], verb: char) -> bool {
+        match verb {
+            'v' if self.flags.sharp_v => self.fmt_q(string),
+            'v' | 's' => self.fmt_s(string),
+            'x' | 'X' => self.fmt_sx(string, verb == 'X'),
+            'q' => self.fmt_q(string),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Writes a string, truncated to the precision (in runes)
+    fn fmt_s(&mut self, string: &[u8]) {
+        let string = self.truncate(string);
+        self.pad_spaces(string);
+    }
+
+    /// Writes the bytes of a string in hexadecimal. With the ` ` flag they're separated by spaces,
+    /// and with the `#` flag they're prefixed with `0x` (each of them if they're separated)
+    fn fmt_sx(&mut self, string: &[u8], upper: bool) {
+        let len = string.len().min(self.flags.precision.unwrap_or(usize::MAX));
+        let prefix = if upper { "0X" } else { "0x" };
+        let mut hex = String::new();
+        for (idx, byte) in string[..len].iter().enumerate() {
+            if idx > 0 && self.flags.space {
+                hex.push(' ');
+            }
+            if self.flags.sharp && (idx == 0 || self.flags.space) {
+                hex.push_str(prefix);
+            }
+            if upper {
+                hex.push_str(&format!("{:02X}", byte));
+            } else {
+                hex.push_str(&format!("{:02x}", byte));
+            }
+        }
+        self.pad(hex.as_bytes());
+    }
+
+    /// Writes a string as a double-quoted go string (or a raw string with the `#` flag, if it can
+    /// be one). The `+` flag escapes the characters that aren't ASCII
+    fn fmt_q(&mut self, string: &[u8]) {
+        let string = self.truncate(string);
+        if self.flags.sharp && can_backquote(string) {
+            self.pad_spaces(&[b"`", string, b"`"].concat());
+            return;
+        }
+        self.pad_spaces(quote(string, self.flags.plus).as_bytes());
+    }
+
+    /// The first runes of `string`, as many as the precision
+    fn truncate<'a>(&self, string: &'a [u8]) -> &'a [u8] {
+        let mut len = 0;
+        if let Some(precision) = self.flags.precision {
+            for _ in 0..precision {
+                if len == string.len() {
+                    break;
+                }
+                len += decode_rune(&string[len..]).1;
+            }
+            return &string[..len];
+        }
+        string
+    }
+}
+
+/// The number of runes in `bytes`, where each byte of an invalid encoding counts as one
+fn rune_count(bytes: &[u8]) -> usize {
+    let mut count = 0;
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        rest = &rest[decode_rune(rest).1..];
+        count += 1;
+    }
+    count
+}
+
+/// The rune with the code point `int`, or U+FFFD if it's not one
+fn to_rune(int: u64) -> char {
+    u32::try_from(int)
+        .ok()
+        .and_then(char::from_u32)
+        .unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// Parses the number at `format[start..]`, returning it and the index after it. The number is
+/// `None` if there are no digits (or if it's too large, in which case the rest of the format is
+/// skipped)
+fn parse_num(format: &[u8], start: usize) -> (Option<usize>, usize) {
+    let mut num = None;
+    let mut i = start;
+    while i < format.len() && format[i].is_ascii_digit() {
+        let value = num.unwrap_or(0) * 10 + (format[i] - b'0') as i64;
+        if value > MAX_WIDTH {
+            return (None, format.len());
+        }
+        num = Some(value);
+        i += 1;
+    }
+    (num.map(|num| num as usize), i)
+}
+
+/// Takes the `*` width or precision from `args[arg_num]`, returning it (if that's an integer) and
+/// the number of the next operand
+fn int_from_arg(args: &[Arg], arg_num: usize) -> (Option<i64>, usize) {
+    let Some(arg) = args.get(arg_num) else {
+        return (None, arg_num);
+    };
+    let int = match arg.value {
+        Value::Int(int) => Some(int),
+        Value::Uint(uint) => i64::try_from(uint).ok(),
+        _ => None,
+    };
+    let int = int.filter(|int| (-MAX_WIDTH..=MAX_WIDTH).contains(int));
+    (int, arg_num + 1)
+}
+
+/// A non-negative float as its decimal digits `0.d1d2d3... * 10^point`. It has no trailing zeros,
+/// so zero has no digits
+struct Decimal {
+    digits: Vec<u8>,
+    point: i32,
+}
+
+impl Decimal {
+    /// Parses the output of rust's `{:e}` formatting (e.g. `1.5e-7`)
+    fn parse(sci: &str) -> Self {
+        let (mantissa, exp) = sci.split_once('e').unwrap();
+        let mut digits: Vec<u8> = mantissa.bytes().filter(|byte| *byte != b'.').collect();
+        while digits.last() == Some(&b'0') {
+            digits.pop();
+        }
+        let point = if digits.is_empty() {
+            0
+        } else {
+            exp.parse::<i32>().unwrap() + 1
+        };
+        Decimal { digits, point }
+    }
+
+    /// The shortest digits that parse back to `float` (as a float of `size` bits)
+    fn shortest(float: f64, size: u32) -> Self {
+        if size == 32 {
+            Decimal::parse(&format!("{:e}", float as f32))
+        } else {
+            Decimal::parse(&format!("{:e}", float))
+        }
+    }
+
+    /// `float` rounded to `digits` significant digits
+    fn rounded(float: f64, digits: usize) -> Self {
+        Decimal::parse(&format!("{:.*e}", digits - 1, float))
+    }
+
+    fn digit(&self, idx: i32) -> char {
+        match usize::try_from(idx)
+            .ok()
+            .and_then(|idx| self.digits.get(idx))
+        {
+            Some(digit) => *digit as char,
+            None => '0',
+        }
+    }
+
+    /// Formats the number like `d.ddde±dd`, with `precision` digits after the point
+    fn fmt_e(&self, precision: usize, upper: bool) -> String {
+        let mut num = self.digit(0).to_string();
+        if precision > 0 {
+            num.push('.');
+            num.extend((1..=precision as i32).map(|idx| self.digit(idx)));
+        }
+        let exp = if self.digits.is_empty() {
+            0
+        } else {
+            self.point - 1
+        };
+        let sign = if exp < 0 { '-' } else { '+' };
+        let e = if upper { 'E' } else { 'e' };
+        num.push_str(&format!("{}{}{:02}", e, sign, exp.abs()));
+        num
+    }
+
+    /// Formats the number like `ddd.ddd`, with `precision` digits after the point
+    fn fmt_f(&self, precision: usize) -> String {
+        let mut num: String = if self.point > 0 {
+            (0..self.point).map(|idx| self.digit(idx)).collect()
+        } else {
+            "0".to_string()
+        };
+        if precision > 0 {
+            num.push('.');
+            num.extend((0..precision as i32).map(|idx| self.digit(self.point + idx)));
+        }
+        num
+    }
+}
+
+/// Formats `float` like go's `strconv.FormatFloat` does, for the verbs `e`, `f` and `g` (and their
+/// upper-case variants). A precision of `None` uses the fewest digits that represent the float
+/// exactly, as a float of `size` bits
+fn format_float(float: f64, size: u32, verb: char, precision: Option<usize>) -> String {
+    if float.is_nan() {
+        return "NaN".to_string();
+    }
+    if float.is_infinite() {
+        return if float > 0.0 { "+Inf" } else { "-Inf" }.to_string();
+    }
+    let sign = if float.is_sign_negative() { "-" } else { "" };
+    let abs = float.abs();
+    let upper = verb.is_ascii_uppercase();
+    let num = match (verb.to_ascii_lowercase(), precision) {
+        ('f', Some(precision)) => format!("{:.*}", precision, abs),
+        ('f', None) => {
+            let decimal = Decimal::shortest(abs, size);
+            let precision = decimal.digits.len() as i32 - decimal.point;
+            decimal.fmt_f(precision.max(0) as usize)
+        }
+        ('e', Some(precision)) => Decimal::rounded(abs, precision + 1).fmt_e(precision, upper),
+        ('e', None) => {
+            let decimal = Decimal::shortest(abs, size);
+            decimal.fmt_e(decimal.digits.len().saturating_sub(1), upper)
+        }
+        (_, precision) => {
+            let (decimal, eprec, precision) = match precision {
+                Some(precision) => {
+                    let precision = precision.max(1);
+                    let decimal = Decimal::rounded(abs, precision);
+                    let len = decimal.digits.len();
+                    let eprec = if precision > len && len as i32 >= decimal.point {
+                        len
+                    } else {
+                        precision
+                    };
+                    (decimal, eprec, precision.min(len))
+                }
+                // The shortest representation switches to an exponent from 1e+06 on
+                None => {
+                    let decimal = Decimal::shortest(abs, size);
+                    let len = decimal.digits.len();
+                    (decimal, 6, len)
+                }
+            };
+            let exp = decimal.point - 1;
+            if exp < -4 || exp >= eprec as i32 {
+                decimal.fmt_e(precision.saturating_sub(1), upper)
+            } else {
+                decimal.fmt_f((precision as i32 - decimal.point).max(0) as usize)
+            }
+        }
+    };
+    format!("{}{}", sign, num)
+}
+
+/// Whether the character is printed as is in quoted strings
+fn is_print(c: char) -> bool {
+    !c.is_control() && c != '\u{feff}'
+}
+
+/// Whether `bytes` can be written as a raw string literal
+fn can_backquote(bytes: &[u8]) -> bool {
+    match std::str::from_utf8(bytes) {
+        Ok(string) => string
+            .chars()
+            .all(|c| c != '`' && (c == '\t' || is_print(c))),
+        Err(_) => false,
+    }
+}
+
+/// Quotes `bytes` as a double-quoted go string, like `strconv.Quote` (or `strconv.QuoteToASCII`,
+/// if `ascii`)
+fn quote(bytes: &[u8], ascii: bool) -> String {
+    let mut quoted = "\"".to_string();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let (rune, len) = decode_rune(rest);
+        if rune == char::REPLACEMENT_CHARACTER && len == 1 {
+            quoted.push_str(&format!("\\x{:02x}", rest[0]));
+        } else {
+            push_escaped(&mut quoted, rune, '"', ascii);
+        }
+        rest = &rest[len..];
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Quotes `rune` as a go rune literal, like `strconv.QuoteRune` (or `strconv.QuoteRuneToASCII`, if
+/// `ascii`)
+fn quote_rune(rune: char, ascii: bool) -> String {
+    let mut quoted = "'".to_string();
+    push_escaped(&mut quoted, rune, '\'', ascii);
+    quoted.push('\'');
+    quoted
+}
+
+fn push_escaped(quoted: &mut String, c: char, quote: char, ascii: bool) {
+    match c {
+        c if c == quote || c == '\\' => {
+            quoted.push('\\');
+            quoted.push(c);
+        }
+        '\x07' => quoted.push_str("\\a"),
+        '\x08' => quoted.push_str("\\b"),
+        '\x0c' => quoted.push_str("\\f"),
+        '\n' => quoted.push_str("\\n"),
+        '\r' => quoted.push_str("\\r"),
+        '\t' => quoted.push_str("\\t"),
+        '\x0b' => quoted.push_str("\\v"),
+        c if c < ' ' || c == '\x7f' => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+        c if !is_print(c) || (ascii && !c.is_ascii()) => {
+            if (c as u32) < 0x10000 {
+                quoted.push_str(&format!("\\u{:04x}", c as u32));
+            } else {
+                quoted.push_str(&format!("\\U{:08x}", c as u32));
+            }
+        }
+        c => quoted.push(c),
+    }
+}
+
+/// Writes to stdout, which go doesn't buffer. Errors are ignored, since the program can't see the
+/// error results of the `fmt` functions
+fn write_stdout(bytes: &[u8]) {
+    let mut stdout = io::stdout().lock();
+    let _ = stdout.write_all(bytes).and_then(|_| stdout.flush());
+}
+
+/// # Safety
+///
+/// `args` must point to `len` valid arguments
+#[no_mangle]
+pub unsafe extern "C" fn __fmt_print(args: *const FmtArg, len: i64) {
+    let mut printer = Printer::default();
+    printer.print(&read_args(args, len));
+    write_stdout(&printer.buf);
+}
+
+/// # Safety
+///
+/// `args` must point to `len` valid arguments
+#[no_mangle]
+pub unsafe extern "C" fn __fmt_println(args: *const FmtArg, len: i64) {
+    let mut printer = Printer::default();
+    printer.println(&read_args(args, len));
+    write_stdout(&printer.buf);
+}
+
+/// # Safety
+///
+/// `format` must be a valid string, and `args` must point to `len` valid arguments
+#[no_mangle]
+pub unsafe extern "C" fn __fmt_printf(format: GoString, args: *const FmtArg, len: i64) {
+    let mut printer = Printer::default();
+    printer.printf(format.as_bytes(), &read_args(args, len));
+    write_stdout(&printer.buf);
+}
+
+/// # Safety
+///
+/// `args` must point to `len` valid arguments
+#[no_mangle]
+pub unsafe extern "C" fn __fmt_sprint(args: *const FmtArg, len: i64) -> GoString {
+    let mut printer = Printer::default();
+    printer.print(&read_args(args, len));
+    GoString::from_bytes(printer.buf)
+}
+
+/// # Safety
+///
+/// `args` must point to `len` valid arguments
+#[no_mangle]
+pub unsafe extern "C" fn __fmt_sprintln(args: *const FmtArg, len: i64) -> GoString {
+    let mut printer = Printer::default();
+    printer.println(&read_args(args, len));
+    GoString::from_bytes(printer.buf)
+}
+
+/// # Safety
+///
+/// `format` must be a valid string, and `args` must point to `len` valid arguments
+#[no_mangle]
+pub unsafe extern "C" fn __fmt_sprintf(
+    format: GoString,
+    args: *const FmtArg,
+    len: i64,
+) -> GoString {
+    let mut printer = Printer::default();
+    printer.printf(format.as_bytes(), &read_args(args, len));
+    GoString::from_bytes(printer.buf)
+}
+
+/// Returns a new `error` with the message `format` describes, like `fmt.Errorf`
+///
+/// # Safety
+///
+/// `format` must be a valid string, and `args` must point to `len` valid arguments
+#[no_mangle]
+pub unsafe extern "C" fn __fmt_errorf(format: GoString, args: *const FmtArg, len: i64) -> *mut u8 {
+    let mut printer = Printer {
+        wrap_errs: true,
+        ..Printer::default()
+    };
+    printer.printf(format.as_bytes(), &read_args(args, len));
+    let msg = GoString::from_bytes(printer.buf);
+    // Nothing else points to the message while the error is allocated
+    let slot = ptr::addr_of!(msg.ptr) as *const *mut u8;
+    let error = with_root(slot, || {
+        __go_alloc(size_of::<ErrorString>() as i64, ERROR_STRING_TYPE.as_ptr())
+    });
+    (*(error as *mut ErrorString)).msg = msg;
+    error
+}
+
+/// Prints an `error` the way go's `print` does: the addresses of its type and its value (e.g.
+/// `(0x4b8f40,0xc000014070)`, or `(0x0,0x0)` for nil)
+#[no_mangle]
+pub extern "C" fn __print_error(error: *const u8) {
+    let typ = if error.is_null() {
+        ptr::null()
+    } else {
+        ERROR_STRING_TYPE.as_ptr()
+    };
+    crate::print_stderr(format!("({:#x},{:#x})", typ as usize, error as usize).as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gc::{
+        __go_gc,
+        tests::{leave_room, live, lock_heap},
+    };
+    use crate::heap::round_up_size;
+
+    fn arg(value: Value, type_name: &'static str) -> Arg {
+        Arg {
+            value,
+            type_name: type_name.as_bytes(),
+        }
+    }
+
+    fn int(int: i64) -> Arg {
+        arg(Value::Int(int), "int")
+    }
+
+    fn float(float: f64) -> Arg {
+        arg(Value::Float(float, 64), "float64")
+    }
+
+    fn string(string: &'static str) -> Arg {
+        arg(Value::String(string.as_bytes()), "string")
+    }
+
+    fn sprintf(format: &str, args: &[Arg]) -> String {
+        let mut printer = Printer::default();
+        printer.printf(format.as_bytes(), args);
+        String::from_utf8(printer.buf).unwrap()
+    }
+
+    #[test]
+    fn print_and_println() {
+        let args = [
+            int(1),
+            int(2),
+            string("a"),
+            int(3),
+            arg(Value::Bool(true), "bool"),
+        ];
+        let mut printer = Printer::default();
+        printer.print(&args);
+        assert_eq!(printer.buf, b"1 2a3 true");
+        let mut printer = Printer::default();
+        printer.println(&args);
+        assert_eq!(printer.buf, b"1 2 a 3 true\n");
+    }
+
+    #[test]
+    fn verbs() {
+        assert_eq!(sprintf("%v %d", &[int(42), int(-7)]), "42 -7");
+        assert_eq!(
+            sprintf("%x %#x %X %o %b", &[int(255); 5]),
+            "ff 0xff FF 377 11111111"
+        );
+        assert_eq!(sprintf("%c %q %U", &[int(65); 3]), "A 'A' U+0041");
+        assert_eq!(
+            sprintf("%v %t", &[arg(Value::Bool(false), "bool"); 2]),
+            "false false"
+        );
+        assert_eq!(sprintf("%v %s", &[string("hi"); 2]), "hi hi");
+        assert_eq!(sprintf("%q", &[string("a\"b\n")]), r#""a\"b\n""#);
+        assert_eq!(sprintf("%#v", &[string("x")]), r#""x""#);
+        assert_eq!(sprintf("%v %v", &[float(1.5), float(1e21)]), "1.5 1e+21");
+        assert_eq!(
+            sprintf("%e %f", &[float(1234.5678); 2]),
+            "1.234568e+03 1234.567800"
+        );
+        assert_eq!(sprintf("%T %T", &[int(1), string("")]), "int string");
+        assert_eq!(sprintf("100%%", &[]), "100%");
+    }
+
+    #[test]
+    fn width_and_precision() {
+        assert_eq!(
+            sprintf("[%5d|%-5d|%05d]", &[int(42); 3]),
+            "[   42|42   |00042]"
+        );
+        assert_eq!(
+            sprintf("[%+d|% d|%05d]", &[int(5), int(5), int(-5)]),
+            "[+5| 5|-0005]"
+        );
+        assert_eq!(
+            sprintf("[%.2f|%8.3f|%-8.1f]", &[float(1.23456); 3]),
+            "[1.23|   1.235|1.2     ]"
+        );
+        assert_eq!(sprintf("[%08.2f]", &[float(-1.5)]), "[-0001.50]");
+        assert_eq!(
+            sprintf(
+                "[%.3s|%5s|%-5s]",
+                &[string("abcdef"), string("ab"), string("ab")]
+            ),
+            "[abc|   ab|ab   ]"
+        );
+        assert_eq!(sprintf("[%.5d]", &[int(42)]), "[00042]");
+        assert_eq!(
+            sprintf("[%*d|%-*d]", &[int(4), int(1), int(3), int(2)]),
+            "[   1|2  ]"
+        );
+        assert_eq!(sprintf("[%.*f]", &[int(1), float(2.25)]), "[2.2]");
+    }
+
+    #[test]
+    fn pointers() {
+        let ptr = arg(Value::Pointer(0xc000010000), "*int");
+        let nil = arg(Value::Pointer(0), "*int");
+        assert_eq!(
+            sprintf("%p %v %#p", &[ptr; 3]),
+            "0xc000010000 0xc000010000 c000010000"
+        );
+        assert_eq!(
+            sprintf("%#v %#v", &[ptr, nil]),
+            "(*int)(0xc000010000) (*int)(nil)"
+        );
+        assert_eq!(sprintf("%v %p %d", &[nil; 3]), "<nil> 0x0 0");
+        assert_eq!(sprintf("%s", &[ptr]), "%!s(*int=0xc000010000)");
+    }
+
+    #[test]
+    fn errors() {
+        let error = arg(Value::Error(0xc000010000, b"oops"), "*errors.errorString");
+        let nil = arg(Value::Error(0, &[]), "<nil>");
+        assert_eq!(
+            sprintf("%v|%s|%q|%x|%5.2s", &[error; 5]),
+            r#"oops|oops|"oops"|6f6f7073|   oo"#
+        );
+        assert_eq!(
+            sprintf("%#v %p %d", &[error; 3]),
+            r#"&errors.errorString{s:"oops"} 0xc000010000 &{%!d(string=oops)}"#
+        );
+        assert_eq!(sprintf("%T %T", &[error, nil]), "*errors.errorString <nil>");
+        assert_eq!(
+            sprintf("%v %s %d", &[nil; 3]),
+            "<nil> %!s(<nil>) %!d(<nil>)"
+        );
+        assert_eq!(sprintf("%d", &[int(1), nil]), "1%!(EXTRA <nil>)");
+        // Only `Errorf` wraps errors
+        assert_eq!(sprintf("%w", &[error]), "%!w(*errors.errorString=oops)");
+        let mut printer = Printer {
+            wrap_errs: true,
+            ..Printer::default()
+        };
+        printer.printf(b"%w %w %w", &[error, nil, int(1)]);
+        assert_eq!(printer.buf, b"oops %!w(<nil>) %!w(int=1)");
+    }
+
+    /// A string that isn't on the heap
+    fn static_string(string: &'static str) -> GoString {
+        GoString {
+            ptr: string.as_ptr(),
+            len: string.len(),
+        }
+    }
+
+    fn fmt_arg(kind: i64, bits: u64, string: &'static str) -> FmtArg {
+        FmtArg {
+            kind,
+            bits,
+            string: static_string(string),
+            type_name: static_string(""),
+        }
+    }
+
+    #[test]
+    fn errorf() {
+        let _lock = lock_heap();
+        unsafe {
+            // Collections start while either the messages or the errors are allocated, depending
+            // on whether an odd number of 16 byte slots fit before the goal
+            for odd in [false, true] {
+                leave_room(0);
+                if odd {
+                    __go_alloc(16, ptr::null());
+                }
+                for int in 0..1000 {
+                    let args = [fmt_arg(INT, int, ""), fmt_arg(STRING, 0, "bad")];
+                    let live_before = live();
+                    let error = __fmt_errorf(static_string("%05d: %s"), args.as_ptr(), 2);
+                    let msg = error_message(error as *const ErrorString);
+                    assert_eq!(msg, format!("{:05}: bad", int).as_bytes());
+                    // Either nothing was collected, or everything but the message and the error
+                    let size = round_up_size(msg.len()) + size_of::<ErrorString>();
+                    assert!(live() == live_before + size || live() == size);
+                }
+            }
+            let error = __fmt_errorf(static_string("oops"), ptr::null(), 0);
+            // The error keeps its message alive
+            with_root(ptr::addr_of!(error), || {
+                __go_gc();
+                assert_eq!(error_message(error as *const ErrorString), b"oops");
+                let wrapped = fmt_arg(ERROR, error as u64, "");
+                let error = __fmt_errorf(static_string("wrapped: %w"), &wrapped, 1);
+                let msg = error_message(error as *const ErrorString);
+                assert_eq!(msg, b"wrapped: oops");
+            });
+        }
+    }
+
+    #[test]
+    fn bad_verbs_and_arguments() {
+        assert_eq!(sprintf("%d", &[string("hi")]), "%!d(string=hi)");
+        assert_eq!(sprintf("%s", &[int(1)]), "%!s(int=1)");
+        assert_eq!(
+            sprintf("%z", &[arg(Value::Bool(true), "bool")]),
+            "%!z(bool=true)"
+        );
+        assert_eq!(sprintf("%d %d", &[int(1)]), "1 %!d(MISSING)");
+        assert_eq!(sprintf("%d", &[int(1), string("x")]), "1%!(EXTRA string=x)");
+        assert_eq!(sprintf("%", &[]), "%!(NOVERB)");
+        assert_eq!(sprintf("%*d", &[string("x"), int(1)]), "%!(BADWIDTH)1");
+    }
+}
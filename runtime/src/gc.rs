@@ -0,0 +1,316 @@
+//! The garbage collector: a precise, non-moving mark and sweep collector.
+//!
+//! The roots are the variables of the running functions. Every function with variables that hold
+//! pointers pushes a frame onto `__go_frames` when it's called, and pops it when it returns. The
+//! frame points to those variables, and to the compiler's frame map, which has the type of each of
+//! them. There are no package level variables yet, so the frames are all the roots. The type
+//! descriptors tell the collector where the pointers in a value are, so only those are followed.
+//!
+//! Collections are paced like go's: one starts when the heap has grown by `GOGC` percent (100 by
+//! default) since the last one, or when it reaches 4 MiB for the first time. `GOGC=off` turns the
+//! collector off, except for explicit calls of `runtime.GC()`.
+
+use crate::heap::{Heap, Object};
+use std::{
+    env,
+    mem::size_of,
+    ptr, slice,
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        Mutex, OnceLock,
+    },
+};
+
+/// The heap size the first collection starts at, with the default `GOGC`
+const MIN_HEAP: usize = 4 << 20;
+
+/// The type descriptor the compiler generates for a type with pointers: the size of a value, and
+/// the offsets of the pointers in it (of strings and slices, the pointer to their bytes or
+/// elements). A null type descriptor stands for a type without pointers
+#[repr(C)]
+pub struct TypeDesc {
+    size: usize,
+    num_ptrs: usize,
+    offsets: [usize; 0],
+}
+
+impl TypeDesc {
+    /// # Safety
+    ///
+    /// The descriptor must be followed by its offsets, as the compiler's are
+    unsafe fn offsets(&self) -> &[usize] {
+        slice::from_raw_parts(self.offsets.as_ptr(), self.num_ptrs)
+    }
+}
+
+/// A type descriptor for the runtime's own objects that have `N` pointers, laid out like the
+/// compiler's
+#[repr(C)]
+pub struct StaticTypeDesc<const N: usize> {
+    size: usize,
+    num_ptrs: usize,
+    offsets: [usize; N],
+}
+
+impl<const N: usize> StaticTypeDesc<N> {
+    pub const fn new(size: usize, offsets: [usize; N]) -> Self {
+        StaticTypeDesc {
+            size,
+            num_ptrs: N,
+            offsets,
+        }
+    }
+
+    pub fn as_ptr(&self) -> *const TypeDesc {
+        self as *const Self as *const TypeDesc
+    }
+}
+
+/// The types of the roots of a function's frames
+#[repr(C)]
+pub struct FrameMap {
+    num_roots: usize,
+    types: [*const TypeDesc; 0],
+}
+
+/// The frame of a running function: the frame of its caller (or the nearest caller with roots),
+/// the function's frame map, and the addresses of its roots
+#[repr(C)]
+pub struct Frame {
+    prev: *const Frame,
+    map: *const FrameMap,
+    roots: [*const u8; 0],
+}
+
+/// The innermost frame, which the compiler pushes and pops frames onto
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static __go_frames: AtomicPtr<Frame> = AtomicPtr::new(ptr::null_mut());
+
+struct Collector {
+    heap: Heap,
+    /// The number of bytes that were live after the last collection
+    marked: usize,
+}
+
+static COLLECTOR: Mutex<Collector> = Mutex::new(Collector {
+    heap: Heap::new(),
+    marked: 0,
+});
+
+/// The value of `GOGC`, or `None` if the collector is off
+fn gogc() -> Option<usize> {
+    static GOGC: OnceLock<Option<usize>> = OnceLock::new();
+    *GOGC.get_or_init(|| match env::var("GOGC").as_deref() {
+        Ok("off") => None,
+        Ok(value) => match value.parse::<i64>() {
+            Ok(percent) => usize::try_from(percent).ok(),
+            Err(_) => Some(100),
+        },
+        Err(_) => Some(100),
+    })
+}
+
+impl Collector {
+    /// The heap size the next collection starts at, or `None` if the collector is off
+    fn goal(&self) -> Option<usize> {
+        let percent = gogc()?;
+        let goal = self.marked.saturating_mul(100 + percent) / 100;
+        Some(goal.max(MIN_HEAP.saturating_mul(percent) / 100))
+    }
+
+    fn alloc(&mut self, size: usize, typ: *const TypeDesc) -> *mut u8 {
+        if let Some(goal) = self.goal() {
+            if self.heap.live().saturating_add(size) > goal {
+                unsafe { self.collect() };
+            }
+        }
+        self.heap.alloc(size, typ)
+    }
+
+    /// # Safety
+    ///
+    /// The frames must be the compiler's, and their roots and the heap's objects must hold valid
+    /// values of their types
+    unsafe fn collect(&mut self) {
+        let mut gray = vec![];
+        let mut frame = __go_frames.load(Ordering::Relaxed) as *const Frame;
+        while !frame.is_null() {
+            let map = (*frame).map;
+            let roots = ptr::addr_of!((*frame).roots) as *const *const u8;
+            let types = ptr::addr_of!((*map).types) as *const *const TypeDesc;
+            for index in 0..(*map).num_roots {
+                let typ = *types.add(index);
+                self.scan(*roots.add(index), (*typ).size, typ, &mut gray);
+            }
+            frame = (*frame).prev;
+        }
+        while let Some(object) = gray.pop() {
+            self.scan(object.ptr, object.size, object.typ, &mut gray);
+        }
+        self.heap.sweep();
+        self.marked = self.heap.live();
+        // The heap will grow up to the goal before the next collection, so the idle spans that fit
+        // in the difference are worth keeping
+        let keep = self.goal().unwrap_or(self.marked) - self.marked;
+        self.heap.release_idle(keep);
+    }
+
+    /// Marks what the pointers in the `size` bytes at `ptr`, which hold values of type `typ`,
+    /// point to. The objects that were marked and have pointers of their own are added to `gray`
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to `size` bytes of values of type `typ`, which mustn't be null
+    unsafe fn scan(
+        &mut self,
+        ptr: *const u8,
+        size: usize,
+        typ: *const TypeDesc,
+        gray: &mut Vec<Object>,
+    ) {
+        let typ = &*typ;
+        for index in 0..size / typ.size {
+            let value = ptr.add(index * typ.size);
+            for offset in typ.offsets() {
+                let addr = *(value.add(*offset) as *const usize);
+                gray.extend(self.heap.mark(addr));
+            }
+        }
+    }
+}
+
+/// Runs `f` with the pointer at `slot` as a root, for the runtime's own functions that allocate
+/// more than once: an object only they point to would otherwise be collected by the next
+/// allocation
+///
+/// # Safety
+///
+/// `slot` must hold null or a pointer to the heap for as long as `f` runs
+pub unsafe fn with_root<R>(slot: *const *mut u8, f: impl FnOnce() -> R) -> R {
+    /// A frame map with a single root, laid out like the compiler's
+    #[repr(C)]
+    struct RootMap {
+        num_roots: usize,
+        types: [*const TypeDesc; 1],
+    }
+    /// A frame with a single root, laid out like the compiler's
+    #[repr(C)]
+    struct RootFrame {
+        prev: *const Frame,
+        map: *const RootMap,
+        roots: [*const u8; 1],
+    }
+    static POINTER_TYPE: StaticTypeDesc<1> = StaticTypeDesc::new(size_of::<usize>(), [0]);
+    let map = RootMap {
+        num_roots: 1,
+        types: [POINTER_TYPE.as_ptr()],
+    };
+    let frame = RootFrame {
+        prev: __go_frames.load(Ordering::Relaxed),
+        map: &map,
+        roots: [slot as *const u8],
+    };
+    __go_frames.store(&frame as *const RootFrame as *mut Frame, Ordering::Relaxed);
+    let result = f();
+    __go_frames.store(frame.prev as *mut Frame, Ordering::Relaxed);
+    result
+}
+
+/// Allocates `size` zeroed bytes on the heap, for values of type `typ` (null if they have no
+/// pointers). This collects garbage first if the heap has grown enough since the last collection
+#[no_mangle]
+pub extern "C" fn __go_alloc(size: i64, typ: *const TypeDesc) -> *mut u8 {
+    COLLECTOR.lock().unwrap().alloc(size as usize, typ)
+}
+
+/// Implements `runtime.GC()`, which collects garbage right away
+#[no_mangle]
+pub extern "C" fn __go_gc() {
+    // The only callers are the compiler's functions, which keep their frames up to date
+    unsafe { COLLECTOR.lock().unwrap().collect() };
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::heap::round_up_size;
+    use std::sync::MutexGuard;
+
+    /// A linked list node: a pointer to the next node, and a value
+    static NODE_TYPE: StaticTypeDesc<1> = StaticTypeDesc::new(16, [0]);
+
+    /// Serializes the tests that use the heap, which is shared, and drops the frames a failed test
+    /// may have left behind
+    pub(crate) fn lock_heap() -> MutexGuard<'static, ()> {
+        static LOCK: Mutex<()> = Mutex::new(());
+        let guard = LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        __go_frames.store(ptr::null_mut(), Ordering::Relaxed);
+        guard
+    }
+
+    pub(crate) fn live() -> usize {
+        COLLECTOR.lock().unwrap().heap.live()
+    }
+
+    /// Collects garbage, then fills the heap with more, so that the next collection starts once
+    /// `room` to `room + 8 KiB` more bytes are allocated
+    pub(crate) fn leave_room(room: usize) {
+        let mut collector = COLLECTOR.lock().unwrap();
+        unsafe { collector.collect() };
+        let goal = collector.goal().expect("the collector is off");
+        let garbage = goal - collector.heap.live() - room - 8192;
+        collector.heap.alloc(garbage, ptr::null());
+    }
+
+    #[test]
+    fn collects_unrooted_objects() {
+        let _lock = lock_heap();
+        __go_gc();
+        assert_eq!(live(), 0);
+        __go_alloc(100, ptr::null());
+        assert_eq!(live(), round_up_size(100));
+        __go_gc();
+        assert_eq!(live(), 0);
+    }
+
+    #[test]
+    fn keeps_what_roots_point_to() {
+        let _lock = lock_heap();
+        __go_gc();
+        let node = __go_alloc(16, NODE_TYPE.as_ptr());
+        let next = __go_alloc(16, NODE_TYPE.as_ptr());
+        unsafe {
+            *(node as *mut *mut u8) = next;
+            *(next.add(8) as *mut u64) = 42;
+            with_root(ptr::addr_of!(node), || {
+                __go_gc();
+                assert_eq!(live(), 32);
+                assert_eq!(*(next.add(8) as *const u64), 42);
+            });
+        }
+        __go_gc();
+        assert_eq!(live(), 0);
+    }
+
+    #[test]
+    fn allocation_collects_at_goal() {
+        let _lock = lock_heap();
+        leave_room(16 << 10);
+        let rooted = __go_alloc(64, ptr::null());
+        let unrooted = __go_alloc(64, ptr::null());
+        unsafe {
+            ptr::write_bytes(unrooted, 0xff, 64);
+            with_root(ptr::addr_of!(rooted), || {
+                // Too large to fit, so the garbage and the unrooted object are collected first
+                __go_alloc(32 << 10, ptr::null());
+                assert_eq!(live(), 64 + (32 << 10));
+                // Reused slots are zeroed
+                let reused = __go_alloc(64, ptr::null());
+                assert!(slice::from_raw_parts(reused, 64).iter().all(|byte| *byte == 0));
+            });
+        }
+        __go_gc();
+        assert_eq!(live(), 0);
+    }
+}
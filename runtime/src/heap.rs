@@ -0,0 +1,265 @@
+//! The garbage collected heap. Objects of up to 32 KiB are allocated in slots of go's size
+//! classes, in spans that hold slots of a single class, and larger objects get a span of their
+//! own. Objects never move, and any address inside an object refers to it.
+
+use crate::gc::TypeDesc;
+use std::{
+    alloc::{self, Layout},
+    collections::BTreeMap,
+    process, ptr,
+};
+
+/// The sizes of the classes of go's allocator, which an object of up to 32 KiB is rounded up to.
+/// Larger objects are rounded up to a whole number of pages
+const SIZE_CLASSES: &[usize] = &[
+    8, 16, 24, 32, 48, 64, 80, 96, 112, 128, 144, 160, 176, 192, 208, 224, 240, 256, 288, 320, 352,
+    384, 416, 448, 480, 512, 576, 640, 704, 768, 896, 1024, 1152, 1280, 1408, 1536, 1792, 2048,
+    2304, 2688, 3072, 3200, 3456, 4096, 4864, 5376, 6144, 6528, 6784, 6912, 8192, 9472, 9728,
+    10240, 10880, 12288, 13568, 14336, 16384, 18432, 19072, 20480, 21760, 24576, 27264, 28672,
+    32768,
+];
+const PAGE_SIZE: usize = 8192;
+/// The size of the spans of small objects, which fits at least two of the largest class
+const SPAN_SIZE: usize = 64 * 1024;
+
+/// The size of the slot go's allocator would use for `size` bytes
+pub fn round_up_size(size: usize) -> usize {
+    match SIZE_CLASSES.iter().find(|class| **class >= size) {
+        Some(class) => *class,
+        None => size.next_multiple_of(PAGE_SIZE),
+    }
+}
+
+pub fn out_of_memory() -> ! {
+    eprintln!("fatal error: runtime: out of memory");
+    process::abort();
+}
+
+/// An allocated object: its address, the size of its slot, and the type of its values
+pub struct Object {
+    pub ptr: *const u8,
+    pub size: usize,
+    pub typ: *const TypeDesc,
+}
+
+#[derive(Clone, Copy)]
+struct Slot {
+    allocated: bool,
+    marked: bool,
+    /// Whether the slot was used before, and so needs zeroing
+    dirty: bool,
+    /// The type of the values in the slot, or null if they have no pointers
+    typ: *const TypeDesc,
+}
+
+impl Slot {
+    const FREE: Slot = Slot {
+        allocated: false,
+        marked: false,
+        dirty: false,
+        typ: ptr::null(),
+    };
+}
+
+/// A block of pages, divided into slots of `slot_size` bytes
+struct Span {
+    base: *mut u8,
+    layout: Layout,
+    slot_size: usize,
+    /// The size class of the slots, or `None` for the span of a single large object
+    class: Option<usize>,
+    slots: Vec<Slot>,
+    /// The indices of the free slots, the lowest last
+    free: Vec<usize>,
+}
+
+impl Span {
+    fn new(size: usize) -> Self {
+        let layout = match Layout::from_size_align(size, PAGE_SIZE) {
+            Ok(layout) => layout,
+            Err(_) => out_of_memory(),
+        };
+        let base = unsafe { alloc::alloc_zeroed(layout) };
+        if base.is_null() {
+            out_of_memory();
+        }
+        Span {
+            base,
+            layout,
+            slot_size: size,
+            class: None,
+            slots: vec![],
+            free: vec![],
+        }
+    }
+
+    /// Divides the span into free slots of `slot_size` bytes
+    fn divide(&mut self, slot_size: usize, class: Option<usize>, dirty: bool) {
+        let count = self.layout.size() / slot_size;
+        self.slot_size = slot_size;
+        self.class = class;
+        self.slots = vec![Slot { dirty, ..Slot::FREE }; count];
+        self.free = (0..count).rev().collect();
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.base, self.layout) };
+    }
+}
+
+pub struct Heap {
+    /// The spans, by their base address
+    spans: BTreeMap<usize, Span>,
+    /// The base addresses of the spans of each size class that have free slots
+    partial: [Vec<usize>; SIZE_CLASSES.len()],
+    /// The spans that were left without objects, which are reused before new ones are allocated
+    idle: Vec<Span>,
+    /// The number of bytes in allocated slots
+    live: usize,
+}
+
+// The runtime is single threaded, and the heap is only behind a mutex so that it can be a static
+unsafe impl Send for Heap {}
+
+impl Heap {
+    pub const fn new() -> Self {
+        const NO_SPANS: Vec<usize> = Vec::new();
+        Heap {
+            spans: BTreeMap::new(),
+            partial: [NO_SPANS; SIZE_CLASSES.len()],
+            idle: Vec::new(),
+            live: 0,
+        }
+    }
+
+    pub fn live(&self) -> usize {
+        self.live
+    }
+
+    /// Allocates a zeroed slot of at least `size` bytes for values of type `typ`. Every slot has
+    /// an address of its own, even if it's empty
+    pub fn alloc(&mut self, size: usize, typ: *const TypeDesc) -> *mut u8 {
+        let size = size.max(1);
+        let base = match SIZE_CLASSES.iter().position(|class| *class >= size) {
+            Some(class) => match self.partial[class].last() {
+                Some(base) => *base,
+                None => {
+                    let base = self.new_span(SPAN_SIZE, SIZE_CLASSES[class], Some(class));
+                    self.partial[class].push(base);
+                    base
+                }
+            },
+            None => match size.checked_next_multiple_of(PAGE_SIZE) {
+                Some(size) => self.new_span(size, size, None),
+                None => out_of_memory(),
+            },
+        };
+        let span = self.spans.get_mut(&base).unwrap();
+        let index = span.free.pop().unwrap();
+        if span.free.is_empty() {
+            if let Some(class) = span.class {
+                self.partial[class].pop();
+            }
+        }
+        let ptr = unsafe { span.base.add(index * span.slot_size) };
+        if span.slots[index].dirty {
+            unsafe { ptr::write_bytes(ptr, 0, span.slot_size) };
+        }
+        span.slots[index] = Slot {
+            allocated: true,
+            marked: false,
+            dirty: true,
+            typ,
+        };
+        self.live += span.slot_size;
+        ptr
+    }
+
+    /// Adds a span of `size` bytes divided into slots of `slot_size` bytes, returning its base
+    /// address. An idle span of the same size is reused if there is one
+    fn new_span(&mut self, size: usize, slot_size: usize, class: Option<usize>) -> usize {
+        let span = match self.idle.iter().position(|span| span.layout.size() == size) {
+            Some(index) => {
+                let mut span = self.idle.swap_remove(index);
+                span.divide(slot_size, class, true);
+                span
+            }
+            None => {
+                let mut span = Span::new(size);
+                span.divide(slot_size, class, false);
+                span
+            }
+        };
+        let base = span.base as usize;
+        self.spans.insert(base, span);
+        base
+    }
+
+    /// Marks the object `addr` points into, if it's on the heap. Returns the object if it has
+    /// pointers and wasn't marked already, since what it points to needs marking too
+    pub fn mark(&mut self, addr: usize) -> Option<Object> {
+        let (base, span) = self.spans.range_mut(..=addr).next_back()?;
+        let index = (addr - base) / span.slot_size;
+        let slot = span.slots.get_mut(index)?;
+        if !slot.allocated || slot.marked {
+            return None;
+        }
+        slot.marked = true;
+        if slot.typ.is_null() {
+            return None;
+        }
+        Some(Object {
+            ptr: unsafe { span.base.add(index * span.slot_size) },
+            size: span.slot_size,
+            typ: slot.typ,
+        })
+    }
+
+    /// Frees the objects that weren't marked, and unmarks the rest. Spans left without objects
+    /// become idle
+    pub fn sweep(&mut self) {
+        self.live = 0;
+        for bases in self.partial.iter_mut() {
+            bases.clear();
+        }
+        let mut empty = vec![];
+        for (base, span) in self.spans.iter_mut() {
+            span.free.clear();
+            for (index, slot) in span.slots.iter_mut().enumerate().rev() {
+                if slot.marked {
+                    slot.marked = false;
+                    self.live += span.slot_size;
+                } else {
+                    *slot = Slot {
+                        dirty: true,
+                        ..Slot::FREE
+                    };
+                    span.free.push(index);
+                }
+            }
+            if span.free.len() == span.slots.len() {
+                empty.push(*base);
+            } else if let (Some(class), false) = (span.class, span.free.is_empty()) {
+                self.partial[class].push(*base);
+            }
+        }
+        for base in empty {
+            self.idle.push(self.spans.remove(&base).unwrap());
+        }
+    }
+
+    /// Returns the idle spans to the system, except for the smallest ones (the likeliest to be
+    /// reused) that fit in `keep` bytes
+    pub fn release_idle(&mut self, keep: usize) {
+        self.idle.sort_by_key(|span| span.layout.size());
+        let mut size = 0;
+        let kept = self.idle.iter().take_while(|span| {
+            size += span.layout.size();
+            size <= keep
+        });
+        let kept = kept.count();
+        self.idle.truncate(kept);
+    }
+}
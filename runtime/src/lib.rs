@@ -6,32 +6,131 @@
 
 extern crate libc;
 
-use libc::c_char;
 use std::{
-    ffi::CStr,
     io::{self, Write},
-    process,
+    ptr, slice, str,
 };
 
 macro_rules! __local_go_panic {
     ($msg:expr) => {{
         eprintln!("panic: {}", $msg);
-        process::abort();
+        std::process::abort();
     }};
 }
 
-macro_rules! cstr_to_str {
-    ($msg:expr) => {
-        match CStr::from_ptr($msg).to_str() {
-            Ok(cstr) => cstr,
-            Err(_) => __local_go_panic!("unable to interpret passed string"),
+mod fmt;
+mod gc;
+mod heap;
+mod maps;
+mod slices;
+
+/// A go string: the bytes (usually, but not necessarily, UTF-8) and their number. It's passed by
+/// value, in the same registers as the `{ i8*, i64 }` the compiler generates
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct GoString {
+    ptr: *const u8,
+    len: usize,
+}
+
+impl GoString {
+    /// # Safety
+    ///
+    /// `ptr` must point to `len` bytes, which mustn't be collected while the result is used
+    unsafe fn as_bytes(&self) -> &'static [u8] {
+        if self.len == 0 {
+            // The pointer of an empty string may be anything
+            return &[];
         }
-    };
+        slice::from_raw_parts(self.ptr, self.len)
+    }
+
+    /// Copies `bytes` to a new string on the heap
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        // Bytes have no pointers, so the collector needn't scan them
+        let ptr = gc::__go_alloc(bytes.len() as i64, ptr::null());
+        unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len()) };
+        GoString {
+            ptr,
+            len: bytes.len(),
+        }
+    }
 }
 
+/// A rune decoded by `__decode_rune`, and the byte index of the rune after it
+#[repr(C)]
+pub struct DecodedRune {
+    rune: i32,
+    next: i64,
+}
+
+/// # Safety
+///
+/// `msg` must be a valid string
+#[no_mangle]
+pub unsafe extern "C" fn __gopanic(msg: GoString) {
+    __local_go_panic!(String::from_utf8_lossy(msg.as_bytes()));
+}
+
+/// Panics for an index out of the range `[0, len)` of a string, array or slice. `msg` is the
+/// compiler's `ERR_INDEX_OUT_OF_RANGE`, which the index and the length are appended to
+///
+/// # Safety
+///
+/// `msg` must be a valid string
 #[no_mangle]
-pub unsafe extern "C" fn __gopanic(msg: *const c_char) {
-    __local_go_panic!(cstr_to_str!(msg));
+pub unsafe extern "C" fn __gopanic_index(msg: GoString, index: i64, len: i64) {
+    let msg = String::from_utf8_lossy(msg.as_bytes());
+    if index < 0 {
+        __local_go_panic!(format!("{} [{}]", msg, index));
+    }
+    __local_go_panic!(format!("{} [{}] with length {}", msg, index, len));
+}
+
+/// Panics for the bounds of `x[low:high]`, or of `x[low:high:max]` if `three_index` is set,
+/// unless `0 <= low <= high <= max <= bound`. The bound is the capacity of a slice (if `is_cap`
+/// is set), or the length of a string or an array. Like go, this reports the first bound that's
+/// out of range, checking from the right. `msg` is the compiler's `ERR_SLICE_BOUNDS_OUT_OF_RANGE`
+///
+/// # Safety
+///
+/// `msg` must be a valid string
+#[no_mangle]
+pub unsafe extern "C" fn __gopanic_slice(
+    msg: GoString,
+    low: i64,
+    high: i64,
+    max: i64,
+    bound: i64,
+    three_index: bool,
+    is_cap: bool,
+) {
+    let kind = if is_cap { "capacity" } else { "length" };
+    let bounds = if three_index {
+        if max < 0 {
+            format!("[::{}]", max)
+        } else if max > bound {
+            format!("[::{}] with {} {}", max, kind, bound)
+        } else if high < 0 {
+            format!("[:{}:]", high)
+        } else if high > max {
+            format!("[:{}:{}]", high, max)
+        } else if low < 0 {
+            format!("[{}::]", low)
+        } else {
+            format!("[{}:{}:]", low, high)
+        }
+    } else if high < 0 {
+        format!("[:{}]", high)
+    } else if high > bound {
+        format!("[:{}] with {} {}", high, kind, bound)
+    } else if low < 0 {
+        format!("[{}:]", low)
+    } else {
+        format!("[{}:{}]", low, high)
+    };
+    let msg = String::from_utf8_lossy(msg.as_bytes());
+    __local_go_panic!(format!("{} {}", msg, bounds));
 }
 
 #[no_mangle]
@@ -41,27 +140,183 @@ pub extern "C" fn __flush_stdout() {
     }
 }
 
+/// Returns `a + b`, in a new string
+///
+/// # Safety
+///
+/// `a` and `b` must be valid strings
 #[no_mangle]
-pub unsafe extern "C" fn __print_int(int: i64) {
-    print!("{}", int);
+pub unsafe extern "C" fn __gostring_concat(a: GoString, b: GoString) -> GoString {
+    GoString::from_bytes([a.as_bytes(), b.as_bytes()].concat())
 }
 
+/// Compares `a` and `b` byte by byte, returning a negative number if `a < b`, 0 if they're equal
+/// and a positive number if `a > b`
+///
+/// # Safety
+///
+/// `a` and `b` must be valid strings
 #[no_mangle]
-pub unsafe extern "C" fn __print_bool(boolean: bool) {
-    print!("{}", boolean);
+pub unsafe extern "C" fn __gostring_compare(a: GoString, b: GoString) -> i64 {
+    a.as_bytes().cmp(b.as_bytes()) as i64
 }
 
+/// Decodes the UTF-8 encoded rune starting at byte `index` of `s`. An invalid encoding decodes to
+/// the replacement character U+FFFD, and is one byte long
+///
+/// # Safety
+///
+/// `s` must be a valid string, and `index` must be less than its length
 #[no_mangle]
-pub unsafe extern "C" fn __print_float32(float: f32) {
-    print!("{}", float);
+pub unsafe extern "C" fn __decode_rune(s: GoString, index: i64) -> DecodedRune {
+    let (rune, len) = decode_rune(&s.as_bytes()[index as usize..]);
+    DecodedRune {
+        rune: rune as i32,
+        next: index + len as i64,
+    }
+}
+
+/// Decodes the UTF-8 encoded rune at the start of `bytes`, which mustn't be empty, returning it and
+/// its length in bytes. An invalid encoding decodes to U+FFFD, and is one byte long
+fn decode_rune(bytes: &[u8]) -> (char, usize) {
+    // A rune is at most 4 bytes long, and `from_utf8` fails if they end in the middle of a rune
+    let prefix = &bytes[..bytes.len().min(4)];
+    let valid = match str::from_utf8(prefix) {
+        Ok(valid) => valid,
+        Err(err) => str::from_utf8(&prefix[..err.valid_up_to()]).unwrap(),
+    };
+    match valid.chars().next() {
+        Some(rune) => (rune, rune.len_utf8()),
+        None => (char::REPLACEMENT_CHARACTER, 1),
+    }
+}
+
+// The print functions implement go's `print` and `println` builtins, which write to stderr without
+// buffering (and ignore errors, like go does)
+
+fn print_stderr(bytes: &[u8]) {
+    let _ = io::stderr().write_all(bytes);
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn __print_float64(float: f64) {
-    print!("{}", float);
+pub extern "C" fn __print_int(int: i64) {
+    print_stderr(int.to_string().as_bytes());
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn __print_gostring(string: *const c_char) {
-    print!("{}", cstr_to_str!(string));
+pub extern "C" fn __print_uint(uint: u64) {
+    print_stderr(uint.to_string().as_bytes());
+}
+
+#[no_mangle]
+pub extern "C" fn __print_bool(boolean: bool) {
+    print_stderr(if boolean { b"true" } else { b"false" });
+}
+
+#[no_mangle]
+pub extern "C" fn __print_float32(float: f32) {
+    __print_float64(float.into());
+}
+
+/// Prints `float` the way go's runtime does, with a sign, 7 significant digits and a 3 digit
+/// exponent (e.g. `+1.500000e+000`)
+#[no_mangle]
+pub extern "C" fn __print_float64(float: f64) {
+    print_stderr(&format_float(float));
+}
+
+/// Formats `float` for `__print_float64`
+fn format_float(float: f64) -> Vec<u8> {
+    if float.is_nan() {
+        return b"NaN".to_vec();
+    }
+    if float.is_infinite() {
+        return if float > 0.0 { b"+Inf" } else { b"-Inf" }.to_vec();
+    }
+    // This follows `printfloat` in go's runtime, so that the digits are rounded the same way
+    const DIGITS: usize = 7;
+    let mut buf = [0; DIGITS + 7];
+    // Like go, this prints negative zero as `+0.000000e+000`
+    buf[0] = if float < 0.0 { b'-' } else { b'+' };
+    let mut v = float.abs();
+    let mut exp: i32 = 0;
+    if v != 0.0 {
+        // Normalize to [1, 10)
+        while v >= 10.0 {
+            exp += 1;
+            v /= 10.0;
+        }
+        while v < 1.0 {
+            exp -= 1;
+            v *= 10.0;
+        }
+        // Round
+        let mut h = 5.0;
+        for _ in 0..DIGITS {
+            h /= 10.0;
+        }
+        v += h;
+        if v >= 10.0 {
+            exp += 1;
+            v /= 10.0;
+        }
+    }
+    for i in 0..DIGITS {
+        let digit = v as u8;
+        buf[i + 2] = digit + b'0';
+        v -= digit as f64;
+        v *= 10.0;
+    }
+    buf[1] = buf[2];
+    buf[2] = b'.';
+    buf[DIGITS + 2] = b'e';
+    buf[DIGITS + 3] = if exp < 0 { b'-' } else { b'+' };
+    let exp = exp.unsigned_abs();
+    buf[DIGITS + 4] = (exp / 100) as u8 + b'0';
+    buf[DIGITS + 5] = (exp / 10 % 10) as u8 + b'0';
+    buf[DIGITS + 6] = (exp % 10) as u8 + b'0';
+    buf.to_vec()
+}
+
+/// Prints a slice the way go's `print` does: its length, its capacity and the address of its
+/// first element (e.g. `[3/4]0xc000016100`)
+#[no_mangle]
+pub extern "C" fn __print_slice(ptr: *const u8, len: i64, cap: i64) {
+    print_stderr(format!("[{}/{}]{:#x}", len, cap, ptr as usize).as_bytes());
+}
+
+/// Prints a pointer the way go's `print` does: its address (`0x0` for nil)
+#[no_mangle]
+pub extern "C" fn __print_pointer(ptr: *const u8) {
+    print_stderr(format!("{:#x}", ptr as usize).as_bytes());
+}
+
+/// # Safety
+///
+/// `string` must be a valid string
+#[no_mangle]
+pub unsafe extern "C" fn __print_gostring(string: GoString) {
+    print_stderr(string.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(float: f64) -> String {
+        String::from_utf8(format_float(float)).unwrap()
+    }
+
+    #[test]
+    fn print_float() {
+        assert_eq!(format(1.5), "+1.500000e+000");
+        assert_eq!(format(-1.5), "-1.500000e+000");
+        assert_eq!(format(123456789.0), "+1.234568e+008");
+        assert_eq!(format(0.001), "+1.000000e-003");
+        assert_eq!(format(0.0), "+0.000000e+000");
+        assert_eq!(format(-0.0), "+0.000000e+000");
+        assert_eq!(format(f64::NAN), "NaN");
+        assert_eq!(format(f64::INFINITY), "+Inf");
+        assert_eq!(format(f64::NEG_INFINITY), "-Inf");
+    }
 }
@@ -0,0 +1,587 @@
+//! Maps: hash tables with open addressing and linear probing, on the garbage collected heap.
+//!
+//! A map is a pointer to an `HMap` (nil being null), which holds an array of entries. An entry is
+//! the compiler's `{ i64, K, V }`: the state of the entry (empty, deleted, or the hash of its
+//! key), the key and the value. Keys are hashed and compared field by field, as the compiler's
+//! `MapType` describes them, so that the padding in them doesn't matter, `+0.0` and `-0.0` are the
+//! same key, and `NaN`s are never equal to anything. The hashes are seeded per map, and iterations
+//! start at a random entry, so programs can't depend on the order of a map.
+
+use crate::{
+    gc::{__go_alloc, with_root, StaticTypeDesc, TypeDesc},
+    heap::out_of_memory,
+    GoString,
+};
+use std::{
+    collections::hash_map::{DefaultHasher, RandomState},
+    hash::{BuildHasher, Hasher},
+    mem::{offset_of, size_of},
+    ptr, slice,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// The state of an entry that was never used
+const EMPTY: u64 = 0;
+/// The state of an entry whose key was deleted, which lookups must go past
+const DELETED: u64 = 1;
+
+// The kinds of the fields of keys, which tell how to hash and compare them
+/// Integers, booleans and pointers, which are equal if their bytes are
+const KEY_MEMORY: usize = 0;
+/// `float32`s and `float64`s
+const KEY_FLOAT: usize = 1;
+/// Strings, which are equal if their bytes are
+const KEY_STRING: usize = 2;
+
+/// A field of a key (every field of a struct and every element of an array, flattened), at an
+/// offset from the start of the key
+#[repr(C)]
+struct KeyField {
+    offset: usize,
+    size: usize,
+    kind: usize,
+}
+
+/// The type of a map, which the compiler generates: the layout of its entries, their type
+/// descriptor, and the fields of its keys
+#[repr(C)]
+pub struct MapType {
+    entry_size: usize,
+    key_offset: usize,
+    key_size: usize,
+    value_offset: usize,
+    entry_typ: *const TypeDesc,
+    num_fields: usize,
+    fields: [KeyField; 0],
+}
+
+impl MapType {
+    /// # Safety
+    ///
+    /// The type must be followed by its fields, as the compiler's are
+    unsafe fn fields(&self) -> &[KeyField] {
+        slice::from_raw_parts(self.fields.as_ptr(), self.num_fields)
+    }
+
+    /// Hashes the key at `key` with the map's `seed`
+    ///
+    /// # Safety
+    ///
+    /// `key` must point to a key of this type
+    unsafe fn hash(&self, key: *const u8, seed: u64) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        hasher.write_u64(seed);
+        for field in self.fields() {
+            let ptr = key.add(field.offset);
+            match field.kind {
+                KEY_FLOAT => {
+                    let value = read_float(ptr, field.size);
+                    // `NaN`s are all different keys, so they may as well go anywhere
+                    let bits = match value {
+                        _ if value == 0.0 => 0,
+                        _ if value.is_nan() => fastrand(),
+                        _ => value.to_bits(),
+                    };
+                    hasher.write_u64(bits);
+                }
+                KEY_STRING => {
+                    let string = &*(ptr as *const GoString);
+                    hasher.write(string.as_bytes());
+                    hasher.write_usize(string.len);
+                }
+                KEY_MEMORY => hasher.write(slice::from_raw_parts(ptr, field.size)),
+                _ => unreachable!(),
+            }
+        }
+        hasher.finish()
+    }
+
+    /// Whether the keys at `a` and `b` are equal
+    ///
+    /// # Safety
+    ///
+    /// `a` and `b` must point to keys of this type
+    unsafe fn key_eq(&self, a: *const u8, b: *const u8) -> bool {
+        self.fields().iter().all(|field| {
+            let (a, b) = (a.add(field.offset), b.add(field.offset));
+            match field.kind {
+                KEY_FLOAT => read_float(a, field.size) == read_float(b, field.size),
+                KEY_STRING => {
+                    let (a, b) = (&*(a as *const GoString), &*(b as *const GoString));
+                    a.as_bytes() == b.as_bytes()
+                }
+                KEY_MEMORY => {
+                    slice::from_raw_parts(a, field.size) == slice::from_raw_parts(b, field.size)
+                }
+                _ => unreachable!(),
+            }
+        })
+    }
+}
+
+/// Reads the `float32` or `float64` (of `size` bytes) at `ptr`
+///
+/// # Safety
+///
+/// `ptr` must point to a float of that size
+unsafe fn read_float(ptr: *const u8, size: usize) -> f64 {
+    match size {
+        4 => *(ptr as *const f32) as f64,
+        _ => *(ptr as *const f64),
+    }
+}
+
+/// A pseudo-random number, for the seeds of maps and the starts of iterations
+fn fastrand() -> u64 {
+    static STATE: AtomicU64 = AtomicU64::new(0);
+    let mut x = STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        x = RandomState::new().build_hasher().finish() | 1;
+    }
+    // xorshift64
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    STATE.store(x, Ordering::Relaxed);
+    x
+}
+
+/// A map. The entries are only allocated once something is assigned, unless a size was given
+#[repr(C)]
+pub struct HMap {
+    typ: *const MapType,
+    /// The number of keys in the map
+    count: usize,
+    /// The number of deleted entries
+    deleted: usize,
+    /// The number of entries, which is 0 or a power of 2
+    cap: usize,
+    seed: u64,
+    entries: *mut u8,
+}
+
+/// The only pointer of a map the collector follows is to its entries (its type is a global)
+static HMAP_TYPE: StaticTypeDesc<1> =
+    StaticTypeDesc::new(size_of::<HMap>(), [offset_of!(HMap, entries)]);
+
+impl HMap {
+    fn typ(&self) -> &'static MapType {
+        unsafe { &*self.typ }
+    }
+
+    /// The entry at `index`
+    ///
+    /// # Safety
+    ///
+    /// `index` must be less than the capacity
+    unsafe fn entry(&self, index: usize) -> *mut u8 {
+        self.entries.add(index * self.typ().entry_size)
+    }
+
+    /// The state of a full entry with the key at `key`: its hash, other than those of empty and
+    /// deleted entries
+    ///
+    /// # Safety
+    ///
+    /// `key` must point to a key of the map's type
+    unsafe fn hash(&self, key: *const u8) -> u64 {
+        self.typ().hash(key, self.seed).max(DELETED + 1)
+    }
+
+    /// Finds the entry of the key at `key` (whose state is `hash`), or else the entry it would be
+    /// inserted in (the first deleted one on the way to an empty one). Returns the index of the
+    /// entry, and whether it has the key
+    ///
+    /// # Safety
+    ///
+    /// The map must have entries, at least one of them empty, and `key` must point to a key of
+    /// its type
+    unsafe fn probe(&self, key: *const u8, hash: u64) -> (usize, bool) {
+        let mask = self.cap - 1;
+        let mut index = hash as usize & mask;
+        let mut insert_at = None;
+        loop {
+            let entry = self.entry(index);
+            match *(entry as *const u64) {
+                EMPTY => return (insert_at.unwrap_or(index), false),
+                DELETED => {
+                    insert_at.get_or_insert(index);
+                }
+                state => {
+                    let typ = self.typ();
+                    if state == hash && typ.key_eq(entry.add(typ.key_offset), key) {
+                        return (index, true);
+                    }
+                }
+            }
+            index = (index + 1) & mask;
+        }
+    }
+
+    /// The entry of the key at `key`, if the map has it
+    ///
+    /// # Safety
+    ///
+    /// `key` must point to a key of the map's type
+    unsafe fn find(&self, key: *const u8) -> Option<*mut u8> {
+        if self.count == 0 {
+            return None;
+        }
+        match self.probe(key, self.hash(key)) {
+            (index, true) => Some(self.entry(index)),
+            _ => None,
+        }
+    }
+
+    /// Moves the entries to a new array of `cap` entries, leaving out the deleted ones
+    ///
+    /// # Safety
+    ///
+    /// `cap` must be a power of 2 with room for the keys of the map
+    unsafe fn resize(&mut self, cap: usize) {
+        let typ = self.typ();
+        let size = match cap.checked_mul(typ.entry_size) {
+            Some(size) if size <= isize::MAX as usize => size,
+            _ => out_of_memory(),
+        };
+        let entries = __go_alloc(size as i64, typ.entry_typ);
+        let (old_entries, old_cap) = (self.entries, self.cap);
+        self.entries = entries;
+        self.cap = cap;
+        self.deleted = 0;
+        for index in 0..old_cap {
+            let old = old_entries.add(index * typ.entry_size);
+            let state = *(old as *const u64);
+            if state == EMPTY || state == DELETED {
+                continue;
+            }
+            // The keys are all different, so the first empty entry will do
+            let mut new_index = state as usize & (cap - 1);
+            while *(self.entry(new_index) as *const u64) != EMPTY {
+                new_index = (new_index + 1) & (cap - 1);
+            }
+            ptr::copy_nonoverlapping(old, self.entry(new_index), typ.entry_size);
+        }
+    }
+}
+
+/// The number of entries a map needs for `count` keys: at most three quarters of them are full
+fn capacity_for(count: usize) -> usize {
+    let needed = count
+        .checked_mul(4)
+        .map(|needed| needed.div_ceil(3))
+        .and_then(usize::checked_next_power_of_two);
+    match needed {
+        Some(cap) => cap.max(8),
+        None => out_of_memory(),
+    }
+}
+
+/// Implements `make(map[K]V, hint)` (and map literals, with the number of their elements). A
+/// negative hint is treated as 0
+///
+/// # Safety
+///
+/// `typ` must be the compiler's type of the map
+#[no_mangle]
+pub unsafe extern "C" fn __go_makemap(typ: *const MapType, hint: i64) -> *mut HMap {
+    let map = __go_alloc(size_of::<HMap>() as i64, HMAP_TYPE.as_ptr()) as *mut HMap;
+    map.write(HMap {
+        typ,
+        count: 0,
+        deleted: 0,
+        cap: 0,
+        seed: fastrand(),
+        entries: ptr::null_mut(),
+    });
+    // Nothing else points to the map while its entries are allocated
+    if hint > 0 {
+        let slot = ptr::addr_of!(map) as *const *mut u8;
+        with_root(slot, || (*map).resize(capacity_for(hint as usize)));
+    }
+    map
+}
+
+/// Looks up the key at `key`, returning a pointer to its value, or null if the map (which may be
+/// nil) doesn't have it
+///
+/// # Safety
+///
+/// `map` must be null or a map, and `key` must point to a key of its type
+#[no_mangle]
+pub unsafe extern "C" fn __go_mapaccess(map: *const HMap, key: *const u8) -> *const u8 {
+    if map.is_null() {
+        return ptr::null();
+    }
+    match (*map).find(key) {
+        Some(entry) => entry.add((*map).typ().value_offset),
+        None => ptr::null(),
+    }
+}
+
+/// Adds the key at `key` to the map (unless it has it already), returning a pointer to its value
+/// for the compiler to store the value in. The pointer is only valid until the map is changed
+/// again
+///
+/// # Safety
+///
+/// `map` must be a map, which isn't nil, and `key` must point to a key of its type
+#[no_mangle]
+pub unsafe extern "C" fn __go_mapassign(map: *mut HMap, key: *const u8) -> *mut u8 {
+    let map = &mut *map;
+    // Deleted entries count towards the load, since lookups go past them. If they make up much of
+    // it, the entries are only rehashed, rather than doubled
+    if (map.count + map.deleted + 1) * 4 > map.cap * 3 {
+        let cap = match map.cap {
+            0 => 8,
+            cap if (map.count + 1) * 2 > cap => cap * 2,
+            cap => cap,
+        };
+        map.resize(cap);
+    }
+    let hash = map.hash(key);
+    let (index, found) = map.probe(key, hash);
+    let entry = map.entry(index);
+    let typ = map.typ();
+    if !found {
+        if *(entry as *const u64) == DELETED {
+            map.deleted -= 1;
+        }
+        map.count += 1;
+        *(entry as *mut u64) = hash;
+    }
+    // Assigning to an existing key stores the key again, like go does (e.g. `-0.0` replaces `+0.0`)
+    ptr::copy_nonoverlapping(key, entry.add(typ.key_offset), typ.key_size);
+    entry.add(typ.value_offset)
+}
+
+/// Implements `delete(m, k)`, which does nothing if the map is nil or doesn't have the key
+///
+/// # Safety
+///
+/// `map` must be null or a map, and `key` must point to a key of its type
+#[no_mangle]
+pub unsafe extern "C" fn __go_mapdelete(map: *mut HMap, key: *const u8) {
+    if map.is_null() {
+        return;
+    }
+    let map = &mut *map;
+    let entry = match map.find(key) {
+        Some(entry) => entry,
+        None => return,
+    };
+    let typ = map.typ();
+    // The key and the value are zeroed, so that the collector doesn't keep what they point to
+    // alive. If the next entry is empty, no lookup needs to go past this one
+    ptr::write_bytes(entry, 0, typ.entry_size);
+    let index = (entry as usize - map.entries as usize) / typ.entry_size;
+    let next = map.entry((index + 1) & (map.cap - 1));
+    if *(next as *const u64) != EMPTY {
+        *(entry as *mut u64) = DELETED;
+        map.deleted += 1;
+    }
+    map.count -= 1;
+}
+
+/// Implements `len(m)`
+///
+/// # Safety
+///
+/// `map` must be null or a map
+#[no_mangle]
+pub unsafe extern "C" fn __go_maplen(map: *const HMap) -> i64 {
+    if map.is_null() {
+        return 0;
+    }
+    (*map).count as i64
+}
+
+/// An iteration over a map, which goes through the entries the map had when it started (from a
+/// random one). If the map has moved its entries since, each key is looked up in the map, so that
+/// the keys deleted since aren't produced, and the values are the current ones
+#[repr(C)]
+pub struct MapIter {
+    map: *const HMap,
+    entries: *const u8,
+    cap: usize,
+    start: usize,
+    /// The number of entries gone through
+    index: usize,
+}
+
+static MAP_ITER_TYPE: StaticTypeDesc<2> = StaticTypeDesc::new(
+    size_of::<MapIter>(),
+    [offset_of!(MapIter, map), offset_of!(MapIter, entries)],
+);
+
+/// Starts an iteration over a map (which may be nil), for `__go_mapiternext`. An iteration over an
+/// empty map is null
+///
+/// # Safety
+///
+/// `map` must be null or a map
+#[no_mangle]
+pub unsafe extern "C" fn __go_mapiterinit(map: *const HMap) -> *mut MapIter {
+    if map.is_null() || (*map).count == 0 {
+        return ptr::null_mut();
+    }
+    let it = __go_alloc(size_of::<MapIter>() as i64, MAP_ITER_TYPE.as_ptr()) as *mut MapIter;
+    let cap = (*map).cap;
+    it.write(MapIter {
+        map,
+        entries: (*map).entries,
+        cap,
+        start: fastrand() as usize & (cap - 1),
+        index: 0,
+    });
+    it
+}
+
+/// The next entry of an iteration, or null once it's over. Keys added during the iteration may or
+/// may not be produced
+///
+/// # Safety
+///
+/// `it` must be null or an iteration started by `__go_mapiterinit`
+#[no_mangle]
+pub unsafe extern "C" fn __go_mapiternext(it: *mut MapIter) -> *const u8 {
+    if it.is_null() {
+        return ptr::null();
+    }
+    let it = &mut *it;
+    let map = &*it.map;
+    let typ = map.typ();
+    while it.index < it.cap {
+        let index = (it.start + it.index) & (it.cap - 1);
+        it.index += 1;
+        let entry = it.entries.add(index * typ.entry_size);
+        let state = *(entry as *const u64);
+        if state == EMPTY || state == DELETED {
+            continue;
+        }
+        if it.entries == map.entries {
+            return entry;
+        }
+        // A key that isn't equal to itself (a `NaN`) can't be looked up, and can't have been
+        // deleted either
+        let key = entry.add(typ.key_offset);
+        if !typ.key_eq(key, key) {
+            return entry;
+        }
+        if let Some(entry) = map.find(key) {
+            return entry;
+        }
+    }
+    ptr::null()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gc::{
+        __go_gc,
+        tests::{leave_room, live, lock_heap},
+    };
+
+    /// A map type followed by the fields of its keys, like the compiler's
+    #[repr(C)]
+    struct TestMapType {
+        typ: MapType,
+        fields: [KeyField; 1],
+    }
+
+    /// The type of a `map[int]int`, whose entries are `{ i64, i64, i64 }`
+    fn int_map_type() -> *const MapType {
+        let typ = Box::leak(Box::new(TestMapType {
+            typ: MapType {
+                entry_size: 24,
+                key_offset: 8,
+                key_size: 8,
+                value_offset: 16,
+                entry_typ: ptr::null(),
+                num_fields: 1,
+                fields: [],
+            },
+            fields: [KeyField {
+                offset: 0,
+                size: 8,
+                kind: KEY_MEMORY,
+            }],
+        }));
+        &typ.typ
+    }
+
+    unsafe fn get(map: *const HMap, key: i64) -> Option<i64> {
+        let value = __go_mapaccess(map, ptr::addr_of!(key) as *const u8);
+        (!value.is_null()).then(|| *(value as *const i64))
+    }
+
+    unsafe fn set(map: *mut HMap, key: i64, value: i64) {
+        *(__go_mapassign(map, ptr::addr_of!(key) as *const u8) as *mut i64) = value;
+    }
+
+    unsafe fn delete(map: *mut HMap, key: i64) {
+        __go_mapdelete(map, ptr::addr_of!(key) as *const u8);
+    }
+
+    #[test]
+    fn insert_lookup_and_delete_across_resizes() {
+        let _lock = lock_heap();
+        unsafe {
+            let map = __go_makemap(int_map_type(), 0);
+            with_root(ptr::addr_of!(map) as *const *mut u8, || {
+                for key in 0..1000 {
+                    set(map, key, key * 10);
+                }
+                assert_eq!(__go_maplen(map), 1000);
+                // The entries are only reachable through the map
+                __go_gc();
+                for key in 0..1000 {
+                    assert_eq!(get(map, key), Some(key * 10));
+                }
+                assert_eq!(get(map, 1000), None);
+                for key in (0..1000).step_by(2) {
+                    delete(map, key);
+                }
+                assert_eq!(__go_maplen(map), 500);
+                for key in 0..1000 {
+                    let expected = (key % 2 == 1).then_some(key * 10);
+                    assert_eq!(get(map, key), expected);
+                }
+                // Filling the deleted entries again rehashes them away
+                for key in 0..2000 {
+                    set(map, key, -key);
+                }
+                assert_eq!(__go_maplen(map), 2000);
+                for key in 0..2000 {
+                    assert_eq!(get(map, key), Some(-key));
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn nil_map_is_empty() {
+        unsafe {
+            assert_eq!(get(ptr::null(), 1), None);
+            assert_eq!(__go_maplen(ptr::null()), 0);
+            delete(ptr::null_mut(), 1);
+        }
+    }
+
+    #[test]
+    fn makemap_survives_collection_of_its_entries() {
+        let _lock = lock_heap();
+        // The map fits, but its 2048 entries start a collection
+        leave_room(4 << 10);
+        unsafe {
+            let map = __go_makemap(int_map_type(), 1000);
+            with_root(ptr::addr_of!(map) as *const *mut u8, || {
+                __go_gc();
+                assert_eq!(live(), size_of::<HMap>() + 2048 * 24);
+                set(map, 7, 70);
+                assert_eq!(get(map, 7), Some(70));
+            });
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! The arrays of slices. `append` moves the elements of a slice that's out of capacity to a larger
+//! array, whose capacity grows the way go's does (so that programs see the same `cap`s).
+
+use crate::{
+    gc::{__go_alloc, TypeDesc},
+    heap::round_up_size,
+};
+use std::ptr;
+
+/// A slice header, which matches the compiler's `{ T*, i64, i64 }` with the element pointer as an
+/// `i8*`: the address of the first element, the length and the capacity
+#[repr(C)]
+pub struct Slice {
+    ptr: *mut u8,
+    len: i64,
+    cap: i64,
+}
+
+/// Moves the `len` elements (of `elem_size` bytes each, and of type `typ`) at `ptr` to a new array
+/// with room for more than `cap` of them, returning a slice of its first `new_len` elements (the
+/// rest are zeroed)
+///
+/// # Safety
+///
+/// `ptr` must point to `len` elements, and `new_len` must be greater than `cap`
+#[no_mangle]
+pub unsafe extern "C" fn __go_growslice(
+    ptr: *const u8,
+    len: i64,
+    cap: i64,
+    new_len: i64,
+    elem_size: i64,
+    typ: *const TypeDesc,
+) -> Slice {
+    let (len, new_len, elem_size) = (len as usize, new_len as usize, elem_size as usize);
+    if elem_size == 0 {
+        // Elements without a size take no room, so any capacity fits them
+        return Slice {
+            ptr: __go_alloc(0, typ),
+            len: new_len as i64,
+            cap: new_len as i64,
+        };
+    }
+    let size = next_capacity(new_len, cap as usize)
+        .checked_mul(elem_size)
+        .filter(|size| *size <= isize::MAX as usize);
+    let size = match size {
+        Some(size) => round_up_size(size),
+        None => __local_go_panic!("runtime error: growslice: len out of range"),
+    };
+    let new_ptr = __go_alloc(size as i64, typ);
+    ptr::copy_nonoverlapping(ptr, new_ptr, len * elem_size);
+    Slice {
+        ptr: new_ptr,
+        len: new_len as i64,
+        cap: (size / elem_size) as i64,
+    }
+}
+
+/// Copies as many elements (of `elem_size` bytes each) as both `dst` and `src` have, returning
+/// their number. The slices may overlap
+///
+/// # Safety
+///
+/// `dst` and `src` must point to `dst_len` and `src_len` elements
+#[no_mangle]
+pub unsafe extern "C" fn __go_slicecopy(
+    dst: *mut u8,
+    dst_len: i64,
+    src: *const u8,
+    src_len: i64,
+    elem_size: i64,
+) -> i64 {
+    let len = dst_len.min(src_len);
+    if len > 0 {
+        ptr::copy(src, dst, (len * elem_size) as usize);
+    }
+    len
+}
+
+/// The capacity of a slice of capacity `cap` grown to hold `new_len` elements: twice as much for
+/// small slices, and 1.25 times as much for large ones, with a smooth transition between the two
+fn next_capacity(new_len: usize, cap: usize) -> usize {
+    const THRESHOLD: usize = 256;
+    let double_cap = cap + cap;
+    if new_len > double_cap {
+        return new_len;
+    }
+    if cap < THRESHOLD {
+        return double_cap;
+    }
+    let mut new_cap = cap;
+    while new_cap < new_len {
+        new_cap += (new_cap + 3 * THRESHOLD) >> 2;
+    }
+    new_cap
+}